@@ -1,8 +1,35 @@
 fn main() {
     linker_be_nice();
-    println!("cargo:rustc-link-arg=-Tdefmt.x");
-    // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
-    println!("cargo:rustc-link-arg=-Tlinkall.x");
+    if for_board() {
+        println!("cargo:rustc-link-arg=-Tdefmt.x");
+        // make sure linkall.x is the last linker script (otherwise might cause problems with flip-link)
+        println!("cargo:rustc-link-arg=-Tlinkall.x");
+    }
+    git_hash();
+}
+
+/// Whether this is a build for the esp32, rather than the computer it's running on with the
+/// `host` feature. Only the board has the linker scripts.
+fn for_board() -> bool {
+    std::env::var("CARGO_CFG_TARGET_ARCH").as_deref() == Ok("xtensa")
+}
+
+/// Set `GIT_HASH` to the commit being built, for `status::build_info`. Left unset without git or
+/// outside a checkout.
+fn git_hash() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-changed=.git/packed-refs");
+    let Ok(output) = std::process::Command::new("git")
+        .args(["rev-parse", "--short", "HEAD"])
+        .output()
+    else {
+        return;
+    };
+    if output.status.success() {
+        let hash = String::from_utf8_lossy(&output.stdout);
+        println!("cargo:rustc-env=GIT_HASH={}", hash.trim());
+    }
 }
 
 fn linker_be_nice() {
@@ -50,6 +77,9 @@ fn linker_be_nice() {
         std::process::exit(0);
     }
 
+    if !for_board() {
+        return;
+    }
     println!(
         "cargo:rustc-link-arg=-Wl,--error-handling-script={}",
         std::env::current_exe().unwrap().display()
@@ -0,0 +1,134 @@
+//! Replays captured api responses on the computer you're on, no board needed. Every `.json` in a
+//! directory, `test_data/replay` unless another is given, goes through the parser and comes out as
+//! the rows the firmware logs after a poll:
+//!
+//! ```sh
+//! cargo +stable run --example replay --features host --target x86_64-unknown-linux-gnu
+//! ```
+//!
+//! Set `--target` to the `host:` that `rustc -vV` reports, since `.cargo/config.toml` builds for
+//! the board otherwise, and `+stable` leaves out the board's `build-std`.
+//!
+//! A `.txt` next to a capture holds what it should render as, and a capture that renders any
+//! differently, or no longer parses, fails the run with a non-zero exit. After changing a fixture
+//! or the rows on purpose, run with `--bless` to write the `.txt` files from the current output
+//! instead. `cargo +stable test --example replay --features host`, with the same `--target`,
+//! checks `test_data/replay` the same way as a test.
+
+use std::{env, fs, path::Path, process::ExitCode};
+
+use esp_wmata_pids::wmata::{
+    json,
+    types::{Abbreviations, NextTrainsResponse, Rows},
+};
+use heapless::String;
+
+/// Rows the firmware shows before summing up the rest, same as its `MAX_ROWS`.
+const MAX_ROWS: usize = 6;
+
+fn main() -> ExitCode {
+    let mut bless = false;
+    let mut dir = std::string::String::from("test_data/replay");
+    for arg in env::args().skip(1) {
+        match arg.as_str() {
+            "--bless" => bless = true,
+            _ => dir = arg,
+        }
+    }
+
+    let mut captures: Vec<_> = match fs::read_dir(&dir) {
+        Ok(entries) => entries
+            .filter_map(|entry| Some(entry.ok()?.path()))
+            .filter(|path| path.extension().is_some_and(|ext| ext == "json"))
+            .collect(),
+        Err(e) => {
+            eprintln!("can't read {dir}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+    captures.sort();
+
+    let mut failed = 0;
+    for capture in &captures {
+        println!("== {}", capture.display());
+        let rendered = match render(capture) {
+            Ok(rendered) => rendered,
+            Err(e) => {
+                println!("{e}");
+                failed += 1;
+                continue;
+            }
+        };
+        print!("{rendered}");
+
+        let expected = capture.with_extension("txt");
+        if bless {
+            if let Err(e) = fs::write(&expected, &rendered) {
+                println!("can't write {}: {e}", expected.display());
+                failed += 1;
+            }
+        } else if let Ok(expected) = fs::read_to_string(&expected)
+            && expected != rendered
+        {
+            print!("-- expected\n{expected}");
+            failed += 1;
+        }
+    }
+
+    println!("{} captures, {failed} failed", captures.len());
+    if failed == 0 {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+/// The rows for the predictions in `capture`, one per line like `print_rows` in the firmware.
+fn render(capture: &Path) -> Result<std::string::String, std::string::String> {
+    let body = fs::read_to_string(capture).map_err(|e| format!("can't read it: {e}"))?;
+    let trains: NextTrainsResponse =
+        json::from_str(&body).map_err(|e| format!("doesn't parse: {e:?}"))?;
+
+    let mut rendered = std::string::String::new();
+    let mut row: String<48> = String::new();
+    let rows = trains.rows(MAX_ROWS);
+    for train in &rows.shown {
+        row.clear();
+        train
+            .write_debug_display_with(&mut row, &Abbreviations::NONE)
+            .map_err(|_| "a row doesn't fit in 48 characters")?;
+        rendered.push_str(&row);
+        rendered.push('\n');
+    }
+    row.clear();
+    rows.write_overflow(&mut row, Rows::DEFAULT_OVERFLOW_FORMAT)
+        .map_err(|_| "the overflow row doesn't fit in 48 characters")?;
+    if !row.is_empty() {
+        rendered.push_str(&row);
+        rendered.push('\n');
+    }
+
+    Ok(rendered)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_capture_renders_as_expected() {
+        let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test_data/replay");
+        let mut checked = 0;
+        for entry in fs::read_dir(&dir).unwrap() {
+            let capture = entry.unwrap().path();
+            if capture.extension().is_none_or(|ext| ext != "json") {
+                continue;
+            }
+            let expected = fs::read_to_string(capture.with_extension("txt"))
+                .unwrap_or_else(|e| panic!("no .txt for {}: {e}", capture.display()));
+            assert_eq!(render(&capture).unwrap(), expected, "{}", capture.display());
+            checked += 1;
+        }
+        assert!(checked > 0, "no captures in {}", dir.display());
+    }
+}
@@ -0,0 +1,191 @@
+use core::convert::Infallible;
+
+use embedded_hal::digital::OutputPin;
+
+#[cfg(feature = "rail")]
+use crate::wmata::types::{EtaClass, NextTrainsResponse};
+
+/// Physical outputs (buzzers, LEDs, ...) used to get a rider's attention.
+/// Implementations decide what each alert looks or sounds like, callers decide when to raise them.
+pub trait Alerter {
+    type Error;
+
+    /// A train is arriving or boarding at the displayed station.
+    fn arriving(&mut self) -> Result<(), Self::Error>;
+
+    /// Service at the displayed station is disrupted.
+    fn disrupted(&mut self) -> Result<(), Self::Error>;
+
+    /// Return all outputs to idle.
+    fn clear(&mut self) -> Result<(), Self::Error>;
+}
+
+/// Drives one pin per alert, held high while that alert is active.
+pub struct PinAlerter<A, D> {
+    arriving: A,
+    disrupted: D,
+}
+
+impl<A, D, E> PinAlerter<A, D>
+where
+    A: OutputPin<Error = E>,
+    D: OutputPin<Error = E>,
+{
+    pub fn new(arriving: A, disrupted: D) -> Self {
+        Self {
+            arriving,
+            disrupted,
+        }
+    }
+
+    /// Give back the pins, e.g. to reuse them elsewhere.
+    pub fn release(self) -> (A, D) {
+        (self.arriving, self.disrupted)
+    }
+}
+
+impl<A, D, E> Alerter for PinAlerter<A, D>
+where
+    A: OutputPin<Error = E>,
+    D: OutputPin<Error = E>,
+{
+    type Error = E;
+
+    fn arriving(&mut self) -> Result<(), E> {
+        self.arriving.set_high()
+    }
+
+    fn disrupted(&mut self) -> Result<(), E> {
+        self.disrupted.set_high()
+    }
+
+    fn clear(&mut self) -> Result<(), E> {
+        self.arriving.set_low()?;
+        self.disrupted.set_low()
+    }
+}
+
+/// Does nothing, for headless builds with no alert hardware.
+pub struct NoopAlerter;
+
+impl Alerter for NoopAlerter {
+    type Error = Infallible;
+
+    fn arriving(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn disrupted(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), Infallible> {
+        Ok(())
+    }
+}
+
+/// Calls [`Alerter::arriving`] as the first train comes into [`EtaClass::Now`], ARR or BRD, and
+/// [`Alerter::clear`] once none is, rather than on every poll a train sits at the platform.
+#[cfg(feature = "rail")]
+#[derive(Default)]
+pub struct ArrivalAlert {
+    arriving: bool,
+}
+
+#[cfg(feature = "rail")]
+impl ArrivalAlert {
+    pub const fn new() -> Self {
+        Self { arriving: false }
+    }
+
+    /// Alert on `alerter` if `trains` changed whether one is arriving since the last update.
+    pub fn update<A: Alerter>(
+        &mut self,
+        alerter: &mut A,
+        trains: &NextTrainsResponse,
+    ) -> Result<(), A::Error> {
+        let arriving = trains
+            .iter()
+            .any(|t| t.min.is_some_and(|min| min.class() == EtaClass::Now));
+        if arriving == self.arriving {
+            return Ok(());
+        }
+
+        if arriving {
+            alerter.arriving()?;
+        } else {
+            alerter.clear()?;
+        }
+        self.arriving = arriving;
+        Ok(())
+    }
+}
+
+#[cfg(all(test, feature = "rail"))]
+mod tests {
+    use embedded_hal::digital::ErrorType;
+
+    use super::*;
+    use crate::wmata::types::{Eta, LineKind, NextTrain, Station};
+
+    /// Counts the times it's set high, and whether it is now.
+    #[derive(Default)]
+    struct FakePin {
+        highs: u8,
+        high: bool,
+    }
+
+    impl ErrorType for FakePin {
+        type Error = Infallible;
+    }
+
+    impl OutputPin for FakePin {
+        fn set_high(&mut self) -> Result<(), Infallible> {
+            self.highs += 1;
+            self.high = true;
+            Ok(())
+        }
+
+        fn set_low(&mut self) -> Result<(), Infallible> {
+            self.high = false;
+            Ok(())
+        }
+    }
+
+    fn due(eta: Eta) -> NextTrainsResponse {
+        NextTrainsResponse::new(alloc::vec![
+            NextTrain::new(Station::K04, LineKind::OR, "Vienna", eta),
+            NextTrain::new(Station::K04, LineKind::SV, "Ashburn", Eta::Minutes(9)),
+        ])
+    }
+
+    #[test]
+    fn arriving_fires_once_on_entering_the_band() {
+        let mut alerter = PinAlerter::new(FakePin::default(), FakePin::default());
+        let mut alert = ArrivalAlert::new();
+
+        for eta in [Eta::Minutes(2), Eta::Minutes(1), Eta::Arriving] {
+            alert.update(&mut alerter, &due(eta)).unwrap();
+        }
+        let (arriving, _) = alerter.release();
+        assert_eq!((arriving.highs, arriving.high), (1, true));
+
+        // still in it while the train boards, so no second alert
+        let mut alerter = PinAlerter::new(arriving, FakePin::default());
+        alert.update(&mut alerter, &due(Eta::Boarding)).unwrap();
+        alert.update(&mut alerter, &due(Eta::Boarding)).unwrap();
+        let (arriving, disrupted) = alerter.release();
+        assert_eq!((arriving.highs, arriving.high), (1, true));
+        assert_eq!(disrupted.highs, 0);
+
+        // gone, then the next one comes in
+        let mut alerter = PinAlerter::new(arriving, disrupted);
+        alert.update(&mut alerter, &due(Eta::Minutes(8))).unwrap();
+        let (arriving, disrupted) = alerter.release();
+        assert_eq!((arriving.highs, arriving.high), (1, false));
+        let mut alerter = PinAlerter::new(arriving, disrupted);
+        alert.update(&mut alerter, &due(Eta::Arriving)).unwrap();
+        let (arriving, _) = alerter.release();
+        assert_eq!((arriving.highs, arriving.high), (2, true));
+    }
+}
@@ -0,0 +1,437 @@
+//! Live predictions on a 128x32 SSD1306 OLED over I2C, SDA on GPIO21 and SCL on GPIO22.
+//!
+//! A starting point for driving a real panel: the same client and config as `main.rs`, without
+//! its power modes, roaming, status page or event log. Build it with
+//! `cargo run --release --bin display --features ssd1306`.
+//!
+//! The config is read from flash, as saved by the main firmware, falling back to the same
+//! `SSID`, `PASSWORD` and `API_KEY` build-time variables. With neither, it opens the same setup
+//! network the main firmware does and shows how to join it.
+
+#![no_std]
+#![no_main]
+#![deny(
+    clippy::mem_forget,
+    reason = "mem::forget is generally not safe to do with esp_hal types, especially those \
+    holding buffers for the duration of a data transfer."
+)]
+
+use core::fmt::Write as _;
+
+use defmt::*;
+use embassy_executor::{Spawner, task};
+use embassy_futures::select::{Either, select};
+use embassy_net::{Ipv4Cidr, Runner, StackResources, StaticConfigV4};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_graphics::mono_font::MonoTextStyle;
+use embedded_graphics::mono_font::ascii::FONT_6X10;
+use embedded_graphics::pixelcolor::BinaryColor;
+use embedded_graphics::prelude::*;
+use embedded_graphics::primitives::{PrimitiveStyle, Rectangle};
+use embedded_graphics::text::{Baseline, Text};
+use esp_hal::clock::CpuClock;
+use esp_hal::efuse::Efuse;
+use esp_hal::i2c::master::{Config as I2cConfig, I2c};
+use esp_hal::interrupt::software::SoftwareInterruptControl;
+use esp_hal::peripherals::WIFI;
+use esp_hal::rng::Rng;
+use esp_hal::timer::timg::TimerGroup;
+use esp_radio::wifi::ap::AccessPointConfig;
+use esp_radio::wifi::sta::StationConfig;
+use esp_radio::wifi::{
+    ControllerConfig, ModeConfig, WifiController, WifiDevice, WifiEvent, WifiStationState,
+};
+use esp_storage::FlashStorage;
+use esp_wmata_pids::screen::DisplayState;
+use esp_wmata_pids::setup;
+use esp_wmata_pids::wmata::types::{NextTrainsResponse, Rows, Station};
+use esp_wmata_pids::wmata::{API, Client, ClientResources, Config, Rotation};
+use heapless::String;
+use ssd1306::mode::BufferedGraphicsMode;
+use ssd1306::prelude::*;
+use ssd1306::{I2CDisplayInterface, Ssd1306};
+use {esp_backtrace as _, esp_println as _};
+
+// This creates a default app-descriptor required by the esp-idf bootloader.
+// For more information see: <https://docs.espressif.com/projects/esp-idf/en/stable/esp32/api-reference/system/app_image_format.html#application-description>
+esp_bootloader_esp_idf::esp_app_desc!();
+
+// When you are okay with using a nightly compiler it's better to use https://docs.rs/static_cell/2.1.0/static_cell/macro.make_static.html
+macro_rules! mk_static {
+    ($t:ty,$val:expr) => {{
+        static STATIC_CELL: static_cell::StaticCell<$t> = static_cell::StaticCell::new();
+        #[deny(unused_attributes)]
+        let x = STATIC_CELL.uninit().write(($val));
+        x
+    }};
+}
+
+const SSID: Option<&str> = option_env!("SSID");
+const PASSWORD: Option<&str> = option_env!("PASSWORD");
+const API_KEY: Option<&str> = option_env!("API_KEY");
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// Shown until a station is picked and saved to the config, see `Config::with_station`.
+const DEFAULT_STATION: Station = Station::K04;
+/// Text rows that fit the panel in `FONT_6X10`, 21 characters each.
+const LINES: usize = 3;
+const LINE_HEIGHT: i32 = 10;
+
+type Panel<DI> = Ssd1306<DI, DisplaySize128x32, BufferedGraphicsMode<DisplaySize128x32>>;
+
+#[esp_rtos::main]
+async fn main(spawner: Spawner) -> ! {
+    let config = esp_hal::Config::default().with_cpu_clock(CpuClock::max());
+    let peripherals = esp_hal::init(config);
+
+    esp_alloc::heap_allocator!(#[esp_hal::ram(reclaimed)] size: 98768);
+    esp_alloc::heap_allocator!(size: 64 * 1024);
+
+    let timg0 = TimerGroup::new(peripherals.TIMG0);
+    let sw_int = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
+    esp_rtos::start(timg0.timer0, sw_int.software_interrupt0);
+
+    let i2c = unwrap!(
+        I2c::new(peripherals.I2C0, I2cConfig::default()),
+        "failed to set up I2C"
+    )
+    .with_sda(peripherals.GPIO21)
+    .with_scl(peripherals.GPIO22);
+    let mut display = Ssd1306::new(
+        I2CDisplayInterface::new(i2c),
+        DisplaySize128x32,
+        DisplayRotation::Rotate0,
+    )
+    .into_buffered_graphics_mode();
+    if let Err(e) = display.init() {
+        defmt::panic!("couldn't start the display: {:?}", Debug2Format(&e));
+    }
+
+    let mut flash = FlashStorage::new(peripherals.FLASH);
+    let saved = Config::load(&mut flash);
+    if let Err(e) = &saved {
+        info!("no valid config ({}), using environment variables", e);
+    }
+    let from_env = || {
+        Some(unwrap!(
+            Config::new(SSID?, PASSWORD?, API_KEY?),
+            "SSID, PASSWORD or API_KEY too long"
+        ))
+    };
+    let cfg = Config::resolve(&saved, from_env);
+    let mac = Efuse::mac_address();
+    show(
+        &mut display,
+        &DisplayState::at_boot(cfg.as_ref(), mac),
+        None,
+    );
+    let Some(cfg) = cfg else {
+        warn!("SSID, PASSWORD or API_KEY not set, and no config in flash");
+        set_up(spawner, flash, peripherals.WIFI, mac).await
+    };
+    info!("config: {:?}", cfg);
+    let station = cfg.station().unwrap_or(DEFAULT_STATION);
+
+    // only the panel size this binary was built for is supported, see `LINES`
+    if (cfg.width(), cfg.height()) != (128, 32) {
+        warn!(
+            "config is for a {}x{} panel, drawing for 128x32",
+            cfg.width(),
+            cfg.height()
+        );
+    }
+    let rotation = match cfg.rotation() {
+        Rotation::Deg90 => DisplayRotation::Rotate90,
+        Rotation::Deg180 => DisplayRotation::Rotate180,
+        Rotation::Deg270 => DisplayRotation::Rotate270,
+        _ => DisplayRotation::Rotate0,
+    };
+    if let Err(e) = display.set_rotation(rotation) {
+        warn!("couldn't rotate the display: {:?}", Debug2Format(&e));
+    }
+
+    let mut wifi_config = ControllerConfig::default();
+    if let Some(country) = cfg.wifi_country() {
+        let code: [u8; 2] = unwrap!(country.as_bytes().try_into().ok());
+        wifi_config = wifi_config.with_country_info(code);
+    }
+    let (wifi_controller, interfaces) = unwrap!(
+        esp_radio::wifi::new(peripherals.WIFI, wifi_config),
+        "Failed to initialize Wi-Fi controller"
+    );
+
+    let rng = Rng::new();
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+    // 3 sockets: the DHCPv4 client, DNS and the single TCP connection used for api requests
+    let (stack, runner) = embassy_net::new(
+        interfaces.station,
+        embassy_net::Config::dhcpv4(Default::default()),
+        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        seed,
+    );
+
+    let cfg = mk_static!(Config, cfg);
+    unwrap!(
+        spawner.spawn(connection(
+            wifi_controller,
+            cfg.ssid(),
+            cfg.pass(),
+            cfg.wifi_max_tx_power()
+        )),
+        "failed to spawn task"
+    );
+    unwrap!(spawner.spawn(net_task(runner)), "failed to spawn task");
+
+    let resources = mk_static!(ClientResources, ClientResources::new());
+    let mut client = unwrap!(Client::with_base_and_key(
+        stack,
+        resources,
+        API,
+        cfg.api_key()
+    ))
+    .with_destinations(cfg.destinations())
+    .with_lines_mask(cfg.lines_mask())
+    .with_min_minutes(cfg.min_minutes(), cfg.keeps_arriving())
+    .with_no_passenger(cfg.no_passenger());
+
+    stack.wait_config_up().await;
+    info!("showing predictions for {}", station);
+
+    let mut next_poll = Instant::now();
+    let mut last_success = None;
+    // what `DisplayState::Stale` keeps up
+    let mut last_trains = None;
+    // whether `last_trains` are the whole screen, so the next trains only redraw what changed
+    let mut showing_trains = false;
+    loop {
+        let now = Instant::now();
+        let res = client.next_trains(station).await;
+        match &res {
+            Ok(_) => last_success = Some(now),
+            Err(e) => error!("{}", e),
+        }
+
+        let state = DisplayState::after_fetch(res, last_success, now);
+        match (&state, last_trains.as_ref().filter(|_| showing_trains)) {
+            (DisplayState::Trains(trains), Some(previous)) => {
+                update(&mut display, trains, previous)
+            }
+            _ => show(&mut display, &state, last_trains.as_ref()),
+        }
+        showing_trains = matches!(state, DisplayState::Trains(_));
+        last_trains = match state {
+            DisplayState::Trains(trains) => Some(trains),
+            DisplayState::Stale { .. } => last_trains,
+            _ => None,
+        };
+
+        next_poll = (next_poll + POLL_INTERVAL).max(Instant::now());
+        Timer::at(next_poll).await;
+    }
+}
+
+/// Draw `state` and push it to the panel, logging rather than stopping if either fails.
+fn show<DI: WriteOnlyDataCommand>(
+    display: &mut Panel<DI>,
+    state: &DisplayState,
+    last_trains: Option<&NextTrainsResponse>,
+) {
+    if let Err(e) = draw(display, state, last_trains) {
+        error!("couldn't draw: {:?}", Debug2Format(&e));
+    }
+    if let Err(e) = display.flush() {
+        error!("couldn't update the display: {:?}", Debug2Format(&e));
+    }
+}
+
+/// Redraw only the rows of `trains` that differ from the `previous` ones on the panel, so the
+/// flush only sends those rather than the whole screen. Logs rather than stopping, like [`show`].
+fn update<DI: WriteOnlyDataCommand>(
+    display: &mut Panel<DI>,
+    trains: &NextTrainsResponse,
+    previous: &NextTrainsResponse,
+) {
+    if let Err(e) = redraw_rows(display, trains, previous) {
+        error!("couldn't draw: {:?}", Debug2Format(&e));
+    }
+    if let Err(e) = display.flush() {
+        error!("couldn't update the display: {:?}", Debug2Format(&e));
+    }
+}
+
+fn redraw_rows<D: DrawTarget<Color = BinaryColor>>(
+    display: &mut D,
+    trains: &NextTrainsResponse,
+    previous: &NextTrainsResponse,
+) -> Result<(), D::Error> {
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let width = display.bounding_box().size.width;
+    let mut line: String<32> = String::new();
+
+    let rows = board_rows(trains, LINES);
+    for i in rows.changed_from(&board_rows(previous, LINES)) {
+        let y = i as i32 * LINE_HEIGHT;
+        Rectangle::new(Point::new(0, y), Size::new(width, LINE_HEIGHT as u32))
+            .into_styled(PrimitiveStyle::with_fill(BinaryColor::Off))
+            .draw(display)?;
+
+        line.clear();
+        // past the last row there's nothing to write, it's just blanked
+        let _ = match rows.shown.get(i) {
+            Some(train) => write!(line, "{train}"),
+            None => rows.write_overflow(&mut line, Rows::DEFAULT_OVERFLOW_FORMAT),
+        };
+        if !line.is_empty() {
+            Text::with_baseline(&line, Point::new(0, y), style, Baseline::Top).draw(display)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// The trains that fit in `rows_left` rows, keeping the last of them for the overflow line if
+/// they don't all fit.
+fn board_rows(trains: &NextTrainsResponse, rows_left: usize) -> Rows<'_> {
+    let rows = trains.rows(rows_left);
+    if rows.hidden > 0 {
+        return trains.rows(rows_left - 1);
+    }
+    rows
+}
+
+/// One train per row, soonest first, with the last row summing up any that didn't fit.
+/// Other states get a line or two of text instead, or for [`DisplayState::Stale`] one under the
+/// `last_trains` that are still up.
+fn draw<D: DrawTarget<Color = BinaryColor>>(
+    display: &mut D,
+    state: &DisplayState,
+    last_trains: Option<&NextTrainsResponse>,
+) -> Result<(), D::Error> {
+    display.clear(BinaryColor::Off)?;
+    let style = MonoTextStyle::new(&FONT_6X10, BinaryColor::On);
+    let mut line: String<32> = String::new();
+    let mut y = 0;
+
+    let (trains, rows_left) = match state {
+        DisplayState::Trains(trains) => (Some(trains), LINES),
+        // keep the last row for how old they are
+        DisplayState::Stale { .. } => (last_trains, LINES - 1),
+        _ => (None, 0),
+    };
+    if let Some(trains) = trains {
+        let rows = board_rows(trains, rows_left);
+
+        for train in &rows.shown {
+            line.clear();
+            // a destination too long for the row just gets cut off
+            let _ = write!(line, "{train}");
+            Text::with_baseline(&line, Point::new(0, y), style, Baseline::Top).draw(display)?;
+            y += LINE_HEIGHT;
+        }
+
+        line.clear();
+        if rows
+            .write_overflow(&mut line, Rows::DEFAULT_OVERFLOW_FORMAT)
+            .is_ok()
+            && !line.is_empty()
+        {
+            Text::with_baseline(&line, Point::new(0, y), style, Baseline::Top).draw(display)?;
+            y += LINE_HEIGHT;
+        }
+    }
+
+    line.clear();
+    // an error's message can run long, and a cut off one still says what it's about
+    let _ = state.write_status(&mut line);
+    if !line.is_empty() {
+        Text::with_baseline(&line, Point::new(0, y), style, Baseline::Top).draw(display)?;
+        y += LINE_HEIGHT;
+    }
+
+    line.clear();
+    let _ = state.write_detail(&mut line);
+    if !line.is_empty() {
+        Text::with_baseline(&line, Point::new(0, y), style, Baseline::Top).draw(display)?;
+    }
+
+    Ok(())
+}
+
+/// Open the setup network named after `mac` and take a config from its form like `main.rs` does,
+/// see `esp_wmata_pids::setup`, and reboot into it once it's saved.
+async fn set_up(spawner: Spawner, mut flash: FlashStorage, wifi: WIFI<'static>, mac: [u8; 6]) -> ! {
+    let (mut controller, interfaces) = unwrap!(
+        esp_radio::wifi::new(wifi, ControllerConfig::default()),
+        "Failed to initialize Wi-Fi controller"
+    );
+    let ap = AccessPointConfig::default().with_ssid(setup::ap_ssid(mac).as_str().into());
+    unwrap!(controller.set_config(&ModeConfig::AccessPoint(ap)));
+    unwrap!(controller.start_async().await);
+
+    let rng = Rng::new();
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+    let config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(setup::SETUP_IP, setup::SETUP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Default::default(),
+    });
+    // 2 sockets: the DHCP server and the form
+    let (stack, runner) = embassy_net::new(
+        interfaces.access_point,
+        config,
+        mk_static!(StackResources<2>, StackResources::<2>::new()),
+        seed,
+    );
+    unwrap!(spawner.spawn(net_task(runner)), "failed to spawn task");
+
+    let cfg = match select(setup::serve_dhcp(stack), setup::serve_form(stack)).await {
+        Either::First(never) => never,
+        Either::Second(cfg) => cfg,
+    };
+    if let Err(e) = cfg.save(&mut flash) {
+        defmt::panic!("couldn't save the config: {}", e);
+    }
+    info!("set up, rebooting into the new config");
+    // give the page a moment to get out
+    Timer::after_millis(500).await;
+    esp_hal::system::software_reset()
+}
+
+#[task]
+async fn connection(
+    mut controller: WifiController<'static>,
+    ssid: &'static str,
+    password: &'static str,
+    max_tx_power: Option<u8>,
+) {
+    loop {
+        if esp_radio::wifi::station_state() == WifiStationState::Connected {
+            controller
+                .wait_for_event(WifiEvent::StationDisconnected)
+                .await;
+            Timer::after_millis(5000).await
+        }
+        if !matches!(controller.is_started(), Ok(true)) {
+            let config = StationConfig::default()
+                .with_ssid(ssid.into())
+                .with_password(password.into());
+            controller.set_config(&ModeConfig::Station(config)).unwrap();
+            controller.start_async().await.unwrap();
+            if let Some(power) = max_tx_power
+                && let Err(e) = controller.set_max_tx_power(power as i8)
+            {
+                warn!("Failed to cap tx power: {:?}", e);
+            }
+        }
+
+        if let Err(e) = controller.connect_async().await {
+            warn!("Failed to connect to wifi: {:?}", e);
+            Timer::after_millis(5000).await
+        }
+    }
+}
+
+#[task]
+async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    runner.run().await
+}
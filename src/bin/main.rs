@@ -6,26 +6,70 @@
     holding buffers for the duration of a data transfer."
 )]
 
+use core::fmt::Write as _;
+
 use defmt::*;
 use embassy_executor::{Spawner, task};
-use embassy_net::dns::DnsSocket;
-use embassy_net::tcp::client::{TcpClient, TcpClientState};
-use embassy_net::{Runner, StackResources};
-use embassy_time::Timer;
+use embassy_futures::select::{Either, Either4, select, select4};
+use embassy_net::tcp::TcpSocket;
+use embassy_net::{
+    ConfigV4, ConfigV6, Ipv4Cidr, Ipv6Address, Ipv6Cidr, Runner, Stack, StackResources,
+    StaticConfigV4, StaticConfigV6,
+};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::channel::Channel;
+use embassy_sync::mutex::Mutex;
+use embassy_sync::signal::Signal;
+#[cfg(feature = "bridge")]
+use embassy_sync::watch::Watch;
+use embassy_time::{Duration, Instant, Timer, with_timeout};
 use esp_hal::clock::CpuClock;
+use esp_hal::efuse::Efuse;
 use esp_hal::interrupt::software::SoftwareInterruptControl;
+use esp_hal::peripherals::WIFI;
 use esp_hal::rng::Rng;
+use esp_hal::rtc_cntl::sleep::TimerWakeupSource;
+use esp_hal::rtc_cntl::{Rtc, reset_reason, wakeup_cause};
+use esp_hal::system::{Cpu, SleepSource};
 use esp_hal::timer::timg::TimerGroup;
+#[cfg(feature = "bridge")]
+use esp_hal::uart::UartTx;
+use esp_hal::{
+    Async,
+    uart::{self, UartRx},
+};
 
+use embedded_io_async::Write as _;
+use esp_radio::wifi::ap::AccessPointConfig;
 use esp_radio::wifi::event::{EventExt, StationDisconnected};
 use esp_radio::wifi::sta::StationConfig;
-use esp_radio::wifi::{ModeConfig, WifiController, WifiDevice};
-use esp_radio::wifi::{ScanConfig, WifiEvent, WifiStationState};
+use esp_radio::wifi::{
+    AccessPointInfo, AuthenticationMethod, ScanConfig, WifiEvent, WifiStationState,
+};
+use esp_radio::wifi::{ControllerConfig, ModeConfig, PowerSaveMode, WifiController, WifiDevice};
 use esp_storage::FlashStorage;
-use esp_wmata_pids::wmata::Client;
+use esp_wmata_pids::alerts::{ArrivalAlert, NoopAlerter};
+use esp_wmata_pids::console::{Command, HELP, LineReader};
+use esp_wmata_pids::screen::DisplayState;
+use esp_wmata_pids::setup;
+use esp_wmata_pids::status::{ResetCause, ScanEntry, Status, build_info};
+use esp_wmata_pids::supervisor::{BringUp, BringUpAction, Heartbeat, Supervisor};
 use esp_wmata_pids::wmata::Config;
-use heapless::String;
-use reqwless::client::HttpClient;
+use esp_wmata_pids::wmata::ConfigEdit;
+use esp_wmata_pids::wmata::ConfigError;
+use esp_wmata_pids::wmata::ConfigSource;
+#[cfg(feature = "name-overrides")]
+use esp_wmata_pids::wmata::NameOverrides;
+use esp_wmata_pids::wmata::PowerMode;
+#[cfg(feature = "history")]
+use esp_wmata_pids::wmata::PredictionHistory;
+use esp_wmata_pids::wmata::types::{Abbreviations, LineKind, NextTrainsResponse, Rows, Station};
+use esp_wmata_pids::wmata::{
+    API, Client, ClientResources, DhcpFallback, Error, EtaSmoother, Event, EventLog,
+    POWER_ON_HOURS_SZ, PowerOnHours, REQUEST_COUNTER_SZ, RequestCounter, SNAPSHOT_SZ, SharedClient,
+    StackDns, StackTcpClient, StaticIpv4, WallClock,
+};
+use heapless::{String, Vec};
 use {esp_backtrace as _, esp_println as _};
 
 // This creates a default app-descriptor required by the esp-idf bootloader.
@@ -45,6 +89,184 @@ macro_rules! mk_static {
 const SSID: Option<&str> = option_env!("SSID");
 const PASSWORD: Option<&str> = option_env!("PASSWORD");
 const API_KEY: Option<&str> = option_env!("API_KEY");
+// a second key to switch to when WMATA turns `API_KEY` down or it's out of quota. unset for none
+const BACKUP_API_KEY: Option<&str> = option_env!("BACKUP_API_KEY");
+// the `offline` feature doesn't need any of the above, so they fall back to empty
+const OFFLINE_FALLBACK: Option<&str> = if cfg!(feature = "offline") {
+    Some("")
+} else {
+    None
+};
+const POWER_MODE: Option<&str> = option_env!("POWER_MODE");
+// Wi-Fi regulatory domain, e.g. `WIFI_COUNTRY=US`, and transmit power cap in quarter dBm, e.g.
+// `WIFI_TX_POWER=60` for 15 dBm. unset keeps esp-radio's defaults, see `Config::with_wifi_region`
+const WIFI_COUNTRY: Option<&str> = option_env!("WIFI_COUNTRY");
+const WIFI_TX_POWER: Option<&str> = option_env!("WIFI_TX_POWER");
+// e.g. a caching proxy shared by several displays, defaults to the official api
+const API_URL: Option<&str> = option_env!("API_URL");
+// only show trains heading to these stations, e.g. `DESTINATIONS=B11,A15`. unset shows every train
+const DESTINATIONS: Option<&str> = option_env!("DESTINATIONS");
+// only show trains on these lines, e.g. `LINES=SV`. unset shows every line
+const LINES: Option<&str> = option_env!("LINES");
+// optional static IPv6, e.g. `IPV6_ADDRESS=2001:db8::10/64`. see `ipv6_config`. on an IPv6-only
+// network also set `IPV6_DNS`, the DNS servers otherwise only come from a DHCPv4 lease
+const IPV6_ADDRESS: Option<&str> = option_env!("IPV6_ADDRESS");
+const IPV6_GATEWAY: Option<&str> = option_env!("IPV6_GATEWAY");
+const IPV6_DNS: Option<&str> = option_env!("IPV6_DNS");
+// static IPv4 to fall back to if DHCP doesn't answer, e.g. `IPV4_ADDRESS=192.168.1.50/24`, saved
+// with the rest of the config. see `env_static_ipv4`
+const IPV4_ADDRESS: Option<&str> = option_env!("IPV4_ADDRESS");
+const IPV4_GATEWAY: Option<&str> = option_env!("IPV4_GATEWAY");
+const IPV4_DNS: Option<&str> = option_env!("IPV4_DNS");
+// shown when the station has no trains, e.g. `FALLBACK_STATION=K05`. unset shows the empty board
+const FALLBACK_STATION: Option<&str> = option_env!("FALLBACK_STATION");
+// wording of the line summing up trains that didn't fit, `{}` is the count. defaults to `+{} more`
+const OVERFLOW_FORMAT: Option<&str> = option_env!("OVERFLOW_FORMAT");
+// short destination names on top of the built-in ones, e.g. `ABBREVIATIONS=C15=Hunt,K08=Vie`
+const ABBREVIATIONS: Option<&str> = option_env!("ABBREVIATIONS");
+// how far each poll may land either side of `POLL_INTERVAL`, in percent, e.g. `POLL_JITTER=20`.
+// unset keeps `Config::DEFAULT_POLL_JITTER`
+const POLL_JITTER: Option<&str> = option_env!("POLL_JITTER");
+// time server to use instead of the public pool, e.g. `NTP_SERVER=ntp.corp.example`, saved with
+// the rest of the config but not used yet, see `Config::with_ntp_server`. unset keeps
+// `Config::DEFAULT_NTP_SERVER`
+const NTP_SERVER: Option<&str> = option_env!("NTP_SERVER");
+// minutes between snapshots of the predictions kept in flash, e.g. `HISTORY_INTERVAL=15`. unset
+// keeps `DEFAULT_HISTORY_INTERVAL`
+#[cfg(feature = "history")]
+const HISTORY_INTERVAL: Option<&str> = option_env!("HISTORY_INTERVAL");
+// hide trains due in under this many minutes, e.g. `MIN_MINUTES=2` where the platform is a walk
+// away. ARR and BRD go too, unless `KEEP_ARRIVING=true`. unset shows every train
+const MIN_MINUTES: Option<&str> = option_env!("MIN_MINUTES");
+const KEEP_ARRIVING: Option<&str> = option_env!("KEEP_ARRIVING");
+// what to do with trains that aren't taking passengers: `NO_PASSENGER=hide`, `dim` or `label`.
+// this board has no shades to dim with, so `dim` shows them like any other train. unset hides them
+const NO_PASSENGER: Option<&str> = option_env!("NO_PASSENGER");
+// drop trains WMATA reports headed somewhere their line doesn't go, `DROP_UNREACHABLE=true`,
+// logging each. off when unset, since a train rerouted around a closure looks the same
+const DROP_UNREACHABLE: Option<&str> = option_env!("DROP_UNREACHABLE");
+// seconds the network gets at boot to link up and get an address before the radio is restarted,
+// e.g. `BRINGUP_TIMEOUT=120`, `0` to wait forever, and how many restarts before the chip resets
+// instead, e.g. `BRINGUP_RESTARTS=5`. keep the timeout over `DHCP_TIMEOUT` so the static fallback
+// gets a chance. unset keeps `BringUp::DEFAULT_TIMEOUT` and `BringUp::DEFAULT_RESTARTS`
+const BRINGUP_TIMEOUT: Option<&str> = option_env!("BRINGUP_TIMEOUT");
+const BRINGUP_RESTARTS: Option<&str> = option_env!("BRINGUP_RESTARTS");
+
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+/// How often predictions go into the `PredictionHistory` without `HISTORY_INTERVAL`.
+#[cfg(feature = "history")]
+const DEFAULT_HISTORY_INTERVAL: Duration = Duration::from_secs(5 * 60);
+/// Shown until a station is picked and saved to the config, see `Config::with_station`.
+const DEFAULT_STATION: Station = Station::K04;
+/// Port of the json status page served by `status_server`.
+const STATUS_PORT: u16 = 8080;
+/// How often `manage_station` refreshes the RSSI while connected.
+const RSSI_INTERVAL: Duration = Duration::from_secs(30);
+/// Below this signal (dBm) `manage_station` looks for a stronger AP with the same SSID.
+const ROAM_BELOW_RSSI: i8 = -70;
+/// How much stronger (dB) another AP has to be before we roam to it.
+const ROAM_MARGIN_DB: i8 = 8;
+/// Trains drawn per update, the rest are summed up in an overflow line.
+const MAX_ROWS: usize = 6;
+/// How long the link can be up without DHCP giving us an address before falling back to
+/// `Config::static_ipv4`, or complaining if there isn't one.
+const DHCP_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long before service opens polling starts again, so the first trains are up in time.
+const SERVICE_LEAD_MINUTES: u16 = 15;
+/// How often `supervise` checks on the other tasks.
+const SUPERVISE_INTERVAL: Duration = Duration::from_secs(5);
+/// Leeway on top of a task's own schedule before `supervise` takes it as hung, enough for a slow
+/// request or a Wi-Fi scan and connect.
+const STALL_MARGIN: Duration = Duration::from_secs(60);
+/// Baud rate of the json lines `bridge` writes on UART1.
+#[cfg(feature = "bridge")]
+const BRIDGE_BAUD: u32 = 115_200;
+
+/// Requests from the main loop to `manage_station`, used by `PowerMode::Disconnect`.
+enum RadioRequest {
+    Sleep,
+    Wake,
+}
+
+static RADIO: Signal<CriticalSectionRawMutex, RadioRequest> = Signal::new();
+
+/// Raise after saving a new `Config` to flash, e.g. with `save` at the serial console, for the main
+/// loop to load it and apply whatever changed without a reboot. See `Config::changes_from`.
+static RELOAD: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// New Wi-Fi credentials from a reloaded config, for `manage_station` to reconnect with.
+struct Credentials {
+    ssid: String<32>,
+    password: String<64>,
+}
+
+static CREDENTIALS: Signal<CriticalSectionRawMutex, Credentials> = Signal::new();
+
+/// Raised by `bring_up_network` when the network is taking too long, for `manage_station` to
+/// stop the Wi-Fi controller and start it again.
+static RESTART_RADIO: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Commands typed at the serial console, from `serial_console` to whatever's taking them.
+static CONSOLE: Channel<CriticalSectionRawMutex, Command, 2> = Channel::new();
+
+/// What `render` should show, from the fetch loop after every poll and when it stops polling for
+/// the night. A `Signal` only keeps the newest value, so a slow panel skips stale updates instead
+/// of queueing them up (or blocking the fetch loop) like a `Channel` would.
+static SCREEN: Signal<CriticalSectionRawMutex, DisplayState> = Signal::new();
+
+/// Latest predictions, handed from the fetch loop to `bridge`. Only the newest is kept, like `SCREEN`.
+#[cfg(feature = "bridge")]
+static PREDICTIONS: Watch<CriticalSectionRawMutex, NextTrainsResponse, 1> = Watch::new();
+
+/// Local time from the api's responses, for `Config::service_hours`.
+static CLOCK: WallClock = WallClock::new();
+
+/// The api client behind a lock, so it can be shared by every task that queries WMATA.
+type ApiClient = SharedClient<'static, CriticalSectionRawMutex, StackTcpClient, StackDns>;
+
+/// Health counters for the status page.
+static STATUS: Status = Status::new();
+
+/// Events from other tasks for the main loop to write to the `EventLog`, since it owns the flash.
+/// Full means the main loop is stuck anyway, so senders drop events rather than wait.
+static EVENTS: Channel<CriticalSectionRawMutex, Event, 8> = Channel::new();
+
+/// Proof of life from the main loop and each task, for `supervise` to reset the board if one of
+/// them exits or hangs. `render`, `status_server` and `bridge` wait on others with no deadline, so
+/// they're only watched for exiting.
+static MAIN_LOOP: Heartbeat = Heartbeat::new("main");
+static STATION_TASK: Heartbeat = Heartbeat::new("manage_station");
+static NET_TASK: Heartbeat = Heartbeat::new("net_task");
+static RENDER_TASK: Heartbeat = Heartbeat::new("render");
+static STATUS_TASK: Heartbeat = Heartbeat::new("status_server");
+#[cfg(feature = "bridge")]
+static BRIDGE_TASK: Heartbeat = Heartbeat::new("bridge");
+static SUPERVISOR: Supervisor = Supervisor::new(&[
+    &MAIN_LOOP,
+    &STATION_TASK,
+    &NET_TASK,
+    &RENDER_TASK,
+    &STATUS_TASK,
+    #[cfg(feature = "bridge")]
+    &BRIDGE_TASK,
+]);
+
+/// 802.11 reason for leaving on purpose, which `PowerMode::Disconnect` does every poll.
+const REASON_ASSOC_LEAVE: u8 = 8;
+
+/// Last predictions, kept through deep sleep by `PowerMode::DeepSleep`.
+/// RTC fast memory is 8KB on the esp32, this, `REQUESTS` and `POWER_ON` take 369 bytes of it.
+/// The config is reloaded from flash on every wake, so it doesn't need a copy here.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut SNAPSHOT: [u8; SNAPSHOT_SZ] = [0; SNAPSHOT_SZ];
+
+/// The request counter through deep sleep, so it only needs writing to flash as often as when awake.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut REQUESTS: [u8; REQUEST_COUNTER_SZ] = [0; REQUEST_COUNTER_SZ];
+
+/// Power-on time through deep sleep, for the same reason.
+#[esp_hal::ram(unstable(rtc_fast, persistent))]
+static mut POWER_ON: [u8; POWER_ON_HOURS_SZ] = [0; POWER_ON_HOURS_SZ];
 
 #[esp_rtos::main]
 async fn main(spawner: Spawner) -> ! {
@@ -55,197 +277,1346 @@ async fn main(spawner: Spawner) -> ! {
     // COEX needs more RAM - so we've added some more
     esp_alloc::heap_allocator!(size: 64 * 1024);
 
+    let mut rtc = Rtc::new(peripherals.LPWR);
+    let woke_from_sleep = wakeup_cause() == SleepSource::Timer;
+
     let timg0 = TimerGroup::new(peripherals.TIMG0);
     let sw_int = SoftwareInterruptControl::new(peripherals.SW_INTERRUPT);
     esp_rtos::start(timg0.timer0, sw_int.software_interrupt0);
 
     info!("Embassy initialized!");
+    #[cfg(feature = "offline")]
+    warn!("OFFLINE build: every prediction shown is canned demo data, not live");
+
+    let mut flash = FlashStorage::new(peripherals.FLASH);
+
+    let mut ssid: String<32> = String::new();
+    let mut pass: String<64> = String::new();
+    let mut api_key: String<32> = String::new();
+
+    let reset_cause = ResetCause::from_code(reset_reason(Cpu::ProCpu).map_or(0, |r| r as u8));
+    STATUS.set_reset_cause(reset_cause);
+    let build = build_info().with_built(ESP_APP_DESC.date(), ESP_APP_DESC.time());
+    STATUS.set_build_info(build);
+
+    let mut event_log = match EventLog::open(&mut flash) {
+        Ok(event_log) => Some(event_log),
+        Err(e) => {
+            error!("couldn't open the event log: {}", e);
+            None
+        }
+    };
+    // a wake from deep sleep isn't worth an entry, or a dump every poll
+    if !woke_from_sleep {
+        info!("firmware: {}", build);
+        info!("reset cause: {}", reset_cause);
+        match EventLog::entries(&mut flash) {
+            Ok(entries) => {
+                info!("event log, oldest first:");
+                for entry in entries {
+                    info!("  {}", entry);
+                }
+            }
+            Err(e) => error!("couldn't read the event log: {}", e),
+        }
+        EVENTS.try_send(Event::Boot).ok();
 
+        #[cfg(feature = "history")]
+        {
+            info!("prediction history, oldest first:");
+            let dumped = PredictionHistory::for_each_entry(&mut flash, |entry| {
+                info!("  {}", entry);
+            });
+            if let Err(e) = dumped {
+                error!("couldn't read the prediction history: {}", e);
+            }
+        }
+    }
+    #[cfg(feature = "history")]
+    let history_interval = match HISTORY_INTERVAL {
+        Some(mins) => Duration::from_secs(
+            60 * unwrap!(mins.parse::<u64>().ok(), "HISTORY_INTERVAL not recognized"),
+        ),
+        None => DEFAULT_HISTORY_INTERVAL,
+    };
+    // deep sleep restarts the interval on every wake, so only a board awake that long records
+    #[cfg(feature = "history")]
+    let mut history = match PredictionHistory::open(&mut flash, history_interval) {
+        Ok(history) => Some(history),
+        Err(e) => {
+            error!("couldn't open the prediction history: {}", e);
+            None
+        }
+    };
+
+    let wmata_cfg = Config::load(&mut flash);
+    match &wmata_cfg {
+        // a device that was never set up isn't worth logging
+        Err(ConfigError::Empty) => {}
+        Err(_) => {
+            EVENTS.try_send(Event::ConfigLoadFailed).ok();
+        }
+        Ok(_) if !woke_from_sleep => {
+            EVENTS.try_send(Event::ConfigLoaded).ok();
+        }
+        Ok(_) => {}
+    }
+    let Some(cfg) = Config::resolve(&wmata_cfg, env_config) else {
+        await_setup(spawner, flash, peripherals.WIFI).await
+    };
+    // the default 115200 baud is what `espflash monitor` runs at
+    let console = unwrap!(
+        UartRx::new(peripherals.UART0, uart::Config::default()).ok(),
+        "couldn't set up UART0"
+    )
+    .with_rx(peripherals.GPIO3)
+    .into_async();
+    if cfg.source() == ConfigSource::Environment {
+        if let Err(e) = cfg.save(&mut flash) {
+            error!("flash error: {}", e);
+        } else {
+            info!("saved config:\n{:?}\n", cfg);
+        }
+    } else {
+        info!("found a config:\n{:?}\n", cfg);
+    }
+
+    let power_mode = cfg.power_mode();
+    let config_source = cfg.source();
+    let destinations = cfg.destinations();
+    let mut station = cfg.station().unwrap_or(DEFAULT_STATION);
+    let mut fallback_station = cfg.fallback_station();
+    ssid.clear();
+    ssid.push_str(cfg.ssid()).unwrap();
+
+    pass.clear();
+    pass.push_str(cfg.pass()).unwrap();
+
+    api_key.clear();
+    api_key.push_str(cfg.api_key()).unwrap();
+    // what's running now, for working out what a reload changes
+    let mut running_cfg = cfg;
+
+    // the regulatory domain can only be set as the controller is created, so this waits for the config
+    let mut wifi_config = ControllerConfig::default();
+    if let Some(country) = running_cfg.wifi_country() {
+        let code: [u8; 2] = unwrap!(country.as_bytes().try_into().ok());
+        wifi_config = wifi_config.with_country_info(code);
+    }
     let (wifi_controller, interfaces) = unwrap!(
-        esp_radio::wifi::new(peripherals.WIFI, Default::default()),
+        esp_radio::wifi::new(peripherals.WIFI, wifi_config),
         "Failed to initialize Wi-Fi controller"
     );
     info!("wifi controller initialized");
 
     let device = interfaces.station;
-    let config = embassy_net::Config::dhcpv4(Default::default());
+    let mut config = embassy_net::Config::dhcpv4(Default::default());
+    if let Some(v6) = ipv6_config() {
+        info!("static ipv6: {}", v6.address);
+        config.ipv6 = ConfigV6::Static(v6);
+    }
 
     let rng = Rng::new();
     let seed = (rng.random() as u64) << 32 | rng.random() as u64;
 
-    // Init network stack
+    // Init network stack.
+    // 4 sockets: the DHCPv4 client, DNS, the single TCP connection used for api requests, and the status page.
+    // a static IPv6 config doesn't need any more, but SLAAC/DHCPv6 would each need their own once embassy-net supports them
     let (stack, runner) = embassy_net::new(
         device,
         config,
-        mk_static!(StackResources<3>, StackResources::<3>::new()),
+        mk_static!(StackResources<4>, StackResources::<4>::new()),
         seed,
     );
 
     init_wifi_handlers();
 
-    let mut flash = FlashStorage::new(peripherals.FLASH);
+    unwrap!(
+        spawner.spawn(manage_station(
+            wifi_controller,
+            ssid,
+            pass,
+            power_mode,
+            running_cfg.wifi_max_tx_power()
+        )),
+        "failed to spawn task"
+    );
+    unwrap!(spawner.spawn(net_task(runner)), "failed to spawn task");
+    let abbreviations = mk_static!(Vec<(Station, &'static str), 16>, env_abbreviations());
+    let abbreviations = Abbreviations::new(abbreviations);
+    // nothing saved on a board that never had any set
+    #[cfg(feature = "name-overrides")]
+    let abbreviations = abbreviations.with_saved(mk_static!(
+        NameOverrides,
+        NameOverrides::load(&mut flash).unwrap_or_default()
+    ));
+    unwrap!(spawner.spawn(render(abbreviations)), "failed to spawn task");
+    unwrap!(spawner.spawn(status_server(stack)), "failed to spawn task");
+    #[cfg(feature = "bridge")]
+    {
+        let uart_config = uart::Config::default().with_baudrate(BRIDGE_BAUD);
+        let tx = unwrap!(
+            UartTx::new(peripherals.UART1, uart_config).ok(),
+            "couldn't set up UART1"
+        )
+        .with_tx(peripherals.GPIO17)
+        .into_async();
+        unwrap!(spawner.spawn(bridge(tx)), "failed to spawn task");
+    }
+    unwrap!(
+        spawner.spawn(serial_console(console)),
+        "failed to spawn task"
+    );
+    unwrap!(spawner.spawn(supervise()), "failed to spawn task");
 
-    let ssid = mk_static!(String<32>, String::<32>::new());
-    let pass = mk_static!(String<64>, String::<64>::new());
-    let api_key = mk_static!(String<32>, String::<32>::new());
+    if woke_from_sleep {
+        // SAFETY: only touched from `main`, and not yet borrowed anywhere else
+        let snapshot = unsafe { &*(&raw const SNAPSHOT) };
+        match NextTrainsResponse::from_snapshot(snapshot) {
+            Ok(trains) => {
+                #[cfg(feature = "bridge")]
+                PREDICTIONS.sender().send(trains.clone());
+                SCREEN.signal(DisplayState::Trains(trains));
+            }
+            Err(e) => {
+                warn!("no snapshot to restore: {}", e);
+                SCREEN.signal(DisplayState::Connecting);
+            }
+        }
+    } else {
+        SCREEN.signal(DisplayState::Connecting);
+    }
 
-    let wmata_cfg = Config::load(&mut flash);
+    let resources = mk_static!(ClientResources, ClientResources::new());
+    // SAFETY: only touched from `main`, and not yet borrowed anywhere else
+    let requests = unsafe { &mut *(&raw mut REQUESTS) };
+    let counter = if woke_from_sleep {
+        RequestCounter::from_bytes(requests).or_else(|_| RequestCounter::load(&mut flash))
+    } else {
+        RequestCounter::load(&mut flash)
+    };
+    // nothing saved yet on a fresh board, so start from zero
+    let request_counter = mk_static!(RequestCounter, counter.unwrap_or_default());
+    // SAFETY: as for `REQUESTS`
+    let power_on = unsafe { &mut *(&raw mut POWER_ON) };
+    let power_on_hours = if woke_from_sleep {
+        PowerOnHours::from_bytes(power_on).or_else(|_| PowerOnHours::load(&mut flash))
+    } else {
+        PowerOnHours::load(&mut flash)
+    };
+    // likewise nothing saved on a fresh board
+    let power_on_hours = power_on_hours.unwrap_or_default();
+    if !woke_from_sleep {
+        info!("powered on for {} hours in all", power_on_hours.hours());
+    }
+    let client = unwrap!(
+        Client::with_base_and_key(stack, resources, API_URL.unwrap_or(API), &api_key),
+        "API_URL not recognized"
+    )
+    .with_request_counter(request_counter)
+    .with_clock(&CLOCK)
+    .with_destinations(destinations)
+    .with_lines_mask(running_cfg.lines_mask())
+    .with_min_minutes(running_cfg.min_minutes(), running_cfg.keeps_arriving())
+    .with_no_passenger(running_cfg.no_passenger())
+    .with_backup_api_key(running_cfg.backup_api_key())
+    .with_drop_unreachable(
+        DROP_UNREACHABLE
+            .map(|drop| unwrap!(drop.parse().ok(), "DROP_UNREACHABLE not recognized"))
+            .unwrap_or_default(),
+    );
+    // only the main loop uses it for now, but any task handed `client` can query the api too
+    let client = mk_static!(ApiClient, Mutex::new(client));
 
-    if let Ok(cfg) = wmata_cfg {
-        info!("found a config:\n{:?}\n", cfg);
-        ssid.clear();
-        ssid.push_str(cfg.ssid()).unwrap();
+    // swap for a `PinAlerter` on boards with a buzzer/LED attached
+    let mut alerter = NoopAlerter;
 
-        pass.clear();
-        pass.push_str(cfg.pass()).unwrap();
+    // boot carries on after a failure, so a transient one doesn't brick the display
+    // it already passed on the boot that first went to sleep
+    #[cfg(all(feature = "selftest", not(feature = "offline")))]
+    if !woke_from_sleep
+        && let Err(fault) =
+            esp_wmata_pids::selftest::run(&wmata_cfg, stack, &mut *client.lock().await).await
+    {
+        unwrap!(esp_wmata_pids::selftest::blink(&mut alerter, fault).await);
+    }
 
-        api_key.clear();
-        api_key.push_str(cfg.api_key()).unwrap();
-    } else {
-        info!("no valid config. loading environment variables");
-        ssid.clear();
-        unwrap!(
-            ssid.push_str(unwrap!(SSID, "SSID not set")),
-            "SSID too long"
-        );
+    // offline builds never touch the network, so don't wait for one
+    if !cfg!(feature = "offline") {
+        bring_up_network(stack, running_cfg.static_ipv4(), env_bring_up()).await;
+        if let Some(config) = stack.config_v4() {
+            info!("Got IP: {}", config.address);
+        }
+        if let Some(config) = stack.config_v6() {
+            info!("Got IPv6: {}", config.address);
+        }
+    }
 
-        pass.clear();
-        unwrap!(
-            pass.push_str(unwrap!(PASSWORD, "PASSWORD not set")),
-            "PASSWORD too long"
-        );
+    info!("config source: {}", config_source);
+    info!("power mode: {}", power_mode);
+    info!("station: {}", station);
+    if let Some(fallback) = fallback_station {
+        info!("fallback station: {}", fallback);
+    }
+    let mut next_poll = Instant::now();
+    let mut bring_up = Duration::from_secs(0);
 
-        api_key.clear();
-        unwrap!(
-            api_key.push_str(unwrap!(API_KEY, "API_KEY not set")),
-            "API_KEY too long"
-        );
+    // only alert on transitions, not on every poll a train sits at the platform
+    let mut arrival_alert = ArrivalAlert::new();
+    // only log a fetch error when it changes, not every poll while the network is down
+    let mut last_error = None;
+    // for how long failed fetches leave the last predictions up, see `DisplayState::after_fetch`
+    let mut last_success = None;
+    let mut console_edits = ConsoleEdits::default();
 
-        let cfg = Config::new(ssid.as_str(), pass.as_str(), api_key.as_str()).unwrap();
-        if let Err(e) = cfg.save(&mut flash) {
+    loop {
+        MAIN_LOOP.beat(Instant::now(), STALL_MARGIN);
+        if RELOAD.try_take().is_some() {
+            match Config::load(&mut flash) {
+                Ok(cfg) => {
+                    let changes = cfg.changes_from(&running_cfg);
+                    info!("reloaded config, changed: {}", changes);
+                    if changes.wifi {
+                        CREDENTIALS.signal(Credentials {
+                            ssid: unwrap!(String::try_from(cfg.ssid())),
+                            password: unwrap!(String::try_from(cfg.pass())),
+                        });
+                    }
+                    if changes.api_key {
+                        let mut client = client.lock().await;
+                        match client
+                            .set_api_key(cfg.api_key())
+                            .and_then(|()| client.set_backup_api_key(cfg.backup_api_key()))
+                        {
+                            Err(e) => error!("couldn't swap the api key: {}", e),
+                            // catch a mistyped key now, not at the next poll. a new network
+                            // might not be up yet, so that's left to the poll
+                            Ok(()) if !changes.wifi => match client.ping().await {
+                                Ok(()) => info!("new api key ok"),
+                                Err(Error::Unauthorized) => error!("new api key rejected"),
+                                Err(e) => warn!("couldn't check the new api key: {}", e),
+                            },
+                            Ok(()) => {}
+                        }
+                    }
+                    if changes.stations {
+                        station = cfg.station().unwrap_or(DEFAULT_STATION);
+                        fallback_station = cfg.fallback_station();
+                        let mut client = client.lock().await;
+                        client.set_destinations(cfg.destinations());
+                        client.set_lines_mask(cfg.lines_mask());
+                        client.set_min_minutes(cfg.min_minutes(), cfg.keeps_arriving());
+                        client.set_no_passenger(cfg.no_passenger());
+                        info!("station: {}", station);
+                    }
+                    if changes.needs_reboot {
+                        warn!("power mode and display changes apply after a reboot");
+                    }
+                    running_cfg = cfg;
+                }
+                Err(e) => error!("couldn't reload the config: {}", e),
+            }
+        }
+
+        // the network can take as long as it likes to come back, that's not the loop hanging
+        MAIN_LOOP.idle();
+        if cfg!(feature = "offline") {
+            // canned predictions don't need the network
+        } else if power_mode == PowerMode::Disconnect {
+            let woke_at = Instant::now();
+            RADIO.signal(RadioRequest::Wake);
+            if IPV6_ADDRESS.is_none() {
+                stack.wait_config_up().await;
+            } else if with_timeout(DHCP_TIMEOUT, wait_ipv4(stack)).await.is_err() {
+                // the static IPv6 address is up as soon as the link is, so that's not enough
+                warn!("no DHCP lease after waking, carrying on with IPv6 only");
+            }
+            bring_up = woke_at.elapsed();
+            debug!("radio bring-up took {}ms", bring_up.as_millis());
+        } else {
+            // stack may go down but it should come back up eventually
+            while !stack.is_link_up() {
+                Timer::after_millis(1000).await;
+            }
+        }
+
+        MAIN_LOOP.beat(Instant::now(), STALL_MARGIN);
+        // a response trickling in slowly shouldn't hold the board up past the next poll. the
+        // guard only lives for this block, so the deadline never applies to anyone else's requests
+        let trains = {
+            let mut client = client.lock().await;
+            client.set_deadline(Some(Instant::now() + POLL_INTERVAL));
+            let trains = client.next_trains_or(station, fallback_station).await;
+            client.set_deadline(None);
+            trains
+        };
+
+        let now = Instant::now();
+        match &trains {
+            Ok(trains) => {
+                unwrap!(arrival_alert.update(&mut alerter, trains));
+
+                if power_mode == PowerMode::DeepSleep {
+                    // SAFETY: only touched from `main`, and not yet borrowed anywhere else
+                    let snapshot = unsafe { &mut *(&raw mut SNAPSHOT) };
+                    if let Err(e) = trains.write_snapshot(snapshot) {
+                        error!("couldn't snapshot predictions: {}", e);
+                    }
+                }
+
+                // under whichever station answered, which may be the fallback
+                #[cfg(feature = "history")]
+                if let Some(history) = &mut history
+                    && history.is_due()
+                    && let Err(e) = history.record(
+                        &mut flash,
+                        trains.trains.first().map_or(station, |t| t.location_code),
+                        trains,
+                    )
+                {
+                    error!("couldn't record predictions: {}", e);
+                }
+
+                STATUS.record_fetch(true);
+                #[cfg(feature = "bridge")]
+                PREDICTIONS.sender().send(trains.clone());
+                last_error = None;
+                last_success = Some(now);
+            }
+            Err(e) => {
+                STATUS.record_fetch(false);
+                error!("{:?}", e);
+                if last_error != Some(e.code()) {
+                    last_error = Some(e.code());
+                    EVENTS.try_send(Event::FetchFailed { code: e.code() }).ok();
+                }
+            }
+        }
+        SCREEN.signal(DisplayState::after_fetch(trains, last_success, now));
+
+        if let Some(event_log) = &mut event_log {
+            while let Ok(event) = EVENTS.try_receive() {
+                if let Err(e) = event_log.record(&mut flash, event) {
+                    error!("couldn't log {}: {}", event, e);
+                }
+            }
+        }
+
+        if request_counter.needs_save() {
+            info!("api requests today: {}", request_counter.today());
+            if let Err(e) = request_counter.save(&mut flash) {
+                error!("flash error: {}", e);
+            }
+        }
+
+        STATUS.set_power_on_secs(power_on_hours.total_secs());
+        if power_on_hours.needs_save()
+            && let Err(e) = power_on_hours.save(&mut flash)
+        {
             error!("flash error: {}", e);
+        }
+
+        // overnight, sleep through to shortly before service opens rather than polling an empty board.
+        // the clock comes from the last response, so this only kicks in once a poll has got through
+        let mut interval = running_cfg.jittered(POLL_INTERVAL, rng.random());
+        if let Some(now) = CLOCK.local_now() {
+            let until_open = running_cfg.service_hours().until_open(now);
+            if until_open > SERVICE_LEAD_MINUTES {
+                info!(
+                    "closed, pausing for {} minutes",
+                    until_open - SERVICE_LEAD_MINUTES
+                );
+                let opens = (now.minute_of_day() + until_open) % (24 * 60);
+                SCREEN.signal(DisplayState::Closed { opens });
+                interval = Duration::from_secs(60 * u64::from(until_open - SERVICE_LEAD_MINUTES));
+            }
+        }
+
+        // schedule from the start of the last poll, so time spent fetching (or reconnecting) isn't added on top.
+        // if we've fallen behind, poll once right away rather than bursting to catch up
+        next_poll = (next_poll + interval).max(Instant::now());
+        MAIN_LOOP.beat(
+            Instant::now(),
+            next_poll.saturating_duration_since(Instant::now()) + STALL_MARGIN,
+        );
+        if power_mode == PowerMode::Disconnect {
+            RADIO.signal(RadioRequest::Sleep);
+            // wake early by however long the last reconnect took, so the radio is back up when the poll is due
+            let wake_at = next_poll.checked_sub(bring_up).unwrap_or(next_poll);
+            wait_taking_commands(wake_at, &mut console_edits, &running_cfg, &mut flash).await;
+        } else if power_mode == PowerMode::DeepSleep {
+            // let `render` show the new predictions before everything powers down
+            embassy_futures::yield_now().await;
+            // every wake is a fresh boot, so the whole uptime counts against the interval
+            let uptime = Instant::now().duration_since(Instant::from_ticks(0));
+            let sleep = interval.checked_sub(uptime).unwrap_or_default();
+            info!("deep sleeping for {}ms", sleep.as_millis());
+            if let Err(e) = request_counter.to_bytes(requests) {
+                error!("couldn't keep request count: {}", e);
+            }
+            if let Err(e) = power_on_hours.to_bytes(power_on) {
+                error!("couldn't keep power-on hours: {}", e);
+            }
+            let timer =
+                TimerWakeupSource::new(core::time::Duration::from_millis(sleep.as_millis()));
+            rtc.sleep_deep(&[&timer]);
         } else {
-            info!("saved config:\n{:?}\n", cfg);
+            wait_taking_commands(next_poll, &mut console_edits, &running_cfg, &mut flash).await;
         }
     }
+}
 
-    unwrap!(
-        spawner.spawn(manage_station(
-            wifi_controller,
-            ssid.as_str(),
-            pass.as_str(),
-        )),
-        "failed to spawn task"
+/// Static IPv6 config from the `IPV6_*` build-time environment variables, alongside DHCPv4.
+/// embassy-net doesn't do SLAAC or DHCPv6 yet, so on IPv6-only networks the address has to be set here.
+/// With an address configured, DNS asks for AAAA records first and falls back to A, and vice versa without one.
+/// The DNS servers come from the DHCPv4 lease, so without one, e.g. IPv6-only, `IPV6_DNS` has to be set
+/// or no host resolves. Boot still waits `DHCP_TIMEOUT` for a lease before carrying on without.
+fn ipv6_config() -> Option<StaticConfigV6> {
+    let (address, prefix_len) = unwrap!(
+        IPV6_ADDRESS?.split_once('/'),
+        "IPV6_ADDRESS must include a prefix length, e.g. 2001:db8::10/64"
     );
-    unwrap!(spawner.spawn(net_task(runner)), "failed to spawn task");
+    let address: Ipv6Address = unwrap!(address.parse().ok(), "IPV6_ADDRESS not recognized");
+    let prefix_len: u8 = unwrap!(
+        prefix_len.parse().ok(),
+        "IPV6_ADDRESS prefix not recognized"
+    );
+    let gateway = IPV6_GATEWAY.map(|gw| unwrap!(gw.parse().ok(), "IPV6_GATEWAY not recognized"));
 
-    while !stack.is_link_up() {
-        Timer::after_millis(200).await;
+    let mut config = StaticConfigV6 {
+        address: Ipv6Cidr::new(address, prefix_len),
+        gateway,
+        dns_servers: Default::default(),
+    };
+    if let Some(dns) = IPV6_DNS {
+        let dns = unwrap!(dns.parse().ok(), "IPV6_DNS not recognized");
+        unwrap!(config.dns_servers.push(dns).ok());
     }
 
+    Some(config)
+}
+
+/// The static IPv4 fallback from the `IPV4_*` build-time environment variables, if `IPV4_ADDRESS` is set.
+fn env_static_ipv4() -> Option<StaticIpv4> {
+    let (address, prefix_len) = unwrap!(
+        IPV4_ADDRESS?.split_once('/'),
+        "IPV4_ADDRESS must include a prefix length, e.g. 192.168.1.50/24"
+    );
+    let parse = |addr: &str| addr.parse().ok();
+
+    Some(StaticIpv4 {
+        address: unwrap!(parse(address), "IPV4_ADDRESS not recognized"),
+        prefix_len: unwrap!(
+            prefix_len.parse().ok(),
+            "IPV4_ADDRESS prefix not recognized"
+        ),
+        gateway: IPV4_GATEWAY.map(|gw| unwrap!(parse(gw), "IPV4_GATEWAY not recognized")),
+        dns: IPV4_DNS.map(|dns| unwrap!(parse(dns), "IPV4_DNS not recognized")),
+    })
+}
+
+/// Wait for the Wi-Fi link and then an address, falling back to `static_ipv4` if DHCP hasn't
+/// answered after `DHCP_TIMEOUT`. A try that outlasts `bring_up`'s timeout restarts the radio
+/// through `manage_station`, and once it's out of restarts the chip is reset.
+async fn bring_up_network(
+    stack: Stack<'static>,
+    static_ipv4: Option<StaticIpv4>,
+    mut bring_up: BringUp,
+) {
     loop {
-        if let Some(config) = stack.config_v4() {
-            println!("Got IP: {}", config.address);
-            break;
+        let up = async {
+            while !stack.is_link_up() {
+                Timer::after_millis(200).await;
+            }
+
+            // a static IPv6 address is up with the link, so don't count it as having an address.
+            // DNS and the api may only be reachable over IPv4
+            if with_timeout(DHCP_TIMEOUT, wait_ipv4(stack)).await.is_err() {
+                match DhcpFallback::choose(static_ipv4, stack.config_v6().is_some()) {
+                    DhcpFallback::Static(ip) => {
+                        warn!(
+                            "no DHCP lease after {}s, falling back to the static {}/{}",
+                            DHCP_TIMEOUT.as_secs(),
+                            ip.address,
+                            ip.prefix_len
+                        );
+                        stack.set_config_v4(ConfigV4::Static(static_config_v4(ip)));
+                    }
+                    DhcpFallback::Ipv6Only => {
+                        warn!(
+                            "no DHCP lease after {}s, carrying on with IPv6 only",
+                            DHCP_TIMEOUT.as_secs()
+                        );
+                        return;
+                    }
+                    DhcpFallback::Wait => error!(
+                        "no DHCP lease after {}s. check the network's DHCP server, or set \
+                        IPV4_ADDRESS for a static fallback. still waiting",
+                        DHCP_TIMEOUT.as_secs()
+                    ),
+                }
+                wait_ipv4(stack).await;
+            }
+        };
+
+        let Some(timeout) = bring_up.timeout() else {
+            return up.await;
+        };
+        if with_timeout(timeout, up).await.is_ok() {
+            return;
+        }
+        match bring_up.timed_out() {
+            BringUpAction::RestartRadio => {
+                warn!(
+                    "network not up after {}s, restarting the radio ({})",
+                    timeout.as_secs(),
+                    bring_up.failures()
+                );
+                RESTART_RADIO.signal(());
+            }
+            BringUpAction::Reset => {
+                error!(
+                    "network still not up after {} radio restarts, resetting",
+                    bring_up.failures() - 1
+                );
+                // give the log a moment to get out
+                Timer::after_millis(100).await;
+                esp_hal::system::software_reset();
+            }
         }
-        Timer::after_millis(500).await;
     }
+}
 
-    let state = mk_static!(TcpClientState<1, 4096, 4096>, TcpClientState::<1, 4096, 4096>::new());
-    let mut tcp = TcpClient::new(stack, state);
-    tcp.set_timeout(Some(embassy_time::Duration::from_secs(5)));
-    let dns = DnsSocket::new(stack);
+/// Wait for an IPv4 address, from DHCP or the static fallback. `Stack::wait_config_up` won't do
+/// with a static IPv6 address, which counts as configured from the start.
+async fn wait_ipv4(stack: Stack<'_>) {
+    while stack.config_v4().is_none() {
+        Timer::after_millis(200).await;
+    }
+}
 
-    let reqwless = HttpClient::new(&tcp, &dns);
-    let rx_buf = mk_static!([u8; 4096], [0u8; 4096]);
-    let mut client = Client::new(reqwless, rx_buf, api_key);
+/// `ip` as embassy-net wants it, for when DHCP gives up.
+fn static_config_v4(ip: StaticIpv4) -> StaticConfigV4 {
+    let mut config = StaticConfigV4 {
+        address: Ipv4Cidr::new(ip.address, ip.prefix_len),
+        gateway: ip.gateway,
+        dns_servers: Default::default(),
+    };
+    if let Some(dns) = ip.dns {
+        unwrap!(config.dns_servers.push(dns).ok());
+    }
 
-    loop {
-        // stack may go down but it should come back up eventually
-        while !stack.is_link_up() {
-            Timer::after_millis(1000).await;
+    config
+}
+
+/// A config from the compiled-in environment variables, `None` if the build wasn't given the
+/// Wi-Fi credentials and api key, e.g. a generic firmware to be provisioned on the device.
+/// Anything that was given but can't be used is a mistake in the build, so that still faults.
+fn env_config() -> Option<Config> {
+    info!("no valid config. loading environment variables");
+    let (Some(ssid), Some(pass), Some(api_key)) = (
+        SSID.or(OFFLINE_FALLBACK),
+        PASSWORD.or(OFFLINE_FALLBACK),
+        API_KEY.or(OFFLINE_FALLBACK),
+    ) else {
+        warn!("SSID, PASSWORD or API_KEY not set");
+        return None;
+    };
+
+    let power_mode = match POWER_MODE {
+        Some(mode) => unwrap!(mode.parse::<PowerMode>(), "POWER_MODE not recognized"),
+        None => PowerMode::default(),
+    };
+    let fallback_station =
+        FALLBACK_STATION.map(|code| unwrap!(code.parse().ok(), "FALLBACK_STATION not recognized"));
+
+    let cfg = unwrap!(
+        Config::new(ssid, pass, api_key),
+        "SSID, PASSWORD or API_KEY too long"
+    )
+    .with_power_mode(power_mode)
+    .with_destinations(&env_destinations())
+    .unwrap()
+    .with_lines(&env_lines())
+    .with_fallback_station(fallback_station)
+    .with_source(ConfigSource::Environment);
+    let cfg = unwrap!(
+        cfg.with_wifi_region(WIFI_COUNTRY, env_tx_power()),
+        "WIFI_COUNTRY or WIFI_TX_POWER not recognized"
+    );
+    let cfg = unwrap!(
+        cfg.with_static_ipv4(env_static_ipv4()),
+        "IPV4_ADDRESS, IPV4_GATEWAY or IPV4_DNS can't be used"
+    );
+    let cfg = match POLL_JITTER {
+        Some(percent) => unwrap!(
+            cfg.with_poll_jitter(unwrap!(percent.parse().ok(), "POLL_JITTER not recognized")),
+            "POLL_JITTER is over Config::MAX_POLL_JITTER"
+        ),
+        None => cfg,
+    };
+    let cfg = match NTP_SERVER {
+        Some(host) => unwrap!(cfg.with_ntp_server(host), "NTP_SERVER not recognized"),
+        None => cfg,
+    };
+    let keep_arriving = KEEP_ARRIVING
+        .map(|keep| unwrap!(keep.parse().ok(), "KEEP_ARRIVING not recognized"))
+        .unwrap_or_default();
+    let cfg = match MIN_MINUTES {
+        Some(mins) => cfg.with_min_minutes(
+            unwrap!(mins.parse().ok(), "MIN_MINUTES not recognized"),
+            keep_arriving,
+        ),
+        None => cfg,
+    };
+    let cfg = match NO_PASSENGER {
+        Some(policy) => {
+            cfg.with_no_passenger(unwrap!(policy.parse().ok(), "NO_PASSENGER not recognized"))
         }
+        None => cfg,
+    };
+    let cfg = match BACKUP_API_KEY {
+        Some(key) => unwrap!(cfg.with_backup_api_key(key), "BACKUP_API_KEY too long"),
+        None => cfg,
+    };
+    Some(cfg)
+}
 
-        let trains = client
-            .next_trains(esp_wmata_pids::wmata::types::Station::K04)
-            .await;
+/// With no config to run, open the setup network and show how to join it, see
+/// `esp_wmata_pids::setup`, until a config is posted to its form, then save it and reboot into it.
+async fn await_setup(spawner: Spawner, mut flash: FlashStorage, wifi: WIFI<'static>) -> ! {
+    let mac = Efuse::mac_address();
+    let ssid = setup::ap_ssid(mac);
+    warn!(
+        "no config in flash or the build, join {} and open http://{} to set up",
+        ssid.as_str(),
+        setup::SETUP_IP
+    );
+    let abbreviations = mk_static!(Vec<(Station, &'static str), 16>, env_abbreviations());
+    unwrap!(
+        spawner.spawn(render(Abbreviations::new(abbreviations))),
+        "failed to spawn task"
+    );
+    SCREEN.signal(DisplayState::at_boot(None, mac));
 
-        match trains {
-            Ok(trains) => {
-                println!("\nUpdate: ");
-
-                let mut format_str: String<48> = String::new();
-                for t in &trains {
-                    format_str.clear();
-                    t.write_debug_display(&mut format_str)
-                        .expect("couldn't write debug display");
-                    println!("{}", format_str);
+    let (mut controller, interfaces) = unwrap!(
+        esp_radio::wifi::new(wifi, ControllerConfig::default()),
+        "Failed to initialize Wi-Fi controller"
+    );
+    let ap = AccessPointConfig::default().with_ssid(ssid.as_str().into());
+    unwrap!(controller.set_config(&ModeConfig::AccessPoint(ap)));
+    unwrap!(controller.start_async().await);
+
+    let config = embassy_net::Config::ipv4_static(StaticConfigV4 {
+        address: Ipv4Cidr::new(setup::SETUP_IP, setup::SETUP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Default::default(),
+    });
+    let rng = Rng::new();
+    let seed = (rng.random() as u64) << 32 | rng.random() as u64;
+    // 2 sockets: the DHCP server and the form
+    let (stack, runner) = embassy_net::new(
+        interfaces.access_point,
+        config,
+        mk_static!(StackResources<2>, StackResources::<2>::new()),
+        seed,
+    );
+    unwrap!(spawner.spawn(net_task(runner)), "failed to spawn task");
+
+    let cfg = match select(setup::serve_dhcp(stack), setup::serve_form(stack)).await {
+        Either::First(never) => never,
+        Either::Second(cfg) => cfg,
+    };
+    if let Err(e) = cfg.save(&mut flash) {
+        defmt::panic!("couldn't save the config: {}", e);
+    }
+    info!("set up, rebooting into the new config");
+    // give the page a moment to get out
+    Timer::after_millis(500).await;
+    esp_hal::system::software_reset()
+}
+
+/// Settings typed at the serial console since the last `save`, see `esp_wmata_pids::console`.
+/// Nothing is written to flash until then, so a typo can be fixed before it takes effect.
+#[derive(Default)]
+struct ConsoleEdits {
+    config: Option<ConfigEdit>,
+    #[cfg(feature = "name-overrides")]
+    names: Option<NameOverrides>,
+}
+
+impl ConsoleEdits {
+    /// Apply `cmd` on top of the `running` config, or the names saved in `flash`. Returns whether
+    /// it saved the config, for the caller to load and run with.
+    ///
+    /// The renderer only reads the names at boot, so saving new ones reboots.
+    fn handle(&mut self, cmd: Command, running: &Config, flash: &mut FlashStorage) -> bool {
+        let applied = match &cmd {
+            Command::Save => return self.save(flash),
+            Command::Help => {
+                info!("{}", HELP);
+                return false;
+            }
+            #[cfg(feature = "name-overrides")]
+            Command::Name(..) | Command::Unname(_) => cmd.apply_to_names(
+                self.names
+                    .get_or_insert_with(|| NameOverrides::load(flash).unwrap_or_default()),
+            ),
+            _ => cmd.apply(self.config.get_or_insert_with(|| running.begin_edit())),
+        };
+        match applied {
+            Ok(()) => info!("ok, save when done"),
+            Err(e) => warn!("{}", e),
+        }
+        false
+    }
+
+    fn save(&mut self, flash: &mut FlashStorage) -> bool {
+        let mut saved = false;
+        if let Some(mut edit) = self.config.take() {
+            if let Err(e) = Command::Save.apply(&mut edit) {
+                warn!("{}", e);
+                self.config = Some(edit);
+                return false;
+            }
+            match edit.commit(flash) {
+                Ok(_) => saved = true,
+                Err(e) => error!("couldn't save the config: {}", e),
+            }
+        }
+        #[cfg(feature = "name-overrides")]
+        if let Some(names) = self.names.take() {
+            match names.save(flash) {
+                Ok(()) => {
+                    info!("saved the names, rebooting to show them");
+                    esp_hal::system::software_reset()
                 }
+                Err(e) => error!("couldn't save the names: {}", e),
             }
-            Err(e) => error!("{:?}", e),
         }
-        Timer::after_secs(10).await;
+        if saved {
+            info!("saved");
+        }
+        saved
+    }
+}
+
+/// Wait until `at`, handling what's typed at the serial console in the meantime. Returns early
+/// after a `save` that changed the config, raising `RELOAD` for the main loop to apply it right
+/// away.
+async fn wait_taking_commands(
+    at: Instant,
+    edits: &mut ConsoleEdits,
+    running: &Config,
+    flash: &mut FlashStorage,
+) {
+    while let Either::Second(cmd) = select(Timer::at(at), CONSOLE.receive()).await {
+        if edits.handle(cmd, running, flash) {
+            RELOAD.signal(());
+            return;
+        }
     }
 }
 
+/// Parse the comma separated station codes in `DESTINATIONS`, if set.
+fn env_destinations() -> Vec<Station, { Config::MAX_DESTINATIONS }> {
+    let mut destinations = Vec::new();
+    for code in DESTINATIONS.unwrap_or_default().split(',') {
+        let code = code.trim();
+        if code.is_empty() {
+            continue;
+        }
+
+        let station = unwrap!(code.parse().ok(), "DESTINATIONS code not recognized");
+        unwrap!(destinations.push(station).ok(), "too many DESTINATIONS");
+    }
+
+    destinations
+}
+
+/// Parse the comma separated line codes in `LINES`, if set.
+fn env_lines() -> Vec<LineKind, 7> {
+    let mut lines = Vec::new();
+    for code in LINES.unwrap_or_default().split(',') {
+        let code = code.trim();
+        if code.is_empty() {
+            continue;
+        }
+
+        let line = unwrap!(LineKind::from_code(code), "LINES code not recognized");
+        unwrap!(lines.push(line).ok(), "too many LINES");
+    }
+
+    lines
+}
+
+/// `ABBREVIATIONS`, as `code=name` pairs separated by commas.
+fn env_abbreviations() -> Vec<(Station, &'static str), 16> {
+    let mut abbreviations = Vec::new();
+    for pair in ABBREVIATIONS.unwrap_or_default().split(',') {
+        let pair = pair.trim();
+        if pair.is_empty() {
+            continue;
+        }
+
+        let (code, name) = unwrap!(
+            pair.split_once('='),
+            "ABBREVIATIONS entries look like C15=Hunt"
+        );
+        let station = unwrap!(code.parse().ok(), "ABBREVIATIONS code not recognized");
+        unwrap!(
+            abbreviations.push((station, name.trim())).ok(),
+            "too many ABBREVIATIONS"
+        );
+    }
+
+    abbreviations
+}
+
+/// `BRINGUP_TIMEOUT` and `BRINGUP_RESTARTS`, each defaulting to `BringUp`'s own.
+fn env_bring_up() -> BringUp {
+    let timeout = match BRINGUP_TIMEOUT {
+        Some(secs) => match unwrap!(secs.parse::<u64>().ok(), "BRINGUP_TIMEOUT not recognized") {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        },
+        None => Some(BringUp::DEFAULT_TIMEOUT),
+    };
+    let restarts = BRINGUP_RESTARTS.map_or(BringUp::DEFAULT_RESTARTS, |restarts| {
+        unwrap!(restarts.parse().ok(), "BRINGUP_RESTARTS not recognized")
+    });
+    BringUp::new(timeout, restarts)
+}
+
+/// `WIFI_TX_POWER`, if set.
+fn env_tx_power() -> Option<u8> {
+    WIFI_TX_POWER.map(|power| unwrap!(power.parse().ok(), "WIFI_TX_POWER not recognized"))
+}
+
+/// Keeps the station connected, roaming between APs and sleeping the radio as asked.
+///
+/// New credentials on `CREDENTIALS` are swapped in and, if connected, the current AP is left.
+/// The next time round the loop scans for the new SSID, sets the new config and connects as it
+/// would after any drop, and the main loop waits out the link going down and back up in the
+/// meantime. A wrong password just keeps retrying until corrected credentials arrive, though at
+/// boot `bring_up_network` restarts the radio and eventually resets the chip in the meantime.
+/// A restart asked for on `RESTART_RADIO` stops the controller, and it's started again like at boot.
 #[task]
 async fn manage_station(
     mut controller: WifiController<'static>,
-    ssid: &'static str,
-    password: &'static str,
+    mut ssid: String<32>,
+    mut password: String<64>,
+    power_mode: PowerMode,
+    max_tx_power: Option<u8>,
 ) {
+    let _exit = STATION_TASK.exit_guard();
     debug!("starting manage_connection task");
     debug!("device capabilities: {:?}", controller.capabilities());
 
     // loop forever, keeping the controller started and the connection up
     loop {
+        STATION_TASK.beat(Instant::now(), STALL_MARGIN);
+        if let Some(credentials) = CREDENTIALS.try_take() {
+            ssid = credentials.ssid;
+            password = credentials.password;
+        }
+        if RESTART_RADIO.try_take().is_some() {
+            info!("Restarting wifi");
+            if let Err(e) = controller.stop_async().await {
+                warn!("Failed to stop wifi: {:?}", e);
+            }
+        }
+
         if esp_radio::wifi::station_state() == WifiStationState::Connected {
-            // wait until we're no longer connected
-            controller
-                .wait_for_event(WifiEvent::StationDisconnected)
-                .await;
-            Timer::after_millis(5000).await;
+            // wait until we're no longer connected, the main loop asks us to sleep or the
+            // credentials change, checking the signal every so often in case there's a better AP to roam to
+            STATION_TASK.beat(Instant::now(), RSSI_INTERVAL + STALL_MARGIN);
+            match select4(
+                controller.wait_for_event(WifiEvent::StationDisconnected),
+                RADIO.wait(),
+                Timer::after(RSSI_INTERVAL),
+                CREDENTIALS.wait(),
+            )
+            .await
+            {
+                Either4::First(_) => {
+                    STATUS.set_rssi(None);
+                    Timer::after_millis(5000).await
+                }
+                Either4::Second(RadioRequest::Sleep) => {
+                    debug!("radio going to sleep");
+                    if let Err(e) = controller.disconnect_async().await {
+                        warn!("Failed to disconnect from wifi: {:?}", e);
+                    }
+                    STATUS.set_rssi(None);
+                    STATION_TASK.idle();
+                    while !matches!(RADIO.wait().await, RadioRequest::Wake) {}
+                    debug!("radio waking up");
+                }
+                Either4::Second(RadioRequest::Wake) => continue,
+                Either4::Third(()) => {
+                    let rssi = controller.rssi().ok().map(|rssi| rssi as i8);
+                    STATUS.set_rssi(rssi);
+                    if let Some(rssi) = rssi
+                        && rssi < ROAM_BELOW_RSSI
+                        && should_roam(&mut controller, &ssid, rssi).await
+                    {
+                        info!("Roaming to a stronger AP");
+                        // next time round the loop scans again and connects to the strongest one
+                        if let Err(e) = controller.disconnect_async().await {
+                            warn!("Failed to disconnect from wifi: {:?}", e);
+                        }
+                    }
+                    continue;
+                }
+                Either4::Fourth(credentials) => {
+                    info!("Wifi credentials changed, reconnecting");
+                    ssid = credentials.ssid;
+                    password = credentials.password;
+                    if let Err(e) = controller.disconnect_async().await {
+                        warn!("Failed to disconnect from wifi: {:?}", e);
+                    }
+                    STATUS.set_rssi(None);
+                }
+            }
         }
         if !matches!(controller.is_started(), Ok(true)) {
-            let station_config = ModeConfig::Station(
-                StationConfig::default()
-                    .with_ssid(ssid.into())
-                    .with_password(password.into()),
-            );
-            controller.set_config(&station_config).unwrap();
-            println!("Starting wifi");
+            controller
+                .set_config(&ModeConfig::Station(station_config(&ssid, &password, None)))
+                .unwrap();
+            debug!("Starting wifi");
             controller.start_async().await.unwrap();
-            println!("Wifi started!");
+            info!("Wifi started");
 
-            println!("Scan");
-            let scan_config = ScanConfig::default().with_max(10);
-            let result = controller
-                .scan_with_config_async(scan_config)
-                .await
-                .unwrap();
-            for ap in result {
-                println!("{:?}", ap);
+            if power_mode == PowerMode::ModemSleep {
+                controller.set_power_saving(PowerSaveMode::Minimum).unwrap();
+            }
+            // the cap only takes once the radio is started
+            if let Some(power) = max_tx_power
+                && let Err(e) = controller.set_max_tx_power(power as i8)
+            {
+                warn!("Failed to cap tx power: {:?}", e);
             }
         }
-        println!("About to connect...");
+
+        // scan before every attempt, so a reconnect after losing the AP picks whichever is strongest now
+        let ap = strongest_ap(&mut controller, &ssid).await;
+        if let Some(ap) = &ap {
+            info!(
+                "Connecting to {:?} on channel {} ({} dBm)",
+                ap.bssid, ap.channel, ap.signal_strength
+            );
+        }
+        let station_config = ModeConfig::Station(station_config(&ssid, &password, ap.as_ref()));
+        if let Err(e) = controller.set_config(&station_config) {
+            warn!("Failed to configure wifi: {:?}", e);
+        }
+        debug!("About to connect...");
 
         match controller.connect_async().await {
-            Ok(_) => println!("Wifi connected!"),
+            Ok(_) => {
+                info!("Wifi connected");
+                STATUS.set_rssi(controller.rssi().ok().map(|rssi| rssi as i8));
+            }
             Err(e) => {
-                println!("Failed to connect to wifi: {:?}", e);
+                warn!("Failed to connect to wifi: {:?}", e);
                 Timer::after_millis(5000).await
             }
         }
     }
 }
 
+/// Station config for `ssid`, pinned to `ap` if given so we don't end up on a weaker AP with the same SSID.
+fn station_config(ssid: &str, password: &str, ap: Option<&AccessPointInfo>) -> StationConfig {
+    let config = StationConfig::default()
+        .with_ssid(ssid.into())
+        .with_password(password.into());
+    match ap {
+        Some(ap) => config.with_bssid(ap.bssid).with_channel(ap.channel),
+        None => config,
+    }
+}
+
+/// Scan for APs broadcasting `ssid` and pick the one with the strongest signal.
+/// `None` if the scan fails or finds nothing, e.g. a hidden network, in which case we let
+/// the driver pick by SSID alone. What it found is kept for the status page, see `Status::scan`.
+async fn strongest_ap(
+    controller: &mut WifiController<'static>,
+    ssid: &str,
+) -> Option<AccessPointInfo> {
+    let scan_config = ScanConfig::default().with_ssid(ssid).with_max(10);
+    match controller.scan_with_config_async(scan_config).await {
+        Ok(aps) => {
+            for ap in &aps {
+                debug!("{:?}", ap);
+            }
+            STATUS.record_scan(aps.iter().map(|ap| ScanEntry {
+                ssid: String::try_from(ap.ssid.as_str()).unwrap_or_default(),
+                rssi: ap.signal_strength,
+                channel: ap.channel,
+                auth: ap.auth_method.map(auth_name),
+            }));
+            aps.into_iter().max_by_key(|ap| ap.signal_strength)
+        }
+        Err(e) => {
+            warn!("Failed to scan for APs: {:?}", e);
+            None
+        }
+    }
+}
+
+/// Short name of `auth` for the status page.
+fn auth_name(auth: AuthenticationMethod) -> &'static str {
+    match auth {
+        AuthenticationMethod::None => "open",
+        AuthenticationMethod::Wep => "WEP",
+        AuthenticationMethod::Wpa => "WPA",
+        AuthenticationMethod::Wpa2Personal => "WPA2",
+        AuthenticationMethod::WpaWpa2Personal => "WPA/WPA2",
+        AuthenticationMethod::Wpa2Enterprise => "WPA2-Enterprise",
+        AuthenticationMethod::Wpa3Personal => "WPA3",
+        AuthenticationMethod::Wpa2Wpa3Personal => "WPA2/WPA3",
+        _ => "other",
+    }
+}
+
+/// Whether some AP for `ssid` beats our current signal of `rssi` by at least `ROAM_MARGIN_DB`,
+/// so a few dB of noise between scans doesn't have us hopping back and forth.
+async fn should_roam(controller: &mut WifiController<'static>, ssid: &str, rssi: i8) -> bool {
+    strongest_ap(controller, ssid)
+        .await
+        .is_some_and(|ap| ap.signal_strength >= rssi.saturating_add(ROAM_MARGIN_DB))
+}
+
+/// Draws the latest `DisplayState`, at whatever pace the display can manage.
+#[task]
+async fn render(abbreviations: Abbreviations<'static>) {
+    let _exit = RENDER_TASK.exit_guard();
+    let mut format_str: String<48> = String::new();
+    let mut smoother: EtaSmoother<16> = EtaSmoother::default();
+    // what `DisplayState::Stale` keeps up
+    let mut last_trains = None;
+
+    loop {
+        let state = SCREEN.wait().await;
+        info!("Update:");
+        match state {
+            DisplayState::Trains(mut trains) => {
+                smoother.smooth(&mut trains);
+                print_rows(&trains, &abbreviations, &mut format_str);
+                last_trains = Some(trains);
+                continue;
+            }
+            DisplayState::Stale { .. } => {
+                if let Some(trains) = &last_trains {
+                    print_rows(trains, &abbreviations, &mut format_str);
+                }
+            }
+            _ => last_trains = None,
+        }
+
+        format_str.clear();
+        // an error's message can run long, and a cut off one still says what it's about
+        let _ = state.write_status(&mut format_str);
+        info!("{=str}", format_str.as_str());
+        format_str.clear();
+        if state.write_detail(&mut format_str).is_ok() && !format_str.is_empty() {
+            info!("{=str}", format_str.as_str());
+        }
+    }
+}
+
+/// Prints the trains that fit on the board, one row each, and a row for any left over.
+fn print_rows<const N: usize>(
+    trains: &NextTrainsResponse,
+    abbreviations: &Abbreviations<'_>,
+    format_str: &mut String<N>,
+) {
+    let rows = trains.rows(MAX_ROWS);
+    for t in &rows.shown {
+        format_str.clear();
+        t.write_debug_display_with(format_str, abbreviations)
+            .expect("couldn't write debug display");
+        info!("{=str}", format_str.as_str());
+    }
+
+    if rows.hidden > 0 {
+        format_str.clear();
+        let format = OVERFLOW_FORMAT.unwrap_or(Rows::DEFAULT_OVERFLOW_FORMAT);
+        match rows.write_overflow(format_str, format) {
+            Ok(()) => info!("{=str}", format_str.as_str()),
+            Err(_) => warn!("OVERFLOW_FORMAT doesn't fit in a row"),
+        }
+    }
+}
+
+/// Serves the json from `Status::write_json` to any request on `STATUS_PORT`, one connection at a time,
+/// or with the `metrics` feature Prometheus text from `Status::write_metrics` on `/metrics`.
+/// It's unauthenticated and plain http, so only expose it on a trusted LAN.
+#[task]
+async fn status_server(stack: Stack<'static>) {
+    let _exit = STATUS_TASK.exit_guard();
+    let mut rx_buf = [0u8; 256];
+    let mut tx_buf = [0u8; 512];
+    let mut request = [0u8; 256];
+    // room for a full scan of ten APs with everyday SSIDs, though not ten 32 byte ones full of
+    // escapes, and every disconnect reason
+    let mut body: String<2048> = String::new();
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+        socket.set_timeout(Some(Duration::from_secs(5)));
+        if let Err(e) = socket.accept(STATUS_PORT).await {
+            warn!("status: accept failed: {:?}", e);
+            continue;
+        }
+
+        // only the request line matters, so just wait for the end of the headers (or a full buffer)
+        let mut len = 0;
+        while len < request.len() {
+            match socket.read(&mut request[len..]).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => len += n,
+            }
+            if request[..len].windows(4).any(|w| w == b"\r\n\r\n") {
+                break;
+            }
+        }
+
+        body.clear();
+        let (content_type, written) = write_status_body(&request[..len], &mut body);
+        // json cut off partway is useless and metrics cut off would just be missing some, so
+        // neither is sent as if it were the whole thing
+        let status = match written {
+            Ok(()) => "200 OK",
+            Err(_) => {
+                warn!("status: body didn't fit");
+                body.clear();
+                "500 Internal Server Error"
+            }
+        };
+        let mut header: String<128> = String::new();
+        unwrap!(write!(
+            header,
+            "HTTP/1.0 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            status,
+            content_type,
+            body.len()
+        )
+        .ok());
+
+        let sent = async {
+            socket.write_all(header.as_bytes()).await?;
+            socket.write_all(body.as_bytes()).await?;
+            socket.flush().await
+        };
+        if let Err(e) = sent.await {
+            warn!("status: write failed: {:?}", e);
+        }
+        socket.close();
+        // give the FIN a moment before the socket is dropped
+        Timer::after_millis(50).await;
+        socket.abort();
+    }
+}
+
+/// The body `status_server` answers `request` with, and its content type.
+#[cfg_attr(not(feature = "metrics"), allow(unused_variables))]
+fn write_status_body<const N: usize>(
+    request: &[u8],
+    body: &mut String<N>,
+) -> (&'static str, core::fmt::Result) {
+    #[cfg(feature = "metrics")]
+    if request.starts_with(b"GET /metrics ") || request.starts_with(b"GET /metrics?") {
+        let written = STATUS.write_metrics(body, esp_alloc::HEAP.free());
+        return ("text/plain; version=0.0.4", written);
+    }
+
+    ("application/json", STATUS.write_json(body))
+}
+
+/// Writes each update from `PREDICTIONS` to UART1 as one line of json, see
+/// `NextTrainsResponse::write_json`, for a host wired to GPIO17 to read. Nothing is smoothed or
+/// cut down to the board's rows, the host gets every train the api sent.
+#[cfg(feature = "bridge")]
+#[task]
+async fn bridge(mut tx: UartTx<'static, Async>) {
+    let _exit = BRIDGE_TASK.exit_guard();
+    let mut predictions = unwrap!(PREDICTIONS.receiver());
+    // about 220 bytes a train, so room for the busiest transfer station's predictions
+    let mut line: String<4096> = String::new();
+
+    loop {
+        let trains = predictions.changed().await;
+        line.clear();
+        // half a line would only confuse the host, so skip the update instead
+        if trains.write_json(&mut line).is_err() || line.push('\n').is_err() {
+            warn!("bridge: {} trains didn't fit in a line", trains.len());
+            continue;
+        }
+        if let Err(e) = tx.write_all(line.as_bytes()).await {
+            warn!("bridge: write failed: {:?}", e);
+        }
+    }
+}
+
+/// Reads the lines typed into `espflash monitor`, which shares UART0 with the logs, and hands each
+/// command to `CONSOLE`. A line that doesn't parse is logged, not what was typed, since it could be
+/// a password, and dropped.
+#[task]
+async fn serial_console(mut rx: UartRx<'static, Async>) {
+    let mut reader = LineReader::new();
+    let mut buf = [0u8; 32];
+
+    loop {
+        let n = match rx.read_async(&mut buf).await {
+            Ok(n) => n,
+            Err(e) => {
+                warn!("console: read failed: {:?}", e);
+                continue;
+            }
+        };
+        for &byte in &buf[..n] {
+            match reader.push(byte) {
+                Some(Ok(cmd)) => CONSOLE.send(cmd).await,
+                Some(Err(e)) => warn!("console: {}", e),
+                None => {}
+            }
+        }
+    }
+}
+
 #[embassy_executor::task]
 async fn net_task(mut runner: Runner<'static, WifiDevice<'static>>) {
+    let _exit = NET_TASK.exit_guard();
     runner.run().await
 }
 
+/// Resets the board as soon as any task has exited or stopped beating, see `esp_wmata_pids::supervisor`.
+/// A reset is the only way back: the failed task can't be spawned again, and what it held is gone.
+#[task]
+async fn supervise() {
+    loop {
+        Timer::after(SUPERVISE_INTERVAL).await;
+        if let Some((task, failure)) = SUPERVISOR.check(Instant::now()) {
+            error!("{} failed ({}), resetting", task, failure);
+            // give the log a moment to get out
+            Timer::after_millis(100).await;
+            esp_hal::system::software_reset();
+        }
+    }
+}
+
 fn init_wifi_handlers() {
     StationDisconnected::update_handler(|event| {
         debug!("EVENT: StationDisconnected - {}", event.reason());
+        if event.reason() != REASON_ASSOC_LEAVE {
+            STATUS.record_disconnect(event.reason());
+            EVENTS
+                .try_send(Event::WifiDisconnected {
+                    reason: event.reason(),
+                })
+                .ok();
+        }
     });
 }
@@ -0,0 +1,321 @@
+//! Commands typed over serial, one a line, for changing the Wi-Fi credentials or api key of a
+//! running device without reflashing it, and with the `name-overrides` feature, renaming stations
+//! on the display. A device with no config at all is set up over Wi-Fi instead, see
+//! [`setup`](crate::setup). The firmware reads them off UART0, the same port the logs go out on,
+//! so they can be typed straight into `espflash monitor`.
+
+use heapless::{String, Vec};
+use thiserror::Error;
+
+use crate::wmata::{API_KEY_MAX_LEN, ConfigEdit, PASS_MAX_LEN, SSID_MAX_LEN};
+#[cfg(feature = "name-overrides")]
+use crate::wmata::{NAME_OVERRIDE_MAX_LEN, NameOverrides, types::Station};
+
+/// Every command, for `help`.
+#[cfg(not(feature = "name-overrides"))]
+pub const HELP: &str = "commands: ssid <name>, pass <password>, key <api key>, save, help";
+#[cfg(feature = "name-overrides")]
+pub const HELP: &str = "commands: ssid <name>, pass <password>, key <api key>, \
+    name <station code> <name>, unname <station code>, save, help";
+
+/// The longest line taken, past which the rest of it is dropped.
+const LINE_MAX_LEN: usize = 128;
+
+/// One line typed at the console.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Command {
+    /// `ssid <name>`, the Wi-Fi network to join.
+    Ssid(String<SSID_MAX_LEN>),
+    /// `pass <password>`, or just `pass` for an open network.
+    Pass(String<PASS_MAX_LEN>),
+    /// `key <api key>`, the WMATA api key.
+    ApiKey(String<API_KEY_MAX_LEN>),
+    /// `name <station code> <name>`, show the station as `name`, see [`NameOverrides::set`].
+    #[cfg(feature = "name-overrides")]
+    Name(Station, String<NAME_OVERRIDE_MAX_LEN>),
+    /// `unname <station code>`, back to the station's usual name.
+    #[cfg(feature = "name-overrides")]
+    Unname(Station),
+    /// `save`, write the config, and any names, to flash and run with them.
+    Save,
+    /// `help`, list the commands.
+    Help,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Error)]
+pub enum ConsoleError {
+    #[error("unknown command, try help")]
+    Unknown,
+    #[error("missing its argument")]
+    Missing,
+    #[error("too long")]
+    TooLong,
+    #[error("not text")]
+    NotText,
+    #[cfg(feature = "name-overrides")]
+    #[error("no station with that code")]
+    NoSuchStation,
+    /// Already [`NameOverrides::MAX`] other stations renamed.
+    #[cfg(feature = "name-overrides")]
+    #[error("no room for another name, unname one first")]
+    TooManyNames,
+    /// `save` before there's anything to run with.
+    #[error("set an ssid and an api key first")]
+    Incomplete,
+}
+
+impl defmt::Format for ConsoleError {
+    fn format(&self, fmt: defmt::Formatter) {
+        defmt::write!(fmt, "{}", defmt::Display2Format(self))
+    }
+}
+
+impl Command {
+    /// Parse a line without its line ending. The command is matched ignoring case, and its
+    /// argument is the rest of the line, inner spaces and all, with any around it trimmed.
+    pub fn parse(line: &str) -> Result<Self, ConsoleError> {
+        let line = line.trim();
+        let (name, arg) = line.split_once(' ').unwrap_or((line, ""));
+        let arg = arg.trim();
+        let required = |arg: &str| {
+            if arg.is_empty() {
+                Err(ConsoleError::Missing)
+            } else {
+                Ok(())
+            }
+        };
+        let too_long = |_| ConsoleError::TooLong;
+
+        match name.to_ascii_lowercase().as_str() {
+            "ssid" => {
+                required(arg)?;
+                Ok(Command::Ssid(String::try_from(arg).map_err(too_long)?))
+            }
+            "pass" => Ok(Command::Pass(String::try_from(arg).map_err(too_long)?)),
+            "key" => {
+                required(arg)?;
+                Ok(Command::ApiKey(String::try_from(arg).map_err(too_long)?))
+            }
+            #[cfg(feature = "name-overrides")]
+            "name" => {
+                let (code, name) = arg.split_once(' ').unwrap_or((arg, ""));
+                let station = code.parse().map_err(|_| ConsoleError::NoSuchStation)?;
+                let name = name.trim();
+                required(name)?;
+                Ok(Command::Name(
+                    station,
+                    String::try_from(name).map_err(too_long)?,
+                ))
+            }
+            #[cfg(feature = "name-overrides")]
+            "unname" => {
+                required(arg)?;
+                let station = arg.parse().map_err(|_| ConsoleError::NoSuchStation)?;
+                Ok(Command::Unname(station))
+            }
+            "save" => Ok(Command::Save),
+            "help" => Ok(Command::Help),
+            _ => Err(ConsoleError::Unknown),
+        }
+    }
+
+    /// Apply a setting to `edit`. [`Command::Save`] changes nothing, but is
+    /// [`ConsoleError::Incomplete`] until `edit` has an SSID and an api key to run with, and
+    /// otherwise leaves saving it to the caller, e.g. with [`ConfigEdit::commit`]. Names aren't
+    /// part of the config, see [`Command::apply_to_names`].
+    pub fn apply(&self, edit: &mut ConfigEdit) -> Result<(), ConsoleError> {
+        let too_long = |_| ConsoleError::TooLong;
+        match self {
+            Command::Ssid(ssid) => edit.set_ssid(ssid).map_err(too_long),
+            Command::Pass(pass) => edit.set_pass(pass).map_err(too_long),
+            Command::ApiKey(key) => edit.set_api_key(key).map_err(too_long),
+            Command::Save => {
+                let config = edit.config();
+                if config.ssid().is_empty() || config.api_key().is_empty() {
+                    return Err(ConsoleError::Incomplete);
+                }
+                Ok(())
+            }
+            #[cfg(feature = "name-overrides")]
+            Command::Name(..) | Command::Unname(_) => Ok(()),
+            Command::Help => Ok(()),
+        }
+    }
+
+    /// Apply a [`Command::Name`] or [`Command::Unname`] to `names`, leaving saving them to the
+    /// caller, with [`NameOverrides::save`]. Anything else changes nothing.
+    #[cfg(feature = "name-overrides")]
+    pub fn apply_to_names(&self, names: &mut NameOverrides) -> Result<(), ConsoleError> {
+        match self {
+            Command::Name(station, name) => names
+                .set(*station, name)
+                .map_err(|_| ConsoleError::TooManyNames),
+            Command::Unname(station) => {
+                names.clear(*station);
+                Ok(())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Gathers the bytes read off the serial port into lines, a byte at a time, ending each at a `\r`
+/// or `\n` so either line ending works.
+pub struct LineReader {
+    line: Vec<u8, LINE_MAX_LEN>,
+    overflowed: bool,
+}
+
+impl LineReader {
+    pub const fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            overflowed: false,
+        }
+    }
+
+    /// Take the next byte, returning the line's command once it ends. `None` mid-line and for a
+    /// blank one, e.g. the `\n` of a `\r\n`.
+    pub fn push(&mut self, byte: u8) -> Option<Result<Command, ConsoleError>> {
+        if byte != b'\r' && byte != b'\n' {
+            self.overflowed |= self.line.push(byte).is_err();
+            return None;
+        }
+
+        let cmd = match core::str::from_utf8(&self.line) {
+            _ if self.overflowed => Some(Err(ConsoleError::TooLong)),
+            Ok(typed) if typed.trim().is_empty() => None,
+            Ok(typed) => Some(Command::parse(typed)),
+            Err(_) => Some(Err(ConsoleError::NotText)),
+        };
+        self.line.clear();
+        self.overflowed = false;
+        cmd
+    }
+}
+
+impl Default for LineReader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wmata::{Config, FlashStorage};
+
+    #[test]
+    fn commands_parse_ignoring_case_and_surrounding_space() {
+        assert_eq!(
+            Command::parse("SSID  Home Wi-Fi "),
+            Ok(Command::Ssid(String::try_from("Home Wi-Fi").unwrap()))
+        );
+        assert_eq!(
+            Command::parse("key abc123"),
+            Ok(Command::ApiKey(String::try_from("abc123").unwrap()))
+        );
+        assert_eq!(Command::parse("pass"), Ok(Command::Pass(String::new())));
+        assert_eq!(Command::parse(" save"), Ok(Command::Save));
+        assert_eq!(Command::parse("Help"), Ok(Command::Help));
+
+        assert_eq!(Command::parse("ssid"), Err(ConsoleError::Missing));
+        assert_eq!(Command::parse("ssidHome"), Err(ConsoleError::Unknown));
+        assert_eq!(Command::parse("reboot"), Err(ConsoleError::Unknown));
+        let long_key = [b'k'; API_KEY_MAX_LEN + 1];
+        let mut line: alloc::string::String = "key ".into();
+        line.push_str(core::str::from_utf8(&long_key).unwrap());
+        assert_eq!(Command::parse(&line), Err(ConsoleError::TooLong));
+    }
+
+    #[test]
+    fn a_device_is_set_up_one_command_at_a_time() {
+        let mut flash = FlashStorage::new(4 * 1024 * 1024);
+        let mut edit = Config::new("", "", "").unwrap().begin_edit();
+
+        for line in ["ssid Home", "save", "key abc123"] {
+            let cmd = Command::parse(line).unwrap();
+            let applied = cmd.apply(&mut edit);
+            if cmd == Command::Save {
+                // no api key yet
+                assert_eq!(applied, Err(ConsoleError::Incomplete));
+            } else {
+                assert_eq!(applied, Ok(()));
+            }
+        }
+        assert_eq!(Command::Save.apply(&mut edit), Ok(()));
+        edit.commit(&mut flash).unwrap();
+
+        let saved = Config::load(&mut flash).unwrap();
+        assert_eq!(
+            (saved.ssid(), saved.pass(), saved.api_key()),
+            ("Home", "", "abc123")
+        );
+    }
+
+    #[test]
+    fn lines_end_at_either_line_ending() {
+        let mut reader = LineReader::new();
+        let mut typed = |bytes: &[u8]| -> alloc::vec::Vec<_> {
+            bytes.iter().filter_map(|b| reader.push(*b)).collect()
+        };
+
+        assert_eq!(typed(b"sa"), []);
+        assert_eq!(
+            typed(b"ve\r\nhelp\n"),
+            [Ok(Command::Save), Ok(Command::Help)]
+        );
+        assert_eq!(typed(b"  \r\n"), []);
+        assert_eq!(typed(b"ssid \xff\n"), [Err(ConsoleError::NotText)]);
+
+        // an overlong line is dropped whole, not cut short into a command
+        let mut long = alloc::vec![b'x'; LINE_MAX_LEN];
+        long.splice(..5, *b"ssid ");
+        long.push(b'\n');
+        assert_eq!(typed(&long), [Err(ConsoleError::TooLong)]);
+        assert_eq!(typed(b"save\n"), [Ok(Command::Save)]);
+    }
+
+    #[cfg(feature = "name-overrides")]
+    #[test]
+    fn stations_are_renamed_by_code() {
+        let mut names = NameOverrides::new();
+        for line in ["name k04 Ballston", "NAME A01  Metro Ctr ", "unname k04"] {
+            let cmd = Command::parse(line).unwrap();
+            assert_eq!(cmd.apply_to_names(&mut names), Ok(()));
+        }
+        assert_eq!(names.get(Station::K04), None);
+        assert_eq!(names.get(Station::A01), Some("Metro Ctr"));
+
+        assert_eq!(Command::parse("name K04"), Err(ConsoleError::Missing));
+        assert_eq!(
+            Command::parse("name Z99 Nowhere"),
+            Err(ConsoleError::NoSuchStation)
+        );
+        assert_eq!(Command::parse("unname"), Err(ConsoleError::Missing));
+        assert_eq!(
+            Command::parse("name K04 Ballston-MU and beyond"),
+            Err(ConsoleError::TooLong)
+        );
+
+        let mut full = NameOverrides::new();
+        let others = [
+            Station::A02,
+            Station::A03,
+            Station::A04,
+            Station::A05,
+            Station::A06,
+            Station::A07,
+            Station::A08,
+            Station::A09,
+        ];
+        for station in &others[..NameOverrides::MAX] {
+            full.set(*station, "x").unwrap();
+        }
+        let cmd = Command::parse("name A01 Metro Ctr").unwrap();
+        assert_eq!(
+            cmd.apply_to_names(&mut full),
+            Err(ConsoleError::TooManyNames)
+        );
+    }
+}
@@ -0,0 +1,75 @@
+//! One error type for the whole crate, for top-level code that talks to the api and to flash in
+//! the same function and wants `?` for both. The specific enums stay as they are, this only wraps
+//! them.
+
+use crate::wmata::{self, ConfigError};
+
+/// Any error from the crate, either a client error from the api or a config error from flash.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Client(wmata::Error),
+    Config(ConfigError),
+}
+
+/// A `Result` with the crate's [`Error`].
+pub type Result<T, E = Error> = core::result::Result<T, E>;
+
+impl From<wmata::Error> for Error {
+    fn from(e: wmata::Error) -> Self {
+        Self::Client(e)
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(e: ConfigError) -> Self {
+        Self::Config(e)
+    }
+}
+
+impl core::fmt::Display for Error {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Error::Client(e) => write!(f, "client: {}", e),
+            Error::Config(e) => write!(f, "config: {}", e),
+        }
+    }
+}
+
+impl defmt::Format for Error {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Error::Client(e) => defmt::write!(f, "client: {}", e),
+            Error::Config(e) => defmt::write!(f, "config: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::{format, string::ToString};
+
+    use super::*;
+
+    fn load() -> Result<()> {
+        Err(ConfigError::Empty)?
+    }
+
+    fn fetch() -> Result<()> {
+        Err(wmata::Error::Timeout)?
+    }
+
+    #[test]
+    fn each_error_converts_and_says_where_it_came_from() {
+        let e = load().unwrap_err();
+        assert!(matches!(e, Error::Config(ConfigError::Empty)));
+        assert_eq!(e.to_string(), "config: no config saved");
+
+        let e = fetch().unwrap_err();
+        assert!(matches!(e, Error::Client(wmata::Error::Timeout)));
+        assert_eq!(format!("{e}"), "client: request timed out");
+
+        // defmt output can't be read back here, this only checks the umbrella logs like the rest
+        defmt::info!("{} {}", Error::from(ConfigError::BadArgs), e);
+    }
+}
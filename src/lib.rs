@@ -1,5 +1,18 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 
+pub mod alerts;
+pub mod console;
+mod error;
+pub mod picker;
+#[cfg(feature = "rail")]
+pub mod screen;
+#[cfg(feature = "selftest")]
+pub mod selftest;
+pub mod setup;
+pub mod status;
+pub mod supervisor;
 pub mod wmata;
 
 extern crate alloc;
+
+pub use crate::error::{Error, Result};
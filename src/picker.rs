@@ -0,0 +1,120 @@
+//! Choosing a station with a couple of buttons, for reconfiguring a device without serial.
+
+use crate::wmata::types::{LineKind, Station};
+
+/// Lines in the order the picker goes through them.
+const LINES: [LineKind; 6] = [
+    LineKind::RD,
+    LineKind::OR,
+    LineKind::SV,
+    LineKind::BL,
+    LineKind::YL,
+    LineKind::GN,
+];
+
+/// Scrolls through every station a line at a time, each line in track order, wrapping round
+/// from the last station of the last line to the first of the first, and back.
+///
+/// Stations on several lines come up once per line, so the same station can be picked from
+/// whichever line the rider thinks of it on. Debouncing the buttons is up to the caller.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct StationPicker {
+    line: usize,
+    station: usize,
+}
+
+impl StationPicker {
+    /// Start at the first station of the first line.
+    pub const fn new() -> Self {
+        Self {
+            line: 0,
+            station: 0,
+        }
+    }
+
+    /// Start at `station`, e.g. the one currently configured, on the first line serving it.
+    /// Falls back to [`StationPicker::new`] for a station that isn't on any line.
+    pub fn starting_at(station: Station) -> Self {
+        LINES
+            .iter()
+            .enumerate()
+            .find_map(|(line, kind)| {
+                let index = station.line_index(*kind)?;
+                Some(Self {
+                    line,
+                    station: index,
+                })
+            })
+            .unwrap_or_default()
+    }
+
+    pub fn next(&mut self) {
+        self.station += 1;
+        if self.station >= LINES[self.line].stations().len() {
+            self.line = (self.line + 1) % LINES.len();
+            self.station = 0;
+        }
+    }
+
+    pub fn prev(&mut self) {
+        if self.station == 0 {
+            self.line = (self.line + LINES.len() - 1) % LINES.len();
+            self.station = LINES[self.line].stations().len() - 1;
+        } else {
+            self.station -= 1;
+        }
+    }
+
+    /// The line and station to draw, e.g. the station's code in the line's color.
+    pub fn current(&self) -> (LineKind, Station) {
+        let line = LINES[self.line];
+        (line, line.stations()[self.station])
+    }
+
+    /// The station to keep. Save it with [`Config::with_station`](crate::wmata::Config::with_station).
+    pub fn select(&self) -> Station {
+        self.current().1
+    }
+}
+
+impl Default for StationPicker {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn scrolls_and_wraps_between_lines() {
+        let mut picker = StationPicker::new();
+        let red = LineKind::RD.stations();
+        assert_eq!(picker.current(), (LineKind::RD, red[0]));
+
+        picker.next();
+        assert_eq!(picker.select(), red[1]);
+        picker.prev();
+        picker.prev();
+        let green = LineKind::GN.stations();
+        assert_eq!(picker.current(), (LineKind::GN, green[green.len() - 1]));
+        picker.next();
+        assert_eq!(picker.current(), (LineKind::RD, red[0]));
+
+        for _ in 0..red.len() {
+            picker.next();
+        }
+        assert_eq!(picker.current(), (LineKind::OR, LineKind::OR.stations()[0]));
+    }
+
+    #[test]
+    fn starts_at_the_first_line_serving_the_station() {
+        let picker = StationPicker::starting_at(Station::K04);
+        assert_eq!(picker.current(), (LineKind::OR, Station::K04));
+        assert_eq!(
+            StationPicker::starting_at(Station::Unknown),
+            StationPicker::new()
+        );
+    }
+}
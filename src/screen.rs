@@ -0,0 +1,186 @@
+//! What the display should be showing, worked out by the fetch loop after every poll and handed
+//! to whatever draws it, so a failing api shows up as an error rather than frozen or blank rows.
+
+use core::fmt::Write;
+use core::net::Ipv4Addr;
+
+use embassy_time::{Duration, Instant};
+use heapless::String;
+
+use crate::setup;
+use crate::wmata::{Config, Error, types::NextTrainsResponse};
+
+/// The display's lifecycle: connecting until the first poll, then trains, going stale and then
+/// an error while fetches fail, and closed overnight. Or waiting to be set up, if it never was.
+pub enum DisplayState {
+    /// Nothing to show yet, e.g. waiting for Wi-Fi or the first response after boot.
+    Connecting,
+    /// Fresh predictions.
+    Trains(NextTrainsResponse),
+    /// The last fetch failed, but the last predictions are only `age` old, so they stay up with
+    /// a note of how old they are.
+    Stale { age: Duration },
+    /// Fetches have been failing for longer than [`DisplayState::STALE_LIMIT`], or nothing has
+    /// come through since boot, so the last predictions, if any, are too old to show.
+    Error(Error),
+    /// Outside service hours, with polling paused until just before service opens at this minute
+    /// of the day.
+    Closed { opens: u16 },
+    /// No Wi-Fi credentials or api key, neither saved in flash nor built in, so nothing to do
+    /// until the device is set up from the form served on its own network `ssid` at `ip`, see
+    /// [`setup`]. See [`DisplayState::at_boot`].
+    Setup { ssid: String<32>, ip: Ipv4Addr },
+}
+
+impl DisplayState {
+    /// How old predictions can get while fetches fail before they're taken down.
+    /// Trains move on a lot in two minutes, so past that a countdown does more harm than good.
+    pub const STALE_LIMIT: Duration = Duration::from_secs(120);
+
+    /// What to show at boot with the `config` [`Config::resolve`] picked: connecting, or without
+    /// one, how to set the device up from its setup network, named after the board's `mac`.
+    pub fn at_boot(config: Option<&Config>, mac: [u8; 6]) -> Self {
+        match config {
+            Some(_) => DisplayState::Connecting,
+            None => DisplayState::Setup {
+                ssid: setup::ap_ssid(mac),
+                ip: setup::SETUP_IP,
+            },
+        }
+    }
+
+    /// What to show after a poll with result `res`, given when the last one succeeded, `None` if
+    /// none has yet. Pass `Instant::now()` as `now`, or any other clock for testing.
+    pub fn after_fetch(
+        res: Result<NextTrainsResponse, Error>,
+        last_success: Option<Instant>,
+        now: Instant,
+    ) -> Self {
+        let e = match res {
+            Ok(trains) => return DisplayState::Trains(trains),
+            Err(e) => e,
+        };
+
+        match last_success.map(|at| now.saturating_duration_since(at)) {
+            Some(age) if age <= Self::STALE_LIMIT => DisplayState::Stale { age },
+            _ => DisplayState::Error(e),
+        }
+    }
+
+    /// A line of text for the state, to show instead of trains or, for
+    /// [`DisplayState::Stale`], below the last ones. Writes nothing for
+    /// [`DisplayState::Trains`], which draw themselves, see [`NextTrainsResponse::rows`].
+    /// Kept short enough for a 21 character row, apart from the error's own message.
+    pub fn write_status<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        match self {
+            DisplayState::Connecting => w.write_str("Connecting..."),
+            DisplayState::Trains(_) => Ok(()),
+            DisplayState::Stale { age } if age.as_secs() < 60 => {
+                write!(w, "Updated {}s ago", age.as_secs())
+            }
+            DisplayState::Stale { age } => write!(w, "Updated {} min ago", age.as_secs() / 60),
+            DisplayState::Error(Error::Unauthorized) => w.write_str("Bad API key"),
+            DisplayState::Error(Error::Status(code)) => write!(w, "No data: HTTP {code}"),
+            DisplayState::Error(e) => write!(w, "No data: {e}"),
+            DisplayState::Closed { opens } => {
+                write!(w, "Closed, opens {}:{:02}", opens / 60, opens % 60)
+            }
+            DisplayState::Setup { ssid, .. } => write!(w, "Join {ssid}"),
+        }
+    }
+
+    /// A second line for states that need one, under [`DisplayState::write_status`]'s. Only
+    /// [`DisplayState::Setup`] does, for the page to open once the network's joined. Writes
+    /// nothing for the rest.
+    pub fn write_detail<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        match self {
+            DisplayState::Setup { ip, .. } => write!(w, "then open {ip}"),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status(state: &DisplayState) -> String<32> {
+        let mut s = String::new();
+        state.write_status(&mut s).unwrap();
+        s
+    }
+
+    #[test]
+    fn fetch_outcomes_pick_the_state() {
+        let now = Instant::from_secs(1000);
+        let trains = NextTrainsResponse::new(alloc::vec::Vec::new());
+        assert!(matches!(
+            DisplayState::after_fetch(Ok(trains), None, now),
+            DisplayState::Trains(_)
+        ));
+
+        let stale = DisplayState::after_fetch(
+            Err(Error::Timeout),
+            Some(now - Duration::from_secs(45)),
+            now,
+        );
+        assert!(matches!(stale, DisplayState::Stale { .. }));
+        assert_eq!(status(&stale), "Updated 45s ago");
+        let stale = DisplayState::after_fetch(
+            Err(Error::Timeout),
+            Some(now - DisplayState::STALE_LIMIT),
+            now,
+        );
+        assert_eq!(status(&stale), "Updated 2 min ago");
+
+        let too_old = DisplayState::after_fetch(
+            Err(Error::Unauthorized),
+            Some(now - DisplayState::STALE_LIMIT - Duration::from_secs(1)),
+            now,
+        );
+        assert!(matches!(too_old, DisplayState::Error(Error::Unauthorized)));
+        assert_eq!(status(&too_old), "Bad API key");
+        let never = DisplayState::after_fetch(Err(Error::Status(503)), None, now);
+        assert_eq!(status(&never), "No data: HTTP 503");
+
+        assert_eq!(status(&DisplayState::Connecting), "Connecting...");
+        assert_eq!(
+            status(&DisplayState::Closed { opens: 5 * 60 }),
+            "Closed, opens 5:00"
+        );
+    }
+
+    #[test]
+    fn setup_is_shown_only_when_there_is_no_config() {
+        use crate::wmata::ConfigError;
+
+        let mac = [0x24, 0x0a, 0xc4, 0x12, 0x3f, 0x0a];
+        // nothing saved and nothing built in
+        let none = Config::resolve(&Err(ConfigError::Empty), || None);
+        let state = DisplayState::at_boot(none.as_ref(), mac);
+        assert!(matches!(
+            &state,
+            DisplayState::Setup { ssid, ip } if ssid == "WMATA-PIDS-3F0A" && *ip == setup::SETUP_IP
+        ));
+        assert_eq!(status(&state), "Join WMATA-PIDS-3F0A");
+        let mut detail: String<32> = String::new();
+        state.write_detail(&mut detail).unwrap();
+        assert_eq!(detail, "then open 192.168.4.1");
+        // both fit a 21 character row
+        assert!(status(&state).len() <= 21 && detail.len() <= 21);
+
+        let saved = Config::new("Home", "", "abc123").unwrap();
+        let some = Config::resolve(&Ok(saved), || None);
+        assert!(matches!(
+            DisplayState::at_boot(some.as_ref(), mac),
+            DisplayState::Connecting
+        ));
+        let built_in = Config::resolve(&Err(ConfigError::Empty), || {
+            Config::new("Home", "", "abc123").ok()
+        });
+        assert!(matches!(
+            DisplayState::at_boot(built_in.as_ref(), mac),
+            DisplayState::Connecting
+        ));
+    }
+}
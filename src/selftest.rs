@@ -0,0 +1,147 @@
+//! Boot-time checks, so a display that won't come up on-site can say why.
+
+use defmt::{error, info, warn};
+use embassy_net::Stack;
+use embassy_time::{Duration, Timer, with_timeout};
+use embedded_nal_async::{Dns, TcpConnect};
+
+use crate::alerts::Alerter;
+use crate::wmata::{Client, Config, ConfigError, Error};
+
+/// How long to wait for the station to associate and get an address.
+const NETWORK_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// A failed self-test check. The discriminant is the number of blinks in the alert code.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, defmt::Format)]
+#[non_exhaustive]
+pub enum Fault {
+    /// No link or no DHCP lease within `NETWORK_TIMEOUT`.
+    Network = 1,
+    /// The api rejected the key with a 401.
+    Unauthorized = 2,
+    /// Any other failure making an authenticated api request.
+    Api = 3,
+}
+
+/// Check the config sector, the Wi-Fi connection and the api key, reporting each over defmt.
+/// A missing flash config isn't a fault, since the build-time environment variables are used instead.
+/// Wi-Fi hardware init isn't checked here, `esp_radio::wifi::new` already panics with a message when it fails.
+pub async fn run<T, D>(
+    config: &Result<Config, ConfigError>,
+    stack: Stack<'_>,
+    client: &mut Client<'_, T, D>,
+) -> Result<(), Fault>
+where
+    T: TcpConnect,
+    D: Dns,
+{
+    info!("selftest: starting");
+
+    match config {
+        Ok(cfg) => info!("selftest: flash config ok (version {})", cfg.version()),
+        Err(e) => warn!("selftest: no valid flash config ({}), using env vars", e),
+    }
+
+    if with_timeout(NETWORK_TIMEOUT, stack.wait_config_up())
+        .await
+        .is_err()
+    {
+        error!("selftest: no network after {}s", NETWORK_TIMEOUT.as_secs());
+        return Err(Fault::Network);
+    }
+    info!("selftest: wifi connected");
+
+    check_api(client).await
+}
+
+/// Make one authenticated api request, telling a rejected key apart from any other failure.
+async fn check_api<T, D>(client: &mut Client<'_, T, D>) -> Result<(), Fault>
+where
+    T: TcpConnect,
+    D: Dns,
+{
+    match client.ping().await {
+        Ok(()) => {}
+        Err(Error::Unauthorized) => {
+            error!("selftest: api key rejected (401)");
+            return Err(Fault::Unauthorized);
+        }
+        Err(e) => {
+            error!("selftest: api request failed: {}", e);
+            return Err(Fault::Api);
+        }
+    }
+    info!("selftest: api key ok");
+
+    Ok(())
+}
+
+/// Blink `fault` on the alerter's disruption output, once per its discriminant.
+pub async fn blink<A: Alerter>(alerter: &mut A, fault: Fault) -> Result<(), A::Error> {
+    for _ in 0..fault as u8 {
+        alerter.disrupted()?;
+        Timer::after_millis(250).await;
+        alerter.clear()?;
+        Timer::after_millis(250).await;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::Infallible;
+
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::wmata::mock::{self, MockDns, MockNet};
+
+    /// Counts each alert it's asked for.
+    #[derive(Default)]
+    struct FakeAlerter {
+        disrupted: u8,
+        clears: u8,
+    }
+
+    impl Alerter for FakeAlerter {
+        type Error = Infallible;
+
+        fn arriving(&mut self) -> Result<(), Infallible> {
+            Ok(())
+        }
+
+        fn disrupted(&mut self) -> Result<(), Infallible> {
+            self.disrupted += 1;
+            Ok(())
+        }
+
+        fn clear(&mut self) -> Result<(), Infallible> {
+            self.clears += 1;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_failing_api_check_reports_its_fault() {
+        let net = MockNet::default();
+        net.reply_ok("{\"Lines\":[]}")
+            .reply("401 Access Denied", &[], "{\"statusCode\": 401}")
+            .refuse();
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        assert_eq!(block_on(check_api(&mut client)), Ok(()));
+        assert_eq!(block_on(check_api(&mut client)), Err(Fault::Unauthorized));
+        assert_eq!(block_on(check_api(&mut client)), Err(Fault::Api));
+    }
+
+    #[test]
+    fn a_fault_blinks_its_code() {
+        let mut alerter = FakeAlerter::default();
+        block_on(blink(&mut alerter, Fault::Unauthorized)).unwrap();
+
+        assert_eq!((alerter.disrupted, alerter.clears), (2, 2));
+    }
+}
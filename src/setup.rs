@@ -0,0 +1,415 @@
+//! Setting up a device that has no config from a phone or laptop, without reflashing it. The
+//! firmware opens a Wi-Fi network of its own, named by [`ap_ssid`], hands out addresses on it
+//! with a bare-bones DHCP server, see [`serve_dhcp`], and serves a form at [`SETUP_IP`] taking
+//! the Wi-Fi credentials and api key, see [`serve_form`].
+//!
+//! It's an open network, and the form is plain http, so what's typed into it can be read by
+//! anyone in range while the device is being set up.
+
+use core::fmt::Write as _;
+use core::net::Ipv4Addr;
+
+use embassy_net::Stack;
+use embassy_net::tcp::TcpSocket;
+use embassy_net::udp::{PacketMetadata, UdpSocket};
+use embassy_time::Duration;
+use heapless::{String, Vec};
+
+use crate::wmata::{API_KEY_MAX_LEN, Config, PASS_MAX_LEN, SSID_MAX_LEN};
+
+/// The device's own address on its setup network, and the page to open once joined.
+pub const SETUP_IP: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+/// The setup network is [`SETUP_IP`]`/24`.
+pub const SETUP_PREFIX_LEN: u8 = 24;
+/// Port the form is served on, plain http so it's just the address to type in.
+pub const SETUP_PORT: u16 = 80;
+
+const SSID_PREFIX: &str = "WMATA-PIDS-";
+const DHCP_SERVER_PORT: u16 = 67;
+const DHCP_CLIENT_PORT: u16 = 68;
+/// Seconds each address is handed out for, longer than setting up should ever take.
+const LEASE_SECS: u32 = 3600;
+/// Where a DHCP message's options start, after the fixed fields and the magic cookie.
+const DHCP_OPTIONS: usize = 240;
+const DHCP_COOKIE: [u8; 4] = [99, 130, 83, 99];
+/// Shortest reply, some clients drop anything under the old BOOTP size.
+const DHCP_REPLY_LEN: usize = 300;
+/// Longest request taken, headers and form together.
+const REQUEST_MAX_LEN: usize = 1024;
+
+/// Name of the setup network, the prefix and the last two bytes of the board's `mac` in hex,
+/// e.g. `WMATA-PIDS-3F0A`, so two boards being set up side by side can be told apart.
+pub fn ap_ssid(mac: [u8; 6]) -> String<32> {
+    let mut ssid = String::new();
+    // fits: 11 for the prefix and 4 for the hex
+    let _ = write!(ssid, "{SSID_PREFIX}{:02X}{:02X}", mac[4], mac[5]);
+    ssid
+}
+
+/// Answer a DHCP `request` from a client on the setup network served from `server`, writing the
+/// reply to `out` and returning its length. A DISCOVER gets an OFFER and a REQUEST an ACK, each
+/// for the address [`lease_for`] the client's hardware address. `None` for anything else, or
+/// anything that isn't a DHCP request from an Ethernet-style client, or an `out` too small.
+///
+/// There's no lease table: the address is worked out from the client every time, which is
+/// all a network that's up for a few minutes with one or two clients on it needs.
+pub fn dhcp_reply(request: &[u8], server: Ipv4Addr, out: &mut [u8]) -> Option<usize> {
+    // BOOTREQUEST, Ethernet, 6 byte addresses
+    if request.len() < DHCP_OPTIONS || request[..3] != [1, 1, 6] {
+        return None;
+    }
+    if request[236..DHCP_OPTIONS] != DHCP_COOKIE || out.len() < DHCP_REPLY_LEN {
+        return None;
+    }
+    let reply_type = match dhcp_option(&request[DHCP_OPTIONS..], 53) {
+        // DISCOVER, OFFER
+        Some([1]) => 2,
+        // REQUEST, ACK
+        Some([3]) => 5,
+        _ => return None,
+    };
+    let chaddr: [u8; 6] = request[28..34].try_into().ok()?;
+
+    let out = &mut out[..DHCP_REPLY_LEN];
+    out.fill(0);
+    // BOOTREPLY, Ethernet, 6 byte addresses
+    out[..3].copy_from_slice(&[2, 1, 6]);
+    // transaction id and flags, the broadcast bit in particular
+    out[4..8].copy_from_slice(&request[4..8]);
+    out[10..12].copy_from_slice(&request[10..12]);
+    out[16..20].copy_from_slice(&lease_for(chaddr, server).octets());
+    out[20..24].copy_from_slice(&server.octets());
+    out[28..44].copy_from_slice(&request[28..44]);
+    out[236..DHCP_OPTIONS].copy_from_slice(&DHCP_COOKIE);
+
+    let server = server.octets();
+    let mask = u32::MAX << (32 - SETUP_PREFIX_LEN);
+    let mut options = Vec::<u8, { DHCP_REPLY_LEN - DHCP_OPTIONS }>::new();
+    for (code, value) in [
+        (53, &[reply_type][..]),
+        (54, &server[..]),
+        (51, &LEASE_SECS.to_be_bytes()[..]),
+        (1, &mask.to_be_bytes()[..]),
+        (3, &server[..]),
+    ] {
+        options.push(code).ok()?;
+        options.push(value.len() as u8).ok()?;
+        options.extend_from_slice(value).ok()?;
+    }
+    options.push(255).ok()?;
+    out[DHCP_OPTIONS..DHCP_OPTIONS + options.len()].copy_from_slice(&options);
+
+    Some(DHCP_REPLY_LEN)
+}
+
+/// The address handed to the client with hardware address `chaddr`, one of 200 past `server`'s
+/// own picked by the last byte. Two clients can land on the same one, which setting up one device
+/// from one phone doesn't run into.
+pub fn lease_for(chaddr: [u8; 6], server: Ipv4Addr) -> Ipv4Addr {
+    let [a, b, c, _] = server.octets();
+    Ipv4Addr::new(a, b, c, 2 + chaddr[5] % 200)
+}
+
+/// The value of option `code` among DHCP `options`, `None` if it isn't there.
+fn dhcp_option(mut options: &[u8], code: u8) -> Option<&[u8]> {
+    loop {
+        match options {
+            [] | [255, ..] => return None,
+            [0, rest @ ..] => options = rest,
+            [found, len, rest @ ..] => {
+                let value = rest.get(..*len as usize)?;
+                if *found == code {
+                    return Some(value);
+                }
+                options = &rest[*len as usize..];
+            }
+            [_] => return None,
+        }
+    }
+}
+
+/// The body of an http `request` once all of it has come in, going by its `Content-Length`,
+/// or an empty one without. `None` while the headers or the body are still arriving.
+pub fn request_body(request: &[u8]) -> Option<&[u8]> {
+    let end = request.windows(4).position(|w| w == b"\r\n\r\n")?;
+    let headers = core::str::from_utf8(&request[..end]).ok()?;
+    let body = &request[end + 4..];
+
+    let len = headers
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.trim().eq_ignore_ascii_case("content-length"))
+        .map_or(Some(0), |(_, len)| len.trim().parse().ok())?;
+    body.get(..len)
+}
+
+/// A config from the form [`serve_form`] serves, urlencoded as browsers post it, `None` without
+/// an SSID or an api key, or with anything too long for the config. The password can be left
+/// blank for an open network.
+pub fn parse_form(body: &[u8]) -> Option<Config> {
+    let mut ssid = None;
+    let mut pass = None;
+    let mut key = None;
+
+    for field in body.split(|&b| b == b'&') {
+        let mut parts = field.splitn(2, |&b| b == b'=');
+        let (Some(name), Some(value)) = (parts.next(), parts.next()) else {
+            continue;
+        };
+        match name {
+            b"ssid" => ssid = Some(url_decode::<SSID_MAX_LEN>(value)?),
+            b"pass" => pass = Some(url_decode::<PASS_MAX_LEN>(value)?),
+            b"key" => key = Some(url_decode::<API_KEY_MAX_LEN>(value)?),
+            _ => {}
+        }
+    }
+
+    let (ssid, key) = (ssid?, key?);
+    let (ssid, key) = (ssid.trim(), key.trim());
+    if ssid.is_empty() || key.is_empty() {
+        return None;
+    }
+    Config::new(ssid, pass.as_deref().unwrap_or(""), key).ok()
+}
+
+/// `value` with the `+`s and `%XX` escapes of a urlencoded form undone, `None` if it's malformed,
+/// not UTF-8 or longer than `N`.
+fn url_decode<const N: usize>(value: &[u8]) -> Option<String<N>> {
+    let hex = |b: u8| (b as char).to_digit(16);
+    let mut bytes = Vec::<u8, N>::new();
+    let mut rest = value;
+
+    while let [b, tail @ ..] = rest {
+        let (byte, tail) = match (b, tail) {
+            (b'+', _) => (b' ', tail),
+            (b'%', [hi, lo, tail @ ..]) => ((hex(*hi)? * 16 + hex(*lo)?) as u8, tail),
+            (b'%', _) => return None,
+            _ => (*b, tail),
+        };
+        bytes.push(byte).ok()?;
+        rest = tail;
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// Answer DHCP on the setup network brought up on `stack` at [`SETUP_IP`], see [`dhcp_reply`].
+/// Replies are broadcast, since the client has no address to send them to yet.
+pub async fn serve_dhcp(stack: Stack<'_>) -> ! {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buf = [0u8; 576];
+    let mut tx_buf = [0u8; 576];
+    let mut socket = UdpSocket::new(stack, &mut rx_meta, &mut rx_buf, &mut tx_meta, &mut tx_buf);
+    if let Err(e) = socket.bind(DHCP_SERVER_PORT) {
+        defmt::panic!("setup: couldn't listen for DHCP: {:?}", e);
+    }
+
+    let mut request = [0u8; 576];
+    let mut reply = [0u8; DHCP_REPLY_LEN];
+    loop {
+        let n = match socket.recv_from(&mut request).await {
+            Ok((n, _)) => n,
+            Err(e) => {
+                defmt::warn!("setup: DHCP receive failed: {:?}", e);
+                continue;
+            }
+        };
+        let Some(len) = dhcp_reply(&request[..n], SETUP_IP, &mut reply) else {
+            continue;
+        };
+        let to = (Ipv4Addr::BROADCAST, DHCP_CLIENT_PORT);
+        if let Err(e) = socket.send_to(&reply[..len], to).await {
+            defmt::warn!("setup: DHCP reply failed: {:?}", e);
+        }
+    }
+}
+
+/// Serve the setup form on [`SETUP_PORT`] of `stack` to whoever asks, one connection at a time,
+/// until one posts a config [`parse_form`] takes, and return it for the caller to save. Any other
+/// request gets the form, so whatever page a phone opens on joining shows it.
+pub async fn serve_form(stack: Stack<'_>) -> Config {
+    let mut rx_buf = [0u8; 1024];
+    let mut tx_buf = [0u8; 1024];
+    let mut request = [0u8; REQUEST_MAX_LEN];
+
+    loop {
+        let mut socket = TcpSocket::new(stack, &mut rx_buf, &mut tx_buf);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(SETUP_PORT).await {
+            defmt::warn!("setup: accept failed: {:?}", e);
+            continue;
+        }
+
+        let mut len = 0;
+        let body = loop {
+            match socket.read(&mut request[len..]).await {
+                Ok(0) | Err(_) => break None,
+                Ok(n) => len += n,
+            }
+            if let Some(body) = request_body(&request[..len]) {
+                break Some(body);
+            }
+            if len == request.len() {
+                break None;
+            }
+        };
+
+        let posted = request.starts_with(b"POST ");
+        let config = body.filter(|_| posted).and_then(parse_form);
+        let page = match (&config, posted) {
+            (Some(_), _) => SAVED_PAGE,
+            (None, true) => RETRY_PAGE,
+            (None, false) => FORM_PAGE,
+        };
+        for part in [
+            "HTTP/1.1 200 OK\r\nContent-Type: text/html\r\nConnection: close\r\n\r\n",
+            page,
+        ] {
+            if let Err(e) = write_all(&mut socket, part.as_bytes()).await {
+                defmt::warn!("setup: write failed: {:?}", e);
+                break;
+            }
+        }
+        let _ = socket.flush().await;
+        socket.close();
+
+        if let Some(config) = config {
+            return config;
+        }
+    }
+}
+
+async fn write_all(
+    socket: &mut TcpSocket<'_>,
+    mut buf: &[u8],
+) -> Result<(), embassy_net::tcp::Error> {
+    while !buf.is_empty() {
+        let n = socket.write(buf).await?;
+        buf = &buf[n..];
+    }
+    Ok(())
+}
+
+const FORM: &str = "<form method=post>\
+    <p><label>Wi-Fi network <input name=ssid required maxlength=32></label>\
+    <p><label>Wi-Fi password <input name=pass type=password maxlength=64></label>\
+    <p><label>WMATA api key <input name=key required maxlength=32></label>\
+    <p><button>Save</button></form>";
+
+const FORM_PAGE: &str = const_format::concatcp!(
+    "<!doctype html><meta name=viewport content=\"width=device-width\">\
+    <title>Set up</title><h1>Set up the display</h1>",
+    FORM
+);
+
+const RETRY_PAGE: &str = const_format::concatcp!(
+    "<!doctype html><meta name=viewport content=\"width=device-width\">\
+    <title>Set up</title><h1>Set up the display</h1>\
+    <p>The network and api key are needed, and can't be too long. Try again.",
+    FORM
+);
+
+const SAVED_PAGE: &str = "<!doctype html><meta name=viewport content=\"width=device-width\">\
+    <title>Saved</title><h1>Saved</h1>\
+    <p>The display is restarting to join your network. This one will go away.";
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A DHCP request of `kind`, 1 for DISCOVER or 3 for REQUEST, as a phone would send it.
+    fn dhcp_request(kind: u8) -> alloc::vec::Vec<u8> {
+        let mut request = alloc::vec![0u8; DHCP_OPTIONS];
+        request[..3].copy_from_slice(&[1, 1, 6]);
+        request[4..8].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        // broadcast the reply
+        request[10] = 0x80;
+        request[28..34].copy_from_slice(&[0x02, 0, 0, 0, 0, 205]);
+        request[236..].copy_from_slice(&DHCP_COOKIE);
+        // padding before the message type, then a parameter request list
+        request.extend_from_slice(&[0, 53, 1, kind, 55, 2, 1, 3, 255]);
+        request
+    }
+
+    #[test]
+    fn the_network_is_named_after_the_board() {
+        assert_eq!(
+            ap_ssid([0x24, 0x0a, 0xc4, 0x12, 0x3f, 0x0a]),
+            "WMATA-PIDS-3F0A"
+        );
+    }
+
+    #[test]
+    fn discover_gets_an_offer_and_request_an_ack() {
+        let mut reply = [0u8; 512];
+        for (kind, reply_type) in [(1, 2), (3, 5)] {
+            let len = dhcp_reply(&dhcp_request(kind), SETUP_IP, &mut reply).unwrap();
+            let reply = &reply[..len];
+
+            assert_eq!(reply[0], 2);
+            assert_eq!(reply[4..8], [0xde, 0xad, 0xbe, 0xef]);
+            assert_eq!(reply[10], 0x80);
+            // 205 % 200 past the first two
+            assert_eq!(reply[16..20], [192, 168, 4, 7]);
+            assert_eq!(reply[28..34], [0x02, 0, 0, 0, 0, 205]);
+            let options = &reply[DHCP_OPTIONS..];
+            assert_eq!(dhcp_option(options, 53), Some(&[reply_type][..]));
+            assert_eq!(dhcp_option(options, 54), Some(&[192, 168, 4, 1][..]));
+            assert_eq!(dhcp_option(options, 1), Some(&[255, 255, 255, 0][..]));
+            assert_eq!(dhcp_option(options, 3), Some(&[192, 168, 4, 1][..]));
+        }
+    }
+
+    #[test]
+    fn anything_but_discover_or_request_is_ignored() {
+        let mut reply = [0u8; 512];
+        // RELEASE
+        assert_eq!(dhcp_reply(&dhcp_request(7), SETUP_IP, &mut reply), None);
+        let mut reply_from_a_server = dhcp_request(1);
+        reply_from_a_server[0] = 2;
+        assert_eq!(dhcp_reply(&reply_from_a_server, SETUP_IP, &mut reply), None);
+        assert_eq!(
+            dhcp_reply(&dhcp_request(1)[..200], SETUP_IP, &mut reply),
+            None
+        );
+        assert_eq!(
+            dhcp_reply(&dhcp_request(1), SETUP_IP, &mut [0u8; 100]),
+            None
+        );
+    }
+
+    #[test]
+    fn the_body_is_there_once_all_of_it_has_come_in() {
+        let request = b"POST / HTTP/1.1\r\nHost: 192.168.4.1\r\ncontent-length: 9\r\n\r\nssid=Home";
+        assert_eq!(request_body(request), Some(&b"ssid=Home"[..]));
+        assert_eq!(request_body(&request[..request.len() - 1]), None);
+        assert_eq!(
+            request_body(b"GET / HTTP/1.1\r\nHost: 192.168.4.1\r\n"),
+            None
+        );
+        assert_eq!(
+            request_body(b"GET / HTTP/1.1\r\nHost: 192.168.4.1\r\n\r\n"),
+            Some(&b""[..])
+        );
+    }
+
+    #[test]
+    fn the_form_decodes_into_a_config() {
+        let config = parse_form(b"ssid=Home+Wi-Fi&pass=p%40ss%2Bword&key=abc123").unwrap();
+        assert_eq!(
+            (config.ssid(), config.pass(), config.api_key()),
+            ("Home Wi-Fi", "p@ss+word", "abc123")
+        );
+        // an open network
+        let config = parse_form(b"ssid=Cafe&pass=&key=abc123").unwrap();
+        assert_eq!(config.pass(), "");
+
+        assert!(parse_form(b"ssid=&pass=x&key=abc123").is_none());
+        assert!(parse_form(b"ssid=Home&pass=x").is_none());
+        assert!(parse_form(b"ssid=Home&key=abc%2").is_none());
+        let long_key = [b'k'; API_KEY_MAX_LEN + 1];
+        let mut form = b"ssid=Home&key=".to_vec();
+        form.extend_from_slice(&long_key);
+        assert!(parse_form(&form).is_none());
+    }
+}
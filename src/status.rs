@@ -0,0 +1,580 @@
+//! Device health, collected from the tasks and reported as json, e.g. by a status page on the LAN.
+
+use core::cell::{Cell, RefCell};
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::{CriticalSectionMutex, Mutex};
+use embassy_time::Instant;
+use heapless::{String, Vec};
+
+use crate::wmata::json::write_json_str;
+
+/// Firmware version reported in the status json.
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// What firmware is running, for telling the devices of a fleet apart, see [`build_info`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct BuildInfo {
+    /// Same as [`VERSION`].
+    pub version: &'static str,
+    /// Short hash of the commit it was built from, `None` when it wasn't built from a git
+    /// checkout. Set by `build.rs`.
+    pub git_hash: Option<&'static str>,
+    /// Date and time it was built as the app descriptor has them, e.g. `Oct 16 2026` and
+    /// `14:02:11`. Only the firmware has its descriptor, see [`BuildInfo::with_built`].
+    pub built: Option<(&'static str, &'static str)>,
+}
+
+impl BuildInfo {
+    /// Add the build date and time, from the firmware's `esp_app_desc!` data.
+    pub const fn with_built(mut self, date: &'static str, time: &'static str) -> Self {
+        self.built = Some((date, time));
+        self
+    }
+}
+
+/// The version and git hash compiled into this build.
+pub const fn build_info() -> BuildInfo {
+    BuildInfo {
+        version: VERSION,
+        git_hash: option_env!("GIT_HASH"),
+        built: None,
+    }
+}
+
+/// Shared health counters. Meant to live in a `static`, written by the fetch loop and Wi-Fi task.
+pub struct Status {
+    inner: CriticalSectionMutex<Cell<Inner>>,
+    scan: CriticalSectionMutex<RefCell<Vec<ScanEntry, { Status::MAX_SCAN_ENTRIES }>>>,
+    disconnects: CriticalSectionMutex<RefCell<Disconnects>>,
+}
+
+/// How many times the AP dropped us for one reason, see [`Status::record_disconnect`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct DisconnectCount {
+    /// The 802.11 reason code, or one of Espressif's from 200 up, e.g. 200 for a lost beacon.
+    pub reason: u8,
+    pub count: u32,
+}
+
+impl DisconnectCount {
+    /// Short name of the reason for the status page, `None` for ones without a name here.
+    pub fn name(&self) -> Option<&'static str> {
+        match self.reason {
+            1 => Some("unspecified"),
+            2 => Some("auth_expire"),
+            3 => Some("auth_leave"),
+            4 => Some("assoc_expire"),
+            8 => Some("assoc_leave"),
+            15 => Some("handshake_timeout"),
+            200 => Some("beacon_timeout"),
+            201 => Some("no_ap_found"),
+            202 => Some("auth_fail"),
+            203 => Some("assoc_fail"),
+            204 => Some("handshake_timeout"),
+            205 => Some("connection_fail"),
+            _ => None,
+        }
+    }
+}
+
+/// Counts for the first [`Status::MAX_DISCONNECT_REASONS`] reasons seen, and any after that
+/// lumped together.
+struct Disconnects {
+    counts: Vec<DisconnectCount, { Status::MAX_DISCONNECT_REASONS }>,
+    other: u32,
+}
+
+/// One access point from the last Wi-Fi scan, see [`Status::record_scan`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ScanEntry {
+    pub ssid: String<32>,
+    /// Signal strength in dBm.
+    pub rssi: i8,
+    pub channel: u8,
+    /// e.g. `"WPA2"`, `None` if the driver didn't say.
+    pub auth: Option<&'static str>,
+}
+
+/// Why the chip last reset, from the ESP32's reset reason register, to tell a crash or a power
+/// problem in the field from an ordinary reboot.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum ResetCause {
+    /// Power was applied, or the reset pin was pulled.
+    PowerOn,
+    /// The firmware asked for it, e.g. after a panic or a config change that needs a reboot.
+    Software,
+    /// Woke from deep sleep, which is a reset like any other.
+    DeepSleep,
+    /// A watchdog fired, i.e. something hung.
+    Watchdog,
+    /// The supply voltage dipped too low, e.g. a weak USB supply while Wi-Fi transmits.
+    Brownout,
+    /// Any other reason, with the register's raw value.
+    Other(u8),
+}
+
+impl ResetCause {
+    /// From the raw reason, as in esp-hal's `SocResetReason` for the ESP32, e.g.
+    /// `reset_reason(Cpu::ProCpu).map_or(0, |r| r as u8)`.
+    pub fn from_code(code: u8) -> Self {
+        match code {
+            0x01 => ResetCause::PowerOn,
+            0x03 | 0x0C => ResetCause::Software,
+            0x05 => ResetCause::DeepSleep,
+            0x07..=0x09 | 0x0B | 0x0D | 0x10 => ResetCause::Watchdog,
+            0x0F => ResetCause::Brownout,
+            code => ResetCause::Other(code),
+        }
+    }
+
+    /// Lowercase name, as it appears in the status json.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResetCause::PowerOn => "power_on",
+            ResetCause::Software => "software",
+            ResetCause::DeepSleep => "deep_sleep",
+            ResetCause::Watchdog => "watchdog",
+            ResetCause::Brownout => "brownout",
+            ResetCause::Other(_) => "other",
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Inner {
+    rssi: Option<i8>,
+    last_fetch: Option<Instant>,
+    fetches: u32,
+    fetch_errors: u32,
+    reset_cause: Option<ResetCause>,
+    power_on_secs: Option<u32>,
+    build: BuildInfo,
+}
+
+impl Status {
+    /// Access points kept from a scan, the strongest ones.
+    pub const MAX_SCAN_ENTRIES: usize = 10;
+    /// Distinct disconnect reasons counted separately. A flaky deployment tends to have one or
+    /// two, so this is plenty.
+    pub const MAX_DISCONNECT_REASONS: usize = 8;
+
+    pub const fn new() -> Self {
+        Self {
+            inner: Mutex::new(Cell::new(Inner {
+                rssi: None,
+                last_fetch: None,
+                fetches: 0,
+                fetch_errors: 0,
+                reset_cause: None,
+                power_on_secs: None,
+                build: build_info(),
+            })),
+            scan: Mutex::new(RefCell::new(Vec::new())),
+            disconnects: Mutex::new(RefCell::new(Disconnects {
+                counts: Vec::new(),
+                other: 0,
+            })),
+        }
+    }
+
+    fn update(&self, f: impl FnOnce(&mut Inner)) {
+        self.inner.lock(|inner| {
+            let mut value = inner.get();
+            f(&mut value);
+            inner.set(value);
+        });
+    }
+
+    /// Signal strength of the current AP in dBm, `None` while disconnected.
+    pub fn set_rssi(&self, rssi: Option<i8>) {
+        self.update(|inner| inner.rssi = rssi);
+    }
+
+    pub fn rssi(&self) -> Option<i8> {
+        self.inner.lock(|inner| inner.get().rssi)
+    }
+
+    /// Count one attempt at fetching predictions.
+    pub fn record_fetch(&self, ok: bool) {
+        self.update(|inner| {
+            inner.fetches = inner.fetches.saturating_add(1);
+            if ok {
+                inner.last_fetch = Some(Instant::now());
+            } else {
+                inner.fetch_errors = inner.fetch_errors.saturating_add(1);
+            }
+        });
+    }
+
+    /// Why the chip last reset, read once at boot.
+    pub fn set_reset_cause(&self, cause: ResetCause) {
+        self.update(|inner| inner.reset_cause = Some(cause));
+    }
+
+    /// What's running, [`build_info`] until the firmware adds its build time.
+    pub fn set_build_info(&self, build: BuildInfo) {
+        self.update(|inner| inner.build = build);
+    }
+
+    /// Total time powered on across reboots, see
+    /// [`PowerOnHours`](crate::wmata::PowerOnHours::total_secs).
+    pub fn set_power_on_secs(&self, secs: u32) {
+        self.update(|inner| inner.power_on_secs = Some(secs));
+    }
+
+    /// Replace the last scan with `entries`, keeping the [`Status::MAX_SCAN_ENTRIES`] strongest,
+    /// strongest first.
+    pub fn record_scan(&self, entries: impl IntoIterator<Item = ScanEntry>) {
+        let mut kept: Vec<ScanEntry, { Self::MAX_SCAN_ENTRIES }> = Vec::new();
+        for entry in entries {
+            if let Err(entry) = kept.push(entry) {
+                // full, so it only goes in instead of the weakest
+                if let Some(weakest) = kept.iter_mut().min_by_key(|kept| kept.rssi)
+                    && weakest.rssi < entry.rssi
+                {
+                    *weakest = entry;
+                }
+            }
+        }
+        kept.sort_unstable_by_key(|entry| core::cmp::Reverse(entry.rssi));
+
+        self.scan.lock(|scan| *scan.borrow_mut() = kept);
+    }
+
+    /// The last scan's access points, strongest first. Empty before the first scan.
+    pub fn scan(&self) -> Vec<ScanEntry, { Self::MAX_SCAN_ENTRIES }> {
+        self.scan.lock(|scan| scan.borrow().clone())
+    }
+
+    /// Count one disconnect from the AP for `reason`, as the driver's `StationDisconnected`
+    /// event gives it, to tell auth failures from lost beacons and the like.
+    pub fn record_disconnect(&self, reason: u8) {
+        self.disconnects.lock(|disconnects| {
+            let mut disconnects = disconnects.borrow_mut();
+            if let Some(counted) = disconnects.counts.iter_mut().find(|c| c.reason == reason) {
+                counted.count = counted.count.saturating_add(1);
+            } else if disconnects
+                .counts
+                .push(DisconnectCount { reason, count: 1 })
+                .is_err()
+            {
+                disconnects.other = disconnects.other.saturating_add(1);
+            }
+        });
+    }
+
+    /// Disconnects per reason since boot, in the order each reason was first seen, and how many
+    /// more there were for reasons past [`Status::MAX_DISCONNECT_REASONS`].
+    pub fn disconnects(&self) -> (Vec<DisconnectCount, { Self::MAX_DISCONNECT_REASONS }>, u32) {
+        self.disconnects.lock(|disconnects| {
+            let disconnects = disconnects.borrow();
+            (disconnects.counts.clone(), disconnects.other)
+        })
+    }
+
+    /// Write the status as a json object, starting with `version`, `git_hash` and `built` from
+    /// [`Status::set_build_info`]. There's no wall clock, so times are relative to now:
+    /// `uptime_secs` since boot and `last_fetch_secs_ago` since the last successful fetch.
+    /// `scan` lists the access points from the last Wi-Fi scan, see [`Status::record_scan`].
+    /// `reset_cause` is a [`ResetCause::name`] and `power_on_hours` whole hours, both `null` until
+    /// they're set. `disconnects` counts them per reason, see [`Status::record_disconnect`], with
+    /// `disconnects_other` for the rest.
+    pub fn write_json<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        let inner = self.inner.lock(|inner| inner.get());
+
+        write!(w, "{{\"version\":\"{}\"", inner.build.version)?;
+        match inner.build.git_hash {
+            Some(hash) => write!(w, ",\"git_hash\":\"{hash}\"")?,
+            None => write!(w, ",\"git_hash\":null")?,
+        }
+        match inner.build.built {
+            Some((date, time)) => write!(w, ",\"built\":\"{date} {time}\"")?,
+            None => write!(w, ",\"built\":null")?,
+        }
+        write!(w, ",\"uptime_secs\":{}", Instant::now().as_secs())?;
+        match inner.rssi {
+            Some(rssi) => write!(w, ",\"rssi\":{rssi}")?,
+            None => write!(w, ",\"rssi\":null")?,
+        }
+        match inner.last_fetch {
+            Some(at) => write!(w, ",\"last_fetch_secs_ago\":{}", at.elapsed().as_secs())?,
+            None => write!(w, ",\"last_fetch_secs_ago\":null")?,
+        }
+        write!(w, ",\"fetches\":{}", inner.fetches)?;
+        write!(w, ",\"fetch_errors\":{}", inner.fetch_errors)?;
+        match inner.reset_cause {
+            Some(cause) => write!(w, ",\"reset_cause\":\"{}\"", cause.name())?,
+            None => write!(w, ",\"reset_cause\":null")?,
+        }
+        match inner.power_on_secs {
+            Some(secs) => write!(w, ",\"power_on_hours\":{}", secs / (60 * 60))?,
+            None => write!(w, ",\"power_on_hours\":null")?,
+        }
+
+        write!(w, ",\"scan\":[")?;
+        for (i, entry) in self.scan().iter().enumerate() {
+            if i > 0 {
+                w.write_char(',')?;
+            }
+            w.write_str("{\"ssid\":")?;
+            write_json_str(w, &entry.ssid)?;
+            write!(w, ",\"rssi\":{},\"channel\":{}", entry.rssi, entry.channel)?;
+            match entry.auth {
+                Some(auth) => write!(w, ",\"auth\":\"{auth}\"}}")?,
+                None => write!(w, ",\"auth\":null}}")?,
+            }
+        }
+        w.write_char(']')?;
+
+        let (disconnects, other) = self.disconnects();
+        write!(w, ",\"disconnects\":[")?;
+        for (i, counted) in disconnects.iter().enumerate() {
+            if i > 0 {
+                w.write_char(',')?;
+            }
+            write!(w, "{{\"reason\":{}", counted.reason)?;
+            match counted.name() {
+                Some(name) => write!(w, ",\"name\":\"{name}\"")?,
+                None => write!(w, ",\"name\":null")?,
+            }
+            write!(w, ",\"count\":{}}}", counted.count)?;
+        }
+        write!(w, "],\"disconnects_other\":{other}}}")
+    }
+}
+
+#[cfg(feature = "metrics")]
+impl Status {
+    /// Write the counters as Prometheus text, e.g. for a `/metrics` route. `free_heap` is in
+    /// bytes, sampled by the caller since the allocator lives in the firmware.
+    /// `wifi_disconnects` counts every reason, see [`Status::record_disconnect`].
+    pub fn write_metrics<W: Write>(&self, w: &mut W, free_heap: usize) -> core::fmt::Result {
+        let inner = self.inner.lock(|inner| inner.get());
+        let (disconnects, other) = self.disconnects();
+        let disconnects = disconnects
+            .iter()
+            .fold(other, |total, counted| total.saturating_add(counted.count));
+
+        write_metric(
+            w,
+            "fetch_total",
+            "counter",
+            "Prediction fetches attempted.",
+            inner.fetches.into(),
+        )?;
+        write_metric(
+            w,
+            "fetch_errors",
+            "counter",
+            "Prediction fetches that failed.",
+            inner.fetch_errors.into(),
+        )?;
+        write_metric(
+            w,
+            "wifi_disconnects",
+            "counter",
+            "Times the access point dropped the connection.",
+            disconnects.into(),
+        )?;
+        write_metric(
+            w,
+            "uptime_seconds",
+            "counter",
+            "Seconds since boot.",
+            Instant::now().as_secs(),
+        )?;
+        write_metric(
+            w,
+            "free_heap",
+            "gauge",
+            "Free heap in bytes.",
+            free_heap as u64,
+        )
+    }
+}
+
+/// One metric with its `# HELP` and `# TYPE` lines.
+#[cfg(feature = "metrics")]
+fn write_metric<W: Write>(
+    w: &mut W,
+    name: &str,
+    kind: &str,
+    help: &str,
+    value: u64,
+) -> core::fmt::Result {
+    write!(
+        w,
+        "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}\n"
+    )
+}
+
+impl Default for Status {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn access_point(n: u8, rssi: i8) -> ScanEntry {
+        let mut ssid = String::new();
+        write!(ssid, "ap{n}").unwrap();
+        ScanEntry {
+            ssid,
+            rssi,
+            channel: 1 + n % 11,
+            auth: Some("WPA2"),
+        }
+    }
+
+    #[test]
+    fn scan_keeps_the_strongest() {
+        let status = Status::new();
+        assert!(status.scan().is_empty());
+
+        let rssi = [
+            -90, -35, -80, -60, -71, -45, -88, -52, -67, -30, -75, -58, -83, -40,
+        ];
+        status.record_scan(
+            rssi.iter()
+                .enumerate()
+                .map(|(n, rssi)| access_point(n as u8, *rssi)),
+        );
+
+        let kept: alloc::vec::Vec<i8> = status.scan().iter().map(|entry| entry.rssi).collect();
+        assert_eq!(kept, [-30, -35, -40, -45, -52, -58, -60, -67, -71, -75]);
+
+        // a new scan replaces the old one
+        status.record_scan([access_point(0, -50)]);
+        assert_eq!(status.scan(), [access_point(0, -50)]);
+    }
+
+    #[test]
+    fn reset_cause_and_hours_reach_the_json() {
+        assert_eq!(ResetCause::from_code(0x01), ResetCause::PowerOn);
+        assert_eq!(ResetCause::from_code(0x0C), ResetCause::Software);
+        assert_eq!(ResetCause::from_code(0x10), ResetCause::Watchdog);
+        assert_eq!(ResetCause::from_code(0x0F), ResetCause::Brownout);
+        assert_eq!(ResetCause::from_code(0x2A), ResetCause::Other(0x2A));
+
+        let status = Status::new();
+        let mut json: String<1024> = String::new();
+        status.write_json(&mut json).unwrap();
+        assert!(json.contains(",\"reset_cause\":null,\"power_on_hours\":null"));
+
+        status.set_reset_cause(ResetCause::from_code(0x0F));
+        status.set_power_on_secs(3 * 3600 - 1);
+        json.clear();
+        status.write_json(&mut json).unwrap();
+        assert!(json.contains(",\"reset_cause\":\"brownout\",\"power_on_hours\":2"));
+    }
+
+    #[test]
+    fn disconnects_are_counted_per_reason() {
+        let status = Status::new();
+        for reason in [200, 202, 200, 15, 200] {
+            status.record_disconnect(reason);
+        }
+        let (counts, other) = status.disconnects();
+        assert_eq!(
+            counts,
+            [
+                DisconnectCount {
+                    reason: 200,
+                    count: 3
+                },
+                DisconnectCount {
+                    reason: 202,
+                    count: 1
+                },
+                DisconnectCount {
+                    reason: 15,
+                    count: 1
+                },
+            ]
+        );
+        assert_eq!(other, 0);
+
+        // reasons past the limit are lumped together, a known one still counts as its own
+        for reason in 100..100 + Status::MAX_DISCONNECT_REASONS as u8 {
+            status.record_disconnect(reason);
+        }
+        status.record_disconnect(202);
+        let (counts, other) = status.disconnects();
+        assert_eq!(counts.len(), Status::MAX_DISCONNECT_REASONS);
+        assert_eq!(
+            counts[1],
+            DisconnectCount {
+                reason: 202,
+                count: 2
+            }
+        );
+        assert_eq!(other, 3);
+
+        let mut json: String<1024> = String::new();
+        status.write_json(&mut json).unwrap();
+        assert!(json.contains(
+            ",\"disconnects\":[{\"reason\":200,\"name\":\"beacon_timeout\",\"count\":3},"
+        ));
+        assert!(json.contains("{\"reason\":100,\"name\":null,\"count\":1}"));
+        assert!(json.ends_with("],\"disconnects_other\":3}"));
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn metrics_are_prometheus_text() {
+        let status = Status::new();
+        status.record_fetch(true);
+        status.record_fetch(false);
+        status.record_fetch(true);
+        status.record_disconnect(200);
+        status.record_disconnect(202);
+        status.record_disconnect(200);
+
+        let mut text: String<1024> = String::new();
+        status.write_metrics(&mut text, 81920).unwrap();
+        assert!(text.starts_with(
+            "# HELP fetch_total Prediction fetches attempted.\n\
+             # TYPE fetch_total counter\n\
+             fetch_total 3\n\
+             # HELP fetch_errors Prediction fetches that failed.\n\
+             # TYPE fetch_errors counter\n\
+             fetch_errors 1\n\
+             # HELP wifi_disconnects Times the access point dropped the connection.\n\
+             # TYPE wifi_disconnects counter\n\
+             wifi_disconnects 3\n\
+             # HELP uptime_seconds Seconds since boot.\n\
+             # TYPE uptime_seconds counter\n\
+             uptime_seconds "
+        ));
+        assert!(text.ends_with(
+            "# HELP free_heap Free heap in bytes.\n\
+             # TYPE free_heap gauge\n\
+             free_heap 81920\n"
+        ));
+
+        // too small a buffer is an error, for the status server to answer 500 instead of sending
+        // what fit
+        let mut short: String<64> = String::new();
+        assert!(status.write_metrics(&mut short, 81920).is_err());
+    }
+
+    #[test]
+    fn build_info_is_the_crate_version() {
+        let build = build_info();
+        assert!(!build.version.is_empty());
+        assert_eq!(build.version, env!("CARGO_PKG_VERSION"));
+        assert_eq!(build.built, None);
+
+        let status = Status::new();
+        status.set_build_info(build.with_built("Oct 16 2026", "14:02:11"));
+        let mut json: String<1024> = String::new();
+        status.write_json(&mut json).unwrap();
+        let version = alloc::format!("{{\"version\":\"{}\",\"git_hash\":", VERSION);
+        assert!(json.starts_with(&version));
+        assert!(json.contains(",\"built\":\"Oct 16 2026 14:02:11\","));
+    }
+}
@@ -0,0 +1,249 @@
+//! Noticing when a long running task has died or hung, so the device can reset itself rather
+//! than carry on half working, e.g. still showing the last predictions with nothing fetching more.
+//!
+//! Embassy tasks can't be restarted: a task's storage is claimed once at spawn and whatever it
+//! was handed, like the Wi-Fi controller, is gone with it. So tasks loop forever on their own,
+//! and each one has a [`Heartbeat`] it beats as it goes round. A supervisor task checks them all
+//! every few seconds with [`Supervisor::check`], and on a failure logs which task it was and
+//! resets the chip, which is the only way to get it back.
+//!
+//! Everything shares one executor, so a task stuck busy-looping starves the supervisor too. That
+//! takes the hardware watchdog, this only catches tasks that returned or stopped making progress
+//! while others still run.
+//!
+//! Network bring-up at boot has its own bounded retries, see [`BringUp`].
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{CriticalSectionMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+/// How a task is getting on, as of its last [`Heartbeat::beat`].
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum State {
+    /// Waiting on something with no deadline, or not started yet, so it can't be late.
+    Idle,
+    /// Should beat again before this.
+    Due(Instant),
+    Exited,
+}
+
+/// Why [`Heartbeat::check`] thinks a task is dead.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum TaskFailure {
+    /// The task returned, or its future was dropped.
+    Exited,
+    /// The task missed its next beat, by this long.
+    Overdue(Duration),
+}
+
+/// Proof of life from one task, meant to live in a `static` next to it.
+///
+/// A task that waits on a timer calls [`Heartbeat::beat`] each time round its loop, saying how
+/// long until the next one at the latest. Before waiting on something that might never come,
+/// like the next incoming connection, it goes [`Heartbeat::idle`] instead. A task that never
+/// beats is only watched for exiting, through the guard from [`Heartbeat::exit_guard`].
+pub struct Heartbeat {
+    name: &'static str,
+    state: CriticalSectionMutex<Cell<State>>,
+}
+
+impl Heartbeat {
+    /// `name` is what the supervisor logs, e.g. the task's function name.
+    pub const fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            state: Mutex::new(Cell::new(State::Idle)),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The task is alive at `now` and will beat again within `within`.
+    pub fn beat(&self, now: Instant, within: Duration) {
+        self.set(State::Due(now + within));
+    }
+
+    /// The task is alive but about to wait for something with no deadline, so don't expect a
+    /// beat until the next [`Heartbeat::beat`].
+    pub fn idle(&self) {
+        self.set(State::Idle);
+    }
+
+    /// Mark the task dead. Usually left to the guard from [`Heartbeat::exit_guard`].
+    pub fn exited(&self) {
+        self.set(State::Exited);
+    }
+
+    /// Marks the task exited when dropped. Hold it for the whole task, e.g.
+    /// `let _exit = HEARTBEAT.exit_guard();` as its first line, and it's dropped however the task
+    /// ends short of a panic. A panic stops every task, which is the panic handler's to deal with.
+    pub fn exit_guard(&self) -> ExitGuard<'_> {
+        ExitGuard(self)
+    }
+
+    /// `Err` if the task exited, or was due to beat before `now` and hasn't.
+    pub fn check(&self, now: Instant) -> Result<(), TaskFailure> {
+        match self.state.lock(Cell::get) {
+            State::Exited => Err(TaskFailure::Exited),
+            State::Due(due) if now > due => Err(TaskFailure::Overdue(now - due)),
+            State::Due(_) | State::Idle => Ok(()),
+        }
+    }
+
+    fn set(&self, state: State) {
+        self.state.lock(|cell| {
+            // once exited a task can't come back, so a late beat from it shouldn't hide that
+            if cell.get() != State::Exited {
+                cell.set(state);
+            }
+        });
+    }
+}
+
+/// Marks its [`Heartbeat`] exited when dropped, from [`Heartbeat::exit_guard`].
+pub struct ExitGuard<'a>(&'a Heartbeat);
+
+impl Drop for ExitGuard<'_> {
+    fn drop(&mut self) {
+        self.0.exited();
+    }
+}
+
+/// Checks a fixed set of [`Heartbeat`]s together.
+pub struct Supervisor<'a> {
+    heartbeats: &'a [&'a Heartbeat],
+}
+
+impl<'a> Supervisor<'a> {
+    pub const fn new(heartbeats: &'a [&'a Heartbeat]) -> Self {
+        Self { heartbeats }
+    }
+
+    /// The first task that's failed at `now`, by name, or `None` if they're all fine.
+    /// Pass `Instant::now()`, or any other clock for testing.
+    pub fn check(&self, now: Instant) -> Option<(&'static str, TaskFailure)> {
+        self.heartbeats
+            .iter()
+            .find_map(|heartbeat| Some((heartbeat.name, heartbeat.check(now).err()?)))
+    }
+}
+
+/// What to do about network bring-up that ran out of time, from [`BringUp::timed_out`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum BringUpAction {
+    /// Stop and start the Wi-Fi controller, then wait again.
+    RestartRadio,
+    /// Out of restarts, reset the chip.
+    Reset,
+}
+
+/// Bounded retries of network bring-up at boot, the Wi-Fi link and then an address.
+///
+/// A wedged radio doesn't look like a hung task: the task driving it keeps going round its
+/// loop, beating its [`Heartbeat`], it just never connects. So instead each try gets
+/// [`BringUp::timeout`], and [`BringUp::timed_out`] says whether to restart the radio and try
+/// again or give up and reset the chip, the way the supervisor does for a hung task.
+pub struct BringUp {
+    timeout: Option<Duration>,
+    restarts: u8,
+    failures: u8,
+}
+
+impl BringUp {
+    /// Long enough to scan, connect to a slow AP, and give up on DHCP for a static address.
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(60);
+    pub const DEFAULT_RESTARTS: u8 = 2;
+
+    /// Each try gets `timeout`, `None` to wait forever, and the radio is restarted up to
+    /// `restarts` times before the chip is reset.
+    pub const fn new(timeout: Option<Duration>, restarts: u8) -> Self {
+        Self {
+            timeout,
+            restarts,
+            failures: 0,
+        }
+    }
+
+    /// How long the next try gets, `None` for no limit.
+    pub fn timeout(&self) -> Option<Duration> {
+        self.timeout
+    }
+
+    /// Tries that have run out of time so far.
+    pub fn failures(&self) -> u8 {
+        self.failures
+    }
+
+    /// Count a try that ran out of time, and what to do next.
+    pub fn timed_out(&mut self) -> BringUpAction {
+        self.failures = self.failures.saturating_add(1);
+        if self.failures > self.restarts {
+            BringUpAction::Reset
+        } else {
+            BringUpAction::RestartRadio
+        }
+    }
+}
+
+impl Default for BringUp {
+    fn default() -> Self {
+        Self::new(Some(Self::DEFAULT_TIMEOUT), Self::DEFAULT_RESTARTS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_late_or_exited_task_fails_the_check() {
+        let main = Heartbeat::new("main");
+        let render = Heartbeat::new("render");
+        let heartbeats = [&main, &render];
+        let supervisor = Supervisor::new(&heartbeats);
+        let start = Instant::from_secs(100);
+        assert_eq!(supervisor.check(start), None);
+
+        main.beat(start, Duration::from_secs(10));
+        assert_eq!(supervisor.check(start + Duration::from_secs(10)), None);
+        assert_eq!(
+            supervisor.check(start + Duration::from_secs(12)),
+            Some(("main", TaskFailure::Overdue(Duration::from_secs(2))))
+        );
+
+        // going idle means it can wait as long as it likes
+        main.idle();
+        assert_eq!(supervisor.check(start + Duration::from_secs(600)), None);
+
+        {
+            let _exit = render.exit_guard();
+        }
+        assert_eq!(
+            supervisor.check(start),
+            Some(("render", TaskFailure::Exited))
+        );
+        // and a beat from it afterwards doesn't bring it back
+        render.beat(start, Duration::from_secs(10));
+        assert_eq!(render.check(start), Err(TaskFailure::Exited));
+    }
+
+    #[test]
+    fn bring_up_restarts_the_radio_then_resets() {
+        let mut bring_up = BringUp::new(Some(Duration::from_secs(30)), 2);
+        assert_eq!(bring_up.timeout(), Some(Duration::from_secs(30)));
+        assert_eq!(bring_up.timed_out(), BringUpAction::RestartRadio);
+        assert_eq!(bring_up.timed_out(), BringUpAction::RestartRadio);
+        assert_eq!(bring_up.timed_out(), BringUpAction::Reset);
+        assert_eq!(bring_up.failures(), 3);
+
+        // with no restarts the first time out resets
+        let mut bring_up = BringUp::new(None, 0);
+        assert_eq!(bring_up.timeout(), None);
+        assert_eq!(bring_up.timed_out(), BringUpAction::Reset);
+
+        assert_eq!(BringUp::default().timeout(), Some(BringUp::DEFAULT_TIMEOUT));
+    }
+}
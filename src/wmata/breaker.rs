@@ -0,0 +1,134 @@
+use embassy_time::{Duration, Instant};
+
+use crate::wmata::Error;
+
+/// Stops a [`Client`](crate::wmata::Client) from hammering the api while it's down.
+///
+/// Every request the client makes counts towards one breaker, whichever endpoint it's for. After
+/// `threshold` failures in a row it opens, and for `cooldown` every request fails straight away with
+/// [`Error::CircuitOpen`] instead of going out. The first request after that is a probe: if it gets
+/// through the breaker closes again, if not it stays open for another `cooldown`.
+///
+/// Only errors that say the api or the network is down count as failures: connection errors,
+/// timeouts, cut off or non-json bodies, 429 and 5xx statuses. Anything else the api answered,
+/// like a 401 for a bad key or a body that didn't decode, means it's up, and closes the breaker.
+#[derive(Copy, Clone, Debug, defmt::Format)]
+pub struct CircuitBreaker {
+    threshold: u8,
+    cooldown: Duration,
+    failures: u8,
+    open_until: Option<Instant>,
+}
+
+impl CircuitBreaker {
+    pub const DEFAULT_THRESHOLD: u8 = 5;
+    pub const DEFAULT_COOLDOWN: Duration = Duration::from_secs(60);
+
+    /// A closed breaker. A `threshold` of `0` is taken as `1`.
+    pub const fn new(threshold: u8, cooldown: Duration) -> Self {
+        Self {
+            threshold: if threshold == 0 { 1 } else { threshold },
+            cooldown,
+            failures: 0,
+            open_until: None,
+        }
+    }
+
+    /// `Err(Error::CircuitOpen)` while the breaker is open and cooling down, otherwise a request
+    /// may go out. Pass `Instant::now()`, or any other clock for testing.
+    pub fn check(&self, now: Instant) -> Result<(), Error> {
+        match self.open_until {
+            Some(until) if now < until => Err(Error::CircuitOpen),
+            _ => Ok(()),
+        }
+    }
+
+    /// Count the outcome of a request that [`CircuitBreaker::check`] let through.
+    pub fn record<T>(&mut self, now: Instant, result: &Result<T, Error>) {
+        let failed = match result {
+            Ok(_) => false,
+            Err(e) => is_outage(e),
+        };
+        if !failed {
+            self.failures = 0;
+            self.open_until = None;
+            return;
+        }
+
+        self.failures = self.failures.saturating_add(1);
+        if self.failures >= self.threshold {
+            self.open_until = Some(now + self.cooldown);
+        }
+    }
+
+    /// Whether requests are being turned away at `now`.
+    pub fn is_open(&self, now: Instant) -> bool {
+        self.check(now).is_err()
+    }
+}
+
+impl Default for CircuitBreaker {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_THRESHOLD, Self::DEFAULT_COOLDOWN)
+    }
+}
+
+fn is_outage(e: &Error) -> bool {
+    match e {
+        Error::Http(_) | Error::Timeout | Error::NotJson | Error::Truncated => true,
+        Error::Status(code) => *code == 429 || *code >= 500,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOWN: Result<(), Error> = Err(Error::Timeout);
+    const UP: Result<(), Error> = Ok(());
+
+    #[test]
+    fn opens_after_threshold_and_closes_on_a_good_probe() {
+        let mut breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        let start = Instant::from_secs(1000);
+
+        breaker.record(start, &DOWN);
+        breaker.record(start, &DOWN);
+        assert!(breaker.check(start).is_ok());
+        breaker.record(start, &DOWN);
+        assert!(matches!(breaker.check(start), Err(Error::CircuitOpen)));
+        assert!(breaker.is_open(start + Duration::from_secs(59)));
+
+        // the probe after the cooldown fails, so it's another cooldown
+        let probe = start + Duration::from_secs(60);
+        assert!(breaker.check(probe).is_ok());
+        breaker.record(probe, &DOWN);
+        assert!(breaker.is_open(probe + Duration::from_secs(30)));
+
+        let probe = probe + Duration::from_secs(60);
+        assert!(breaker.check(probe).is_ok());
+        breaker.record(probe, &UP);
+        assert!(!breaker.is_open(probe));
+
+        // and it takes the whole threshold to open again
+        breaker.record(probe, &DOWN);
+        assert!(!breaker.is_open(probe));
+    }
+
+    #[test]
+    fn only_outages_count() {
+        let mut breaker = CircuitBreaker::new(1, Duration::from_secs(60));
+        let now = Instant::from_secs(1000);
+        for answered in [
+            Error::Unauthorized,
+            Error::Status(404),
+            Error::EmptyResponse,
+        ] {
+            breaker.record::<()>(now, &Err(answered));
+            assert!(!breaker.is_open(now));
+        }
+        breaker.record::<()>(now, &Err(Error::Status(503)));
+        assert!(breaker.is_open(now));
+    }
+}
@@ -0,0 +1,90 @@
+use core::fmt::Write;
+
+use crate::wmata::json::write_json_str;
+use crate::wmata::types::{Eta, NextTrain, NextTrainsResponse};
+
+impl NextTrainsResponse {
+    /// Write the trains as json in the api's own shape, `{"Trains":[...]}` with WMATA's keys, e.g.
+    /// for a host on the other end of a serial line to read as if it had called the api itself.
+    /// Errors if `w` fills up, like a `heapless::String` that's too short, leaving what fit in it.
+    ///
+    /// Decoding it gives back the same trains, apart from the fetch time, which isn't sent.
+    /// Codes the firmware didn't recognize weren't kept, so they go out as `"???"` for
+    /// [`Station::Unknown`](crate::wmata::types::Station::Unknown) and `""` for a destination code.
+    pub fn write_json<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        w.write_str("{\"Trains\":[")?;
+        for (i, train) in self.trains.iter().enumerate() {
+            if i > 0 {
+                w.write_char(',')?;
+            }
+            train.write_json(w)?;
+        }
+        w.write_str("]}")
+    }
+}
+
+impl NextTrain {
+    /// Write the train as a json object with the api's keys, see [`NextTrainsResponse::write_json`].
+    pub fn write_json<W: Write>(&self, w: &mut W) -> core::fmt::Result {
+        match self.cars {
+            Some(cars) => write!(w, "{{\"Car\":\"{cars}\"")?,
+            None => w.write_str("{\"Car\":null")?,
+        }
+        w.write_str(",\"Destination\":")?;
+        write_json_str(w, &self.destination.0)?;
+        w.write_str(",\"DestinationCode\":")?;
+        match self.destination_code {
+            Some(code) => write_json_str(w, code.station().map_or("", |s| s.code()))?,
+            None => w.write_str("null")?,
+        }
+        w.write_str(",\"DestinationName\":")?;
+        match &self.destination_name {
+            Some(name) => write_json_str(w, &name.0)?,
+            None => w.write_str("null")?,
+        }
+        match self.group {
+            Some(group) => write!(w, ",\"Group\":\"{}\"", u8::from(group))?,
+            None => w.write_str(",\"Group\":null")?,
+        }
+        match self.line {
+            Some(line) => write!(w, ",\"Line\":\"{}\"", line.code())?,
+            None => w.write_str(",\"Line\":null")?,
+        }
+        write!(w, ",\"LocationCode\":\"{}\"", self.location_code.code())?;
+        w.write_str(",\"LocationName\":")?;
+        write_json_str(w, &self.location_name.0)?;
+        // as the api writes it, which isn't how `Eta` displays
+        match self.min {
+            Some(Eta::Minutes(m)) => write!(w, ",\"Min\":\"{m}\"}}"),
+            Some(Eta::Arriving) => w.write_str(",\"Min\":\"ARR\"}"),
+            Some(Eta::Boarding) => w.write_str(",\"Min\":\"BRD\"}"),
+            // the api has no way to say it, and a train it isn't tracking is `---` too
+            Some(Eta::Unknown | Eta::Scheduled(_)) => w.write_str(",\"Min\":\"---\"}"),
+            None => w.write_str(",\"Min\":null}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use heapless::String;
+
+    use super::*;
+    use crate::wmata::json;
+
+    #[test]
+    fn json_reads_back_as_the_same_trains() {
+        let res: NextTrainsResponse =
+            json::from_str(include_str!("../../test_data/get_prediction.json")).unwrap();
+        let mut out: String<1024> = String::new();
+        res.write_json(&mut out).unwrap();
+
+        let again: NextTrainsResponse = json::from_str(&out).unwrap();
+        assert_eq!(again.trains.len(), 4);
+        assert!(again.trains == res.trains);
+
+        let mut short: String<32> = String::new();
+        assert!(res.write_json(&mut short).is_err());
+        assert!(short.starts_with("{\"Trains\":[{\"Car\":\"8\""));
+    }
+}
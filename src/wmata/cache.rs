@@ -0,0 +1,104 @@
+use heapless::String;
+use reqwless::response::HeaderIterator;
+
+/// What a response can be checked against to tell whether it changed, from its `ETag` or, failing
+/// that, `Last-Modified` header.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) enum Validator {
+    ETag(String<64>),
+    LastModified(String<32>),
+}
+
+impl Validator {
+    /// The response's validator, `None` if it has neither header, or only ones too long to keep.
+    pub(crate) fn from_headers(headers: HeaderIterator<'_>) -> Option<Self> {
+        let mut last_modified = None;
+        for (name, value) in headers {
+            let Ok(value) = core::str::from_utf8(value) else {
+                continue;
+            };
+            if name.eq_ignore_ascii_case("etag") {
+                if let Ok(etag) = String::try_from(value) {
+                    return Some(Validator::ETag(etag));
+                }
+            } else if name.eq_ignore_ascii_case("last-modified") {
+                last_modified = String::try_from(value).ok().map(Validator::LastModified);
+            }
+        }
+
+        last_modified
+    }
+
+    /// The header asking for the body only if it's changed since.
+    pub(crate) fn header(&self) -> (&'static str, &str) {
+        match self {
+            Validator::ETag(etag) => ("If-None-Match", etag),
+            Validator::LastModified(date) => ("If-Modified-Since", date),
+        }
+    }
+}
+
+/// The last response body and its validator, for [`Client::with_response_cache`] to ask the api
+/// for the body only if it's changed.
+///
+/// Holds one body, so it helps most when polling the same request over and over, like the
+/// predictions for one station. Any other request replaces it. Responses without an `ETag` or
+/// `Last-Modified` header aren't kept, so against an endpoint that sends neither every request
+/// goes out as usual.
+///
+/// [`Client::with_response_cache`]: crate::wmata::Client::with_response_cache
+pub struct ResponseCache<'a> {
+    buf: &'a mut [u8],
+    entry: Option<Entry>,
+}
+
+struct Entry {
+    url: String<128>,
+    validator: Validator,
+    len: usize,
+}
+
+impl<'a> ResponseCache<'a> {
+    /// Keep bodies in `buf`, which needs to be as big as the biggest one worth caching. Bigger
+    /// ones aren't kept.
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, entry: None }
+    }
+
+    /// Forget the cached body, so the next request fetches it in full.
+    pub fn clear(&mut self) {
+        self.entry = None;
+    }
+
+    /// Validator of the body cached for `url`, to send with the next request for it.
+    pub(crate) fn validator(&self, url: &str) -> Option<Validator> {
+        let entry = self.entry.as_ref().filter(|entry| entry.url == url)?;
+        Some(entry.validator.clone())
+    }
+
+    /// The body cached for `url`, for a `304 Not Modified` response.
+    pub(crate) fn body(&self, url: &str) -> Option<&[u8]> {
+        let entry = self.entry.as_ref().filter(|entry| entry.url == url)?;
+        Some(&self.buf[..entry.len])
+    }
+
+    /// Keep `body` as the response to `url`. Without a validator there's no way to ask whether it
+    /// changed, so that, or a body or url too long to keep, just drops what was cached.
+    pub(crate) fn store(&mut self, url: &str, validator: Option<Validator>, body: &[u8]) {
+        self.entry = None;
+        let (Some(validator), Ok(url), Some(dest)) = (
+            validator,
+            String::try_from(url),
+            self.buf.get_mut(..body.len()),
+        ) else {
+            return;
+        };
+
+        dest.copy_from_slice(body);
+        self.entry = Some(Entry {
+            url,
+            validator,
+            len: body.len(),
+        });
+    }
+}
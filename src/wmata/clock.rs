@@ -0,0 +1,272 @@
+use core::cell::Cell;
+use core::fmt::Write;
+
+use embassy_sync::blocking_mutex::{CriticalSectionMutex, Mutex};
+use embassy_time::Instant;
+use heapless::String;
+
+use crate::wmata::types::Weekday;
+
+const SECS_PER_DAY: i64 = 24 * 60 * 60;
+
+/// The time of day, learnt from the `Date` header of api responses.
+///
+/// There's no SNTP client or battery backed RTC, but every response says what time the server
+/// thinks it is, to the second. That's plenty for telling whether trains are running. Hand it to
+/// [`Client::with_clock`](crate::wmata::Client::with_clock) and it's set on every request. It's
+/// kept as an offset from uptime, so it's lost on a reboot or deep sleep until the next request.
+pub struct WallClock {
+    /// Uptime and unix time of the last sync.
+    synced: CriticalSectionMutex<Cell<Option<(Instant, u64)>>>,
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self {
+            synced: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Record that it's `unix_secs` now.
+    pub fn set(&self, unix_secs: u64) {
+        self.synced
+            .lock(|synced| synced.set(Some((Instant::now(), unix_secs))));
+    }
+
+    /// Set the clock from an http `Date` header, e.g. `Sun, 06 Nov 1994 08:49:37 GMT`.
+    /// Returns whether it could be read.
+    pub fn set_from_http_date(&self, date: &[u8]) -> bool {
+        match core::str::from_utf8(date).ok().and_then(parse_http_date) {
+            Some(unix_secs) => {
+                self.set(unix_secs);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Seconds since the unix epoch, `None` until the first sync.
+    pub fn unix_now(&self) -> Option<u64> {
+        let (at, unix_secs) = self.synced.lock(Cell::get)?;
+        Some(unix_secs + at.elapsed().as_secs())
+    }
+
+    /// The time in Washington, `None` until the first sync.
+    pub fn local_now(&self) -> Option<LocalTime> {
+        self.unix_now().map(LocalTime::eastern)
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// A UTC offset, and whether it goes an hour ahead for daylight saving time on the US rules, for
+/// [`LocalTime::in_zone`]. There's no tz database on the device, so that's the only kind of DST.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct TimeZone {
+    /// Standard time's offset from UTC, e.g. `-5 * 60` for EST.
+    pub offset_minutes: i16,
+    /// Whether daylight time applies, from 2am on the second Sunday in March to 2am on the
+    /// first Sunday in November, local time.
+    pub us_dst: bool,
+}
+
+impl TimeZone {
+    /// Washington, and the rest of the US east coast.
+    pub const US_EASTERN: TimeZone = TimeZone::us(-5 * 60);
+    pub const UTC: TimeZone = TimeZone::fixed(0);
+
+    /// `offset_minutes` from UTC all year round.
+    pub const fn fixed(offset_minutes: i16) -> Self {
+        Self {
+            offset_minutes,
+            us_dst: false,
+        }
+    }
+
+    /// `offset_minutes` from UTC in winter and an hour more during US daylight time.
+    pub const fn us(offset_minutes: i16) -> Self {
+        Self {
+            offset_minutes,
+            us_dst: true,
+        }
+    }
+
+    /// Seconds to add to UTC for local time at `unix_secs`.
+    fn offset_at(&self, unix_secs: i64) -> i64 {
+        let standard = i64::from(self.offset_minutes) * 60;
+        if !self.us_dst {
+            return standard;
+        }
+
+        let daylight = standard + 3600;
+        let year = civil_year(unix_secs.div_euclid(SECS_PER_DAY));
+        // each change happens at 2am on the clock it's changing from
+        let dst_start =
+            sunday_on_or_after(days_from_civil(year, 3, 8)) * SECS_PER_DAY + 2 * 3600 - standard;
+        let dst_end =
+            sunday_on_or_after(days_from_civil(year, 11, 1)) * SECS_PER_DAY + 2 * 3600 - daylight;
+        if (dst_start..dst_end).contains(&unix_secs) {
+            daylight
+        } else {
+            standard
+        }
+    }
+}
+
+/// A day of the week and time of day on local time, to the minute.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct LocalTime {
+    pub weekday: Weekday,
+    pub hour: u8,
+    pub minute: u8,
+}
+
+impl LocalTime {
+    /// US Eastern time at `unix_secs`, on daylight time from the second Sunday in March to the
+    /// first Sunday in November, changing over at 2am local time.
+    pub fn eastern(unix_secs: u64) -> Self {
+        Self::in_zone(unix_secs, TimeZone::US_EASTERN)
+    }
+
+    /// The time in `zone` at `unix_secs`, e.g. to show when the last update arrived.
+    pub fn in_zone(unix_secs: u64, zone: TimeZone) -> Self {
+        let utc = unix_secs as i64;
+        let local = utc + zone.offset_at(utc);
+        let days = local.div_euclid(SECS_PER_DAY);
+        let secs = local.rem_euclid(SECS_PER_DAY);
+        // 1970-01-01 was a Thursday
+        Self {
+            weekday: Weekday::Thursday.plus(days.rem_euclid(7) as u32),
+            hour: (secs / 3600) as u8,
+            minute: (secs % 3600 / 60) as u8,
+        }
+    }
+
+    /// Minutes since local midnight.
+    pub fn minute_of_day(&self) -> u16 {
+        u16::from(self.hour) * 60 + u16::from(self.minute)
+    }
+
+    /// `HH:MM` on a 24 hour clock, e.g. `07:05`.
+    pub fn to_string(&self) -> String<5> {
+        let mut s: String<5> = String::new();
+        write!(s, "{self}").expect("to_string should always succeed");
+        s
+    }
+}
+
+/// `HH:MM` on a 24 hour clock, like the api's scheduled times.
+impl core::fmt::Display for LocalTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+/// Unix seconds from an IMF-fixdate, the only `Date` format servers are supposed to send.
+fn parse_http_date(date: &str) -> Option<u64> {
+    let mut parts = date.split_ascii_whitespace().skip(1);
+    let day: i64 = parts.next()?.parse().ok()?;
+    let month = match parts.next()? {
+        "Jan" => 1,
+        "Feb" => 2,
+        "Mar" => 3,
+        "Apr" => 4,
+        "May" => 5,
+        "Jun" => 6,
+        "Jul" => 7,
+        "Aug" => 8,
+        "Sep" => 9,
+        "Oct" => 10,
+        "Nov" => 11,
+        "Dec" => 12,
+        _ => return None,
+    };
+    let year: i64 = parts.next()?.parse().ok()?;
+    let mut time = parts.next()?.split(':');
+    let mut field = |max: i64| -> Option<i64> {
+        let value: i64 = time.next()?.parse().ok()?;
+        (0..=max).contains(&value).then_some(value)
+    };
+    let (hour, minute, second) = (field(23)?, field(59)?, field(60)?);
+    if !(1..=31).contains(&day) || year < 1970 {
+        return None;
+    }
+
+    let secs =
+        days_from_civil(year, month, day) * SECS_PER_DAY + hour * 3600 + minute * 60 + second;
+    u64::try_from(secs).ok()
+}
+
+/// Days since 1970-01-01 of a date on the proleptic Gregorian calendar.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The year `days` since 1970-01-01 falls in, the inverse of [`days_from_civil`].
+fn civil_year(days: i64) -> i64 {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // the era's years start in March, so January and February belong to the next one
+    let march_based_month = (5 * day_of_year + 2) / 153;
+    year_of_era + era * 400 + i64::from(march_based_month >= 10)
+}
+
+/// The first Sunday on or after `days` since 1970-01-01.
+fn sunday_on_or_after(days: i64) -> i64 {
+    // 1970-01-01 was a Thursday, 4 days after a Sunday
+    let since_sunday = (days + 4).rem_euclid(7);
+    days + (7 - since_sunday) % 7
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eastern(date: &str) -> String<5> {
+        LocalTime::eastern(parse_http_date(date).unwrap()).to_string()
+    }
+
+    #[test]
+    fn eastern_time_changes_over_at_2am() {
+        // 2026's change to daylight time is the second Sunday in March, the 8th
+        assert_eq!(eastern("Sun, 08 Mar 2026 06:59:00 GMT"), "01:59");
+        assert_eq!(eastern("Sun, 08 Mar 2026 07:00:00 GMT"), "03:00");
+        // and back on the first Sunday in November, the 1st, so 1am comes round twice
+        assert_eq!(eastern("Sun, 01 Nov 2026 05:59:00 GMT"), "01:59");
+        assert_eq!(eastern("Sun, 01 Nov 2026 06:00:00 GMT"), "01:00");
+        // a week before, when the EU has already changed, it's still daylight time
+        assert_eq!(eastern("Sun, 25 Oct 2026 12:00:00 GMT"), "08:00");
+        assert_eq!(eastern("Mon, 05 Jan 2026 17:05:00 GMT"), "12:05");
+
+        let new_year =
+            LocalTime::eastern(parse_http_date("Thu, 01 Jan 2026 04:30:00 GMT").unwrap());
+        assert_eq!(new_year.weekday, Weekday::Wednesday);
+        assert_eq!(new_year.to_string(), "23:30");
+    }
+
+    #[test]
+    fn fixed_zones_ignore_dst() {
+        let summer = parse_http_date("Wed, 01 Jul 2026 12:00:00 GMT").unwrap();
+        assert_eq!(
+            LocalTime::in_zone(summer, TimeZone::UTC).to_string(),
+            "12:00"
+        );
+        assert_eq!(
+            LocalTime::in_zone(summer, TimeZone::fixed(5 * 60 + 30)).to_string(),
+            "17:30"
+        );
+    }
+}
@@ -1,25 +1,60 @@
+use core::{net::Ipv4Addr, str::Utf8Error};
+
 use bincode::{
-    Decode, Encode, decode_from_slice, encode_into_slice,
+    Decode, Encode,
+    de::Decoder,
+    decode_from_slice,
+    enc::Encoder,
+    encode_into_slice,
     error::{DecodeError, EncodeError},
 };
+use embassy_time::Duration;
 use embedded_storage::{ReadStorage, Storage};
-use esp_storage::{FlashStorage, FlashStorageError};
+use heapless::Vec;
 use thiserror::Error;
 
+use crate::wmata::{
+    clock::LocalTime,
+    flash::{FlashStorage, FlashStorageError},
+    partition::{storage_end, storage_start},
+    types::{LineKind, Station, Weekday},
+};
+
 pub const CHECKSUM_SZ: usize = core::mem::size_of::<u32>();
 pub const SSID_MAX_LEN: usize = 32;
 pub const PASS_MAX_LEN: usize = 64;
 pub const API_KEY_MAX_LEN: usize = 32;
-pub const CONFIG_SZ: usize = core::mem::size_of::<Config>() + CHECKSUM_SZ; // 132 + 4 = 136
+pub const NTP_SERVER_MAX_LEN: usize = 64;
+pub const CONFIG_SZ: usize = core::mem::size_of::<Config>() + CHECKSUM_SZ; // 298 + 4 = 302
+pub const CONFIG_VERSION: u8 = 15;
+
+/// Encoded length of the config as written by each version, indexed by `version - 1`.
+/// Fields are only ever appended, so every older layout is a prefix of the current one.
+const ENCODED_LEN: [usize; CONFIG_VERSION as usize] = [
+    132, 133, 138, 163, 166, 169, 172, 180, 193, 194, 195, 260, 262, 263, 296,
+];
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum ConfigError {
     #[error("Buffer must be at least length: {}", CONFIG_SZ)]
     BufferTooSmall,
     #[error("Crc checksum failed")]
     BadChecksum,
+    /// The flash is still erased, i.e. no config was ever saved there.
+    #[error("no config saved")]
+    Empty,
     #[error("one or more args were too long")]
     BadArgs,
+    /// The flash is too small, or its size was misdetected, to hold the config clear of the
+    /// bootloader and apps, or the partition table has no partition big enough for it, so nothing
+    /// was read or written.
+    #[error("no safe place in flash for the config")]
+    NoStorage,
+    #[error("unsupported config version: {0}")]
+    UnsupportedVersion(u8),
+    #[error("a stored string is too long or not valid utf8")]
+    InvalidString,
     #[error("flash error: {0:?}")]
     Flash(FlashStorageError),
     #[error("decode error: {0:?}")]
@@ -52,7 +87,78 @@ impl From<EncodeError> for ConfigError {
     }
 }
 
-#[derive(defmt::Format, Encode, Decode)]
+/// How the Wi-Fi radio behaves between polls.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum PowerMode {
+    /// Radio stays fully powered. Lowest latency, highest draw.
+    #[default]
+    AlwaysOn,
+    /// Radio sleeps between DTIM beacons while staying associated with the AP.
+    ModemSleep,
+    /// Disconnect from the AP between polls and reconnect just before the next fetch.
+    /// Saves the most power, at the cost of a full association + DHCP on every poll.
+    Disconnect,
+    /// Deep sleep the whole chip between polls, for battery installations. Every poll is a full boot,
+    /// with the last predictions kept in RTC memory so the display isn't blank until the fetch lands.
+    DeepSleep,
+}
+
+impl PowerMode {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(PowerMode::AlwaysOn),
+            1 => Some(PowerMode::ModemSleep),
+            2 => Some(PowerMode::Disconnect),
+            3 => Some(PowerMode::DeepSleep),
+            _ => None,
+        }
+    }
+}
+
+impl core::str::FromStr for PowerMode {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "always-on" => Ok(PowerMode::AlwaysOn),
+            "modem-sleep" => Ok(PowerMode::ModemSleep),
+            "disconnect" => Ok(PowerMode::Disconnect),
+            "deep-sleep" => Ok(PowerMode::DeepSleep),
+            _ => Err(ConfigError::BadArgs),
+        }
+    }
+}
+
+/// Where the running config came from.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum ConfigSource {
+    /// Loaded from the config sector in flash.
+    Flash,
+    /// Built from the compiled-in environment variables.
+    Environment,
+    /// Built at runtime, e.g. by a provisioning flow, and not yet known to be in flash.
+    #[default]
+    Provisioned,
+}
+
+// the source describes this boot, not the stored config, so it's never written to flash.
+// it encodes to nothing, and anything decoded came from flash
+impl Encode for ConfigSource {
+    fn encode<E: Encoder>(&self, _encoder: &mut E) -> Result<(), EncodeError> {
+        Ok(())
+    }
+}
+
+impl<Context> Decode<Context> for ConfigSource {
+    fn decode<D: Decoder<Context = Context>>(_decoder: &mut D) -> Result<Self, DecodeError> {
+        Ok(ConfigSource::Flash)
+    }
+}
+
+bincode::impl_borrow_decode!(ConfigSource);
+
+/// Logs with the password and api key redacted, see [`Config::unredacted`] for everything.
+#[derive(Clone, Encode, Decode)]
 pub struct Config {
     version: u8,
     ssid_len: u8,
@@ -61,9 +167,246 @@ pub struct Config {
     ssid: [u8; SSID_MAX_LEN],
     pass: [u8; PASS_MAX_LEN],
     api_key: [u8; API_KEY_MAX_LEN],
+    power_mode: u8,
+    width: u16,
+    height: u16,
+    rotation: u8,
+    source: ConfigSource,
+    destinations_len: u8,
+    /// Station codes, stored as text so reordering `Station` can't change what a saved config means.
+    destinations: [[u8; 3]; Config::MAX_DESTINATIONS],
+    /// Code of the station to show, zeroes for the firmware's default.
+    station: [u8; 3],
+    /// Code of the station to show when `station` has no trains, zeroes for none.
+    fallback_station: [u8; 3],
+    /// Two letter country code for the radio's regulatory domain, zeroes for `esp_radio`'s default.
+    wifi_country: [u8; 2],
+    /// Transmit power cap in quarter dBm, `0` for the driver's default.
+    wifi_max_tx_power: u8,
+    /// Weekday opening and closing, then weekend, see [`ServiceHours`].
+    service_hours: [u16; 4],
+    /// Address to fall back to without DHCP, zeroes for none, see [`StaticIpv4`].
+    static_ipv4: [u8; 4],
+    static_ipv4_prefix_len: u8,
+    /// Zeroes for none, as is `static_ipv4_dns`.
+    static_ipv4_gateway: [u8; 4],
+    static_ipv4_dns: [u8; 4],
+    /// [`LineKind::bit`]s of the lines to show, `0` for every line.
+    lines_mask: u8,
+    /// Percent either way, see [`Config::with_poll_jitter`].
+    poll_jitter: u8,
+    ntp_server_len: u8,
+    /// Host name of the time server, see [`Config::with_ntp_server`].
+    ntp_server: [u8; NTP_SERVER_MAX_LEN],
+    /// Trains due sooner are hidden, `0` for none, see [`Config::with_min_minutes`].
+    min_minutes: u8,
+    keep_arriving: bool,
+    /// See [`NoPassengerPolicy`].
+    no_passenger: u8,
+    backup_api_key_len: u8,
+    /// Tried when WMATA turns down `api_key`, see [`Config::with_backup_api_key`].
+    backup_api_key: [u8; API_KEY_MAX_LEN],
+}
+
+/// What differs between a running config and a newly saved one, see [`Config::changes_from`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub struct ConfigChanges {
+    /// The SSID or password, so Wi-Fi has to reconnect.
+    pub wifi: bool,
+    pub api_key: bool,
+    /// The station, fallback station, destinations, lines or minimum minutes.
+    pub stations: bool,
+    pub service_hours: bool,
+    /// The power mode, panel, Wi-Fi region or static IPv4 fallback, which are only read at boot.
+    pub needs_reboot: bool,
+}
+
+impl ConfigChanges {
+    pub fn any(&self) -> bool {
+        self.wifi || self.api_key || self.stations || self.service_hours || self.needs_reboot
+    }
+}
+
+/// When trains run on one kind of day, in minutes after that day's midnight, local time.
+/// `closes` runs past `24 * 60` for service that ends after midnight, e.g. `25 * 60` for 1am.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ServiceWindow {
+    pub opens: u16,
+    pub closes: u16,
+}
+
+/// When Metrorail is open, to stop polling overnight, see [`Config::with_service_hours`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ServiceHours {
+    /// Monday to Friday.
+    pub weekday: ServiceWindow,
+    /// Saturday and Sunday.
+    pub weekend: ServiceWindow,
+}
+
+impl ServiceHours {
+    /// Metrorail's hours: 5am on weekdays and 7am on weekends, until 2am. Only Friday and
+    /// Saturday nights actually run that late, so the other nights poll a couple of hours longer
+    /// than they need to rather than missing the last trains.
+    pub const WMATA: ServiceHours = ServiceHours {
+        weekday: ServiceWindow {
+            opens: 5 * 60,
+            closes: 26 * 60,
+        },
+        weekend: ServiceWindow {
+            opens: 7 * 60,
+            closes: 26 * 60,
+        },
+    };
+    /// Open around the clock, i.e. never stop polling.
+    pub const ALWAYS: ServiceHours = ServiceHours {
+        weekday: ServiceWindow {
+            opens: 0,
+            closes: 24 * 60,
+        },
+        weekend: ServiceWindow {
+            opens: 0,
+            closes: 24 * 60,
+        },
+    };
+
+    pub fn window(&self, day: Weekday) -> ServiceWindow {
+        if day.is_weekend() {
+            self.weekend
+        } else {
+            self.weekday
+        }
+    }
+
+    /// Whether trains run at `now`, counting the late end of the day before's service.
+    pub fn in_service(&self, now: LocalTime) -> bool {
+        self.until_open(now) == 0
+    }
+
+    /// Minutes from `now` until service opens, `0` if it's open.
+    pub fn until_open(&self, now: LocalTime) -> u16 {
+        let minute = now.minute_of_day();
+        let today = self.window(now.weekday);
+        let yesterday = self.window(now.weekday.prev());
+        if (today.opens..today.closes).contains(&minute) || minute + 24 * 60 < yesterday.closes {
+            return 0;
+        }
+
+        if minute < today.opens {
+            today.opens - minute
+        } else {
+            24 * 60 - minute + self.window(now.weekday.next()).opens
+        }
+    }
+
+    fn is_valid(&self) -> bool {
+        [self.weekday, self.weekend].iter().all(|window| {
+            window.opens < 24 * 60 && window.opens < window.closes && window.closes <= 48 * 60
+        })
+    }
+}
+
+/// An IPv4 address to set by hand when the network's DHCP server doesn't answer, see
+/// [`Config::with_static_ipv4`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct StaticIpv4 {
+    pub address: Ipv4Addr,
+    /// e.g. `24` for a netmask of 255.255.255.0.
+    pub prefix_len: u8,
+    /// `None` only reaches the local subnet, so the api would have to be on it too.
+    pub gateway: Option<Ipv4Addr>,
+    pub dns: Option<Ipv4Addr>,
+}
+
+/// What to do once DHCP has had its chance to give an IPv4 address and hasn't, from
+/// [`DhcpFallback::choose`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum DhcpFallback {
+    /// Set this address by hand, see [`Config::static_ipv4`].
+    Static(StaticIpv4),
+    /// Carry on without IPv4, over the static IPv6 address that's already up.
+    Ipv6Only,
+    /// Nothing to fall back to, so keep waiting for a lease.
+    Wait,
+}
+
+impl DhcpFallback {
+    /// A static IPv4 address wins, since DNS and the api may only be reachable over IPv4. Without
+    /// one, `has_ipv6` says whether there's a static IPv6 address to get by on instead.
+    pub fn choose(static_ipv4: Option<StaticIpv4>, has_ipv6: bool) -> Self {
+        match static_ipv4 {
+            Some(ip) => DhcpFallback::Static(ip),
+            None if has_ipv6 => DhcpFallback::Ipv6Only,
+            None => DhcpFallback::Wait,
+        }
+    }
+}
+
+/// What to do with trains that aren't taking passengers, see [`Config::with_no_passenger`].
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum NoPassengerPolicy {
+    /// Leave them off the board, since nobody can board them.
+    #[default]
+    Hide,
+    /// Keep them as they are, for a panel that can gray them out, see `NextTrain::is_revenue`.
+    /// One without shades shows them like any other train.
+    Dim,
+    /// Keep them, with "Not in Service" for the destination.
+    Label,
+}
+
+impl NoPassengerPolicy {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(NoPassengerPolicy::Hide),
+            1 => Some(NoPassengerPolicy::Dim),
+            2 => Some(NoPassengerPolicy::Label),
+            _ => None,
+        }
+    }
+}
+
+impl core::str::FromStr for NoPassengerPolicy {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hide" => Ok(NoPassengerPolicy::Hide),
+            "dim" => Ok(NoPassengerPolicy::Dim),
+            "label" => Ok(NoPassengerPolicy::Label),
+            _ => Err(ConfigError::BadArgs),
+        }
+    }
+}
+
+/// Panel rotation, clockwise.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum Rotation {
+    #[default]
+    Deg0,
+    Deg90,
+    Deg180,
+    Deg270,
+}
+
+impl Rotation {
+    fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(Rotation::Deg0),
+            1 => Some(Rotation::Deg90),
+            2 => Some(Rotation::Deg180),
+            3 => Some(Rotation::Deg270),
+            _ => None,
+        }
+    }
 }
 
 impl Config {
+    pub const DEFAULT_WIDTH: u16 = 128;
+    pub const DEFAULT_HEIGHT: u16 = 32;
+    /// Most destinations [`Config::with_destinations`] can hold.
+    pub const MAX_DESTINATIONS: usize = 8;
+
     pub fn new(ssid: &str, pass: &str, api_key: &str) -> Result<Self, ConfigError> {
         let ssid_len = ssid.len();
         let pass_len = pass.len();
@@ -82,15 +425,286 @@ impl Config {
         let mut new_api_key = [0u8; API_KEY_MAX_LEN];
         new_api_key[..api_key_len].copy_from_slice(api_key.as_bytes());
 
-        Ok(Self {
-            version: 1,
+        let mut ntp_server = [0u8; NTP_SERVER_MAX_LEN];
+        ntp_server[..Self::DEFAULT_NTP_SERVER.len()]
+            .copy_from_slice(Self::DEFAULT_NTP_SERVER.as_bytes());
+
+        Self {
+            version: CONFIG_VERSION,
             ssid_len: ssid_len as u8,
             pass_len: pass_len as u8,
             api_key_len: api_key_len as u8,
             ssid: new_ssid,
             pass: new_pass,
             api_key: new_api_key,
-        })
+            power_mode: PowerMode::default() as u8,
+            width: Self::DEFAULT_WIDTH,
+            height: Self::DEFAULT_HEIGHT,
+            rotation: Rotation::default() as u8,
+            source: ConfigSource::default(),
+            destinations_len: 0,
+            destinations: [[0; 3]; Self::MAX_DESTINATIONS],
+            station: [0; 3],
+            fallback_station: [0; 3],
+            wifi_country: [0; 2],
+            wifi_max_tx_power: 0,
+            service_hours: [0; 4],
+            static_ipv4: [0; 4],
+            static_ipv4_prefix_len: 0,
+            static_ipv4_gateway: [0; 4],
+            static_ipv4_dns: [0; 4],
+            lines_mask: 0,
+            poll_jitter: Self::DEFAULT_POLL_JITTER,
+            ntp_server_len: Self::DEFAULT_NTP_SERVER.len() as u8,
+            ntp_server,
+            min_minutes: 0,
+            keep_arriving: false,
+            no_passenger: NoPassengerPolicy::default() as u8,
+            backup_api_key_len: 0,
+            backup_api_key: [0; API_KEY_MAX_LEN],
+        }
+        .with_service_hours(ServiceHours::WMATA)
+    }
+
+    pub fn with_power_mode(mut self, power_mode: PowerMode) -> Self {
+        self.power_mode = power_mode as u8;
+        self
+    }
+
+    pub fn with_source(mut self, source: ConfigSource) -> Self {
+        self.source = source;
+        self
+    }
+
+    /// Only show trains terminating at one of `destinations`, e.g. just one direction at a junction.
+    /// An empty list shows every train.
+    pub fn with_destinations(mut self, destinations: &[Station]) -> Result<Self, ConfigError> {
+        if destinations.len() > Self::MAX_DESTINATIONS {
+            return Err(ConfigError::BadArgs);
+        }
+
+        self.destinations = [[0; 3]; Self::MAX_DESTINATIONS];
+        for (slot, station) in self.destinations.iter_mut().zip(destinations) {
+            slot.copy_from_slice(station.code().as_bytes());
+        }
+        self.destinations_len = destinations.len() as u8;
+        Ok(self)
+    }
+
+    /// Only show trains on one of `lines`, on every station and fetch, e.g. just the Silver Line on
+    /// a platform it shares with Orange and Blue. An empty list shows every line. Trains whose line
+    /// isn't known are left out by any filter.
+    pub fn with_lines(mut self, lines: &[LineKind]) -> Self {
+        self.lines_mask = lines.iter().fold(0, |mask, line| mask | line.bit());
+        self
+    }
+
+    /// Show predictions for `station` instead of the firmware's default, e.g. as chosen with a
+    /// [`StationPicker`](crate::picker::StationPicker).
+    pub fn with_station(mut self, station: Station) -> Self {
+        self.station.copy_from_slice(station.code().as_bytes());
+        self
+    }
+
+    /// Show predictions for `fallback` whenever the main station has none, e.g. a terminal
+    /// overnight. `None` turns it off.
+    pub fn with_fallback_station(mut self, fallback: Option<Station>) -> Self {
+        match fallback {
+            Some(station) => self
+                .fallback_station
+                .copy_from_slice(station.code().as_bytes()),
+            None => self.fallback_station = [0; 3],
+        }
+        self
+    }
+
+    /// Lowest and highest transmit power cap [`Config::with_wifi_region`] takes, 2 to 20 dBm in
+    /// quarter dBm, the range `esp_radio`'s `WifiController::set_max_tx_power` accepts.
+    pub const MIN_TX_POWER: u8 = 8;
+    pub const MAX_TX_POWER: u8 = 84;
+
+    /// Set the Wi-Fi regulatory domain and transmit power for where the display is installed.
+    ///
+    /// `country` is an ISO 3166 code like `"US"`, which decides the channels the radio may use and
+    /// their power limits; it's passed to `esp_radio` as a `CountryInfo` when the controller is
+    /// created. `None` keeps `esp_radio`'s default of `"CN"`, channels 1 to 13 at up to 20 dBm, so
+    /// an AP on channel 12 or 13 is only reachable outside North America with the right code.
+    ///
+    /// `max_tx_power` caps transmit power in quarter dBm, from [`Config::MIN_TX_POWER`] to
+    /// [`Config::MAX_TX_POWER`], e.g. `60` for 15 dBm. `None` leaves the driver's default.
+    pub fn with_wifi_region(
+        mut self,
+        country: Option<&str>,
+        max_tx_power: Option<u8>,
+    ) -> Result<Self, ConfigError> {
+        let country = match country {
+            Some(code) => match code.as_bytes() {
+                &[a, b] if a.is_ascii_uppercase() && b.is_ascii_uppercase() => [a, b],
+                _ => return Err(ConfigError::BadArgs),
+            },
+            None => [0; 2],
+        };
+        let max_tx_power = match max_tx_power {
+            Some(power) if (Self::MIN_TX_POWER..=Self::MAX_TX_POWER).contains(&power) => power,
+            Some(_) => return Err(ConfigError::BadArgs),
+            None => 0,
+        };
+
+        self.wifi_country = country;
+        self.wifi_max_tx_power = max_tx_power;
+        Ok(self)
+    }
+
+    /// Stop polling when trains aren't running, once the time is known, see
+    /// [`WallClock`](crate::wmata::WallClock). Defaults to [`ServiceHours::WMATA`],
+    /// [`ServiceHours::ALWAYS`] polls around the clock. `BadArgs` for a window that opens after
+    /// midnight, closes before it opens, or lasts more than a day.
+    pub fn with_service_hours(mut self, hours: ServiceHours) -> Result<Self, ConfigError> {
+        if !hours.is_valid() {
+            return Err(ConfigError::BadArgs);
+        }
+
+        self.service_hours = [
+            hours.weekday.opens,
+            hours.weekday.closes,
+            hours.weekend.opens,
+            hours.weekend.closes,
+        ];
+        Ok(self)
+    }
+
+    /// Fall back to `ip` when DHCP doesn't give the device an address in time, e.g. on a network
+    /// whose DHCP server is down or hands out no leases. `None`, the default, keeps waiting on DHCP.
+    /// `BadArgs` for a prefix longer than 32 bits, or an unspecified or broadcast address, gateway
+    /// or DNS server, since those can't be stored.
+    pub fn with_static_ipv4(mut self, ip: Option<StaticIpv4>) -> Result<Self, ConfigError> {
+        let Some(ip) = ip else {
+            self.static_ipv4 = [0; 4];
+            self.static_ipv4_prefix_len = 0;
+            self.static_ipv4_gateway = [0; 4];
+            self.static_ipv4_dns = [0; 4];
+            return Ok(self);
+        };
+
+        let storable = |addr: Ipv4Addr| !addr.is_unspecified() && !addr.is_broadcast();
+        if ip.prefix_len > 32
+            || !storable(ip.address)
+            || !ip.gateway.is_none_or(storable)
+            || !ip.dns.is_none_or(storable)
+        {
+            return Err(ConfigError::BadArgs);
+        }
+
+        self.static_ipv4 = ip.address.octets();
+        self.static_ipv4_prefix_len = ip.prefix_len;
+        self.static_ipv4_gateway = ip.gateway.map_or([0; 4], |gw| gw.octets());
+        self.static_ipv4_dns = ip.dns.map_or([0; 4], |dns| dns.octets());
+        Ok(self)
+    }
+
+    /// Most and default poll jitter [`Config::with_poll_jitter`] takes, in percent.
+    pub const MAX_POLL_JITTER: u8 = 50;
+    pub const DEFAULT_POLL_JITTER: u8 = 10;
+
+    /// Poll up to `percent` sooner or later than the poll interval, at random each time, so
+    /// displays that booted together, e.g. after a power cut, drift apart instead of hitting the
+    /// api in lockstep. Defaults to [`Config::DEFAULT_POLL_JITTER`], `0` polls like clockwork.
+    /// `BadArgs` over [`Config::MAX_POLL_JITTER`].
+    pub fn with_poll_jitter(mut self, percent: u8) -> Result<Self, ConfigError> {
+        if percent > Self::MAX_POLL_JITTER {
+            return Err(ConfigError::BadArgs);
+        }
+
+        self.poll_jitter = percent;
+        Ok(self)
+    }
+
+    /// The public NTP pool, which [`Config::with_ntp_server`] defaults to.
+    pub const DEFAULT_NTP_SERVER: &str = "pool.ntp.org";
+
+    /// Get the time from `host` instead of [`Config::DEFAULT_NTP_SERVER`], e.g. an internal time
+    /// source on a network that blocks or redirects the public pool. `BadArgs` for anything that
+    /// isn't a plausible host name or IPv4 address, or is longer than [`NTP_SERVER_MAX_LEN`].
+    ///
+    /// Only stored for now, for an SNTP client to use once there is one. The clock is set from
+    /// the api's `Date` header, see [`WallClock`](crate::wmata::WallClock), so changing it
+    /// changes nothing on a running device.
+    pub fn with_ntp_server(mut self, host: &str) -> Result<Self, ConfigError> {
+        if !is_host_name(host) {
+            return Err(ConfigError::BadArgs);
+        }
+
+        store_str(&mut self.ntp_server, &mut self.ntp_server_len, host)?;
+        Ok(self)
+    }
+
+    /// Hide trains due in under `minutes`, e.g. `2` for a display a couple of minutes' walk from
+    /// the platform, see `NextTrainsResponse::retain_catchable`. ARR and BRD trains are hidden
+    /// too unless `keep_arriving`. `0`, the default, shows every train.
+    pub fn with_min_minutes(mut self, minutes: u8, keep_arriving: bool) -> Self {
+        self.min_minutes = minutes;
+        self.keep_arriving = keep_arriving;
+        self
+    }
+
+    /// What to do with trains that aren't taking passengers, hiding them by default.
+    pub fn with_no_passenger(mut self, policy: NoPassengerPolicy) -> Self {
+        self.no_passenger = policy as u8;
+        self
+    }
+
+    /// A second api key for the client to switch to when WMATA rejects the first or it runs out
+    /// of quota, e.g. one from another developer account. Empty, the default, for none.
+    pub fn with_backup_api_key(mut self, api_key: &str) -> Result<Self, ConfigError> {
+        store_str(
+            &mut self.backup_api_key,
+            &mut self.backup_api_key_len,
+            api_key,
+        )?;
+        Ok(self)
+    }
+
+    /// Set the panel size in pixels (before rotation) and its rotation.
+    pub fn with_display(
+        mut self,
+        width: u16,
+        height: u16,
+        rotation: Rotation,
+    ) -> Result<Self, ConfigError> {
+        if width == 0 || height == 0 {
+            return Err(ConfigError::BadArgs);
+        }
+
+        self.width = width;
+        self.height = height;
+        self.rotation = rotation as u8;
+        Ok(self)
+    }
+
+    /// What has to be applied to go from running `old` to `self`, e.g. after reloading the
+    /// config from flash. Only the fields that mean something are compared, not the version or
+    /// where it came from.
+    pub fn changes_from(&self, old: &Config) -> ConfigChanges {
+        ConfigChanges {
+            wifi: self.ssid() != old.ssid() || self.pass() != old.pass(),
+            api_key: self.api_key() != old.api_key()
+                || self.backup_api_key() != old.backup_api_key(),
+            stations: self.station() != old.station()
+                || self.fallback_station() != old.fallback_station()
+                || self.destinations() != old.destinations()
+                || self.lines_mask() != old.lines_mask()
+                || self.min_minutes() != old.min_minutes()
+                || self.keeps_arriving() != old.keeps_arriving()
+                || self.no_passenger() != old.no_passenger(),
+            service_hours: self.service_hours() != old.service_hours(),
+            needs_reboot: self.power_mode() != old.power_mode()
+                || self.width() != old.width()
+                || self.height() != old.height()
+                || self.rotation() != old.rotation()
+                || self.wifi_country() != old.wifi_country()
+                || self.wifi_max_tx_power() != old.wifi_max_tx_power()
+                || self.static_ipv4() != old.static_ipv4(),
+        }
     }
 
     /// Encode self using `bincode`, prepending with a crc32 checksum, and storing in `buffer`.
@@ -107,67 +721,902 @@ impl Config {
             payload,
             bincode::config::standard().with_fixed_int_encoding(),
         )?;
+        debug_assert_eq!(len, ENCODED_LEN[CONFIG_VERSION as usize - 1]);
         let crc32 = crc32fast::hash(&payload[..len]);
         crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
 
         Ok(CHECKSUM_SZ + len)
     }
 
+    /// Decode a config written by this or any older firmware, migrating it to the current layout.
+    /// Fields added since the config was written take their default values.
+    ///
+    /// Only the first [`CONFIG_SZ`] bytes are looked at, so whatever follows in the sector, e.g.
+    /// left over from a longer layout, doesn't matter. All `0xFF` is erased flash, [`ConfigError::Empty`].
     fn from_bytes(bytes: &[u8]) -> Result<Self, ConfigError> {
-        if bytes.len() < CONFIG_SZ {
+        let Some(bytes) = bytes.get(..CONFIG_SZ) else {
             return Err(ConfigError::BufferTooSmall);
+        };
+        if bytes.iter().all(|b| *b == 0xFF) {
+            return Err(ConfigError::Empty);
         }
 
         let (crc32_bytes, payload) = bytes.split_at(CHECKSUM_SZ);
         let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
 
-        if crc32 == crc32fast::hash(payload) {
-            Ok(decode_from_slice(
-                payload,
-                bincode::config::standard().with_fixed_int_encoding(),
-            )?
-            .0)
-        } else {
-            Err(ConfigError::BadChecksum)
+        // the checksum only covers the encoded config, whose length depends on the version it was written with
+        let version = payload[0];
+        let len = match version {
+            1..=CONFIG_VERSION => ENCODED_LEN[version as usize - 1],
+            _ => return Err(ConfigError::UnsupportedVersion(version)),
+        };
+
+        if crc32 != crc32fast::hash(&payload[..len]) {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        // lay the stored prefix over a default config, so any newer fields keep their defaults
+        let mut current = [0u8; CONFIG_SZ];
+        Self::new("", "", "")?.to_bytes(&mut current)?;
+        current[CHECKSUM_SZ..CHECKSUM_SZ + len].copy_from_slice(&payload[..len]);
+
+        let mut config: Self = decode_from_slice(
+            &current[CHECKSUM_SZ..],
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?
+        .0;
+        config.version = CONFIG_VERSION;
+
+        // the checksum only proves the bytes are what was written, not that they make sense
+        let strings = [
+            (&config.ssid[..], config.ssid_len),
+            (&config.pass[..], config.pass_len),
+            (&config.api_key[..], config.api_key_len),
+            (&config.backup_api_key[..], config.backup_api_key_len),
+            (&config.ntp_server[..], config.ntp_server_len),
+        ];
+        for (bytes, len) in strings {
+            if bytes
+                .get(..len as usize)
+                .is_none_or(|s| core::str::from_utf8(s).is_err())
+            {
+                return Err(ConfigError::InvalidString);
+            }
         }
+
+        Ok(config)
     }
 
     pub fn version(&self) -> u8 {
         self.version
     }
 
-    // the following few string accessors just unwrap because they should be valid utf8, since they were passed in as &str originially.
-    // unwrap here for simpler call site
+    pub fn source(&self) -> ConfigSource {
+        self.source
+    }
+
+    pub fn power_mode(&self) -> PowerMode {
+        PowerMode::from_u8(self.power_mode).unwrap_or_default()
+    }
+
+    pub fn width(&self) -> u16 {
+        self.width
+    }
+
+    pub fn height(&self) -> u16 {
+        self.height
+    }
 
+    pub fn rotation(&self) -> Rotation {
+        Rotation::from_u8(self.rotation).unwrap_or_default()
+    }
+
+    /// Destinations to keep, see [`Config::with_destinations`].
+    /// Codes this firmware doesn't know (say, saved by a newer one) are left out.
+    pub fn destinations(&self) -> Vec<Station, { Self::MAX_DESTINATIONS }> {
+        let len = (self.destinations_len as usize).min(Self::MAX_DESTINATIONS);
+        self.destinations[..len]
+            .iter()
+            .filter_map(|code| core::str::from_utf8(code).ok())
+            .filter_map(Station::from_code)
+            .collect()
+    }
+
+    /// The lines set by [`Config::with_lines`], as a [`LineKind::bit`] each, `0` for every line.
+    pub fn lines_mask(&self) -> u8 {
+        self.lines_mask
+    }
+
+    /// The station set by [`Config::with_station`], `None` to use the default or if it's a code
+    /// this firmware doesn't know.
+    pub fn station(&self) -> Option<Station> {
+        core::str::from_utf8(&self.station)
+            .ok()
+            .and_then(Station::from_code)
+    }
+
+    /// The station set by [`Config::with_fallback_station`], `None` if there isn't one or it's a
+    /// code this firmware doesn't know.
+    pub fn fallback_station(&self) -> Option<Station> {
+        core::str::from_utf8(&self.fallback_station)
+            .ok()
+            .and_then(Station::from_code)
+    }
+
+    /// The country code set by [`Config::with_wifi_region`], `None` for `esp_radio`'s default.
+    pub fn wifi_country(&self) -> Option<&str> {
+        core::str::from_utf8(&self.wifi_country)
+            .ok()
+            .filter(|code| code.bytes().all(|b| b.is_ascii_uppercase()))
+    }
+
+    /// The transmit power cap set by [`Config::with_wifi_region`], in quarter dBm, `None` for the
+    /// driver's default.
+    pub fn wifi_max_tx_power(&self) -> Option<u8> {
+        (Self::MIN_TX_POWER..=Self::MAX_TX_POWER)
+            .contains(&self.wifi_max_tx_power)
+            .then_some(self.wifi_max_tx_power)
+    }
+
+    /// The hours set by [`Config::with_service_hours`], [`ServiceHours::ALWAYS`] if what's
+    /// stored doesn't make sense.
+    pub fn service_hours(&self) -> ServiceHours {
+        let [weekday_opens, weekday_closes, weekend_opens, weekend_closes] = self.service_hours;
+        let hours = ServiceHours {
+            weekday: ServiceWindow {
+                opens: weekday_opens,
+                closes: weekday_closes,
+            },
+            weekend: ServiceWindow {
+                opens: weekend_opens,
+                closes: weekend_closes,
+            },
+        };
+        if hours.is_valid() {
+            hours
+        } else {
+            ServiceHours::ALWAYS
+        }
+    }
+
+    /// The jitter set by [`Config::with_poll_jitter`], in percent, capped at
+    /// [`Config::MAX_POLL_JITTER`].
+    pub fn poll_jitter(&self) -> u8 {
+        self.poll_jitter.min(Self::MAX_POLL_JITTER)
+    }
+
+    /// The threshold set by [`Config::with_min_minutes`], `0` for none.
+    pub fn min_minutes(&self) -> u8 {
+        self.min_minutes
+    }
+
+    /// Whether ARR and BRD trains stay up under [`Config::min_minutes`].
+    pub fn keeps_arriving(&self) -> bool {
+        self.keep_arriving
+    }
+
+    /// The policy set by [`Config::with_no_passenger`].
+    pub fn no_passenger(&self) -> NoPassengerPolicy {
+        NoPassengerPolicy::from_u8(self.no_passenger).unwrap_or_default()
+    }
+
+    /// `interval` moved by up to [`Config::poll_jitter`] percent either way, by how far `random`
+    /// falls through its range, e.g. a fresh `Rng::random()` each poll.
+    pub fn jittered(&self, interval: Duration, random: u32) -> Duration {
+        let spread = interval.as_ticks() * u64::from(self.poll_jitter()) / 100;
+        // `random` scaled to 0..=2 * spread, then shifted down by `spread`
+        let offset = ((u128::from(2 * spread + 1) * u128::from(random)) >> 32) as u64;
+        Duration::from_ticks(interval.as_ticks() - spread + offset)
+    }
+
+    /// The fallback set by [`Config::with_static_ipv4`], `None` to rely on DHCP alone.
+    pub fn static_ipv4(&self) -> Option<StaticIpv4> {
+        let address = Ipv4Addr::from(self.static_ipv4);
+        if address.is_unspecified() || self.static_ipv4_prefix_len > 32 {
+            return None;
+        }
+
+        let stored = |octets: [u8; 4]| Some(Ipv4Addr::from(octets)).filter(|a| !a.is_unspecified());
+        Some(StaticIpv4 {
+            address,
+            prefix_len: self.static_ipv4_prefix_len,
+            gateway: stored(self.static_ipv4_gateway),
+            dns: stored(self.static_ipv4_dns),
+        })
+    }
+
+    /// The stored ssid, cut short at the first byte that isn't utf-8. See [`Config::try_ssid`].
     pub fn ssid(&self) -> &str {
-        let len = self.ssid_len as usize;
-        core::str::from_utf8(&self.ssid[..len]).unwrap()
+        stored_str(&self.ssid, self.ssid_len)
     }
 
+    /// The stored password, cut short at the first byte that isn't utf-8. See [`Config::try_pass`].
     pub fn pass(&self) -> &str {
-        let len = self.pass_len as usize;
-        core::str::from_utf8(&self.pass[..len]).unwrap()
+        stored_str(&self.pass, self.pass_len)
     }
 
+    /// The stored api key, cut short at the first byte that isn't utf-8. See [`Config::try_api_key`].
     pub fn api_key(&self) -> &str {
-        let len = self.api_key_len as usize;
-        core::str::from_utf8(&self.api_key[..len]).unwrap()
+        stored_str(&self.api_key, self.api_key_len)
+    }
+
+    /// The stored ssid, or why it isn't valid utf-8.
+    pub fn try_ssid(&self) -> Result<&str, Utf8Error> {
+        try_stored_str(&self.ssid, self.ssid_len)
+    }
+
+    /// The stored password, or why it isn't valid utf-8.
+    pub fn try_pass(&self) -> Result<&str, Utf8Error> {
+        try_stored_str(&self.pass, self.pass_len)
     }
 
+    /// The stored api key, or why it isn't valid utf-8.
+    pub fn try_api_key(&self) -> Result<&str, Utf8Error> {
+        try_stored_str(&self.api_key, self.api_key_len)
+    }
+
+    /// The key set by [`Config::with_backup_api_key`], if there is one.
+    pub fn backup_api_key(&self) -> Option<&str> {
+        Some(stored_str(&self.backup_api_key, self.backup_api_key_len))
+            .filter(|key| !key.is_empty())
+    }
+
+    /// The time server set by [`Config::with_ntp_server`].
+    pub fn ntp_server(&self) -> &str {
+        stored_str(&self.ntp_server, self.ntp_server_len)
+    }
+
+    /// Logs the password and api key in full, unlike `Config`'s own `Format`. Only for provisioning
+    /// or debugging on the bench, never on a device whose logs anyone else can read.
+    pub fn unredacted(&self) -> Unredacted<'_> {
+        Unredacted(self)
+    }
+
+    fn format_with(&self, f: defmt::Formatter, reveal: bool) {
+        let backup_api_key = self.backup_api_key().unwrap_or_default();
+        let (pass, api_key, backup_api_key) = if reveal {
+            (
+                Secret::Shown(self.pass()),
+                Secret::Shown(self.api_key()),
+                Secret::Shown(backup_api_key),
+            )
+        } else {
+            (
+                Secret::Redacted(self.pass()),
+                Secret::Redacted(self.api_key()),
+                Secret::Redacted(backup_api_key),
+            )
+        };
+        defmt::write!(
+            f,
+            "Config {{ version: {}, source: {}, ssid: {=str}, pass: {}, api_key: {}, backup_api_key: {}, power_mode: {}, width: {}, height: {}, rotation: {}, destinations: {=[?]}, station: {}, fallback_station: {}, wifi_country: {}, wifi_max_tx_power: {}, service_hours: {}, static_ipv4: {}, lines_mask: {=u8:#010b}, poll_jitter: {}%, ntp_server: {=str}, min_minutes: {}, keep_arriving: {}, no_passenger: {} }}",
+            self.version,
+            self.source,
+            self.ssid(),
+            pass,
+            api_key,
+            backup_api_key,
+            self.power_mode(),
+            self.width,
+            self.height,
+            self.rotation(),
+            &self.destinations()[..],
+            self.station(),
+            self.fallback_station(),
+            self.wifi_country(),
+            self.wifi_max_tx_power(),
+            self.service_hours(),
+            self.static_ipv4(),
+            self.lines_mask,
+            self.poll_jitter(),
+            self.ntp_server(),
+            self.min_minutes,
+            self.keep_arriving,
+            self.no_passenger(),
+        );
+    }
+
+    /// Start changing this config a field at a time, e.g. while provisioning over serial, without
+    /// erasing flash for every field. Nothing is written until [`ConfigEdit::commit`], so an edit
+    /// that's dropped or cut short by a reboot leaves the saved config as it was.
+    pub fn begin_edit(&self) -> ConfigEdit {
+        ConfigEdit {
+            config: self.clone(),
+            changed: false,
+        }
+    }
+
+    /// Write the config to both the primary and backup sectors, primary first.
+    /// If power is lost partway through, at least one of them still holds a whole config.
+    /// A sector that already holds exactly this config is left alone, so saving the same config
+    /// again, e.g. re-provisioning with the same settings, doesn't wear the flash.
     pub fn save(&self, flash: &mut FlashStorage) -> Result<(), ConfigError> {
         let mut bytes = [0u8; CONFIG_SZ];
         self.to_bytes(&mut bytes)?;
-        let offset = flash.capacity() as u32 - FlashStorage::SECTOR_SIZE;
-        flash.write(offset, &bytes)?;
+        let primary_offset = Self::primary_offset(flash)?;
+        Self::write_if_changed(flash, primary_offset, &bytes)?;
+        let backup_offset = Self::backup_offset(flash)?;
+        Self::write_if_changed(flash, backup_offset, &bytes)?;
 
         Ok(())
     }
 
+    /// Load the config from the primary sector, falling back to the backup if the primary is bad.
+    /// A good backup is copied over the bad primary before returning it.
+    ///
+    /// When both are blank, e.g. the first boot after a partition table moved storage, the last
+    /// sector of flash, where older firmware kept the config, is tried too, see
+    /// [`Config::migrate_from`].
     pub fn load(flash: &mut FlashStorage) -> Result<Self, ConfigError> {
+        let primary_offset = Self::primary_offset(flash)?;
+        let primary_err = match Self::read_at(flash, primary_offset) {
+            Ok(config) => return Ok(config),
+            Err(e) => e,
+        };
+
+        let backup_offset = Self::backup_offset(flash)?;
+        let backup = match Self::read_at(flash, backup_offset) {
+            Ok(backup) => backup,
+            Err(ConfigError::Empty) if matches!(primary_err, ConfigError::Empty) => {
+                let legacy_offset = flash.capacity() as u32 - FlashStorage::SECTOR_SIZE;
+                if legacy_offset == primary_offset {
+                    return Err(ConfigError::Empty);
+                }
+                return Self::migrate_from(flash, legacy_offset);
+            }
+            Err(_) => return Err(primary_err),
+        };
+
+        defmt::warn!(
+            "primary config is bad ({}), repairing from backup",
+            primary_err
+        );
+        let mut bytes = [0u8; CONFIG_SZ];
+        backup.to_bytes(&mut bytes)?;
+        flash.write(primary_offset, &bytes)?;
+
+        Ok(backup)
+    }
+
+    /// The config to boot with, best first: `saved`, as [`Config::load`] returned it, then
+    /// whatever `from_env` builds from the compiled-in environment, only called if it's needed.
+    /// `None` when there's neither, e.g. a generic firmware flashed onto a blank board, for the
+    /// device to wait to be provisioned rather than fault.
+    pub fn resolve(
+        saved: &Result<Self, ConfigError>,
+        from_env: impl FnOnce() -> Option<Self>,
+    ) -> Option<Self> {
+        saved.as_ref().ok().cloned().or_else(from_env)
+    }
+
+    /// Copy a good config at `offset` into the primary and backup sectors and return it.
+    /// Anything else there, like whatever partition took the old place over, is
+    /// [`ConfigError::Empty`], since there's no config to carry over, and isn't touched.
+    fn migrate_from(flash: &mut FlashStorage, offset: u32) -> Result<Self, ConfigError> {
+        let Ok(config) = Self::read_at(flash, offset) else {
+            return Err(ConfigError::Empty);
+        };
+
+        defmt::info!("carrying the config over from {=u32:#x}", offset);
+        config.save(flash)?;
+        Ok(config)
+    }
+
+    fn read_at(flash: &mut FlashStorage, offset: u32) -> Result<Self, ConfigError> {
         let mut bytes = [0u8; CONFIG_SZ];
-        let offset = flash.capacity() as u32 - FlashStorage::SECTOR_SIZE;
         flash.read(offset, &mut bytes)?;
 
         Self::from_bytes(&bytes)
     }
+
+    /// Write `bytes` at `offset` unless they're already there. The checksum is part of `bytes`,
+    /// so a sector with a bad or missing one never matches and is always written.
+    fn write_if_changed(
+        flash: &mut FlashStorage,
+        offset: u32,
+        bytes: &[u8; CONFIG_SZ],
+    ) -> Result<(), ConfigError> {
+        let mut current = [0u8; CONFIG_SZ];
+        flash.read(offset, &mut current)?;
+        if current != *bytes {
+            flash.write(offset, bytes)?;
+        }
+
+        Ok(())
+    }
+
+    /// The last sector of the storage partition, or of flash without one, see [`storage_end`].
+    /// [`ConfigError::NoStorage`] if that region isn't safe to write, see [`storage_start`].
+    fn primary_offset(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+        storage_start(flash).ok_or(ConfigError::NoStorage)?;
+        Ok(storage_end(flash)? - FlashStorage::SECTOR_SIZE)
+    }
+
+    /// The sector just before the primary.
+    fn backup_offset(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+        storage_start(flash).ok_or(ConfigError::NoStorage)?;
+        Ok(storage_end(flash)? - 2 * FlashStorage::SECTOR_SIZE)
+    }
+}
+
+impl defmt::Format for Config {
+    fn format(&self, f: defmt::Formatter) {
+        self.format_with(f, false);
+    }
+}
+
+/// Changes to a [`Config`] gathered in RAM and saved together, from [`Config::begin_edit`].
+///
+/// Every save erases and rewrites both config sectors, so setting the SSID, password and api key
+/// one after another would wear the flash three times over for one new config.
+pub struct ConfigEdit {
+    config: Config,
+    changed: bool,
+}
+
+impl ConfigEdit {
+    pub fn set_ssid(&mut self, ssid: &str) -> Result<(), ConfigError> {
+        store_str(&mut self.config.ssid, &mut self.config.ssid_len, ssid)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn set_pass(&mut self, pass: &str) -> Result<(), ConfigError> {
+        store_str(&mut self.config.pass, &mut self.config.pass_len, pass)?;
+        self.changed = true;
+        Ok(())
+    }
+
+    pub fn set_api_key(&mut self, api_key: &str) -> Result<(), ConfigError> {
+        store_str(
+            &mut self.config.api_key,
+            &mut self.config.api_key_len,
+            api_key,
+        )?;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Empty for none, see [`Config::with_backup_api_key`].
+    pub fn set_backup_api_key(&mut self, api_key: &str) -> Result<(), ConfigError> {
+        store_str(
+            &mut self.config.backup_api_key,
+            &mut self.config.backup_api_key_len,
+            api_key,
+        )?;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// Change anything else through the config's own builder methods, e.g.
+    /// `edit.update(|cfg| Ok(cfg.with_station(Station::K04)))`. If `f` errors the edit is left as
+    /// it was.
+    pub fn update(
+        &mut self,
+        f: impl FnOnce(Config) -> Result<Config, ConfigError>,
+    ) -> Result<(), ConfigError> {
+        self.config = f(self.config.clone())?;
+        self.changed = true;
+        Ok(())
+    }
+
+    /// The config as it would be saved now.
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Save the edited config in a single [`Config::save`], returning it to run with. Doesn't touch
+    /// flash at all if nothing was changed.
+    pub fn commit(self, flash: &mut FlashStorage) -> Result<Config, ConfigError> {
+        if self.changed {
+            self.config.save(flash)?;
+        }
+        Ok(self.config)
+    }
+}
+
+/// A [`Config`] that logs its secrets, from [`Config::unredacted`].
+pub struct Unredacted<'a>(&'a Config);
+
+impl defmt::Format for Unredacted<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        self.0.format_with(f, true);
+    }
+}
+
+pub(crate) enum Secret<'a> {
+    Shown(&'a str),
+    /// Just the length, and the first and last characters of anything long enough that they
+    /// give little away, which is enough to tell which key or password is set.
+    Redacted(&'a str),
+}
+
+impl defmt::Format for Secret<'_> {
+    fn format(&self, f: defmt::Formatter) {
+        match self {
+            Secret::Shown(s) => defmt::write!(f, "{=str}", s),
+            Secret::Redacted("") => defmt::write!(f, "<empty>"),
+            Secret::Redacted(s) => {
+                let len = s.chars().count();
+                match (s.chars().next(), s.chars().next_back()) {
+                    (Some(first), Some(last)) if len >= 8 => {
+                        defmt::write!(f, "<{=usize} chars, {=char}...{=char}>", len, first, last)
+                    }
+                    _ => defmt::write!(f, "<{=usize} chars>", len),
+                }
+            }
+        }
+    }
+}
+
+/// Copy `s` into a fixed size field and its length, as [`Config::new`] does.
+/// Errors without changing either if it doesn't fit.
+fn store_str(bytes: &mut [u8], len: &mut u8, s: &str) -> Result<(), ConfigError> {
+    if s.len() > bytes.len() {
+        return Err(ConfigError::BadArgs);
+    }
+
+    bytes.fill(0);
+    bytes[..s.len()].copy_from_slice(s.as_bytes());
+    *len = s.len() as u8;
+    Ok(())
+}
+
+/// Whether `host` could be a host name: dot separated labels of letters, digits and hyphens, up
+/// to 63 characters each and not starting or ending with a hyphen. Dotted IPv4 addresses pass too.
+fn is_host_name(host: &str) -> bool {
+    let host = host.strip_suffix('.').unwrap_or(host);
+    !host.is_empty()
+        && host.len() <= NTP_SERVER_MAX_LEN
+        && host.split('.').all(|label| {
+            (1..=63).contains(&label.len())
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// The first `len` bytes of `bytes` as a string, cut short at the stored length or the first
+/// invalid byte instead of panicking, in case they didn't come through [`Config::from_bytes`].
+fn stored_str(bytes: &[u8], len: u8) -> &str {
+    let bytes = &bytes[..(len as usize).min(bytes.len())];
+    match core::str::from_utf8(bytes) {
+        Ok(s) => s,
+        // can't fail, it's the valid prefix
+        Err(e) => core::str::from_utf8(&bytes[..e.valid_up_to()]).unwrap_or_default(),
+    }
+}
+
+/// `len` bytes of `bytes` as a `&str`, if they're all utf-8.
+fn try_stored_str(bytes: &[u8], len: u8) -> Result<&str, Utf8Error> {
+    core::str::from_utf8(&bytes[..(len as usize).min(bytes.len())])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 4MB like the board's, with no partition table, so storage ends at the end of flash in
+    /// every test. It's only looked up once, see [`storage_end`].
+    fn flash() -> FlashStorage {
+        FlashStorage::new(4 * 1024 * 1024)
+    }
+
+    fn config() -> Config {
+        Config::new("home", "hunter22", "0123456789abcdef").unwrap()
+    }
+
+    /// [`config`] encoded, with its ssid swapped for bytes that aren't utf8 and the checksum
+    /// redone to match, like a bad write that was then checksummed.
+    fn bad_utf8_ssid() -> [u8; CONFIG_SZ] {
+        let mut bytes = [0u8; CONFIG_SZ];
+        config().to_bytes(&mut bytes).unwrap();
+        let ssid = bytes.windows(4).position(|w| w == b"home").unwrap();
+        bytes[ssid..ssid + 4].copy_from_slice(&[0xC3, 0x28, 0xFF, 0xFE]);
+
+        let len = ENCODED_LEN[CONFIG_VERSION as usize - 1];
+        let crc32 = crc32fast::hash(&bytes[CHECKSUM_SZ..CHECKSUM_SZ + len]);
+        bytes[..CHECKSUM_SZ].copy_from_slice(&crc32.to_le_bytes());
+        bytes
+    }
+
+    #[test]
+    fn bad_primary_is_repaired_from_the_backup() {
+        let mut flash = flash();
+        config().save(&mut flash).unwrap();
+        let primary = Config::primary_offset(&mut flash).unwrap();
+        flash.write(primary + 10, &[0x00; 4]).unwrap();
+        assert!(matches!(
+            Config::read_at(&mut flash, primary),
+            Err(ConfigError::BadChecksum)
+        ));
+
+        let loaded = Config::load(&mut flash).unwrap();
+        assert_eq!(loaded.ssid(), "home");
+        assert_eq!(Config::read_at(&mut flash, primary).unwrap().ssid(), "home");
+    }
+
+    #[test]
+    fn bad_utf8_is_an_error_not_a_panic() {
+        let bytes = bad_utf8_ssid();
+        assert!(matches!(
+            Config::from_bytes(&bytes),
+            Err(ConfigError::InvalidString)
+        ));
+
+        let mut flash = flash();
+        let primary = Config::primary_offset(&mut flash).unwrap();
+        let backup = Config::backup_offset(&mut flash).unwrap();
+        flash.write(primary, &bytes).unwrap();
+        flash.write(backup, &bytes).unwrap();
+        assert!(Config::load(&mut flash).is_err());
+    }
+
+    #[test]
+    fn clone_encodes_the_same() {
+        let original = config().with_station(Station::K04);
+        let copy = original.clone();
+        let (mut a, mut b) = ([0u8; CONFIG_SZ], [0u8; CONFIG_SZ]);
+        original.to_bytes(&mut a).unwrap();
+        copy.to_bytes(&mut b).unwrap();
+        assert_eq!(a, b);
+        assert!(!copy.changes_from(&original).any());
+    }
+
+    #[test]
+    fn config_at_the_old_place_is_carried_over() {
+        let mut flash = flash();
+        let old = 0x20_0000;
+        let mut bytes = [0u8; CONFIG_SZ];
+        config().to_bytes(&mut bytes).unwrap();
+        flash.write(old, &bytes).unwrap();
+        assert!(matches!(Config::load(&mut flash), Err(ConfigError::Empty)));
+
+        let migrated = Config::migrate_from(&mut flash, old).unwrap();
+        assert_eq!(migrated.ssid(), "home");
+        let primary = Config::primary_offset(&mut flash).unwrap();
+        let backup = Config::backup_offset(&mut flash).unwrap();
+        assert_eq!(Config::read_at(&mut flash, primary).unwrap().ssid(), "home");
+        assert_eq!(Config::read_at(&mut flash, backup).unwrap().ssid(), "home");
+
+        // whatever else is there now isn't a config
+        flash.write(old, &[0x42; CONFIG_SZ]).unwrap();
+        assert!(matches!(
+            Config::migrate_from(&mut flash, old),
+            Err(ConfigError::Empty)
+        ));
+    }
+
+    #[test]
+    fn changes_say_what_to_apply() {
+        let old = config().with_station(Station::K04);
+        assert_eq!(old.changes_from(&old), ConfigChanges::default());
+
+        let new = Config::new("cafe", "hunter22", "0123456789abcdef")
+            .unwrap()
+            .with_station(Station::K04);
+        assert_eq!(
+            new.changes_from(&old),
+            ConfigChanges {
+                wifi: true,
+                ..Default::default()
+            }
+        );
+
+        let new = Config::new("home", "hunter22", "fedcba9876543210")
+            .unwrap()
+            .with_station(Station::C01);
+        let changes = new.changes_from(&old);
+        assert!(changes.api_key && changes.stations);
+        assert!(!changes.wifi && !changes.needs_reboot);
+
+        let new = old.clone().with_power_mode(PowerMode::Disconnect);
+        assert_eq!(
+            new.changes_from(&old),
+            ConfigChanges {
+                needs_reboot: true,
+                ..Default::default()
+            }
+        );
+        assert!(new.changes_from(&old).any());
+    }
+
+    #[test]
+    fn erased_is_empty_and_trailing_bytes_are_ignored() {
+        assert!(matches!(
+            Config::from_bytes(&[0xFF; CONFIG_SZ]),
+            Err(ConfigError::Empty)
+        ));
+        assert!(matches!(
+            Config::from_bytes(&[0xFF; CONFIG_SZ - 1]),
+            Err(ConfigError::BufferTooSmall)
+        ));
+
+        let mut sector = [0xA5u8; FlashStorage::SECTOR_SIZE as usize];
+        config().to_bytes(&mut sector).unwrap();
+        assert_eq!(Config::from_bytes(&sector).unwrap().ssid(), "home");
+
+        sector[..CONFIG_SZ].fill(0xFF);
+        assert!(matches!(
+            Config::from_bytes(&sector),
+            Err(ConfigError::Empty)
+        ));
+    }
+
+    #[test]
+    fn service_hours_count_the_late_night_before() {
+        use Weekday::*;
+        let at = |weekday, hour, minute| LocalTime {
+            weekday,
+            hour,
+            minute,
+        };
+        let hours = ServiceHours::WMATA;
+
+        assert_eq!(hours.until_open(at(Monday, 4, 59)), 1);
+        assert!(hours.in_service(at(Monday, 5, 0)));
+        assert!(hours.in_service(at(Monday, 23, 59)));
+        // Friday's trains run on past midnight into Saturday
+        assert!(hours.in_service(at(Saturday, 1, 59)));
+        assert_eq!(hours.until_open(at(Saturday, 2, 0)), 5 * 60);
+        assert_eq!(hours.until_open(at(Sunday, 6, 59)), 1);
+        assert!(!hours.in_service(at(Monday, 2, 30)));
+
+        assert!(ServiceHours::ALWAYS.in_service(at(Wednesday, 3, 0)));
+
+        let closes_before_it_opens = ServiceHours {
+            weekday: ServiceWindow {
+                opens: 10 * 60,
+                closes: 9 * 60,
+            },
+            ..ServiceHours::WMATA
+        };
+        assert!(matches!(
+            config().with_service_hours(closes_before_it_opens),
+            Err(ConfigError::BadArgs)
+        ));
+        let saved = config().with_service_hours(hours).unwrap();
+        assert_eq!(saved.service_hours(), hours);
+    }
+
+    #[test]
+    fn try_accessors_report_bad_utf8() {
+        // `from_bytes` turns a good checksum over bad utf8 away, so put the bytes in by hand
+        assert!(matches!(
+            Config::from_bytes(&bad_utf8_ssid()),
+            Err(ConfigError::InvalidString)
+        ));
+        let mut config = config();
+        config.ssid[..4].copy_from_slice(&[0xC3, 0x28, 0xFF, 0xFE]);
+
+        assert_eq!(config.try_ssid().unwrap_err().valid_up_to(), 0);
+        assert_eq!(config.ssid(), "");
+        assert_eq!(config.try_pass(), Ok("hunter22"));
+        assert_eq!(config.try_api_key(), Ok("0123456789abcdef"));
+    }
+
+    #[test]
+    fn an_edit_is_saved_in_one_go() {
+        let mut flash = flash();
+        config().save(&mut flash).unwrap();
+        let before = flash.writes();
+
+        let mut edit = Config::load(&mut flash).unwrap().begin_edit();
+        edit.set_ssid("office").unwrap();
+        edit.set_pass("correct horse").unwrap();
+        edit.set_api_key("fedcba9876543210").unwrap();
+        assert_eq!(flash.writes(), before);
+        assert_eq!(Config::load(&mut flash).unwrap().ssid(), "home");
+
+        edit.commit(&mut flash).unwrap();
+        // one save is a write to each of the primary and backup sectors
+        assert_eq!(flash.writes(), before + 2);
+        let saved = Config::load(&mut flash).unwrap();
+        assert_eq!(saved.ssid(), "office");
+        assert_eq!(saved.pass(), "correct horse");
+        assert_eq!(saved.api_key(), "fedcba9876543210");
+
+        saved.begin_edit().set_ssid("elsewhere").unwrap();
+        assert_eq!(Config::load(&mut flash).unwrap().ssid(), "office");
+        assert_eq!(flash.writes(), before + 2);
+    }
+
+    #[test]
+    fn dhcp_falls_back_to_static_ipv4_then_ipv6() {
+        let ip = StaticIpv4 {
+            address: Ipv4Addr::new(192, 168, 1, 50),
+            prefix_len: 24,
+            gateway: Some(Ipv4Addr::new(192, 168, 1, 1)),
+            dns: None,
+        };
+        let with_ip = config().with_static_ipv4(Some(ip)).unwrap();
+        assert_eq!(with_ip.static_ipv4(), Some(ip));
+        assert_eq!(
+            DhcpFallback::choose(with_ip.static_ipv4(), true),
+            DhcpFallback::Static(ip)
+        );
+        assert_eq!(
+            DhcpFallback::choose(config().static_ipv4(), true),
+            DhcpFallback::Ipv6Only
+        );
+        assert_eq!(
+            DhcpFallback::choose(config().static_ipv4(), false),
+            DhcpFallback::Wait
+        );
+    }
+
+    #[test]
+    fn jittered_interval_stays_within_bounds() {
+        let interval = Duration::from_secs(10);
+        let config = config();
+        assert_eq!(config.poll_jitter(), Config::DEFAULT_POLL_JITTER);
+        assert_eq!(config.jittered(interval, 0), Duration::from_secs(9));
+        assert_eq!(config.jittered(interval, u32::MAX), Duration::from_secs(11));
+        for random in [1, 0x1234_5678, u32::MAX / 2, 0xDEAD_BEEF, u32::MAX - 1] {
+            let jittered = config.jittered(interval, random);
+            assert!((Duration::from_secs(9)..=Duration::from_secs(11)).contains(&jittered));
+        }
+
+        let steady = config.clone().with_poll_jitter(0).unwrap();
+        assert_eq!(steady.jittered(interval, u32::MAX), interval);
+        assert!(matches!(
+            config.with_poll_jitter(Config::MAX_POLL_JITTER + 1),
+            Err(ConfigError::BadArgs)
+        ));
+    }
+
+    #[test]
+    fn ntp_server_survives_a_save() {
+        let mut flash = flash();
+        assert_eq!(config().ntp_server(), Config::DEFAULT_NTP_SERVER);
+        let saved = config().with_ntp_server("ntp.corp.example").unwrap();
+        saved.save(&mut flash).unwrap();
+
+        let loaded = Config::load(&mut flash).unwrap();
+        assert_eq!(loaded.ntp_server(), "ntp.corp.example");
+        // nothing reads it at boot yet, so a new one doesn't need a reboot
+        assert!(!loaded.changes_from(&config()).any());
+        assert!(matches!(
+            config().with_ntp_server("not a host"),
+            Err(ConfigError::BadArgs)
+        ));
+    }
+
+    #[test]
+    fn config_comes_from_flash_then_env_then_provisioning() {
+        let built_in = || Config::new("env", "from-env", "fedcba9876543210").ok();
+
+        // flash first, without even building the env one
+        let saved = Ok(config());
+        let cfg = Config::resolve(&saved, || panic!("env config built with one saved")).unwrap();
+        assert_eq!(cfg.ssid(), "home");
+
+        // then the env
+        let cfg = Config::resolve(&Err(ConfigError::Empty), built_in).unwrap();
+        assert_eq!(cfg.ssid(), "env");
+        let cfg = Config::resolve(&Err(ConfigError::BadChecksum), built_in).unwrap();
+        assert_eq!(cfg.ssid(), "env");
+
+        // and with neither, waiting to be provisioned
+        assert!(Config::resolve(&Err(ConfigError::Empty), || None).is_none());
+    }
+
+    #[test]
+    fn saving_the_same_config_again_writes_nothing() {
+        let mut flash = flash();
+        config().save(&mut flash).unwrap();
+        let after_first = flash.writes();
+        config().save(&mut flash).unwrap();
+        assert_eq!(flash.writes(), after_first);
+
+        // a primary with a bad checksum is still written over, and only it
+        let primary = Config::primary_offset(&mut flash).unwrap();
+        flash.write(primary, &[0]).unwrap();
+        let corrupted = flash.writes();
+        config().save(&mut flash).unwrap();
+        assert_eq!(flash.writes(), corrupted + 1);
+        assert_eq!(Config::load(&mut flash).unwrap().ssid(), "home");
+    }
 }
@@ -0,0 +1,43 @@
+//! Canned predictions for the `offline` feature, for demos and UI work without Wi-Fi or an api key.
+
+use crate::wmata::types::{Eta, LineKind, NextTrain, NextTrainsResponse, Station};
+use embassy_time::Instant;
+
+/// How long each step of the cycle lasts, roughly one poll.
+const STEP_SECS: u64 = 10;
+
+/// Line, car count, destination, and minutes away at the start of the cycle.
+/// Covers every line and the longest destination names the api sends.
+const TRAINS: &[(LineKind, u8, Station, &str, u8)] = &[
+    (LineKind::RD, 8, Station::B11, "Glenmont", 2),
+    (LineKind::BL, 6, Station::J03, "Franconia-Springfield", 5),
+    (LineKind::SV, 8, Station::N12, "Ashburn", 1),
+    (LineKind::OR, 6, Station::D13, "New Carrollton", 9),
+    (LineKind::YL, 8, Station::C15, "Huntington", 14),
+    (LineKind::GN, 8, Station::F11, "Branch Avenue", 3),
+    (LineKind::RD, 8, Station::A15, "Shady Grove", 20),
+];
+
+/// Predictions for `location` at this point in the cycle. Each train counts down a minute per step,
+/// goes through ARR and BRD, then starts over further out, so the list keeps changing like the real thing.
+pub(crate) fn next_trains(location: Station) -> NextTrainsResponse {
+    let step = Instant::now().as_secs() / STEP_SECS;
+    let trains = TRAINS
+        .iter()
+        .map(|&(line, cars, destination, name, start)| {
+            // counts down from `start` to 1, then ARR, then BRD, then back to `start`
+            let period = u64::from(start) + 2;
+            let eta = match (u64::from(start) + 1 + period - step % period) % period {
+                0 => Eta::Boarding,
+                1 => Eta::Arriving,
+                m => Eta::Minutes((m - 1) as u8),
+            };
+
+            NextTrain::new(location, line, name, eta)
+                .with_cars(cars)
+                .with_destination_code(destination)
+        })
+        .collect();
+
+    NextTrainsResponse::new(trains)
+}
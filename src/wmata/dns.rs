@@ -0,0 +1,226 @@
+use core::{cell::RefCell, net::IpAddr};
+
+use defmt::{debug, warn};
+use embassy_time::{Duration, Instant, Timer};
+use embedded_nal_async::{AddrType, Dns};
+use heapless::String;
+
+/// Remembers the last resolved host, so polling the api doesn't cost a DNS round trip every time.
+///
+/// `DnsSocket` doesn't expose record TTLs, so entries live for a fixed `ttl` instead.
+/// Pair it with [`CachedDns`] for the resolver and [`Client::with_dns_cache`](crate::wmata::Client::with_dns_cache)
+/// so the client can drop the entry when a connect fails, in case the server moved.
+pub struct DnsCache {
+    ttl: Duration,
+    entry: RefCell<Option<Entry>>,
+}
+
+struct Entry {
+    host: String<64>,
+    addr_type: AddrType,
+    addr: IpAddr,
+    expires: Instant,
+}
+
+impl DnsCache {
+    pub const DEFAULT_TTL: Duration = Duration::from_secs(300);
+
+    pub const fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entry: RefCell::new(None),
+        }
+    }
+
+    /// Forget the cached address, so the next lookup goes to the network.
+    pub fn invalidate(&self) {
+        self.entry.replace(None);
+    }
+
+    fn get(&self, host: &str, addr_type: &AddrType) -> Option<IpAddr> {
+        let entry = self.entry.borrow();
+        let entry = entry.as_ref()?;
+        let fresh = Instant::now() < entry.expires;
+
+        (fresh && entry.host == host && entry.addr_type == *addr_type).then_some(entry.addr)
+    }
+
+    fn insert(&self, host: &str, addr_type: AddrType, addr: IpAddr) {
+        // hosts too long to cache just get resolved every time
+        let Ok(host) = String::try_from(host) else {
+            return;
+        };
+
+        self.entry.replace(Some(Entry {
+            host,
+            addr_type,
+            addr,
+            expires: Instant::now() + self.ttl,
+        }));
+    }
+}
+
+impl Default for DnsCache {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_TTL)
+    }
+}
+
+/// A resolver that answers from a [`DnsCache`] when it can, and fills it when it can't.
+pub struct CachedDns<'c, D> {
+    inner: D,
+    cache: &'c DnsCache,
+}
+
+impl<'c, D: Dns> CachedDns<'c, D> {
+    pub fn new(inner: D, cache: &'c DnsCache) -> Self {
+        Self { inner, cache }
+    }
+}
+
+impl<D: Dns> Dns for CachedDns<'_, D> {
+    type Error = D::Error;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        if let Some(addr) = self.cache.get(host, &addr_type) {
+            return Ok(addr);
+        }
+
+        let addr = self.inner.get_host_by_name(host, addr_type.clone()).await?;
+        self.cache.insert(host, addr_type, addr);
+
+        Ok(addr)
+    }
+
+    async fn get_host_by_address(
+        &self,
+        addr: IpAddr,
+        result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        self.inner.get_host_by_address(addr, result).await
+    }
+}
+
+/// A resolver that tries a failed lookup again after a short, doubling backoff.
+///
+/// Lookups right after boot often fail while the network is still settling, which would otherwise
+/// fail the first few polls. Only lookups are retried, a request that fails later (connecting,
+/// reading) isn't. Put it inside [`CachedDns`], so answers from the cache skip it entirely.
+/// The retries count against the client's request timeout, so keep `attempts` and `backoff` small.
+pub struct RetryDns<D> {
+    inner: D,
+    attempts: u8,
+    backoff: Duration,
+}
+
+impl<D: Dns> RetryDns<D> {
+    pub const DEFAULT_ATTEMPTS: u8 = 3;
+    pub const DEFAULT_BACKOFF: Duration = Duration::from_millis(250);
+
+    pub fn new(inner: D) -> Self {
+        Self {
+            inner,
+            attempts: Self::DEFAULT_ATTEMPTS,
+            backoff: Self::DEFAULT_BACKOFF,
+        }
+    }
+
+    /// Try each lookup up to `attempts` times (at least once), waiting `backoff` before the first
+    /// retry and twice as long before each one after that.
+    pub fn with_retries(mut self, attempts: u8, backoff: Duration) -> Self {
+        self.attempts = attempts.max(1);
+        self.backoff = backoff;
+        self
+    }
+}
+
+impl<D: Dns> Dns for RetryDns<D> {
+    type Error = D::Error;
+
+    async fn get_host_by_name(
+        &self,
+        host: &str,
+        addr_type: AddrType,
+    ) -> Result<IpAddr, Self::Error> {
+        let mut backoff = self.backoff;
+        let mut attempt = 1;
+        loop {
+            debug!(
+                "dns: resolving {=str}, attempt {}/{}",
+                host, attempt, self.attempts
+            );
+            match self.inner.get_host_by_name(host, addr_type.clone()).await {
+                Ok(addr) => return Ok(addr),
+                Err(e) if attempt >= self.attempts => return Err(e),
+                Err(_) => {
+                    warn!(
+                        "dns: resolving {=str} failed, retrying in {}ms",
+                        host,
+                        backoff.as_millis()
+                    );
+                    Timer::after(backoff).await;
+                    backoff *= 2;
+                    attempt += 1;
+                }
+            }
+        }
+    }
+
+    async fn get_host_by_address(
+        &self,
+        addr: IpAddr,
+        result: &mut [u8],
+    ) -> Result<usize, Self::Error> {
+        self.inner.get_host_by_address(addr, result).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::wmata::mock::{self, MockDns, MockNet};
+
+    #[test]
+    fn cached_address_is_reused_until_connecting_fails() {
+        let net = MockNet::default();
+        net.reply_ok("{}").reply_ok("{}").refuse().reply_ok("{}");
+        let dns = MockDns::default();
+        let cache = DnsCache::default();
+        let resolver = CachedDns::new(&dns, &cache);
+        let mut rx_buf = [0; 1024];
+        let mut client = mock::client(&net, &resolver, &mut rx_buf).with_dns_cache(&cache);
+
+        block_on(client.ping()).unwrap();
+        block_on(client.ping()).unwrap();
+        assert_eq!(dns.lookups.get(), 1);
+
+        assert!(block_on(client.ping()).is_err());
+        assert_eq!(dns.lookups.get(), 1);
+        block_on(client.ping()).unwrap();
+        assert_eq!(dns.lookups.get(), 2);
+    }
+
+    #[test]
+    fn lookups_are_retried_until_one_succeeds() {
+        let net = MockNet::default();
+        net.reply_ok("{}").reply_ok("{}");
+        let dns = MockDns::default();
+        dns.failures.set(2);
+        let resolver = RetryDns::new(&dns).with_retries(3, Duration::from_millis(1));
+        let mut rx_buf = [0; 1024];
+        let mut client = mock::client(&net, &resolver, &mut rx_buf);
+
+        block_on(client.ping()).unwrap();
+        assert_eq!(dns.lookups.get(), 3);
+
+        dns.failures.set(3);
+        assert!(block_on(client.ping()).is_err());
+        assert_eq!(dns.lookups.get(), 6);
+    }
+}
@@ -0,0 +1,270 @@
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use embassy_time::Instant;
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    flash::FlashStorage,
+    partition::storage_end,
+};
+
+/// Bytes per entry: the checksum and [`Stored`], padded to a multiple of the flash word size.
+const SLOT_SZ: usize = 16;
+const STORED_SZ: usize = 11;
+const SLOTS_PER_SECTOR: usize = EventLog::CAPACITY / 2;
+
+/// Something worth knowing about after the fact, for diagnosing a device in the field.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+#[non_exhaustive]
+pub enum Event {
+    /// A cold boot, not a wake from deep sleep.
+    Boot,
+    /// The config loaded from flash.
+    ConfigLoaded,
+    /// Neither config copy in flash was usable, so the build-time environment was used.
+    ConfigLoadFailed,
+    /// The AP dropped us, with the 802.11 reason code, e.g. 200 for a lost beacon.
+    WifiDisconnected { reason: u8 },
+    /// Fetching predictions failed, with the [`Error::code`](crate::wmata::Error::code) of the error.
+    FetchFailed { code: u16 },
+}
+
+/// One recorded [`Event`].
+#[derive(Copy, Clone, Debug, defmt::Format)]
+pub struct LogEntry {
+    /// Counts up across reboots, so it orders entries from different boots.
+    pub seq: u32,
+    /// Uptime when it was recorded. There's no wall clock, so compare it against `Boot` entries.
+    pub uptime_secs: u32,
+    pub event: Event,
+}
+
+#[derive(Encode, Decode)]
+struct Stored {
+    seq: u32,
+    uptime_secs: u32,
+    kind: u8,
+    detail: u16,
+}
+
+/// A ring of the last [`EventLog::CAPACITY`] events in its own two flash sectors, each entry with a crc32 checksum.
+///
+/// `Storage::write` erases a whole sector on every write, far too often for a log. Entries are
+/// written straight into erased space instead, and a sector is only erased when the ring comes
+/// back round to it. That drops its older half, so between half and all of `CAPACITY` are kept.
+/// Entries that fail their checksum, like one cut off by a power loss, are skipped.
+pub struct EventLog {
+    next_seq: u32,
+    next_slot: usize,
+}
+
+impl Event {
+    fn to_parts(self) -> (u8, u16) {
+        match self {
+            Event::Boot => (0, 0),
+            Event::ConfigLoaded => (1, 0),
+            Event::ConfigLoadFailed => (2, 0),
+            Event::WifiDisconnected { reason } => (3, reason.into()),
+            Event::FetchFailed { code } => (4, code),
+        }
+    }
+
+    /// `None` for kinds this firmware doesn't know, e.g. written by a newer one.
+    fn from_parts(kind: u8, detail: u16) -> Option<Self> {
+        match kind {
+            0 => Some(Event::Boot),
+            1 => Some(Event::ConfigLoaded),
+            2 => Some(Event::ConfigLoadFailed),
+            3 => Some(Event::WifiDisconnected {
+                reason: detail as u8,
+            }),
+            4 => Some(Event::FetchFailed { code: detail }),
+            _ => None,
+        }
+    }
+}
+
+impl EventLog {
+    pub const CAPACITY: usize = 64;
+
+    /// Find where the log left off, so new entries carry on after the newest one.
+    pub fn open(flash: &mut FlashStorage) -> Result<Self, ConfigError> {
+        let mut newest: Option<(usize, u32)> = None;
+        for slot in 0..Self::CAPACITY {
+            if let Some(entry) = Self::read_slot(flash, slot)?
+                && newest.is_none_or(|(_, seq)| entry.seq > seq)
+            {
+                newest = Some((slot, entry.seq));
+            }
+        }
+
+        Ok(match newest {
+            Some((slot, seq)) => Self {
+                next_seq: seq.wrapping_add(1),
+                next_slot: (slot + 1) % Self::CAPACITY,
+            },
+            None => Self {
+                next_seq: 0,
+                next_slot: 0,
+            },
+        })
+    }
+
+    /// Append `event`, stamped with the current uptime.
+    pub fn record(&mut self, flash: &mut FlashStorage, event: Event) -> Result<(), ConfigError> {
+        // a slot that isn't blank was half written when power went, and can't be written over without an erase
+        let mut bytes = [0u8; SLOT_SZ];
+        loop {
+            if self.next_slot.is_multiple_of(SLOTS_PER_SECTOR) {
+                let from = Self::slot_offset(flash, self.next_slot)?;
+                flash.erase(from, from + FlashStorage::SECTOR_SIZE)?;
+                break;
+            }
+            let offset = Self::slot_offset(flash, self.next_slot)?;
+            flash.read(offset, &mut bytes)?;
+            if bytes.iter().all(|b| *b == 0xFF) {
+                break;
+            }
+            self.next_slot = (self.next_slot + 1) % Self::CAPACITY;
+        }
+
+        let (kind, detail) = event.to_parts();
+        let stored = Stored {
+            seq: self.next_seq,
+            uptime_secs: Instant::now().as_secs() as u32,
+            kind,
+            detail,
+        };
+
+        // unused bytes stay 0xFF, i.e. unprogrammed
+        let mut bytes = [0xFFu8; SLOT_SZ];
+        let (crc32_bytes, payload) = bytes.split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+        let offset = Self::slot_offset(flash, self.next_slot)?;
+        flash.write(offset, &bytes)?;
+
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.next_slot = (self.next_slot + 1) % Self::CAPACITY;
+        Ok(())
+    }
+
+    /// Every entry still in the log, oldest first, e.g. to dump over serial at boot.
+    pub fn entries(
+        flash: &mut FlashStorage,
+    ) -> Result<Vec<LogEntry, { Self::CAPACITY }>, ConfigError> {
+        let mut entries: Vec<LogEntry, { Self::CAPACITY }> = Vec::new();
+        for slot in 0..Self::CAPACITY {
+            if let Some(entry) = Self::read_slot(flash, slot)? {
+                // can't fail, one entry per slot
+                let _ = entries.push(entry);
+            }
+        }
+
+        entries.sort_unstable_by_key(|entry| entry.seq);
+        Ok(entries)
+    }
+
+    /// `None` for a blank or corrupt slot.
+    fn read_slot(flash: &mut FlashStorage, slot: usize) -> Result<Option<LogEntry>, ConfigError> {
+        let mut bytes = [0u8; SLOT_SZ];
+        let offset = Self::slot_offset(flash, slot)?;
+        flash.read(offset, &mut bytes)?;
+
+        let (crc32_bytes, payload) = bytes.split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(&payload[..STORED_SZ]) {
+            return Ok(None);
+        }
+
+        let Ok((stored, _)) = decode_from_slice::<Stored, _>(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(
+            Event::from_parts(stored.kind, stored.detail).map(|event| LogEntry {
+                seq: stored.seq,
+                uptime_secs: stored.uptime_secs,
+                event,
+            }),
+        )
+    }
+
+    /// The two sectors before the request counter, see [`RequestCounter::save`](crate::wmata::RequestCounter::save).
+    fn slot_offset(flash: &mut FlashStorage, slot: usize) -> Result<u32, ConfigError> {
+        let start = storage_end(flash)? - 5 * FlashStorage::SECTOR_SIZE;
+        let sector = (slot / SLOTS_PER_SECTOR) as u32;
+        Ok(start
+            + sector * FlashStorage::SECTOR_SIZE
+            + ((slot % SLOTS_PER_SECTOR) * SLOT_SZ) as u32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flash() -> FlashStorage {
+        FlashStorage::new(4 * 1024 * 1024)
+    }
+
+    #[test]
+    fn wraps_round_keeping_the_newest() {
+        let mut flash = flash();
+        let mut log = EventLog::open(&mut flash).unwrap();
+        for code in 0..100 {
+            log.record(&mut flash, Event::FetchFailed { code }).unwrap();
+        }
+
+        let entries = EventLog::entries(&mut flash).unwrap();
+        assert!(entries.len() >= EventLog::CAPACITY / 2);
+        assert!(entries.windows(2).all(|e| e[1].seq == e[0].seq + 1));
+        let newest = entries.last().unwrap();
+        assert_eq!(newest.seq, 99);
+        assert_eq!(newest.event, Event::FetchFailed { code: 99 });
+
+        // reopening carries on after the newest
+        let mut log = EventLog::open(&mut flash).unwrap();
+        log.record(&mut flash, Event::Boot).unwrap();
+        let entries = EventLog::entries(&mut flash).unwrap();
+        assert_eq!(entries.last().unwrap().seq, 100);
+        assert_eq!(entries.last().unwrap().event, Event::Boot);
+    }
+
+    #[test]
+    fn corrupt_entries_are_skipped() {
+        let mut flash = flash();
+        let mut log = EventLog::open(&mut flash).unwrap();
+        log.record(&mut flash, Event::Boot).unwrap();
+        log.record(&mut flash, Event::ConfigLoaded).unwrap();
+
+        // clear some bits of the second entry, like a write cut short
+        let second = EventLog::slot_offset(&mut flash, 1).unwrap();
+        NorFlash::write(&mut flash, second + 4, &[0; 4]).unwrap();
+        // and leave the next slot half written, so it can't take an entry
+        let third = EventLog::slot_offset(&mut flash, 2).unwrap();
+        NorFlash::write(&mut flash, third, &[0; 4]).unwrap();
+
+        let mut log = EventLog::open(&mut flash).unwrap();
+        log.record(&mut flash, Event::WifiDisconnected { reason: 200 })
+            .unwrap();
+        let events: alloc::vec::Vec<_> = EventLog::entries(&mut flash)
+            .unwrap()
+            .iter()
+            .map(|entry| entry.event)
+            .collect();
+        assert_eq!(
+            events,
+            [Event::Boot, Event::WifiDisconnected { reason: 200 }]
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! The flash everything in storage is kept in: `esp_storage`'s on the board, or with the `host`
+//! feature a blank one in memory, so the crate builds and runs off the board.
+
+#[cfg(not(feature = "host"))]
+pub use esp_storage::{FlashStorage, FlashStorageError};
+
+#[cfg(feature = "host")]
+pub use host::{FlashStorage, FlashStorageError};
+
+#[cfg(feature = "host")]
+mod host {
+    use alloc::{vec, vec::Vec};
+    use embedded_storage::{
+        ReadStorage, Storage,
+        nor_flash::{ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash},
+    };
+
+    #[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+    pub enum FlashStorageError {
+        OutOfBounds,
+        NotAligned,
+    }
+
+    /// Flash held in memory, erased (all `0xFF`) until something's written to it, with no
+    /// partition table, so [`Config::load`](crate::wmata::Config::load) and the rest find the
+    /// storage partition the same way they do on a board that was flashed without one.
+    pub struct FlashStorage {
+        bytes: Vec<u8>,
+        writes: usize,
+    }
+
+    impl FlashStorage {
+        pub const SECTOR_SIZE: u32 = 4096;
+
+        /// `capacity` bytes of erased flash, e.g. `4 * 1024 * 1024` like the board's.
+        pub fn new(capacity: usize) -> Self {
+            Self {
+                bytes: vec![0xFF; capacity],
+                writes: 0,
+            }
+        }
+
+        /// How many [`Storage`] writes and erases there have been, each one a sector's worth of
+        /// wear on a board.
+        pub fn writes(&self) -> usize {
+            self.writes
+        }
+
+        fn range(
+            &self,
+            offset: u32,
+            len: usize,
+        ) -> Result<core::ops::Range<usize>, FlashStorageError> {
+            let start = offset as usize;
+            let end = start
+                .checked_add(len)
+                .filter(|end| *end <= self.bytes.len())
+                .ok_or(FlashStorageError::OutOfBounds)?;
+            Ok(start..end)
+        }
+    }
+
+    impl ReadStorage for FlashStorage {
+        type Error = FlashStorageError;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let range = self.range(offset, bytes.len())?;
+            bytes.copy_from_slice(&self.bytes[range]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    /// Overwrites whatever was there, erasing first like `esp_storage` does.
+    impl Storage for FlashStorage {
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let range = self.range(offset, bytes.len())?;
+            self.bytes[range].copy_from_slice(bytes);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    impl NorFlashError for FlashStorageError {
+        fn kind(&self) -> NorFlashErrorKind {
+            match self {
+                FlashStorageError::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+                FlashStorageError::NotAligned => NorFlashErrorKind::NotAligned,
+            }
+        }
+    }
+
+    impl ErrorType for FlashStorage {
+        type Error = FlashStorageError;
+    }
+
+    impl ReadNorFlash for FlashStorage {
+        const READ_SIZE: usize = 4;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            if !(offset as usize).is_multiple_of(Self::READ_SIZE)
+                || !bytes.len().is_multiple_of(Self::READ_SIZE)
+            {
+                return Err(FlashStorageError::NotAligned);
+            }
+            ReadStorage::read(self, offset, bytes)
+        }
+
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+    }
+
+    /// Writes can only clear bits, like real NOR flash, so only erased bytes take a write as is.
+    impl NorFlash for FlashStorage {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = FlashStorage::SECTOR_SIZE as usize;
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            if !(offset as usize).is_multiple_of(Self::WRITE_SIZE)
+                || !bytes.len().is_multiple_of(Self::WRITE_SIZE)
+            {
+                return Err(FlashStorageError::NotAligned);
+            }
+            let range = self.range(offset, bytes.len())?;
+            for (old, new) in self.bytes[range].iter_mut().zip(bytes) {
+                *old &= new;
+            }
+            Ok(())
+        }
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let sector = Self::ERASE_SIZE as u32;
+            if !from.is_multiple_of(sector) || !to.is_multiple_of(sector) {
+                return Err(FlashStorageError::NotAligned);
+            }
+            let range = self.range(from, to.saturating_sub(from) as usize)?;
+            self.bytes[range].fill(0xFF);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,129 @@
+use alloc::boxed::Box;
+
+use miniz_oxide::inflate::TINFLStatus;
+use miniz_oxide::inflate::core::{DecompressorOxide, decompress, inflate_flags};
+use reqwless::response::HeaderIterator;
+
+use crate::wmata::Error;
+
+/// Magic bytes and the only compression method, deflate, of a gzip member.
+const MAGIC: [u8; 2] = [0x1f, 0x8b];
+const DEFLATE: u8 = 8;
+/// Header flags for the optional fields between the fixed header and the compressed data.
+const FHCRC: u8 = 1 << 1;
+const FEXTRA: u8 = 1 << 2;
+const FNAME: u8 = 1 << 3;
+const FCOMMENT: u8 = 1 << 4;
+const HEADER_LEN: usize = 10;
+/// crc32 and length of the uncompressed data.
+const TRAILER_LEN: usize = 8;
+
+/// Whether the response says its body is gzipped.
+pub(crate) fn is_gzipped(mut headers: HeaderIterator<'_>) -> bool {
+    headers.any(|(name, value)| {
+        name.eq_ignore_ascii_case("content-encoding") && value.eq_ignore_ascii_case(b"gzip")
+    })
+}
+
+/// Decompress the gzip member in `input` into `out`, returning how many bytes it took up.
+///
+/// All of it has to fit in `out` at once, there's no streaming. Anything that isn't a single
+/// well-formed member, doesn't fit or fails its crc is [`Error::Decompress`].
+pub(crate) fn gunzip(input: &[u8], out: &mut [u8]) -> Result<usize, Error> {
+    let data = skip_header(input).ok_or(Error::Decompress)?;
+    let (deflated, trailer) = data
+        .split_last_chunk::<TRAILER_LEN>()
+        .ok_or(Error::Decompress)?;
+
+    // the decompressor's tables are about 11KB, too much for the stack
+    let mut decompressor = Box::<DecompressorOxide>::default();
+    let (status, _, len) = decompress(
+        &mut decompressor,
+        deflated,
+        out,
+        0,
+        inflate_flags::TINFL_FLAG_USING_NON_WRAPPING_OUTPUT_BUF,
+    );
+    if status != TINFLStatus::Done {
+        return Err(Error::Decompress);
+    }
+
+    let (crc, size) = trailer.split_at(4);
+    let inflated = &out[..len];
+    if crc32fast::hash(inflated).to_le_bytes() != crc || (len as u32).to_le_bytes() != size {
+        return Err(Error::Decompress);
+    }
+    Ok(len)
+}
+
+/// The rest of `input` after the gzip header, `None` if it isn't one.
+fn skip_header(input: &[u8]) -> Option<&[u8]> {
+    let header = input.get(..HEADER_LEN)?;
+    if header[..2] != MAGIC || header[2] != DEFLATE {
+        return None;
+    }
+    let flags = header[3];
+    let mut rest = &input[HEADER_LEN..];
+
+    if flags & FEXTRA != 0 {
+        let (len, extra) = rest.split_first_chunk::<2>()?;
+        rest = extra.get(usize::from(u16::from_le_bytes(*len))..)?;
+    }
+    // the file name and comment are both zero terminated
+    for field in [FNAME, FCOMMENT] {
+        if flags & field != 0 {
+            let end = rest.iter().position(|&b| b == 0)?;
+            rest = &rest[end + 1..];
+        }
+    }
+    if flags & FHCRC != 0 {
+        rest = rest.get(2..)?;
+    }
+    Some(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[cfg(feature = "rail")]
+    use crate::wmata::{json, types::NextTrainsResponse};
+
+    const GZIPPED: &[u8] = include_bytes!("../../test_data/get_prediction.json.gz");
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn a_gzipped_response_decodes_like_the_plain_one() {
+        let mut out = [0u8; 4096];
+        let len = gunzip(GZIPPED, &mut out).unwrap();
+        let plain = include_str!("../../test_data/get_prediction.json");
+        assert_eq!(&out[..len], plain.as_bytes());
+
+        let res: NextTrainsResponse =
+            json::from_str(core::str::from_utf8(&out[..len]).unwrap()).unwrap();
+        let expected: NextTrainsResponse = json::from_str(plain).unwrap();
+        assert_eq!(res.trains.len(), 4);
+        assert!(res.trains == expected.trains);
+    }
+
+    #[test]
+    fn anything_but_a_whole_member_that_fits_is_an_error() {
+        let mut out = [0u8; 4096];
+        assert!(matches!(
+            gunzip(&GZIPPED[..GZIPPED.len() - 1], &mut out),
+            Err(Error::Decompress)
+        ));
+        assert!(matches!(
+            gunzip(b"{\"Trains\":[]}", &mut out),
+            Err(Error::Decompress)
+        ));
+        assert!(matches!(
+            gunzip(GZIPPED, &mut out[..64]),
+            Err(Error::Decompress)
+        ));
+
+        let mut bad_crc = GZIPPED.to_vec();
+        let crc = bad_crc.len() - TRAILER_LEN;
+        bad_crc[crc] ^= 1;
+        assert!(matches!(gunzip(&bad_crc, &mut out), Err(Error::Decompress)));
+    }
+}
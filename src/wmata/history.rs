@@ -0,0 +1,345 @@
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use embassy_time::{Duration, Instant};
+use embedded_storage::nor_flash::{NorFlash, ReadNorFlash};
+use heapless::Vec;
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    flash::FlashStorage,
+    partition::storage_end,
+    snapshot::{NONE, eta_from_byte, eta_to_byte},
+    types::{Eta, LineKind, NextTrainsResponse, Station, TrainCar},
+};
+
+/// Most trains kept per entry, from the front of the list.
+pub const HISTORY_MAX_TRAINS: usize = 8;
+
+/// Bytes per entry: the checksum and [`Stored`], padded to a multiple of the flash word size.
+const SLOT_SZ: usize = 64;
+/// 6 bytes per train and 12 for the rest.
+const STORED_SZ: usize = 12 + HISTORY_MAX_TRAINS * 6;
+const SLOTS_PER_SECTOR: usize = PredictionHistory::CAPACITY / 2;
+
+const _: () = assert!(CHECKSUM_SZ + STORED_SZ <= SLOT_SZ);
+const _: () = assert!(SLOTS_PER_SECTOR * SLOT_SZ == FlashStorage::SECTOR_SIZE as usize);
+
+/// One train as [`PredictionHistory`] keeps it, just enough to follow it from one entry to the
+/// next.
+#[derive(Copy, Clone, defmt::Format)]
+pub struct HistoryTrain {
+    pub line: Option<LineKind>,
+    pub cars: Option<TrainCar>,
+    pub min: Option<Eta>,
+    pub destination: Option<Station>,
+}
+
+/// What the display showed at one point, see [`PredictionHistory::for_each_entry`].
+#[derive(Clone, defmt::Format)]
+pub struct HistoryEntry {
+    /// Counts up across reboots, like [`LogEntry::seq`](crate::wmata::LogEntry::seq).
+    pub seq: u32,
+    /// Uptime when it was recorded, compare it against the event log's `Boot` entries.
+    pub uptime_secs: u32,
+    pub station: Station,
+    /// No trains is worth keeping too, it's what a board that went blank looked like.
+    pub trains: Vec<HistoryTrain, HISTORY_MAX_TRAINS>,
+}
+
+#[derive(Encode, Decode)]
+struct StoredTrain {
+    line: u8,
+    cars: u8,
+    /// See [`eta_to_byte`].
+    eta: u8,
+    /// Zeroes when there isn't a known code.
+    destination: [u8; 3],
+}
+
+#[derive(Encode, Decode)]
+struct Stored {
+    seq: u32,
+    uptime_secs: u32,
+    station: [u8; 3],
+    len: u8,
+    trains: [StoredTrain; HISTORY_MAX_TRAINS],
+}
+
+/// A ring of the last [`PredictionHistory::CAPACITY`] sets of predictions in its own two flash
+/// sectors, each entry with a crc32 checksum, for working out after the fact what a flaky
+/// display was showing, e.g. whether a train at 3 minutes just vanished.
+///
+/// Kept the same way as the [`EventLog`](crate::wmata::EventLog): entries go straight into
+/// erased space and a sector is only erased when the ring comes back round to it, dropping the
+/// older half. Entries are written at most once per interval given to
+/// [`PredictionHistory::open`], which sets both how far back it goes and the wear. At 5 minutes
+/// that's between 5 and 10 hours of history, and each sector erased every 5 hours or so, about
+/// 1700 times a year.
+pub struct PredictionHistory {
+    next_seq: u32,
+    next_slot: usize,
+    every: Duration,
+    last: Instant,
+}
+
+impl PredictionHistory {
+    pub const CAPACITY: usize = 128;
+
+    /// Find where the history left off, so new entries carry on after the newest one. The first
+    /// entry is due `every` after this, as is each one after it.
+    pub fn open(flash: &mut FlashStorage, every: Duration) -> Result<Self, ConfigError> {
+        let (next_seq, next_slot) = match Self::ends(flash)? {
+            Some((_, slot, seq)) => (seq.wrapping_add(1), (slot + 1) % Self::CAPACITY),
+            None => (0, 0),
+        };
+
+        Ok(Self {
+            next_seq,
+            next_slot,
+            every,
+            last: Instant::now(),
+        })
+    }
+
+    /// Whether the interval since the last entry, or since [`PredictionHistory::open`], is up.
+    pub fn is_due(&self) -> bool {
+        self.last.elapsed() >= self.every
+    }
+
+    /// Append the first [`HISTORY_MAX_TRAINS`] trains of `res`, the predictions for `station`,
+    /// stamped with the current uptime. Call it when [`PredictionHistory::is_due`] says so.
+    pub fn record(
+        &mut self,
+        flash: &mut FlashStorage,
+        station: Station,
+        res: &NextTrainsResponse,
+    ) -> Result<(), ConfigError> {
+        // a slot that isn't blank was half written when power went, and can't be written over without an erase
+        let mut bytes = [0u8; SLOT_SZ];
+        loop {
+            if self.next_slot.is_multiple_of(SLOTS_PER_SECTOR) {
+                let from = Self::slot_offset(flash, self.next_slot)?;
+                flash.erase(from, from + FlashStorage::SECTOR_SIZE)?;
+                break;
+            }
+            let offset = Self::slot_offset(flash, self.next_slot)?;
+            flash.read(offset, &mut bytes)?;
+            if bytes.iter().all(|b| *b == 0xFF) {
+                break;
+            }
+            self.next_slot = (self.next_slot + 1) % Self::CAPACITY;
+        }
+
+        let mut stored = Stored {
+            seq: self.next_seq,
+            uptime_secs: Instant::now().as_secs() as u32,
+            station: [0; 3],
+            len: 0,
+            trains: core::array::from_fn(|_| StoredTrain::empty()),
+        };
+        stored.station.copy_from_slice(station.code().as_bytes());
+        for (slot, train) in stored.trains.iter_mut().zip(&res.trains) {
+            slot.line = train.line.map(u8::from).unwrap_or(NONE);
+            slot.cars = train.cars.map(u8::from).unwrap_or(NONE);
+            slot.eta = eta_to_byte(train.min);
+            if let Some(code) = train.destination_station() {
+                slot.destination.copy_from_slice(code.code().as_bytes());
+            }
+            stored.len += 1;
+        }
+
+        // unused bytes stay 0xFF, i.e. unprogrammed
+        let mut bytes = [0xFFu8; SLOT_SZ];
+        let (crc32_bytes, payload) = bytes.split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+        let offset = Self::slot_offset(flash, self.next_slot)?;
+        flash.write(offset, &bytes)?;
+
+        self.next_seq = self.next_seq.wrapping_add(1);
+        self.next_slot = (self.next_slot + 1) % Self::CAPACITY;
+        self.last = Instant::now();
+        Ok(())
+    }
+
+    /// Call `f` with every entry still kept, oldest first, e.g. to dump them over serial. One at
+    /// a time, since all of them together would be a lot of stack.
+    pub fn for_each_entry(
+        flash: &mut FlashStorage,
+        mut f: impl FnMut(HistoryEntry),
+    ) -> Result<(), ConfigError> {
+        let Some((oldest, _, _)) = Self::ends(flash)? else {
+            return Ok(());
+        };
+
+        // slots are written in order round the ring, so it's in order from the oldest
+        for i in 0..Self::CAPACITY {
+            if let Some(entry) = Self::read_slot(flash, (oldest + i) % Self::CAPACITY)? {
+                f(entry);
+            }
+        }
+        Ok(())
+    }
+
+    /// The slot of the oldest entry, and the slot and seq of the newest, `None` if there are none.
+    fn ends(flash: &mut FlashStorage) -> Result<Option<(usize, usize, u32)>, ConfigError> {
+        let mut oldest: Option<(usize, u32)> = None;
+        let mut newest: Option<(usize, u32)> = None;
+        for slot in 0..Self::CAPACITY {
+            let Some(entry) = Self::read_slot(flash, slot)? else {
+                continue;
+            };
+            if oldest.is_none_or(|(_, seq)| entry.seq < seq) {
+                oldest = Some((slot, entry.seq));
+            }
+            if newest.is_none_or(|(_, seq)| entry.seq > seq) {
+                newest = Some((slot, entry.seq));
+            }
+        }
+        Ok(oldest
+            .zip(newest)
+            .map(|((oldest, _), (newest, seq))| (oldest, newest, seq)))
+    }
+
+    /// `None` for a blank or corrupt slot.
+    fn read_slot(
+        flash: &mut FlashStorage,
+        slot: usize,
+    ) -> Result<Option<HistoryEntry>, ConfigError> {
+        let mut bytes = [0u8; SLOT_SZ];
+        let offset = Self::slot_offset(flash, slot)?;
+        flash.read(offset, &mut bytes)?;
+
+        let (crc32_bytes, payload) = bytes.split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(&payload[..STORED_SZ]) {
+            return Ok(None);
+        }
+
+        let Ok((stored, _)) = decode_from_slice::<Stored, _>(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        ) else {
+            return Ok(None);
+        };
+
+        Ok(stored.to_entry())
+    }
+
+    /// The two sectors before the power-on hours, see [`PowerOnHours`](crate::wmata::PowerOnHours).
+    fn slot_offset(flash: &mut FlashStorage, slot: usize) -> Result<u32, ConfigError> {
+        let start = storage_end(flash)? - 8 * FlashStorage::SECTOR_SIZE;
+        let sector = (slot / SLOTS_PER_SECTOR) as u32;
+        Ok(start
+            + sector * FlashStorage::SECTOR_SIZE
+            + ((slot % SLOTS_PER_SECTOR) * SLOT_SZ) as u32)
+    }
+}
+
+impl Stored {
+    /// `None` if the station isn't one this firmware knows, e.g. after an update removed it.
+    fn to_entry(&self) -> Option<HistoryEntry> {
+        let station = Station::from_code(core::str::from_utf8(&self.station).ok()?)?;
+        let len = (self.len as usize).min(HISTORY_MAX_TRAINS);
+        let trains = self.trains[..len]
+            .iter()
+            .map(|train| HistoryTrain {
+                line: LineKind::try_from(train.line).ok(),
+                cars: (train.cars != NONE).then(|| train.cars.into()),
+                min: eta_from_byte(train.eta),
+                destination: core::str::from_utf8(&train.destination)
+                    .ok()
+                    .and_then(Station::from_code),
+            })
+            .collect();
+
+        Some(HistoryEntry {
+            seq: self.seq,
+            uptime_secs: self.uptime_secs,
+            station,
+            trains,
+        })
+    }
+}
+
+impl StoredTrain {
+    fn empty() -> Self {
+        Self {
+            line: NONE,
+            cars: NONE,
+            eta: NONE,
+            destination: [0; 3],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wmata::json;
+
+    fn flash() -> FlashStorage {
+        FlashStorage::new(4 * 1024 * 1024)
+    }
+
+    fn response() -> NextTrainsResponse {
+        json::from_str(include_str!(
+            "../../test_data/get_prediction_all_lines.json"
+        ))
+        .expect("fixture should decode")
+    }
+
+    fn kept(flash: &mut FlashStorage) -> alloc::vec::Vec<HistoryEntry> {
+        let mut entries = alloc::vec::Vec::new();
+        PredictionHistory::for_each_entry(flash, |entry| entries.push(entry)).unwrap();
+        entries
+    }
+
+    #[test]
+    fn an_entry_reads_back_as_recorded() {
+        let mut flash = flash();
+        let metro_center = Station::from_code("A01").unwrap();
+        let res = response();
+        let mut history = PredictionHistory::open(&mut flash, Duration::from_secs(300)).unwrap();
+        history.record(&mut flash, metro_center, &res).unwrap();
+
+        let entries = kept(&mut flash);
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry.seq, 0);
+        assert!(entry.station == metro_center);
+        assert_eq!(entry.trains.len(), res.trains.len().min(HISTORY_MAX_TRAINS));
+        for (kept, train) in entry.trains.iter().zip(&res.trains) {
+            assert_eq!(kept.line, train.line);
+            assert!(kept.cars == train.cars);
+            assert!(kept.min == train.min);
+            assert!(kept.destination == train.destination_station());
+        }
+    }
+
+    #[test]
+    fn wraps_round_keeping_the_newest() {
+        let mut flash = flash();
+        let station = Station::from_code("A01").unwrap();
+        let res = response();
+        let mut history = PredictionHistory::open(&mut flash, Duration::from_secs(300)).unwrap();
+        for _ in 0..300 {
+            history.record(&mut flash, station, &res).unwrap();
+        }
+
+        let entries = kept(&mut flash);
+        assert!(entries.len() >= PredictionHistory::CAPACITY / 2);
+        assert!(entries.len() <= PredictionHistory::CAPACITY);
+        assert!(entries.windows(2).all(|e| e[1].seq == e[0].seq + 1));
+        assert_eq!(entries.last().unwrap().seq, 299);
+
+        // reopening carries on after the newest
+        let mut history = PredictionHistory::open(&mut flash, Duration::from_secs(300)).unwrap();
+        history.record(&mut flash, station, &res).unwrap();
+        assert_eq!(kept(&mut flash).last().unwrap().seq, 300);
+    }
+}
@@ -0,0 +1,207 @@
+use core::cell::Cell;
+
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use embassy_time::{Duration, Instant};
+use embedded_storage::{ReadStorage, Storage};
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    flash::FlashStorage,
+    partition::storage_end,
+};
+
+/// Bytes needed by [`PowerOnHours::to_bytes`].
+pub const POWER_ON_HOURS_SZ: usize = CHECKSUM_SZ + 2 * core::mem::size_of::<u32>();
+
+/// How long the device has been running, in total across reboots, for telling a unit that's
+/// been up for years from one that keeps dying.
+///
+/// Only time awake counts, not deep sleep or time without power. The total is kept in RAM and
+/// written to its own flash sector every [`PowerOnHours::SAVE_EVERY`], so a reset loses at most
+/// that much, at about 9000 writes a year.
+pub struct PowerOnHours {
+    total_secs: Cell<u32>,
+    /// Up to when `total_secs` counts, moved on in whole seconds so no fraction is ever dropped.
+    counted_to: Cell<Instant>,
+    saved_secs: Cell<u32>,
+}
+
+#[derive(Encode, Decode)]
+struct Stored {
+    total_secs: u32,
+    /// Ignored when loading from flash, where there's nothing unsaved by definition.
+    saved_secs: u32,
+}
+
+impl PowerOnHours {
+    pub const SAVE_EVERY: Duration = Duration::from_secs(60 * 60);
+
+    /// Nothing counted yet, apart from the time since boot.
+    pub const fn new() -> Self {
+        Self::starting_at(0, 0)
+    }
+
+    /// Counting on from `total_secs` since boot, since every boot starts the clock from zero.
+    const fn starting_at(total_secs: u32, saved_secs: u32) -> Self {
+        Self {
+            total_secs: Cell::new(total_secs),
+            counted_to: Cell::new(Instant::from_ticks(0)),
+            saved_secs: Cell::new(saved_secs),
+        }
+    }
+
+    /// Total time running, in whole seconds, up to now.
+    pub fn total_secs(&self) -> u32 {
+        self.catch_up();
+        self.total_secs.get()
+    }
+
+    /// Total time running, in whole hours.
+    pub fn hours(&self) -> u32 {
+        self.total_secs() / (60 * 60)
+    }
+
+    /// Whether [`PowerOnHours::SAVE_EVERY`] has gone by since the last save.
+    pub fn needs_save(&self) -> bool {
+        let unsaved = self.total_secs().saturating_sub(self.saved_secs.get());
+        u64::from(unsaved) >= Self::SAVE_EVERY.as_secs()
+    }
+
+    /// Add the whole seconds since the last count. Saturates after 136 years.
+    fn catch_up(&self) {
+        self.count_to(Instant::now());
+    }
+
+    /// Add the whole seconds from the last count to `now`.
+    fn count_to(&self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.counted_to.get());
+        let secs = elapsed.as_secs();
+        self.counted_to
+            .set(self.counted_to.get() + Duration::from_secs(secs));
+        let secs = u32::try_from(secs).unwrap_or(u32::MAX);
+        self.total_secs
+            .set(self.total_secs.get().saturating_add(secs));
+    }
+
+    /// Write the total to flash. Call it when [`PowerOnHours::needs_save`] says so.
+    pub fn save(&self, flash: &mut FlashStorage) -> Result<(), ConfigError> {
+        let mut bytes = [0u8; POWER_ON_HOURS_SZ];
+        self.to_bytes(&mut bytes)?;
+        let offset = Self::offset(flash)?;
+        flash.write(offset, &bytes)?;
+        self.saved_secs.set(self.total_secs.get());
+
+        Ok(())
+    }
+
+    /// Restore the total saved by [`PowerOnHours::save`], carrying on with the time since boot.
+    pub fn load(flash: &mut FlashStorage) -> Result<Self, ConfigError> {
+        let mut bytes = [0u8; POWER_ON_HOURS_SZ];
+        let offset = Self::offset(flash)?;
+        flash.read(offset, &mut bytes)?;
+
+        let hours = Self::from_bytes(&bytes)?;
+        // it's all in flash now
+        hours.saved_secs.set(hours.total_secs.get());
+        Ok(hours)
+    }
+
+    /// Encode the total with a crc32 checksum, including the time not yet saved to flash.
+    /// For memory that outlives a reset but not power loss, like RTC memory through deep sleep.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<(), ConfigError> {
+        if buf.len() < POWER_ON_HOURS_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let stored = Stored {
+            total_secs: self.total_secs(),
+            saved_secs: self.saved_secs.get(),
+        };
+
+        let (crc32_bytes, payload) = buf[..POWER_ON_HOURS_SZ].split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Decode a total written by [`PowerOnHours::to_bytes`]. The time since this boot is added on.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ConfigError> {
+        if buf.len() < POWER_ON_HOURS_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let (crc32_bytes, payload) = buf[..POWER_ON_HOURS_SZ].split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(payload) {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        let stored: Stored = decode_from_slice(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?
+        .0;
+
+        Ok(Self::starting_at(stored.total_secs, stored.saved_secs))
+    }
+
+    /// The sector before the event log, see [`EventLog`](crate::wmata::EventLog).
+    fn offset(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+        Ok(storage_end(flash)? - 6 * FlashStorage::SECTOR_SIZE)
+    }
+}
+
+impl Default for PowerOnHours {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_whole_seconds_and_saturates() {
+        let hours = PowerOnHours::starting_at(3599, 0);
+        hours.count_to(Instant::from_millis(1500));
+        assert_eq!(hours.total_secs.get(), 3600);
+        // the half second left over isn't lost
+        hours.count_to(Instant::from_millis(2500));
+        assert_eq!(hours.total_secs.get(), 3601);
+        hours.count_to(Instant::from_millis(2000));
+        assert_eq!(hours.total_secs.get(), 3601);
+
+        let old = PowerOnHours::starting_at(u32::MAX - 1, 0);
+        old.count_to(Instant::from_secs(10));
+        assert_eq!(old.total_secs.get(), u32::MAX);
+        assert_eq!(old.hours(), u32::MAX / 3600);
+    }
+
+    #[test]
+    fn saves_and_loads_with_a_checksum() {
+        let mut flash = FlashStorage::new(4 * 1024 * 1024);
+        let hours = PowerOnHours::starting_at(2 * 3600, 0);
+        assert!(hours.needs_save());
+        hours.save(&mut flash).unwrap();
+        assert!(!hours.needs_save());
+
+        let loaded = PowerOnHours::load(&mut flash).unwrap();
+        assert!(loaded.hours() >= 2);
+        assert!(!loaded.needs_save());
+
+        let mut bytes = [0u8; POWER_ON_HOURS_SZ];
+        hours.to_bytes(&mut bytes).unwrap();
+        bytes[CHECKSUM_SZ] ^= 1;
+        assert!(matches!(
+            PowerOnHours::from_bytes(&bytes),
+            Err(ConfigError::BadChecksum)
+        ));
+    }
+}
@@ -0,0 +1,360 @@
+//! The json backend: `miniserde` by default, or `serde` with `serde-json-core` behind the `serde` feature.
+//! Both decode the same types through the same `#[serde(rename)]` attributes, so the rest of the
+//! crate only deals with [`Deserialize`], [`from_str`] and [`Error`].
+//! Either way the response types collect into `alloc` vecs, `serde-json-core` just doesn't allocate while parsing.
+
+#[cfg(all(feature = "miniserde", feature = "serde"))]
+compile_error!("the `miniserde` and `serde` features each pick a json backend, enable only one");
+#[cfg(not(any(feature = "miniserde", feature = "serde")))]
+compile_error!("enable either the `miniserde` or the `serde` feature to pick a json backend");
+
+#[cfg(feature = "miniserde")]
+pub use miniserde::{Deserialize, Error};
+
+#[cfg(feature = "serde")]
+pub use serde::de::DeserializeOwned as Deserialize;
+#[cfg(feature = "serde")]
+pub use serde_json_core::de::Error;
+
+#[cfg(feature = "bus")]
+use crate::wmata::types::RouteIds;
+
+/// Longest escaped string (one containing `\"`, `\n`, ...) `serde-json-core` can decode.
+#[cfg(feature = "serde")]
+const UNESCAPE_BUF_LEN: usize = 512;
+
+#[cfg(feature = "miniserde")]
+pub fn from_str<J: Deserialize>(json: &str) -> Result<J, Error> {
+    miniserde::json::from_str(json)
+}
+
+#[cfg(feature = "serde")]
+pub fn from_str<J: Deserialize>(json: &str) -> Result<J, Error> {
+    let mut unescape_buf = [0u8; UNESCAPE_BUF_LEN];
+    serde_json_core::from_str_escaped(json, &mut unescape_buf).map(|(value, _)| value)
+}
+
+#[cfg(all(feature = "miniserde", feature = "bus"))]
+miniserde::make_place!(RoutesPlace);
+
+#[cfg(all(feature = "miniserde", feature = "bus"))]
+impl miniserde::de::Visitor for RoutesPlace<RouteIds> {
+    fn seq(&mut self) -> miniserde::Result<alloc::boxed::Box<dyn miniserde::de::Seq + '_>> {
+        Ok(alloc::boxed::Box::new(RoutesBuilder {
+            out: &mut self.out,
+            routes: RouteIds(heapless::Vec::new()),
+            element: None,
+        }))
+    }
+}
+
+#[cfg(all(feature = "miniserde", feature = "bus"))]
+struct RoutesBuilder<'a> {
+    out: &'a mut Option<RouteIds>,
+    routes: RouteIds,
+    element: Option<alloc::string::String>,
+}
+
+#[cfg(all(feature = "miniserde", feature = "bus"))]
+impl miniserde::de::Seq for RoutesBuilder<'_> {
+    fn element(&mut self) -> miniserde::Result<&mut dyn miniserde::de::Visitor> {
+        if let Some(id) = self.element.take() {
+            self.routes.push(&id);
+        }
+        Ok(miniserde::Deserialize::begin(&mut self.element))
+    }
+
+    fn finish(&mut self) -> miniserde::Result<()> {
+        if let Some(id) = self.element.take() {
+            self.routes.push(&id);
+        }
+        *self.out = Some(core::mem::take(&mut self.routes));
+        Ok(())
+    }
+}
+
+/// Into a bounded list, dropping what doesn't fit rather than failing, see [`RouteIds`].
+#[cfg(all(feature = "miniserde", feature = "bus"))]
+impl miniserde::Deserialize for RouteIds {
+    fn begin(out: &mut Option<Self>) -> &mut dyn miniserde::de::Visitor {
+        RoutesPlace::new(out)
+    }
+}
+
+#[cfg(all(feature = "serde", feature = "bus"))]
+struct RoutesVisitor;
+
+#[cfg(all(feature = "serde", feature = "bus"))]
+impl<'de> serde::de::Visitor<'de> for RoutesVisitor {
+    type Value = RouteIds;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("an array of route ids")
+    }
+
+    fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<RouteIds, A::Error> {
+        let mut routes = RouteIds::default();
+        while let Some(id) = seq.next_element::<alloc::string::String>()? {
+            routes.push(&id);
+        }
+        Ok(routes)
+    }
+}
+
+/// Into a bounded list, dropping what doesn't fit rather than failing, see [`RouteIds`].
+#[cfg(all(feature = "serde", feature = "bus"))]
+impl<'de> serde::Deserialize<'de> for RouteIds {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(RoutesVisitor)
+    }
+}
+
+/// Warn about each of `expected` that fewer elements of `json` have than the most common of them,
+/// e.g. `Car` gone from every train while `Min` is still there. The response still decodes,
+/// missing `Option` fields as `None`, so without this a renamed field upstream goes unnoticed.
+///
+/// Keys are counted across the whole body rather than per element, which is plenty for the flat
+/// lists WMATA sends, and keeps this to a pass over the body per field without decoding anything.
+#[cfg(feature = "strict")]
+pub(crate) fn report_missing_fields(endpoint: &str, json: &str, expected: &[&str]) {
+    for (field, missing, entries) in missing_fields(json, expected) {
+        defmt::warn!(
+            "schema drift on {=str}: {=str} missing from {} of {} entries",
+            endpoint,
+            field,
+            missing,
+            entries
+        );
+    }
+}
+
+/// Each of `expected` that [`report_missing_fields`] warns about, with how many entries it's
+/// missing from and how many there are.
+#[cfg(feature = "strict")]
+fn missing_fields<'a>(
+    json: &'a str,
+    expected: &'a [&'a str],
+) -> impl Iterator<Item = (&'a str, usize, usize)> {
+    let entries = expected
+        .iter()
+        .map(|field| count_key(json, field))
+        .max()
+        .unwrap_or(0);
+
+    expected.iter().filter_map(move |field| {
+        let found = count_key(json, field);
+        (found < entries).then_some((*field, entries - found, entries))
+    })
+}
+
+/// How many times `"key"` appears as an object key in `json`, i.e. followed by a `:`.
+#[cfg(feature = "strict")]
+fn count_key(json: &str, key: &str) -> usize {
+    json.match_indices(key)
+        .filter(|(i, _)| {
+            let before = json[..*i].bytes().next_back();
+            let mut after = json[i + key.len()..].bytes();
+            before == Some(b'"')
+                && after.next() == Some(b'"')
+                && after.find(|b| !b.is_ascii_whitespace()) == Some(b':')
+        })
+        .count()
+}
+
+/// Types decoded from a single json string, like `"ARR"` or `"K04"`.
+/// Use `deserialize_from_str!` to implement the enabled backend's `Deserialize` on top of it.
+pub(crate) trait FromJsonStr: Sized {
+    fn from_json_str(s: &str) -> Option<Self>;
+
+    /// For the fields WMATA sends as strings of digits, like `"Min": "3"`, the same value as a
+    /// bare json number, in case it's ever sent as `"Min": 3`. `None`, the default, rejects
+    /// numbers. `serde-json-core` asks for a string and never offers a number, so only `miniserde`
+    /// decodes them, under `serde` a number is still an error.
+    #[cfg(feature = "miniserde")]
+    fn from_json_u64(_n: u64) -> Option<Self> {
+        None
+    }
+}
+
+#[cfg(feature = "miniserde")]
+miniserde::make_place!(Place);
+
+#[cfg(feature = "miniserde")]
+impl<T: FromJsonStr> miniserde::de::Visitor for Place<T> {
+    fn string(&mut self, s: &str) -> miniserde::Result<()> {
+        self.out = Some(T::from_json_str(s).ok_or(miniserde::Error)?);
+        Ok(())
+    }
+
+    fn nonnegative(&mut self, n: u64) -> miniserde::Result<()> {
+        self.out = Some(T::from_json_u64(n).ok_or(miniserde::Error)?);
+        Ok(())
+    }
+}
+
+#[cfg(feature = "miniserde")]
+pub(crate) fn place<T: FromJsonStr>(out: &mut Option<T>) -> &mut dyn miniserde::de::Visitor {
+    Place::new(out)
+}
+
+#[cfg(all(feature = "miniserde", feature = "rail"))]
+miniserde::make_place!(LenientPlace);
+
+/// Leaves `out` empty instead of failing on a string `T` can't parse. As an `Option<T>` field
+/// that decodes as `None`, anywhere else it's reported as a missing value.
+#[cfg(all(feature = "miniserde", feature = "rail"))]
+impl<T: FromJsonStr> miniserde::de::Visitor for LenientPlace<T> {
+    fn string(&mut self, s: &str) -> miniserde::Result<()> {
+        self.out = T::from_json_str(s);
+        Ok(())
+    }
+
+    fn nonnegative(&mut self, n: u64) -> miniserde::Result<()> {
+        self.out = T::from_json_u64(n);
+        Ok(())
+    }
+}
+
+#[cfg(all(feature = "miniserde", feature = "rail"))]
+pub(crate) fn lenient_place<T: FromJsonStr>(
+    out: &mut Option<T>,
+) -> &mut dyn miniserde::de::Visitor {
+    LenientPlace::new(out)
+}
+
+#[cfg(feature = "serde")]
+struct StrVisitor<T>(core::marker::PhantomData<T>);
+
+#[cfg(feature = "serde")]
+impl<T: FromJsonStr> serde::de::Visitor<'_> for StrVisitor<T> {
+    type Value = T;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("a string")
+    }
+
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<T, E> {
+        T::from_json_str(s).ok_or_else(|| E::invalid_value(serde::de::Unexpected::Str(s), &self))
+    }
+}
+
+#[cfg(feature = "serde")]
+pub(crate) fn deserialize_str<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+where
+    T: FromJsonStr,
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_str(StrVisitor(core::marker::PhantomData))
+}
+
+#[cfg(all(feature = "serde", feature = "rail"))]
+struct LenientVisitor<T>(core::marker::PhantomData<T>);
+
+#[cfg(all(feature = "serde", feature = "rail"))]
+impl<'de, T: FromJsonStr> serde::de::Visitor<'de> for LenientVisitor<T> {
+    type Value = Option<T>;
+
+    fn expecting(&self, f: &mut core::fmt::Formatter) -> core::fmt::Result {
+        f.write_str("a string or null")
+    }
+
+    fn visit_none<E: serde::de::Error>(self) -> Result<Option<T>, E> {
+        Ok(None)
+    }
+
+    fn visit_some<D: serde::Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> Result<Option<T>, D::Error> {
+        deserializer.deserialize_str(self)
+    }
+
+    fn visit_str<E: serde::de::Error>(self, s: &str) -> Result<Option<T>, E> {
+        Ok(T::from_json_str(s))
+    }
+}
+
+/// `deserialize_with` for an `Option<T>` field that should be `None`, not an error, when the
+/// string can't be parsed. Pair it with `default` so a missing field is `None` too.
+#[cfg(all(feature = "serde", feature = "rail"))]
+pub(crate) fn deserialize_lenient<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: FromJsonStr,
+    D: serde::Deserializer<'de>,
+{
+    deserializer.deserialize_option(LenientVisitor(core::marker::PhantomData))
+}
+
+/// Implement the enabled backend's `Deserialize` for a [`FromJsonStr`] type.
+///
+/// With `lenient`, a string that doesn't parse leaves an `Option` field `None` rather than failing
+/// the whole response. Under `serde` the field also needs
+/// `#[serde(default, deserialize_with = "crate::wmata::json::deserialize_lenient")]`.
+macro_rules! deserialize_from_str {
+    ($ty:ty, lenient) => {
+        #[cfg(feature = "miniserde")]
+        impl miniserde::Deserialize for $ty {
+            fn begin(out: &mut Option<Self>) -> &mut dyn miniserde::de::Visitor {
+                $crate::wmata::json::lenient_place(out)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $crate::wmata::json::deserialize_str(deserializer)
+            }
+        }
+    };
+    ($ty:ty) => {
+        #[cfg(feature = "miniserde")]
+        impl miniserde::Deserialize for $ty {
+            fn begin(out: &mut Option<Self>) -> &mut dyn miniserde::de::Visitor {
+                $crate::wmata::json::place(out)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> serde::Deserialize<'de> for $ty {
+            fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                $crate::wmata::json::deserialize_str(deserializer)
+            }
+        }
+    };
+}
+
+pub(crate) use deserialize_from_str;
+
+/// `s` as a quoted json string, escaping quotes, backslashes and control characters, all of
+/// which SSIDs and api strings can hold.
+pub(crate) fn write_json_str<W: core::fmt::Write>(w: &mut W, s: &str) -> core::fmt::Result {
+    w.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => w.write_str("\\\"")?,
+            '\\' => w.write_str("\\\\")?,
+            c if c.is_control() => write!(w, "\\u{:04x}", c as u32)?,
+            c => w.write_char(c)?,
+        }
+    }
+    w.write_char('"')
+}
+
+#[cfg(all(test, feature = "strict", feature = "rail"))]
+mod tests {
+    use super::*;
+    use crate::wmata::types::NextTrain;
+
+    #[test]
+    fn fields_missing_from_some_entries_are_reported() {
+        let json = include_str!("../../test_data/get_prediction_missing_field.json");
+        let missing: alloc::vec::Vec<_> = missing_fields(json, NextTrain::FIELDS).collect();
+        // every train sends `DestCode` instead, and one has no `Group`
+        assert_eq!(missing, [("DestinationCode", 3, 3), ("Group", 1, 3)]);
+
+        // a key has to be a key, not a value or part of a longer one
+        assert_eq!(count_key(r#"{"Line":"Line","LineCode":"RD"}"#, "Line"), 1);
+        let complete = include_str!("../../test_data/get_prediction.json");
+        assert_eq!(missing_fields(complete, NextTrain::FIELDS).count(), 0);
+    }
+}
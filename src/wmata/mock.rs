@@ -0,0 +1,181 @@
+//! A fake network for the tests to drive a real [`Client`] through, without sockets: every
+//! connection gets the next canned response, and what was sent on it is kept to check.
+
+use alloc::{collections::VecDeque, format, string::String, vec::Vec};
+use core::{
+    cell::{Cell, RefCell},
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+};
+
+use embassy_time::{Duration, Timer};
+use embedded_nal_async::{AddrType, Dns, TcpConnect};
+use reqwless::client::HttpClient;
+
+use crate::wmata::Client;
+
+#[derive(Debug)]
+pub(crate) struct MockError;
+
+impl embedded_io_06::Error for MockError {
+    fn kind(&self) -> embedded_io_06::ErrorKind {
+        embedded_io_06::ErrorKind::Other
+    }
+}
+
+enum Reply {
+    Response(Vec<u8>),
+    /// The response, but only after a wait, like a slow server.
+    Slow(Duration, Vec<u8>),
+    /// Connecting fails.
+    Refuse,
+}
+
+/// Answers each connection with the next queued reply, and refuses any past the last one.
+#[derive(Default)]
+pub(crate) struct MockNet {
+    replies: RefCell<VecDeque<Reply>>,
+    /// Everything sent on each connection, request line and headers, in order.
+    pub requests: RefCell<Vec<String>>,
+}
+
+impl MockNet {
+    pub fn reply(&self, status: &str, headers: &[(&str, &str)], body: &str) -> &Self {
+        self.queue(Reply::Response(response(status, headers, body.as_bytes())))
+    }
+
+    pub fn reply_ok(&self, body: &str) -> &Self {
+        self.reply("200 OK", &[], body)
+    }
+
+    pub fn reply_after(&self, delay: Duration, body: &str) -> &Self {
+        self.queue(Reply::Slow(delay, response("200 OK", &[], body.as_bytes())))
+    }
+
+    pub fn refuse(&self) -> &Self {
+        self.queue(Reply::Refuse)
+    }
+
+    fn queue(&self, reply: Reply) -> &Self {
+        self.replies.borrow_mut().push_back(reply);
+        self
+    }
+
+    /// The path and query of request `i`, e.g. `/Rail.svc/json/jLines`.
+    pub fn request_path(&self, i: usize) -> String {
+        let requests = self.requests.borrow();
+        requests[i].split(' ').nth(1).unwrap_or_default().into()
+    }
+
+    /// The value of header `name` in request `i`, ignoring the name's case.
+    pub fn request_header(&self, i: usize, name: &str) -> Option<String> {
+        let requests = self.requests.borrow();
+        requests[i].lines().find_map(|line| {
+            let (n, value) = line.split_once(':')?;
+            n.eq_ignore_ascii_case(name).then(|| value.trim().into())
+        })
+    }
+}
+
+pub(crate) struct MockConnection<'a> {
+    net: &'a MockNet,
+    response: Vec<u8>,
+    read: usize,
+    sent: Vec<u8>,
+}
+
+impl embedded_io_06::ErrorType for MockConnection<'_> {
+    type Error = MockError;
+}
+
+impl embedded_io_async_06::Read for MockConnection<'_> {
+    async fn read(&mut self, buf: &mut [u8]) -> Result<usize, MockError> {
+        let rest = &self.response[self.read..];
+        let n = buf.len().min(rest.len());
+        buf[..n].copy_from_slice(&rest[..n]);
+        self.read += n;
+        Ok(n)
+    }
+}
+
+impl embedded_io_async_06::Write for MockConnection<'_> {
+    async fn write(&mut self, buf: &[u8]) -> Result<usize, MockError> {
+        self.sent.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+}
+
+impl Drop for MockConnection<'_> {
+    fn drop(&mut self) {
+        let sent = String::from_utf8_lossy(&self.sent).into_owned();
+        self.net.requests.borrow_mut().push(sent);
+    }
+}
+
+impl TcpConnect for MockNet {
+    type Error = MockError;
+    type Connection<'a> = MockConnection<'a>;
+
+    async fn connect<'a>(&'a self, _: SocketAddr) -> Result<MockConnection<'a>, MockError> {
+        let reply = self.replies.borrow_mut().pop_front();
+        let response = match reply {
+            Some(Reply::Response(response)) => response,
+            Some(Reply::Slow(delay, response)) => {
+                Timer::after(delay).await;
+                response
+            }
+            Some(Reply::Refuse) | None => return Err(MockError),
+        };
+        Ok(MockConnection {
+            net: self,
+            response,
+            read: 0,
+            sent: Vec::new(),
+        })
+    }
+}
+
+/// Resolves every host to localhost, after failing the first `failures` lookups.
+#[derive(Default)]
+pub(crate) struct MockDns {
+    pub failures: Cell<u32>,
+    pub lookups: Cell<u32>,
+}
+
+impl Dns for MockDns {
+    type Error = MockError;
+
+    async fn get_host_by_name(&self, _: &str, _: AddrType) -> Result<IpAddr, MockError> {
+        self.lookups.set(self.lookups.get() + 1);
+        if self.failures.get() > 0 {
+            self.failures.set(self.failures.get() - 1);
+            return Err(MockError);
+        }
+        Ok(IpAddr::V4(Ipv4Addr::LOCALHOST))
+    }
+
+    async fn get_host_by_address(&self, _: IpAddr, _: &mut [u8]) -> Result<usize, MockError> {
+        Err(MockError)
+    }
+}
+
+/// A client talking to `net` through `dns`, usually a [`MockDns`], with api key `"key"`.
+pub(crate) fn client<'a, D: Dns>(
+    net: &'a MockNet,
+    dns: &'a D,
+    rx_buf: &'a mut [u8],
+) -> Client<'a, MockNet, D> {
+    Client::new(HttpClient::new(net, dns), rx_buf, "key")
+}
+
+/// The raw bytes of an http response.
+pub(crate) fn response(status: &str, headers: &[(&str, &str)], body: &[u8]) -> Vec<u8> {
+    let mut head = format!("HTTP/1.1 {status}\r\nContent-Length: {}\r\n", body.len());
+    for (name, value) in headers {
+        head += &format!("{name}: {value}\r\n");
+    }
+    head += "\r\n";
+
+    let mut response = head.into_bytes();
+    response.extend_from_slice(body);
+    response
+}
@@ -1,32 +1,229 @@
+mod breaker;
+#[cfg(feature = "bridge")]
+mod bridge;
+mod cache;
+mod clock;
 mod config;
+#[cfg(feature = "offline")]
+mod demo;
+mod dns;
+mod eventlog;
+mod flash;
+#[cfg(feature = "gzip")]
+mod gzip;
+#[cfg(feature = "history")]
+mod history;
+mod hours;
+pub mod json;
+#[cfg(test)]
+pub(crate) mod mock;
+#[cfg(feature = "name-overrides")]
+mod overrides;
+mod partition;
+mod quota;
+#[cfg(feature = "rail")]
+mod smooth;
+#[cfg(feature = "rail")]
+mod snapshot;
+mod stack;
+mod trace;
+#[cfg(feature = "rail")]
+mod tracker;
 pub mod types;
 mod util;
 
-pub use crate::wmata::config::Config;
+pub use crate::wmata::breaker::CircuitBreaker;
+pub use crate::wmata::cache::ResponseCache;
+pub use crate::wmata::clock::{LocalTime, TimeZone, WallClock};
+pub use crate::wmata::config::{
+    API_KEY_MAX_LEN, Config, ConfigChanges, ConfigEdit, ConfigError, ConfigSource, DhcpFallback,
+    NoPassengerPolicy, PASS_MAX_LEN, PowerMode, Rotation, SSID_MAX_LEN, ServiceHours,
+    ServiceWindow, StaticIpv4, Unredacted,
+};
+pub use crate::wmata::dns::{CachedDns, DnsCache, RetryDns};
+pub use crate::wmata::eventlog::{Event, EventLog, LogEntry};
+#[cfg(feature = "host")]
+pub use crate::wmata::flash::FlashStorage;
+#[cfg(feature = "history")]
+pub use crate::wmata::history::{
+    HISTORY_MAX_TRAINS, HistoryEntry, HistoryTrain, PredictionHistory,
+};
+pub use crate::wmata::hours::{POWER_ON_HOURS_SZ, PowerOnHours};
+#[cfg(feature = "name-overrides")]
+pub use crate::wmata::overrides::{NAME_OVERRIDE_MAX_LEN, NAME_OVERRIDES_SZ, NameOverrides};
+pub use crate::wmata::partition::Partition;
+pub use crate::wmata::quota::{REQUEST_COUNTER_SZ, RequestCounter};
+#[cfg(feature = "rail")]
+pub use crate::wmata::smooth::EtaSmoother;
+#[cfg(feature = "rail")]
+pub use crate::wmata::snapshot::{SNAPSHOT_MAX_TRAINS, SNAPSHOT_SZ};
+pub use crate::wmata::stack::{
+    ClientResources, MIN_RX_BUF_SZ, RX_BUF_SZ, StackClient, StackDns, StackTcpClient, TCP_BUFFER_SZ,
+};
+#[cfg(feature = "rail")]
+pub use crate::wmata::tracker::{TrackEvent, TrainTracker};
 
-use defmt::debug;
+use defmt::{debug, warn};
+use embassy_sync::mutex::Mutex;
+use embassy_time::{Duration, Instant, with_timeout};
 use embedded_nal_async::{Dns, TcpConnect};
-use heapless::String;
-use miniserde::Deserialize;
+use heapless::{String, Vec};
 use reqwless::{
     client::HttpClient,
     request::{Method, RequestBuilder},
+    response::HeaderIterator,
 };
 
+#[cfg(any(feature = "rail", feature = "incidents"))]
+use crate::wmata::types::Station;
+use crate::wmata::{
+    cache::Validator,
+    json::Deserialize,
+    trace::Trace,
+    util::{URL_LEN, build_url, is_complete_json, url_path, validate_base_url},
+};
+#[cfg(feature = "bus")]
+use crate::wmata::{
+    types::{BusPosition, BusPositionsResponse, BusStop, BusStopsResponse},
+    util::{build_bus_positions_path, build_bus_stops_path},
+};
+#[cfg(feature = "rail")]
+use crate::wmata::{
+    types::{
+        LineKind, LineTrains, MaybeNextTrainsResponse, NextTrain, NextTrainsResponse, StationInfo,
+        StationListResponse, StationTimes, StationTimesResponse, TransferView,
+    },
+    util::{build_next_trains_path, build_station_list_path, build_station_times_path},
+};
+#[cfg(feature = "incidents")]
 use crate::wmata::{
-    types::{NextTrainsResponse, Station},
-    util::build_next_trains_url,
+    types::{
+        LineStatus, RailIncident, RailIncidentsResponse, StationDisruptions, UnitOutage,
+        UnitOutagesResponse,
+    },
+    util::{RAIL_INCIDENTS_PATH, build_unit_outages_path},
+};
+#[cfg(feature = "positions")]
+use crate::wmata::{
+    types::{StandardRoute, StandardRoutesResponse, TrackCircuit, TrackCircuitsResponse},
+    util::{STANDARD_ROUTES_PATH, TRACK_CIRCUITS_PATH},
 };
 
 const USER_AGENT: &str = "esp-wmata-pids";
-const API: &str = "http://api.wmata.com";
+/// The header WMATA reads the api key from, spelled as in its docs. Header names are meant to be
+/// case-insensitive, but a proxy in between might not be, and a key it drops just looks like a
+/// bad one, a 401.
+const API_KEY_HEADER: &str = "api_key";
+/// Base url of the official WMATA api, used unless [`Client::with_api`] says otherwise.
+pub const API: &str = "http://api.wmata.com";
+/// What [`Client::ping`] fetches: the list of lines, a body of well under a kilobyte that every
+/// api key has access to.
+const PING_PATH: &str = "/Rail.svc/json/jLines";
 
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     Http(reqwless::Error),
     Utf8(core::str::Utf8Error),
-    Json(miniserde::Error),
+    Json(JsonError),
     Format(core::fmt::Error),
+    /// The api responded without any data, e.g. an empty body or `{"Trains": null}`.
+    EmptyResponse,
+    /// The body wasn't json at all, usually an html error page served during maintenance.
+    NotJson,
+    /// The api answered with a non-2xx status code other than 401, e.g. 429 when over the rate limit.
+    Status(u16),
+    /// The api rejected the api key with a 401, e.g. a mistyped or revoked key.
+    Unauthorized,
+    /// A base url passed to [`Client::with_api`] was missing its scheme or host, or was too long to
+    /// leave room for the path.
+    InvalidBaseUrl,
+    /// The whole request took longer than the client's timeout, see [`Client::with_timeout`].
+    Timeout,
+    /// The request ran past, or would have started after, the deadline from
+    /// [`Client::set_deadline`]. Not counted as an outage by the [`CircuitBreaker`], since the
+    /// deadline is the caller's budget, not a sign the api is down.
+    DeadlineExceeded,
+    /// The body stopped partway through the json, e.g. the connection dropped mid-response.
+    Truncated,
+    /// Too many requests failed in a row, so this one wasn't sent, see [`CircuitBreaker`].
+    CircuitOpen,
+    /// A gzipped body that wasn't valid gzip or didn't fit the buffer, see `Client::with_gzip`.
+    Decompress,
+    /// No station has a name like the one given, see [`Station::from_name`](types::Station::from_name).
+    NoSuchStation,
+    /// More than one station has a name like the one given, these among them.
+    AmbiguousStation(Vec<types::Station, 8>),
+    /// The api is `https://` and the clock from [`Client::with_clock`] hasn't been set yet, so a
+    /// certificate's dates couldn't be checked. Nothing was sent, see [`Client::with_api`].
+    ClockNotSynced,
+}
+
+/// A body that didn't decode, with enough of where it came from to make sense of it in a log.
+/// Neither json backend says where in the body it gave up, so the start of the body has to do.
+#[derive(Debug)]
+pub struct JsonError {
+    pub error: json::Error,
+    /// Path of the request, e.g. `/StationPrediction.svc/json/GetPrediction/K04`, cut short if long.
+    /// Empty when the error didn't come from a request.
+    pub endpoint: String<64>,
+    /// The first few bytes of the body.
+    pub body_start: String<32>,
+}
+
+impl JsonError {
+    fn new(error: json::Error, url: &str, body: &str) -> Self {
+        Self {
+            error,
+            endpoint: truncated(url_path(url)),
+            body_start: truncated(body),
+        }
+    }
+}
+
+/// As much of `s` as fits in `N` bytes, cut at a character boundary.
+fn truncated<const N: usize>(s: &str) -> String<N> {
+    let mut end = s.len().min(N);
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    String::try_from(&s[..end]).unwrap_or_default()
+}
+
+impl Error {
+    /// A number for the kind of error, small enough to keep in the [`EventLog`].
+    /// Status errors are the http status itself, everything else is below 100.
+    pub fn code(&self) -> u16 {
+        match self {
+            Error::Http(_) => 1,
+            Error::Utf8(_) => 2,
+            Error::Json(_) => 3,
+            Error::Format(_) => 4,
+            Error::EmptyResponse => 5,
+            Error::NotJson => 6,
+            Error::InvalidBaseUrl => 7,
+            Error::Timeout => 8,
+            Error::Truncated => 9,
+            Error::CircuitOpen => 10,
+            Error::Decompress => 11,
+            Error::NoSuchStation => 12,
+            Error::AmbiguousStation(_) => 13,
+            Error::DeadlineExceeded => 14,
+            Error::ClockNotSynced => 15,
+            Error::Status(code) => *code,
+            Error::Unauthorized => 401,
+        }
+    }
+}
+
+impl Error {
+    /// The error for a non-2xx `status`.
+    fn from_status(status: u16) -> Self {
+        match status {
+            401 => Error::Unauthorized,
+            status => Error::Status(status),
+        }
+    }
 }
 
 impl From<reqwless::Error> for Error {
@@ -41,9 +238,9 @@ impl From<core::str::Utf8Error> for Error {
     }
 }
 
-impl From<miniserde::Error> for Error {
-    fn from(value: miniserde::Error) -> Self {
-        Self::Json(value)
+impl From<json::Error> for Error {
+    fn from(value: json::Error) -> Self {
+        Self::Json(JsonError::new(value, "", ""))
     }
 }
 
@@ -58,8 +255,35 @@ impl core::fmt::Display for Error {
         match self {
             Error::Http(e) => write!(f, "http: {:?}", e),
             Error::Utf8(e) => write!(f, "utf8: {}", e),
-            Error::Json(_) => write!(f, "json decode failed"),
+            Error::Json(e) => write!(
+                f,
+                "json decode failed for {}, body starts {:?}",
+                e.endpoint, e.body_start
+            ),
             Error::Format(e) => write!(f, "fmt: {}", e),
+            Error::EmptyResponse => write!(f, "empty response"),
+            Error::NotJson => write!(f, "response was not json"),
+            Error::Status(code) => write!(f, "http status {}", code),
+            Error::Unauthorized => write!(f, "api key rejected"),
+            Error::InvalidBaseUrl => write!(
+                f,
+                "base url needs an http(s) scheme, a host and room for a path"
+            ),
+            Error::Timeout => write!(f, "request timed out"),
+            Error::DeadlineExceeded => write!(f, "request ran past its deadline"),
+            Error::Truncated => write!(f, "response was cut off"),
+            Error::CircuitOpen => write!(f, "api looks down, not sending requests for now"),
+            Error::Decompress => write!(f, "gzipped body didn't decompress"),
+            Error::NoSuchStation => write!(f, "no station by that name"),
+            Error::AmbiguousStation(candidates) => {
+                write!(f, "more than one station by that name:")?;
+                for (i, station) in candidates.iter().enumerate() {
+                    let sep = if i == 0 { " " } else { ", " };
+                    write!(f, "{}{} ({})", sep, station.name(), station.code())?;
+                }
+                Ok(())
+            }
+            Error::ClockNotSynced => write!(f, "clock not set yet, can't check a certificate"),
         }
     }
 }
@@ -69,8 +293,109 @@ impl defmt::Format for Error {
         match self {
             Error::Http(e) => defmt::write!(f, "http: {:?}", e),
             Error::Utf8(e) => defmt::write!(f, "utf8: {:?}", defmt::Display2Format(e)),
-            Error::Json(_) => defmt::write!(f, "json decode failed"),
+            Error::Json(e) => defmt::write!(
+                f,
+                "json decode failed for {=str}, body starts {=str}",
+                e.endpoint.as_str(),
+                e.body_start.as_str()
+            ),
             Error::Format(_) => defmt::write!(f, "fmt error"),
+            Error::EmptyResponse => defmt::write!(f, "empty response"),
+            Error::NotJson => defmt::write!(f, "response was not json"),
+            Error::Status(code) => defmt::write!(f, "http status {}", code),
+            Error::Unauthorized => defmt::write!(f, "api key rejected"),
+            Error::InvalidBaseUrl => {
+                defmt::write!(
+                    f,
+                    "base url needs an http(s) scheme, a host and room for a path"
+                )
+            }
+            Error::Timeout => defmt::write!(f, "request timed out"),
+            Error::DeadlineExceeded => defmt::write!(f, "request ran past its deadline"),
+            Error::Truncated => defmt::write!(f, "response was cut off"),
+            Error::CircuitOpen => defmt::write!(f, "api looks down, not sending requests for now"),
+            Error::Decompress => defmt::write!(f, "gzipped body didn't decompress"),
+            Error::NoSuchStation => defmt::write!(f, "no station by that name"),
+            Error::AmbiguousStation(candidates) => {
+                defmt::write!(f, "more than one station by that name: {}", candidates)
+            }
+            Error::ClockNotSynced => {
+                defmt::write!(f, "clock not set yet, can't check a certificate")
+            }
+        }
+    }
+}
+
+/// Per-phase timing of a single request, see [`Client::try_next_trains`].
+#[cfg(feature = "rail")]
+#[derive(Debug, Clone, Copy, defmt::Format)]
+pub struct FetchStats {
+    /// DNS lookup and TCP connect. `reqwless` does both inside one call, so they can't be split.
+    pub connect: Duration,
+    /// From sending the request until the response headers were read.
+    pub ttfb: Duration,
+    /// Reading the response body, and decompressing it if it was gzipped.
+    pub body: Duration,
+    /// Size of the response body in bytes as it was sent, before any gzip decompression.
+    pub body_size: usize,
+    /// Decoding the body into the response type.
+    pub parse: Duration,
+}
+
+/// A response as it came back from [`Client::get_raw`], whatever its status, with the headers
+/// that were asked for.
+#[derive(Debug)]
+pub struct RawResponse<'b> {
+    pub status: u16,
+    /// The headers asked for that came back, in the order they were sent, with the name as the
+    /// response spelled it. Values that aren't utf8 or don't fit are left out.
+    pub headers: Vec<(String<32>, String<64>), { RawResponse::MAX_HEADERS }>,
+    /// Decompressed if it came gzipped, see [`Client::with_gzip`], otherwise as sent.
+    pub body: &'b [u8],
+}
+
+impl RawResponse<'_> {
+    /// Most headers kept from one response.
+    pub const MAX_HEADERS: usize = 8;
+
+    /// The first kept header called `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// What [`Client::next_trains_chunked`] does when one of its requests fails.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, defmt::Format)]
+pub enum OnChunkError {
+    /// Leave that chunk's stations out and carry on with the rest.
+    #[default]
+    Skip,
+    /// Stop and return the error.
+    Abort,
+}
+
+/// Predictions from [`Client::latest`], fresh or the last good ones.
+#[cfg(feature = "rail")]
+pub enum Latest {
+    Fresh(NextTrainsResponse),
+    /// The fetch failed or ran out of time, so these are the last ones that came through, `age`
+    /// old, for the caller to decide whether that's too old to show.
+    Cached {
+        response: NextTrainsResponse,
+        age: Duration,
+    },
+}
+
+#[cfg(feature = "rail")]
+impl Latest {
+    /// The predictions, whichever they are.
+    pub fn response(&self) -> &NextTrainsResponse {
+        match self {
+            Latest::Fresh(response) | Latest::Cached { response, .. } => response,
         }
     }
 }
@@ -83,43 +408,569 @@ where
 {
     reqwless: HttpClient<'a, T, D>,
     rx_buf: &'a mut [u8],
-    api_key: &'a str,
+    api_key: String<{ config::API_KEY_MAX_LEN }>,
+    backup_api_key: Option<String<{ config::API_KEY_MAX_LEN }>>,
+    api: &'a str,
+    dns_cache: Option<&'a DnsCache>,
+    request_counter: Option<&'a RequestCounter>,
+    clock: Option<&'a WallClock>,
+    #[cfg(feature = "rail")]
+    destinations: Vec<Station, { Config::MAX_DESTINATIONS }>,
+    #[cfg(feature = "rail")]
+    lines_mask: u8,
+    #[cfg(feature = "rail")]
+    min_minutes: (u8, bool),
+    #[cfg(feature = "rail")]
+    no_passenger: NoPassengerPolicy,
+    #[cfg(feature = "rail")]
+    drop_unreachable: bool,
+    /// The last predictions [`Client::latest`] got, and for which station.
+    #[cfg(feature = "rail")]
+    last_good: Option<(Station, NextTrainsResponse)>,
+    timeout: Duration,
+    // only the station list, bus and track layout endpoints have responses big enough to need it
+    #[cfg_attr(
+        not(any(feature = "rail", feature = "bus", feature = "positions")),
+        allow(dead_code)
+    )]
+    large_timeout: Duration,
+    deadline: Option<Instant>,
+    breaker: CircuitBreaker,
+    #[cfg(feature = "gzip")]
+    inflate_buf: Option<&'a mut [u8]>,
+    response_cache: Option<ResponseCache<'a>>,
 }
 
+/// A [`Client`] several tasks can take turns with, e.g. the display loop and a status page both
+/// querying the api. There's one connection and one `rx_buf`, so a task locks the client for each
+/// request and anyone else waits until it's done.
+///
+/// Lock it right in the call, like `client.lock().await.next_trains(station).await`. The guard
+/// lives until the end of that statement, so the lock is held for the whole request and no longer.
+/// Binding the guard to a variable holds the lock until the variable goes out of scope, so never
+/// keep one across a wait on anything else, like a poll timer, or every other task stalls behind it.
+///
+/// Put it in a `static` (or a `StaticCell`) to hand it to spawned tasks. Use a
+/// `CriticalSectionRawMutex` for `M`, or a `NoopRawMutex` if every user runs on the same executor.
+pub type SharedClient<'a, M, T, D> = Mutex<M, Client<'a, T, D>>;
+
 impl<'a, T, D> Client<'a, T, D>
 where
     T: TcpConnect + 'a,
     D: Dns + 'a,
 {
+    pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+    pub const DEFAULT_LARGE_TIMEOUT: Duration = Duration::from_secs(30);
+    /// Stations per request in [`Client::next_trains_for_line`]. Each train is almost 200 bytes of
+    /// json and a busy station has half a dozen, so three stations fit the firmware's 4 KiB `rx_buf`.
+    #[cfg(feature = "rail")]
+    pub const LINE_CHUNK: usize = 3;
+    /// How long [`Client::latest`] waits for fresh predictions before falling back on the last
+    /// ones. A healthy request takes well under a second.
+    #[cfg(feature = "rail")]
+    pub const LATEST_DEADLINE: Duration = Duration::from_secs(3);
+    /// Largest radius [`Client::bus_stops`] asks for, in meters. Each stop is around 200 bytes,
+    /// and downtown this already takes in a few dozen, about as many as a 4KB `rx_buf` holds.
+    #[cfg(feature = "bus")]
+    pub const MAX_STOP_RADIUS: u32 = 300;
+
     /// Create a new `WmataClient` around a `reqwless` client.
+    /// [`Client::with_base_and_key`] sets all of this up from just an `embassy-net` stack.
     /// Takes ownership of the reqwless client.
-    /// The Api key is required.
-    pub fn new(reqwless: HttpClient<'a, T, D>, rx_buf: &'a mut [u8], api_key: &'a str) -> Self {
+    /// The Api key is required. It's copied in, so it can be swapped later with
+    /// [`Client::set_api_key`]; anything past [`API_KEY_MAX_LEN`](config::API_KEY_MAX_LEN) bytes is cut off.
+    ///
+    /// Every response is read into `rx_buf` whole, headers and body, so it decides which
+    /// endpoints are usable, and a response that doesn't fit fails with [`Error::Http`]:
+    /// - 2KB covers one platform's predictions, or a whole station most of the time.
+    /// - 4KB, the default [`ClientResources`] buffer, covers any one station, rail incidents, one
+    ///   station's elevator outages and bus stops out to `Client::MAX_STOP_RADIUS`.
+    /// - 16KB, with the `large-buffers` feature, also takes bigger chunks in `next_trains_chunked`,
+    ///   up to its url limit, and the system-wide elevator outages.
+    /// - The `positions` endpoints run far larger than any of these.
+    ///
+    /// `Client::with_gzip` takes a lot off what has to fit, the body is compressed in `rx_buf`.
+    pub fn new(reqwless: HttpClient<'a, T, D>, rx_buf: &'a mut [u8], api_key: &str) -> Self {
         Self {
+            reqwless,
+            rx_buf,
+            api_key: truncated(api_key),
+            backup_api_key: None,
+            api: API,
+            dns_cache: None,
+            request_counter: None,
+            clock: None,
+            #[cfg(feature = "rail")]
+            destinations: Vec::new(),
+            #[cfg(feature = "rail")]
+            lines_mask: 0,
+            #[cfg(feature = "rail")]
+            min_minutes: (0, false),
+            #[cfg(feature = "rail")]
+            no_passenger: NoPassengerPolicy::Hide,
+            #[cfg(feature = "rail")]
+            drop_unreachable: false,
+            #[cfg(feature = "rail")]
+            last_good: None,
+            timeout: Self::DEFAULT_TIMEOUT,
+            large_timeout: Self::DEFAULT_LARGE_TIMEOUT,
+            deadline: None,
+            breaker: CircuitBreaker::default(),
+            #[cfg(feature = "gzip")]
+            inflate_buf: None,
+            response_cache: None,
+        }
+    }
+
+    /// Send requests to `api` instead of the official api, e.g. a caching proxy shared by several
+    /// displays or a local mock server. Must be `http://` or `https://` followed by a host, and at
+    /// most 64 bytes so every path still fits after it.
+    ///
+    /// `https://` only works if `reqwless` is built with a TLS feature, which this firmware doesn't
+    /// enable, otherwise every request fails with an unsupported scheme error. A certificate's dates
+    /// can't be checked against a clock that still reads 1970, so with a clock from
+    /// [`Client::with_clock`], requests to an `https://` api wait for it: they fail with
+    /// [`Error::ClockNotSynced`], without being sent, until it's been set some other way, e.g.
+    /// [`WallClock::set`] from SNTP or a plain `http://` server's `Date`. Without a clock nothing is
+    /// checked. `reqwless`'s `embedded-tls` backend doesn't verify certificates at all.
+    pub fn with_api(mut self, api: &'a str) -> Result<Self, Error> {
+        self.api = validate_base_url(api).ok_or(Error::InvalidBaseUrl)?;
+        Ok(self)
+    }
+
+    /// Drop `dns_cache`'s entry whenever connecting fails, in case the server moved.
+    /// This should be the same cache the `reqwless` client resolves through, via [`CachedDns`].
+    pub fn with_dns_cache(mut self, dns_cache: &'a DnsCache) -> Self {
+        self.dns_cache = Some(dns_cache);
+        self
+    }
+
+    /// Count every request sent to the api in `request_counter`.
+    pub fn with_request_counter(mut self, request_counter: &'a RequestCounter) -> Self {
+        self.request_counter = Some(request_counter);
+        self
+    }
+
+    /// Only return trains terminating at one of `destinations` from [`Client::next_trains`] and
+    /// [`Client::try_next_trains`], usually [`Config::destinations`]. An empty list keeps every train.
+    #[cfg(feature = "rail")]
+    pub fn with_destinations(
+        mut self,
+        destinations: Vec<Station, { Config::MAX_DESTINATIONS }>,
+    ) -> Self {
+        self.destinations = destinations;
+        self
+    }
+
+    /// Only return trains on a line in `mask` from [`Client::next_trains`] and
+    /// [`Client::try_next_trains`], usually [`Config::lines_mask`]. `0` keeps every line, see
+    /// [`NextTrainsResponse::retain_lines`].
+    #[cfg(feature = "rail")]
+    pub fn with_lines_mask(mut self, mask: u8) -> Self {
+        self.lines_mask = mask;
+        self
+    }
+
+    /// Leave trains due in under `minutes` out of [`Client::next_trains`] and
+    /// [`Client::try_next_trains`], and ARR and BRD trains too unless `keep_arriving`, usually
+    /// [`Config::min_minutes`] and [`Config::keeps_arriving`]. `0` keeps every train, see
+    /// [`NextTrainsResponse::retain_catchable`].
+    #[cfg(feature = "rail")]
+    pub fn with_min_minutes(mut self, minutes: u8, keep_arriving: bool) -> Self {
+        self.min_minutes = (minutes, keep_arriving);
+        self
+    }
+
+    /// What to do with trains that aren't taking passengers, usually [`Config::no_passenger`].
+    /// Hidden by default, see [`NextTrainsResponse::apply_no_passenger`].
+    #[cfg(feature = "rail")]
+    pub fn with_no_passenger(mut self, policy: NoPassengerPolicy) -> Self {
+        self.no_passenger = policy;
+        self
+    }
+
+    /// Leave trains whose destination isn't on their line out of [`Client::next_trains`] and
+    /// [`Client::try_next_trains`], see [`NextTrainsResponse::retain_reachable`]. Off by default.
+    #[cfg(feature = "rail")]
+    pub fn with_drop_unreachable(mut self, drop: bool) -> Self {
+        self.drop_unreachable = drop;
+        self
+    }
+
+    /// Send `api_key` from the next request on, e.g. after a new [`Config`] was saved.
+    /// Errors without changing anything if it's longer than the config allows.
+    pub fn set_api_key(&mut self, api_key: &str) -> Result<(), ConfigError> {
+        self.api_key = String::try_from(api_key).map_err(|_| ConfigError::BadArgs)?;
+        Ok(())
+    }
+
+    /// Switch to `api_key` when WMATA turns the current key down with a 401 or a 429, usually
+    /// [`Config::backup_api_key`]. The request is retried once with it, and it's used from then on
+    /// while the turned down key becomes the backup. Cut off like the key in [`Client::new`].
+    pub fn with_backup_api_key(mut self, api_key: Option<&str>) -> Self {
+        self.backup_api_key = api_key.map(truncated);
+        self
+    }
+
+    /// Same as [`Client::with_backup_api_key`], for a client that's already running.
+    /// Errors without changing anything if it's longer than the config allows.
+    pub fn set_backup_api_key(&mut self, api_key: Option<&str>) -> Result<(), ConfigError> {
+        self.backup_api_key = api_key
+            .map(String::try_from)
+            .transpose()
+            .map_err(|_| ConfigError::BadArgs)?;
+        Ok(())
+    }
+
+    /// Same as [`Client::with_destinations`], for a client that's already running.
+    #[cfg(feature = "rail")]
+    pub fn set_destinations(&mut self, destinations: Vec<Station, { Config::MAX_DESTINATIONS }>) {
+        self.destinations = destinations;
+    }
+
+    /// Same as [`Client::with_lines_mask`], for a client that's already running.
+    #[cfg(feature = "rail")]
+    pub fn set_lines_mask(&mut self, mask: u8) {
+        self.lines_mask = mask;
+    }
+
+    /// Same as [`Client::with_min_minutes`], for a client that's already running.
+    #[cfg(feature = "rail")]
+    pub fn set_min_minutes(&mut self, minutes: u8, keep_arriving: bool) {
+        self.min_minutes = (minutes, keep_arriving);
+    }
+
+    /// Same as [`Client::with_no_passenger`], for a client that's already running.
+    #[cfg(feature = "rail")]
+    pub fn set_no_passenger(&mut self, policy: NoPassengerPolicy) {
+        self.no_passenger = policy;
+    }
+
+    /// Same as [`Client::with_drop_unreachable`], for a client that's already running.
+    #[cfg(feature = "rail")]
+    pub fn set_drop_unreachable(&mut self, drop: bool) {
+        self.drop_unreachable = drop;
+    }
+
+    /// Drop the trains the reachability, destination, line and minimum minutes filters leave
+    /// out, and apply the no passenger policy.
+    #[cfg(feature = "rail")]
+    fn filter(&self, res: &mut NextTrainsResponse) {
+        if self.drop_unreachable {
+            res.retain_reachable();
+        }
+        res.retain_destinations(&self.destinations);
+        res.retain_lines(self.lines_mask);
+        res.retain_catchable(self.min_minutes.0, self.min_minutes.1);
+        res.apply_no_passenger(self.no_passenger);
+    }
+
+    /// Give up on a request after `timeout`, from connecting through reading the body, with
+    /// [`Error::Timeout`]. `large_timeout` is used instead for endpoints with big responses, like
+    /// every bus in [`Client::bus_positions`]. This is on top of the socket's own inactivity timeout.
+    pub fn with_timeout(mut self, timeout: Duration, large_timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self.large_timeout = large_timeout;
+        self
+    }
+
+    /// Abort any request still running at `deadline` with [`Error::DeadlineExceeded`], and fail
+    /// any that would start after it the same way without sending it, until it's cleared with
+    /// `None`. E.g. set it to the next poll, so a response trickling in under the timeout, or a
+    /// run of them in [`Client::next_trains_chunked`], never holds up the display past it.
+    ///
+    /// The timeout from [`Client::with_timeout`] still applies to each request on its own, this
+    /// only cuts one short when the deadline comes first.
+    pub fn set_deadline(&mut self, deadline: Option<Instant>) {
+        self.deadline = deadline;
+    }
+
+    /// Set `clock` from the `Date` header of every response, e.g. to stop polling overnight with
+    /// [`ServiceHours`].
+    pub fn with_clock(mut self, clock: &'a WallClock) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Trip after a different number of failures, or cool down for longer, than
+    /// [`CircuitBreaker::default`]. Every client has a breaker, this just tunes it.
+    pub fn with_circuit_breaker(mut self, breaker: CircuitBreaker) -> Self {
+        self.breaker = breaker;
+        self
+    }
+
+    /// Ask for gzipped responses, and decompress them into `inflate_buf` before decoding.
+    ///
+    /// The api's json shrinks several times over, so a busy station's predictions fit `rx_buf`
+    /// with room to spare and take less time on a slow connection. `inflate_buf` has to hold the
+    /// whole decompressed body, like `rx_buf` does without gzip, and about 11KB more is allocated
+    /// for the decompressor during each request. Responses the server didn't compress are decoded
+    /// straight from `rx_buf` as usual.
+    #[cfg(feature = "gzip")]
+    pub fn with_gzip(mut self, inflate_buf: &'a mut [u8]) -> Self {
+        self.inflate_buf = Some(inflate_buf);
+        self
+    }
+
+    /// Keep the last response body in `cache_buf` and ask for it again only if it's changed since,
+    /// with `If-None-Match` or `If-Modified-Since`, decoding the kept copy on a `304 Not Modified`.
+    /// See [`ResponseCache`] for what's kept. With gzip it's the decompressed body, so `cache_buf`
+    /// needs to be as big as `inflate_buf`.
+    pub fn with_response_cache(mut self, cache_buf: &'a mut [u8]) -> Self {
+        self.response_cache = Some(ResponseCache::new(cache_buf));
+        self
+    }
+
+    /// Whether requests are being turned away with [`Error::CircuitOpen`] right now, e.g. for a
+    /// status page to tell an outage apart from a slow connection.
+    pub fn circuit_open(&self) -> bool {
+        self.breaker.is_open(Instant::now())
+    }
+
+    /// Take the client apart, handing back the `reqwless` client and `rx_buf`, e.g. to reuse the
+    /// buffer and the TCP client's sockets for a provisioning portal.
+    ///
+    /// Every request opens its own connection and drops it before returning, even when cancelled by
+    /// a timeout, so no socket is ever left open in between and there's nothing to shut down. This is
+    /// only needed when those resources are wanted back, otherwise dropping the client is just as clean.
+    /// Nothing is leaked with `mem::forget`, the rest of the client is plain references and data.
+    pub fn close(self) -> (HttpClient<'a, T, D>, &'a mut [u8]) {
+        (self.reqwless, self.rx_buf)
+    }
+
+    /// Fetch any endpoint of the api, e.g. one this crate doesn't wrap yet, and decode the body as `J`.
+    /// `path` is appended to the base url and should start with `/`, including any query string,
+    /// e.g. `"/Rail.svc/json/jLines"`. It's up to the caller that `J` matches what the endpoint returns,
+    /// a mismatch only shows up as [`Error::Json`].
+    pub async fn get<J: Deserialize>(&mut self, path: &str) -> Result<J, Error> {
+        self.get_with_timeout(path, self.timeout).await
+    }
+
+    /// Same as [`Client::get`] with a timeout for just this call, e.g. for a slow endpoint.
+    pub async fn get_with_timeout<J: Deserialize>(
+        &mut self,
+        path: &str,
+        timeout: Duration,
+    ) -> Result<J, Error> {
+        self.request(path, timeout, &[]).await
+    }
+
+    /// Fetch any endpoint like [`Client::get`], but hand back the status, the headers named in
+    /// `keep` and the body as they came, e.g. to look at rate limit headers or debug an endpoint.
+    /// Header names are matched ignoring case, like `&["RateLimit-Remaining", "Date"]`.
+    ///
+    /// A non-2xx status isn't an error here, it's just returned, though it still counts towards
+    /// the circuit breaker as it would anywhere else. The body is left in `rx_buf`, so it's only
+    /// around until the next request. Responses aren't cached, see [`Client::with_response_cache`],
+    /// and a 401 or 429 is returned as is rather than retried with [`Client::with_backup_api_key`].
+    pub async fn get_raw(&mut self, path: &str, keep: &[&str]) -> Result<RawResponse<'_>, Error> {
+        self.breaker.check(Instant::now())?;
+        self.check_clock()?;
+        let mut buf: String<URL_LEN> = String::new();
+        let url = build_url(&mut buf, self.api, path)?;
+        let mut trace = Trace::start(url);
+        let accept_gzip = self.accepts_gzip();
+
+        // split up, so the body can borrow `rx_buf` while the breaker is updated
+        let Self {
             reqwless,
             rx_buf,
             api_key,
+            dns_cache,
+            request_counter,
+            clock,
+            timeout,
+            deadline,
+            breaker,
+            #[cfg(feature = "gzip")]
+            inflate_buf,
+            ..
+        } = self;
+        let fetch = async {
+            let headers = request_headers(api_key, accept_gzip, None);
+            trace.headers(&headers);
+            let mut req = match reqwless.request(Method::GET, url).await {
+                Ok(req) => req.headers(headers.as_slice()),
+                Err(e) => return Err(connect_failed(*dns_cache, e)),
+            };
+
+            if let Some(request_counter) = request_counter {
+                request_counter.record();
+            }
+            let res = req.send(rx_buf).await?;
+            if let Some(clock) = clock {
+                sync_clock(clock, res.headers());
+            }
+            let status = res.status.0;
+            trace.status(status);
+            let mut kept = Vec::new();
+            for (name, value) in res.headers() {
+                if !keep.iter().any(|k| k.eq_ignore_ascii_case(name)) {
+                    continue;
+                }
+                let value = core::str::from_utf8(value).ok().map(String::try_from);
+                if let (Ok(name), Some(Ok(value))) = (String::try_from(name), value) {
+                    let _ = kept.push((name, value));
+                }
+            }
+            #[cfg(feature = "gzip")]
+            let gzipped = gzip::is_gzipped(res.headers());
+            let body = res.body().read_to_end().await?;
+            trace.body(body.len());
+            #[cfg(feature = "gzip")]
+            let body = inflated(body, gzipped, inflate_buf.as_deref_mut())?;
+
+            Ok(RawResponse {
+                status,
+                headers: kept,
+                body,
+            })
+        };
+        let res = timed(*timeout, *deadline, fetch).await;
+        trace.end(&res);
+
+        match &res {
+            Ok(raw) if !(200..300).contains(&raw.status) => {
+                let err = Error::from_status(raw.status);
+                breaker.record(Instant::now(), &Err::<(), _>(err));
+            }
+            _ => breaker.record(Instant::now(), &res),
+        }
+        res
+    }
+
+    /// Check the api can be reached and takes the api key with one cheap request, e.g. before
+    /// the first fetch or after a new key was saved. Any 2xx is `Ok` and a 401 is
+    /// [`Error::Unauthorized`]. Not getting an answer at all is [`Error::Http`], or
+    /// [`Error::Timeout`] past the client's timeout.
+    ///
+    /// Counts as a request like any other, towards the daily quota and the circuit breaker. Like
+    /// [`Client::get_raw`] it never switches to the backup api key, so it checks the current one.
+    pub async fn ping(&mut self) -> Result<(), Error> {
+        let status = self.get_raw(PING_PATH, &[]).await?.status;
+        match status {
+            200..300 => Ok(()),
+            status => Err(Error::from_status(status)),
+        }
+    }
+
+    /// `expected` are the fields every element of the response should have, for the `strict`
+    /// feature to warn about any that went missing, see [`parse`].
+    async fn request<J: Deserialize>(
+        &mut self,
+        path: &str,
+        timeout: Duration,
+        expected: &[&str],
+    ) -> Result<J, Error> {
+        self.breaker.check(Instant::now())?;
+        self.check_clock()?;
+        let mut buf: String<URL_LEN> = String::new();
+        let url = build_url(&mut buf, self.api, path)?;
+        let mut res = self.request_once(url, timeout, expected).await;
+        if self.fail_over(&res) {
+            res = self.request_once(url, timeout, expected).await;
+        }
+        self.breaker.record(Instant::now(), &res);
+        res
+    }
+
+    async fn request_once<J: Deserialize>(
+        &mut self,
+        url: &str,
+        timeout: Duration,
+        expected: &[&str],
+    ) -> Result<J, Error> {
+        let mut trace = Trace::start(url);
+        let res = timed(
+            timeout,
+            self.deadline,
+            self.fetch(url, expected, &mut trace),
+        )
+        .await;
+        trace.end(&res);
+        res
+    }
+
+    /// Swap in the backup api key if `res` is WMATA turning the current one down, returning
+    /// whether to retry with it. Only the retry's result counts towards the circuit breaker.
+    fn fail_over<R>(&mut self, res: &Result<R, Error>) -> bool {
+        let Err(e @ (Error::Unauthorized | Error::Status(429))) = res else {
+            return false;
+        };
+        let Some(backup) = &mut self.backup_api_key else {
+            return false;
+        };
+        core::mem::swap(&mut self.api_key, backup);
+        warn!("api key turned down ({}), switching to the backup key", e);
+        true
+    }
+
+    /// Validator of the cached response to `url`, if there's a cache and it has one.
+    fn cached_validator(&self, url: &str) -> Option<Validator> {
+        self.response_cache.as_ref()?.validator(url)
+    }
+
+    /// [`Error::ClockNotSynced`] if the api is `https://` and the clock hasn't been set, see
+    /// [`Client::with_api`].
+    fn check_clock(&self) -> Result<(), Error> {
+        match self.clock {
+            Some(clock) if self.api.starts_with("https://") && clock.unix_now().is_none() => {
+                Err(Error::ClockNotSynced)
+            }
+            _ => Ok(()),
         }
     }
 
-    /// Convenience function for making requests
-    async fn fetch<J: Deserialize>(&mut self, url: &str) -> Result<J, Error> {
-        let headers = [
-            ("Api_key", self.api_key),
-            ("User-Agent", USER_AGENT),
-            ("Connection", "close"),
-        ];
-        let mut req = self
-            .reqwless
-            .request(Method::GET, url)
-            .await?
-            .headers(&headers);
+    /// Whether to ask for gzipped responses, see [`Client::with_gzip`].
+    fn accepts_gzip(&self) -> bool {
+        #[cfg(feature = "gzip")]
+        return self.inflate_buf.is_some();
+        #[cfg(not(feature = "gzip"))]
+        false
+    }
+
+    async fn fetch<J: Deserialize>(
+        &mut self,
+        url: &str,
+        expected: &[&str],
+        trace: &mut Trace,
+    ) -> Result<J, Error> {
+        let validator = self.cached_validator(url);
+        let headers = request_headers(&self.api_key, self.accepts_gzip(), validator.as_ref());
+        trace.headers(&headers);
+        let mut req = match self.reqwless.request(Method::GET, url).await {
+            Ok(req) => req.headers(headers.as_slice()),
+            Err(e) => return Err(connect_failed(self.dns_cache, e)),
+        };
 
+        if let Some(request_counter) = self.request_counter {
+            request_counter.record();
+        }
         let res = req.send(self.rx_buf).await?;
+        if let Some(clock) = self.clock {
+            sync_clock(clock, res.headers());
+        }
+        trace.status(res.status.0);
+        if let Some(body) = not_modified(self.response_cache.as_ref(), url, res.status.0) {
+            return parse(url, body, expected);
+        }
+        if !res.status.is_successful() {
+            return Err(Error::from_status(res.status.0));
+        }
+        let validator = self
+            .response_cache
+            .as_ref()
+            .and_then(|_| Validator::from_headers(res.headers()));
+        #[cfg(feature = "gzip")]
+        let gzipped = gzip::is_gzipped(res.headers());
         let body = res.body().read_to_end().await?;
-        let json = core::str::from_utf8(body)?;
-        debug!("{:?}", json);
-        miniserde::json::from_str(json).map_err(|e| e.into())
+        trace.body(body.len());
+        #[cfg(feature = "gzip")]
+        let body = inflated(body, gzipped, self.inflate_buf.as_deref_mut())?;
+        if let Some(cache) = &mut self.response_cache {
+            cache.store(url, validator, body);
+        }
+        parse(url, body, expected)
     }
 
     /// Returns next train arrival information for one or more stations.
@@ -131,13 +982,1225 @@ where
     /// For trains with no passengers, the DestinationName will be No Passenger.
     /// Next train arrival information is refreshed once every 20 to 30 seconds approximately.
     ///
+    /// The api has no line filter for predictions, so any line, platform or destination
+    /// filtering happens client-side after the whole station has been fetched.
+    ///
     /// # Arguments
     ///
     /// * `station` - station code like `B03`.
+    #[cfg(feature = "rail")]
     pub async fn next_trains(&mut self, station: Station) -> Result<NextTrainsResponse, Error> {
+        self.next_trains_for(&[station]).await
+    }
+
+    /// Predictions for `station` without waiting long for them, for a display that should always
+    /// have something up: a fresh fetch that gets [`Client::LATEST_DEADLINE`], or any earlier
+    /// deadline set with [`Client::set_deadline`], and if that fails for any reason, the last
+    /// predictions this got for `station` as [`Latest::Cached`]. Only errors when there are none.
+    ///
+    /// The last good response is kept in the client, a copy of it on the heap, until the next
+    /// one replaces it. It's returned however old it is, along with its age.
+    #[cfg(feature = "rail")]
+    pub async fn latest(&mut self, station: Station) -> Result<Latest, Error> {
+        let outer = self.deadline;
+        let deadline = Instant::now() + Self::LATEST_DEADLINE;
+        self.deadline = Some(outer.map_or(deadline, |outer| outer.min(deadline)));
+        let res = self.next_trains(station).await;
+        self.deadline = outer;
+
+        let e = match res {
+            Ok(response) => {
+                self.last_good = Some((station, response.clone()));
+                return Ok(Latest::Fresh(response));
+            }
+            Err(e) => e,
+        };
+        match &self.last_good {
+            Some((last, response)) if *last == station => {
+                warn!("showing cached predictions for {}: {}", station, e);
+                Ok(Latest::Cached {
+                    response: response.clone(),
+                    age: response.age().unwrap_or_default(),
+                })
+            }
+            _ => Err(e),
+        }
+    }
+
+    /// Predictions for the station called `name`, for typing a name at a serial console instead
+    /// of looking up its code. See [`Station::from_name`](types::Station::from_name) for how the
+    /// name is matched, and the errors if it doesn't pick out one station.
+    #[cfg(feature = "rail")]
+    pub async fn next_trains_by_name(&mut self, name: &str) -> Result<NextTrainsResponse, Error> {
+        let stations = Station::from_name(name)?;
+        self.next_trains_for(&stations).await
+    }
+
+    /// Predictions for `station`, or for `fallback` when `station` has none, e.g. a terminal
+    /// overnight. Check the trains' `location_code` to tell which one answered.
+    /// If the fallback fails too, or has nothing either, the empty response for `station` is returned.
+    #[cfg(feature = "rail")]
+    pub async fn next_trains_or(
+        &mut self,
+        station: Station,
+        fallback: Option<Station>,
+    ) -> Result<NextTrainsResponse, Error> {
+        let res = self.next_trains(station).await?;
+        let Some(fallback) = fallback.filter(|_| res.trains.is_empty()) else {
+            return Ok(res);
+        };
+
+        debug!("no trains at {}, trying {}", station, fallback);
+        match self.next_trains(fallback).await {
+            Ok(fallback_res) if !fallback_res.trains.is_empty() => Ok(fallback_res),
+            Ok(_) => Ok(res),
+            Err(e) => {
+                warn!("fallback station {} failed: {}", fallback, e);
+                Ok(res)
+            }
+        }
+    }
+
+    /// Predictions for one platform edge: `line` trains on track `group` at `station`, soonest first.
+    /// What a directional sign on the platform shows. Groups are per station, see [`types::Group`]
+    /// for finding the right one. For stations with a code per level, pass the level's code.
+    #[cfg(feature = "rail")]
+    pub async fn platform_trains(
+        &mut self,
+        station: Station,
+        line: LineKind,
+        group: u8,
+    ) -> Result<NextTrainsResponse, Error> {
+        let mut response = self.next_trains(station).await?;
+        response.retain_platform(line, group);
+        Ok(response)
+    }
+
+    /// Predictions for `station` split in two for a side-by-side display, one level per side at
+    /// a station with two, like Metro Center, otherwise one track per side. Both levels come in
+    /// one request. See [`TransferView::new`].
+    #[cfg(feature = "rail")]
+    pub async fn transfer_view(&mut self, station: Station) -> Result<TransferView, Error> {
+        let res = match station.other_level() {
+            Some(other) => self.next_trains_for(&[station, other]).await?,
+            None => self.next_trains(station).await?,
+        };
+        Ok(TransferView::new(station, res))
+    }
+
+    /// Predictions for many stations, fetched `chunk` stations per request and merged into one
+    /// response, soonest first, see [`NextTrainsResponse::merge`].
+    /// Lets a small `rx_buf` cover stations whose combined response wouldn't fit in a single request.
+    /// The request url fits 16 stations with the default api, and a `chunk` too big for it is
+    /// [`Error::Format`] rather than a request for only some of them.
+    ///
+    /// With [`OnChunkError::Skip`] the stations of a failed chunk are left out, and an error is only
+    /// returned when every chunk failed. With [`OnChunkError::Abort`] the first failure is returned.
+    #[cfg(feature = "rail")]
+    pub async fn next_trains_chunked(
+        &mut self,
+        stations: &[Station],
+        chunk: usize,
+        on_error: OnChunkError,
+    ) -> Result<NextTrainsResponse, Error> {
+        let mut merged: Option<NextTrainsResponse> = None;
+        let mut last_err = None;
+
+        for stations in stations.chunks(chunk.max(1)) {
+            match self.next_trains_for(stations).await {
+                Ok(res) => match &mut merged {
+                    Some(merged) => merged.merge(res),
+                    None => merged = Some(res),
+                },
+                Err(e) if on_error == OnChunkError::Skip => {
+                    warn!("skipping chunk {:?}: {}", stations, e);
+                    last_err = Some(e);
+                }
+                Err(e) => return Err(e),
+            }
+        }
+
+        match (merged, last_err) {
+            (Some(merged), _) => Ok(merged),
+            (None, Some(e)) => Err(e),
+            (None, None) => Ok(NextTrainsResponse::new(alloc::vec::Vec::new())),
+        }
+    }
+
+    /// Predictions for every station on `line`, grouped by station in track order, e.g. for a
+    /// line overview. Only `line`'s own trains are kept, filtered client-side.
+    ///
+    /// Stations are fetched [`Client::LINE_CHUNK`] per request, so this costs one request per
+    /// `LINE_CHUNK` stations against the daily quota, e.g. 9 for the 27 Red Line stations. Polling
+    /// it as often as [`Client::next_trains`] uses the quota up several times faster.
+    /// Stations of a failed request are left empty, see [`OnChunkError::Skip`].
+    #[cfg(feature = "rail")]
+    pub async fn next_trains_for_line(&mut self, line: LineKind) -> Result<LineTrains, Error> {
+        let res = self
+            .next_trains_chunked(line.stations(), Self::LINE_CHUNK, OnChunkError::Skip)
+            .await?;
+        Ok(LineTrains::new(line, res))
+    }
+
+    #[cfg(feature = "rail")]
+    async fn next_trains_for(&mut self, stations: &[Station]) -> Result<NextTrainsResponse, Error> {
+        #[cfg(feature = "offline")]
+        if let Some(station) = stations.first() {
+            warn!("offline: serving canned predictions, not live data");
+            let mut res = demo::next_trains(*station);
+            self.filter(&mut res);
+            return Ok(res);
+        }
+
+        let mut buf: String<128> = String::new();
+        let path = build_next_trains_path(&mut buf, stations)?;
+        let res: MaybeNextTrainsResponse =
+            self.request(path, self.timeout, NextTrain::FIELDS).await?;
+        let mut res = res
+            .trains
+            .map(NextTrainsResponse::new)
+            .ok_or(Error::EmptyResponse)?;
+        self.filter(&mut res);
+        Ok(res)
+    }
+
+    /// Scheduled opening, first and last train times at `station` for every day of the week.
+    /// Live predictions can dry up late at night or during disruptions, but the schedule is always there,
+    /// e.g. to show "last train at 23:47" instead of an empty board.
+    #[cfg(feature = "rail")]
+    pub async fn scheduled_trains(&mut self, station: Station) -> Result<StationTimes, Error> {
         let mut buf: String<128> = String::new();
-        let url = build_next_trains_url(&mut buf, station)?;
-        debug!("{:?}", url);
-        self.fetch(url).await
+        let path = build_station_times_path(&mut buf, station)?;
+        let res: StationTimesResponse = self
+            .request(path, self.timeout, StationTimes::FIELDS)
+            .await?;
+        res.stations.into_iter().next().ok_or(Error::EmptyResponse)
+    }
+
+    /// Every station on `line`, or in the system for `None`, with WMATA's current names, e.g. to
+    /// keep a [`StationNames`](types::StationNames) up to date with renamed stations.
+    ///
+    /// The whole system is about 40KB of json, which takes `Client::with_gzip` or a larger
+    /// `rx_buf` than the default, see [`Client::new`]. One line is a quarter of that or less.
+    #[cfg(feature = "rail")]
+    pub async fn station_list(
+        &mut self,
+        line: Option<LineKind>,
+    ) -> Result<StationListResponse, Error> {
+        let mut buf: String<128> = String::new();
+        let path = build_station_list_path(&mut buf, line)?;
+        self.request(path, self.large_timeout, StationInfo::FIELDS)
+            .await
+    }
+
+    /// Same as [`Client::next_trains`], but also reports how long each phase of the request took.
+    /// Useful for telling whether slow updates are network or parsing bound.
+    #[cfg(feature = "rail")]
+    pub async fn try_next_trains(
+        &mut self,
+        station: Station,
+    ) -> Result<(NextTrainsResponse, FetchStats), Error> {
+        let mut path_buf: String<128> = String::new();
+        let path = build_next_trains_path(&mut path_buf, &[station])?;
+        let mut buf: String<URL_LEN> = String::new();
+        let url = build_url(&mut buf, self.api, path)?;
+        self.breaker.check(Instant::now())?;
+        self.check_clock()?;
+        let mut res = self.fetch_timed_with_stats(url).await;
+        if self.fail_over(&res) {
+            res = self.fetch_timed_with_stats(url).await;
+        }
+        self.breaker.record(Instant::now(), &res);
+        res
+    }
+
+    #[cfg(feature = "rail")]
+    async fn fetch_timed_with_stats(
+        &mut self,
+        url: &str,
+    ) -> Result<(NextTrainsResponse, FetchStats), Error> {
+        let mut trace = Trace::start(url);
+        let res = timed(
+            self.timeout,
+            self.deadline,
+            self.fetch_with_stats(url, &mut trace),
+        )
+        .await;
+        trace.end(&res);
+        res
+    }
+
+    #[cfg(feature = "rail")]
+    async fn fetch_with_stats(
+        &mut self,
+        url: &str,
+        trace: &mut Trace,
+    ) -> Result<(NextTrainsResponse, FetchStats), Error> {
+        let validator = self.cached_validator(url);
+        let headers = request_headers(&self.api_key, self.accepts_gzip(), validator.as_ref());
+        trace.headers(&headers);
+
+        let start = Instant::now();
+        let mut req = match self.reqwless.request(Method::GET, url).await {
+            Ok(req) => req.headers(headers.as_slice()),
+            Err(e) => return Err(connect_failed(self.dns_cache, e)),
+        };
+        let connected = Instant::now();
+
+        if let Some(request_counter) = self.request_counter {
+            request_counter.record();
+        }
+        let res = req.send(self.rx_buf).await?;
+        let headers_read = Instant::now();
+        trace.status(res.status.0);
+        if let Some(clock) = self.clock {
+            sync_clock(clock, res.headers());
+        }
+        let cached = not_modified(self.response_cache.as_ref(), url, res.status.0);
+        if cached.is_none() && !res.status.is_successful() {
+            return Err(Error::from_status(res.status.0));
+        }
+
+        // a 304 has no body to read, the cached one is decoded instead
+        let (body, body_size): (&[u8], usize) = match cached {
+            Some(body) => (body, 0),
+            None => {
+                let validator = self
+                    .response_cache
+                    .as_ref()
+                    .and_then(|_| Validator::from_headers(res.headers()));
+                #[cfg(feature = "gzip")]
+                let gzipped = gzip::is_gzipped(res.headers());
+                let body = res.body().read_to_end().await?;
+                let body_size = body.len();
+                trace.body(body_size);
+                #[cfg(feature = "gzip")]
+                let body = inflated(body, gzipped, self.inflate_buf.as_deref_mut())?;
+                if let Some(cache) = &mut self.response_cache {
+                    cache.store(url, validator, body);
+                }
+                (body, body_size)
+            }
+        };
+        let body_read = Instant::now();
+
+        let res: MaybeNextTrainsResponse = parse(url, body, NextTrain::FIELDS)?;
+        let parsed = Instant::now();
+
+        let trains = res.trains.ok_or(Error::EmptyResponse)?;
+        let stats = FetchStats {
+            connect: connected - start,
+            ttfb: headers_read - connected,
+            body: body_read - headers_read,
+            body_size,
+            parse: parsed - body_read,
+        };
+
+        let mut res = NextTrainsResponse::new(trains);
+        // `req` still borrows the http client, so not `self.filter`
+        if self.drop_unreachable {
+            res.retain_reachable();
+        }
+        res.retain_destinations(&self.destinations);
+        res.retain_lines(self.lines_mask);
+        res.retain_catchable(self.min_minutes.0, self.min_minutes.1);
+        res.apply_no_passenger(self.no_passenger);
+        Ok((res, stats))
+    }
+
+    /// Returns reported rail incidents (significant disruptions and delays to normal service).
+    /// The data is identical to WMATA's Metrorail Service Status feed.
+    /// The api can't filter incidents by line, use [`RailIncident::affects`](types::RailIncident::affects)
+    /// on the response.
+    #[cfg(feature = "incidents")]
+    pub async fn rail_incidents(&mut self) -> Result<RailIncidentsResponse, Error> {
+        self.request(RAIL_INCIDENTS_PATH, self.timeout, RailIncident::FIELDS)
+            .await
+    }
+
+    /// How every line is running, for a compact header like "Red: Good Service", from the rail
+    /// incidents feed, see [`RailIncidentsResponse::line_statuses`] for how.
+    #[cfg(feature = "incidents")]
+    pub async fn line_statuses(&mut self) -> Result<Vec<(types::LineKind, LineStatus), 6>, Error> {
+        Ok(self.rail_incidents().await?.line_statuses())
+    }
+
+    /// Returns a list of in-service elevator and escalator outages for all stations.
+    #[cfg(feature = "incidents")]
+    pub async fn unit_outages(&mut self) -> Result<UnitOutagesResponse, Error> {
+        self.unit_outages_for(None).await
+    }
+
+    /// Elevator and escalator outages at `station` only. The api filters these itself, so the
+    /// response is just that station's outages rather than the whole system's.
+    #[cfg(feature = "incidents")]
+    pub async fn unit_outages_at(
+        &mut self,
+        station: Station,
+    ) -> Result<UnitOutagesResponse, Error> {
+        self.unit_outages_for(Some(station)).await
+    }
+
+    #[cfg(feature = "incidents")]
+    async fn unit_outages_for(
+        &mut self,
+        station: Option<Station>,
+    ) -> Result<UnitOutagesResponse, Error> {
+        let mut buf: String<128> = String::new();
+        let path = build_unit_outages_path(&mut buf, station)?;
+        self.request(path, self.timeout, UnitOutage::FIELDS).await
+    }
+
+    /// Returns live positions of buses on `route_id` (e.g. `"70"`, `"10A"`), or of every bus when `None`.
+    /// Every bus in service is a response of a few hundred kilobytes, far more than a typical `rx_buf`,
+    /// so only pass `None` with a buffer sized for it (roughly 300 bytes per bus).
+    #[cfg(feature = "bus")]
+    pub async fn bus_positions(
+        &mut self,
+        route_id: Option<&str>,
+    ) -> Result<BusPositionsResponse, Error> {
+        let mut buf: String<128> = String::new();
+        let path = build_bus_positions_path(&mut buf, route_id)?;
+        let timeout = match route_id {
+            Some(_) => self.timeout,
+            None => self.large_timeout,
+        };
+        self.request(path, timeout, BusPosition::FIELDS).await
+    }
+
+    /// Returns bus stops within `radius` meters of `lat`/`lon`, with the routes serving each.
+    /// `radius` is capped at [`Client::MAX_STOP_RADIUS`], since a wider search can return more
+    /// stops than `rx_buf` holds.
+    #[cfg(feature = "bus")]
+    pub async fn bus_stops(
+        &mut self,
+        lat: f64,
+        lon: f64,
+        radius: u32,
+    ) -> Result<BusStopsResponse, Error> {
+        let mut buf: String<128> = String::new();
+        let radius = radius.min(Self::MAX_STOP_RADIUS);
+        let path = build_bus_stops_path(&mut buf, lat, lon, radius)?;
+        self.request(path, self.timeout, BusStop::FIELDS).await
+    }
+
+    /// Returns the route of every line over each of its tracks, as the track circuits in order,
+    /// e.g. for drawing the network as a map of circuits. It only changes with the track itself,
+    /// so fetch it once and keep it rather than polling it.
+    ///
+    /// The response is well over 100 KB of json, all of which has to be in `rx_buf` at once since
+    /// neither json backend decodes as it reads, and decoded it still takes tens of kilobytes of heap.
+    /// So it needs a board with PSRAM for the buffer, or a proxy in front of the api, e.g. at the
+    /// url given to [`Client::with_api`], that cuts the response down to the lines wanted.
+    #[cfg(feature = "positions")]
+    pub async fn standard_routes(&mut self) -> Result<StandardRoutesResponse, Error> {
+        self.request(
+            STANDARD_ROUTES_PATH,
+            self.large_timeout,
+            StandardRoute::FIELDS,
+        )
+        .await
+    }
+
+    /// Returns every track circuit in the network with its neighbors, the graph
+    /// [`Client::standard_routes`] runs over. Fetch it once and keep it, like the routes.
+    ///
+    /// This is the bigger of the two, several hundred kilobytes of json, so the same goes for
+    /// `rx_buf` as for [`Client::standard_routes`], only more so.
+    #[cfg(feature = "positions")]
+    pub async fn track_circuits(&mut self) -> Result<TrackCircuitsResponse, Error> {
+        self.request(
+            TRACK_CIRCUITS_PATH,
+            self.large_timeout,
+            TrackCircuit::FIELDS,
+        )
+        .await
+    }
+
+    /// Fetches both rail incidents and unit outages, keeping only those relevant to `station`.
+    /// Incidents are matched against the lines serving `station` here, since the api only sends
+    /// them all. Outages are filtered to `station` by the api.
+    /// Each half carries its own `Result`, so a failure in one request doesn't discard the other.
+    #[cfg(feature = "incidents")]
+    pub async fn disruptions_for(&mut self, station: Station) -> StationDisruptions {
+        let incidents = self.rail_incidents().await.map(|res| {
+            res.incidents
+                .into_iter()
+                .filter(|incident| station.lines().iter().any(|l| incident.affects(*l)))
+                .collect()
+        });
+
+        let outages = self.unit_outages_at(station).await.map(|res| {
+            res.outages
+                .into_iter()
+                .filter(|outage| outage.station_code == station)
+                .collect()
+        });
+
+        StationDisruptions {
+            station,
+            incidents,
+            outages,
+        }
+    }
+}
+
+/// Run `fetch` for up to `timeout`, or until `deadline` if that comes first, with the error for
+/// whichever ran out. Past the deadline already, `fetch` isn't started at all.
+async fn timed<R>(
+    timeout: Duration,
+    deadline: Option<Instant>,
+    fetch: impl Future<Output = Result<R, Error>>,
+) -> Result<R, Error> {
+    let now = Instant::now();
+    match deadline {
+        Some(deadline) if deadline <= now => Err(Error::DeadlineExceeded),
+        Some(deadline) if deadline - now < timeout => with_timeout(deadline - now, fetch)
+            .await
+            .unwrap_or(Err(Error::DeadlineExceeded)),
+        _ => with_timeout(timeout, fetch)
+            .await
+            .unwrap_or(Err(Error::Timeout)),
+    }
+}
+
+/// Set `clock` from the response's `Date` header, if it has one.
+fn sync_clock(clock: &WallClock, mut headers: HeaderIterator<'_>) {
+    if let Some((_, date)) = headers.find(|(name, _)| name.eq_ignore_ascii_case("date"))
+        && !clock.set_from_http_date(date)
+    {
+        warn!("couldn't read the Date header");
+    }
+}
+
+/// Headers sent with every request, asking for a gzipped body if `accept_gzip`, and only for a
+/// changed one if there's a `validator` from last time.
+fn request_headers<'h>(
+    api_key: &'h str,
+    accept_gzip: bool,
+    validator: Option<&'h Validator>,
+) -> Vec<(&'h str, &'h str), 5> {
+    let mut headers = Vec::new();
+    headers.extend([
+        (API_KEY_HEADER, api_key),
+        ("User-Agent", USER_AGENT),
+        ("Connection", "close"),
+    ]);
+    if accept_gzip {
+        let _ = headers.push(("Accept-Encoding", "gzip"));
+    }
+    if let Some(validator) = validator {
+        let _ = headers.push(validator.header());
+    }
+    headers
+}
+
+/// The cached body to use instead, if `status` is `304 Not Modified` and there's one for `url`.
+fn not_modified<'c>(cache: Option<&'c ResponseCache>, url: &str, status: u16) -> Option<&'c [u8]> {
+    const NOT_MODIFIED: u16 = 304;
+    if status != NOT_MODIFIED {
+        return None;
+    }
+    cache?.body(url)
+}
+
+/// `body` decompressed into `inflate_buf` if the response was `gzipped`, otherwise `body` itself.
+/// A gzipped body is only expected after asking for one, without a buffer it can't be decoded.
+#[cfg(feature = "gzip")]
+fn inflated<'b>(
+    body: &'b [u8],
+    gzipped: bool,
+    inflate_buf: Option<&'b mut [u8]>,
+) -> Result<&'b [u8], Error> {
+    if !gzipped {
+        return Ok(body);
+    }
+    let out = inflate_buf.ok_or(Error::Decompress)?;
+    let len = gzip::gunzip(body, out)?;
+    Ok(&out[..len])
+}
+
+/// The cached address may be why we couldn't connect, so drop it before reporting `e`.
+fn connect_failed(dns_cache: Option<&DnsCache>, e: reqwless::Error) -> Error {
+    if let Some(dns_cache) = dns_cache {
+        dns_cache.invalidate();
+    }
+
+    e.into()
+}
+
+/// Decode a response body, telling apart empty and non-json bodies from malformed json.
+/// `url` is only for the context in [`Error::Json`].
+/// With the `strict` feature, logs a warning for each of `expected` that's missing from some of
+/// the elements in `body`, see [`json::report_missing_fields`]. Without it `expected` is ignored.
+fn parse<J: Deserialize>(url: &str, body: &[u8], expected: &[&str]) -> Result<J, Error> {
+    let json = core::str::from_utf8(body)?;
+    debug!("{:?}", json);
+
+    let trimmed = json.trim_start();
+    if trimmed.is_empty() {
+        return Err(Error::EmptyResponse);
+    }
+    if trimmed.starts_with('<') {
+        return Err(Error::NotJson);
+    }
+    // the backends report a cut off body as just another decode error, if they cope at all
+    if !is_complete_json(trimmed) {
+        return Err(Error::Truncated);
+    }
+
+    #[cfg(feature = "strict")]
+    json::report_missing_fields(url_path(url), trimmed, expected);
+    #[cfg(not(feature = "strict"))]
+    let _ = expected;
+
+    json::from_str(json).map_err(|e| Error::Json(JsonError::new(e, url, json)))
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_futures::block_on;
+
+    use super::*;
+    use crate::wmata::mock::{self, MockDns, MockNet};
+
+    const PREDICTIONS: &str = include_str!("../../test_data/get_prediction.json");
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn a_gzipped_body_needs_an_inflate_buffer() {
+        let gzipped = include_bytes!("../../test_data/get_prediction.json.gz");
+        assert!(matches!(
+            inflated(gzipped, true, None),
+            Err(Error::Decompress)
+        ));
+
+        let mut inflate_buf = [0u8; 4096];
+        let body = inflated(gzipped, true, Some(&mut inflate_buf)).unwrap();
+        assert_eq!(body, PREDICTIONS.as_bytes());
+        // a plain body is passed through, buffer or not
+        assert_eq!(
+            inflated(PREDICTIONS.as_bytes(), false, None).unwrap(),
+            PREDICTIONS.as_bytes()
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn get_decodes_any_path() {
+        let net = MockNet::default();
+        net.reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res: NextTrainsResponse =
+            block_on(client.get("/StationPrediction.svc/json/GetPrediction/K04,K05?custom=1"))
+                .unwrap();
+        assert_eq!(res.trains.len(), 4);
+        assert_eq!(
+            net.request_path(0),
+            "/StationPrediction.svc/json/GetPrediction/K04,K05?custom=1"
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn next_trains_chunked_merges_chunks() {
+        let net = MockNet::default();
+        net.reply_ok(PREDICTIONS).reply_ok(include_str!(
+            "../../test_data/get_prediction_long_name.json"
+        ));
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains_chunked(
+            &[Station::K04, Station::K05],
+            1,
+            OnChunkError::Abort,
+        ))
+        .unwrap();
+        assert_eq!(res.trains.len(), 6);
+        assert!(res.trains.windows(2).all(|t| t[0].min <= t[1].min));
+        assert_eq!(
+            net.request_path(0),
+            "/StationPrediction.svc/json/GetPrediction/K04"
+        );
+        assert_eq!(
+            net.request_path(1),
+            "/StationPrediction.svc/json/GetPrediction/K05"
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn next_trains_chunked_skips_or_aborts_on_a_failed_chunk() {
+        let net = MockNet::default();
+        net.refuse().reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+        let stations = [Station::K04, Station::K05];
+
+        let res = block_on(client.next_trains_chunked(&stations, 1, OnChunkError::Skip)).unwrap();
+        assert_eq!(res.trains.len(), 4);
+
+        net.refuse().reply_ok(PREDICTIONS);
+        assert!(block_on(client.next_trains_chunked(&stations, 1, OnChunkError::Abort)).is_err());
+        // refused connections send nothing, so only the skip run's second chunk was requested
+        assert_eq!(net.requests.borrow().len(), 1);
+    }
+
+    #[test]
+    fn https_waits_for_the_clock() {
+        let net = MockNet::default();
+        let dns = MockDns::default();
+        let clock = WallClock::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf)
+            .with_api("https://api.example.com")
+            .unwrap()
+            .with_clock(&clock);
+
+        assert!(matches!(
+            block_on(client.ping()),
+            Err(Error::ClockNotSynced)
+        ));
+        assert!(net.requests.borrow().is_empty());
+
+        clock.set(1_700_000_000);
+        assert!(!matches!(
+            block_on(client.ping()),
+            Err(Error::ClockNotSynced)
+        ));
+    }
+
+    #[test]
+    fn http_doesnt_wait_for_the_clock() {
+        let net = MockNet::default();
+        net.reply_ok("[]");
+        let dns = MockDns::default();
+        let clock = WallClock::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf).with_clock(&clock);
+
+        block_on(client.ping()).unwrap();
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn a_slow_reply_past_the_deadline_is_cut_short() {
+        let net = MockNet::default();
+        net.reply_after(Duration::from_millis(200), PREDICTIONS)
+            .reply_after(Duration::from_millis(20), PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        // the timeout alone would wait for it
+        let mut client = mock::client(&net, &dns, &mut rx_buf)
+            .with_timeout(Duration::from_secs(5), Duration::from_secs(5));
+        let path = "/StationPrediction.svc/json/GetPrediction/K04";
+
+        client.set_deadline(Some(Instant::now() + Duration::from_millis(50)));
+        let res: Result<NextTrainsResponse, _> = block_on(client.get(path));
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+
+        // already past it, so it doesn't even connect, leaving the next reply for the next try
+        let res: Result<NextTrainsResponse, _> = block_on(client.get(path));
+        assert!(matches!(res, Err(Error::DeadlineExceeded)));
+
+        client.set_deadline(Some(Instant::now() + Duration::from_secs(5)));
+        let res: Result<NextTrainsResponse, _> = block_on(client.get(path));
+        assert_eq!(res.unwrap().trains.len(), 4);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn too_short_a_timeout_is_a_timeout() {
+        let net = MockNet::default();
+        net.reply_after(Duration::from_millis(200), PREDICTIONS)
+            .reply_after(Duration::from_millis(200), PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let timeout = Duration::from_millis(20);
+        let mut client = mock::client(&net, &dns, &mut rx_buf).with_timeout(timeout, timeout);
+        let path = "/StationPrediction.svc/json/GetPrediction/K04";
+
+        let res: Result<NextTrainsResponse, _> = block_on(client.get(path));
+        assert!(matches!(res, Err(Error::Timeout)));
+
+        let res: Result<NextTrainsResponse, _> =
+            block_on(client.get_with_timeout(path, Duration::from_secs(5)));
+        assert_eq!(res.unwrap().trains.len(), 4);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn platform_trains_keeps_one_track_of_one_line() {
+        let net = MockNet::default();
+        net.reply_ok(include_str!(
+            "../../test_data/get_prediction_two_platforms.json"
+        ));
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.platform_trains(Station::A01, LineKind::RD, 1)).unwrap();
+        assert_eq!(res.trains.len(), 2);
+        assert!(res.trains.iter().all(|t| t.destination.0 == "Glenmont"));
+        assert!(res.trains[0].min == Some(types::Eta::Minutes(2)));
+        assert!(res.trains[1].min == Some(types::Eta::Minutes(12)));
+        assert_eq!(
+            net.request_path(0),
+            "/StationPrediction.svc/json/GetPrediction/A01"
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn scheduled_trains_reads_each_day() {
+        use types::{LineKind::*, Weekday};
+
+        let net = MockNet::default();
+        net.reply_ok(include_str!("../../test_data/station_times.json"));
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 4096];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let times = block_on(client.scheduled_trains(Station::C01)).unwrap();
+        assert_eq!(times.code, Station::C01);
+        let monday = times.day(Weekday::Monday);
+        assert_eq!(monday.opening_time.minute_of_day(), 4 * 60 + 58);
+        assert_eq!(monday.first_trains.len(), 5);
+        // Metro Center's upper level is on three lines, each running both ways
+        assert_eq!(monday.last_trains_on(OR).count(), 2);
+        assert_eq!(monday.last_trains_on(BL).count(), 2);
+        assert_eq!(monday.last_trains_on(RD).count(), 0);
+
+        let saturday = times.day(Weekday::Saturday);
+        assert_eq!(saturday.opening_time.minute_of_day(), 6 * 60 + 58);
+        assert_eq!(saturday.last_trains[0].time.minute_of_day(), 41);
+        assert_eq!(
+            net.request_path(0),
+            "/Rail.svc/json/jStationTimes?StationCode=C01"
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn next_trains_for_line_requests_every_station_once() {
+        let line = LineKind::OR;
+        let requests = line
+            .stations()
+            .len()
+            .div_ceil(Client::<MockNet, MockDns>::LINE_CHUNK);
+        let net = MockNet::default();
+        for _ in 0..requests {
+            net.reply_ok(PREDICTIONS);
+        }
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains_for_line(line)).unwrap();
+        assert_eq!(net.requests.borrow().len(), requests);
+        let mut requested = alloc::vec::Vec::new();
+        for i in 0..requests {
+            let path = net.request_path(i);
+            let codes = path.strip_prefix("/StationPrediction.svc/json/GetPrediction/");
+            requested.extend(codes.unwrap().split(',').map(alloc::string::String::from));
+        }
+        let expected: alloc::vec::Vec<_> = line.stations().iter().map(|s| s.code()).collect();
+        assert_eq!(requested, expected);
+
+        // only Ballston's Orange Line trains are in the fixture
+        let ballston = res.stations.iter().find(|s| s.station == Station::K04);
+        assert_eq!(ballston.unwrap().trains.len(), 2);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn json_errors_say_where_they_came_from() {
+        let net = MockNet::default();
+        net.reply_ok(r#"{"Trains":"nope"}"#);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res: Result<NextTrainsResponse, _> =
+            block_on(client.get("/StationPrediction.svc/json/GetPrediction/K04"));
+        let err = res.err().unwrap();
+        assert!(matches!(err, Error::Json(_)));
+        let message = format!("{err}");
+        assert!(message.contains("/StationPrediction.svc/json/GetPrediction/K04"));
+        assert!(message.contains(r#"{\"Trains\":\"nope\"}"#));
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn empty_station_falls_back() {
+        let net = MockNet::default();
+        net.reply_ok(r#"{"Trains":[]}"#).reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains_or(Station::N12, Some(Station::K04))).unwrap();
+        assert_eq!(res.trains.len(), 4);
+        assert_eq!(res.trains[0].location_code, Station::K04);
+        assert_eq!(
+            net.request_path(1),
+            "/StationPrediction.svc/json/GetPrediction/K04"
+        );
+
+        // a station with trains, or no fallback, is one request
+        net.reply_ok(PREDICTIONS).reply_ok(r#"{"Trains":[]}"#);
+        let res = block_on(client.next_trains_or(Station::K04, Some(Station::N12))).unwrap();
+        assert_eq!(res.trains.len(), 4);
+        let res = block_on(client.next_trains_or(Station::N12, None)).unwrap();
+        assert!(res.trains.is_empty());
+        assert_eq!(net.requests.borrow().len(), 4);
+    }
+
+    #[cfg(feature = "bus")]
+    #[test]
+    fn bus_stops_caps_the_radius_and_the_routes() {
+        let net = MockNet::default();
+        net.reply_ok(include_str!("../../test_data/bus_stops.json"));
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.bus_stops(38.8998, -77.0219, 5000)).unwrap();
+        assert_eq!(
+            net.request_path(0),
+            "/Bus.svc/json/jStops?Lat=38.899800&Lon=-77.021900&Radius=300"
+        );
+        assert_eq!(res.stops.len(), 3);
+        let stop = &res.stops[0];
+        assert_eq!(stop.stop_id, "1001195");
+        assert_eq!(stop.name, "7TH ST NW + H ST NW");
+        assert_eq!(stop.routes.0, ["70", "74", "79", "P6", "X2"]);
+
+        // 19 routes, one too long to keep, and only the first 16 of the rest fit
+        let bay = &res.stops[1];
+        assert_eq!(bay.routes.0.len(), types::RouteIds::MAX);
+        assert!(bay.routes.0.iter().all(|id| id != "SHUTTLE-EAST-LONG"));
+        assert!(res.stops[2].routes.0.is_empty());
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn cut_off_body_is_truncated() {
+        let net = MockNet::default();
+        net.reply_ok(include_str!(
+            "../../test_data/get_prediction_truncated.json"
+        ));
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains(Station::K04));
+        assert!(matches!(res, Err(Error::Truncated)));
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn not_modified_reuses_the_cached_body() {
+        let net = MockNet::default();
+        net.reply("200 OK", &[("ETag", "\"v1\"")], PREDICTIONS)
+            .reply("304 Not Modified", &[("ETag", "\"v1\"")], "");
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut cache_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf).with_response_cache(&mut cache_buf);
+
+        let first = block_on(client.next_trains(Station::K04)).unwrap();
+        assert_eq!(net.request_header(0, "If-None-Match"), None);
+        let second = block_on(client.next_trains(Station::K04)).unwrap();
+        assert_eq!(
+            net.request_header(1, "If-None-Match").as_deref(),
+            Some("\"v1\"")
+        );
+        assert_eq!(second.trains.len(), 4);
+        assert!(first.trains == second.trains);
+    }
+
+    #[test]
+    fn get_raw_keeps_the_headers_asked_for() {
+        let net = MockNet::default();
+        net.reply(
+            "429 Too Many Requests",
+            &[("RateLimit-Remaining", "0"), ("Retry-After", "30")],
+            "{\"statusCode\": 429}",
+        );
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let raw =
+            block_on(client.get_raw("/Rail.svc/json/jLines", &["ratelimit-remaining"])).unwrap();
+        assert_eq!(raw.status, 429);
+        assert_eq!(raw.headers.len(), 1);
+        assert_eq!(raw.header("RATELIMIT-REMAINING"), Some("0"));
+        assert_eq!(raw.header("Retry-After"), None);
+        assert_eq!(raw.body, b"{\"statusCode\": 429}");
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn lines_mask_filters_every_fetch() {
+        let config = Config::new("home", "hunter22", "0123456789abcdef")
+            .unwrap()
+            .with_lines(&[LineKind::SV]);
+        let net = MockNet::default();
+        net.reply_ok(PREDICTIONS).reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf).with_lines_mask(config.lines_mask());
+
+        let res = block_on(client.next_trains(Station::K04)).unwrap();
+        assert_eq!(res.trains.len(), 2);
+        assert!(
+            res.trains
+                .iter()
+                .all(|train| train.line == Some(LineKind::SV))
+        );
+
+        client.set_lines_mask(0);
+        let res = block_on(client.next_trains(Station::K04)).unwrap();
+        assert_eq!(res.trains.len(), 4);
+    }
+
+    #[test]
+    fn ping_tells_auth_apart_from_the_network() {
+        let net = MockNet::default();
+        net.reply_ok("{\"Lines\":[]}")
+            .reply("401 Access Denied", &[], "{\"statusCode\": 401}")
+            .refuse();
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        block_on(client.ping()).unwrap();
+        assert_eq!(net.request_path(0), PING_PATH);
+        assert!(matches!(block_on(client.ping()), Err(Error::Unauthorized)));
+        assert!(matches!(block_on(client.ping()), Err(Error::Http(_))));
+    }
+
+    #[test]
+    fn api_key_goes_in_the_api_key_header() {
+        let net = MockNet::default();
+        net.reply_ok("{\"Lines\":[]}");
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        block_on(client.ping()).unwrap();
+        assert_eq!(net.request_header(0, "api_key").as_deref(), Some("key"));
+        // spelled exactly as WMATA documents it, lower case with an underscore
+        assert!(net.requests.borrow()[0].contains("\r\napi_key: key\r\n"));
+    }
+
+    #[test]
+    fn a_new_api_key_is_sent_from_the_next_request() {
+        let net = MockNet::default();
+        net.reply_ok("{\"Lines\":[]}")
+            .reply_ok("{\"Lines\":[]}")
+            .reply_ok("{\"Lines\":[]}");
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        block_on(client.ping()).unwrap();
+        client.set_api_key("fedcba9876543210").unwrap();
+        block_on(client.ping()).unwrap();
+        assert_eq!(net.request_header(0, "api_key").as_deref(), Some("key"));
+        assert_eq!(
+            net.request_header(1, "api_key").as_deref(),
+            Some("fedcba9876543210")
+        );
+
+        // one too long is turned down, keeping the last
+        let too_long = "k".repeat(config::API_KEY_MAX_LEN + 1);
+        assert!(matches!(
+            client.set_api_key(&too_long),
+            Err(ConfigError::BadArgs)
+        ));
+        block_on(client.ping()).unwrap();
+        assert_eq!(
+            net.request_header(2, "api_key").as_deref(),
+            Some("fedcba9876543210")
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn a_turned_down_key_fails_over_to_the_backup() {
+        let net = MockNet::default();
+        net.reply("429 Too Many Requests", &[], "{\"statusCode\": 429}")
+            .reply_ok(PREDICTIONS)
+            .reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf).with_backup_api_key(Some("backup"));
+        let path = "/StationPrediction.svc/json/GetPrediction/K04";
+
+        let res: NextTrainsResponse = block_on(client.get(path)).unwrap();
+        assert_eq!(res.trains.len(), 4);
+        assert_eq!(net.request_header(0, "api_key").as_deref(), Some("key"));
+        assert_eq!(net.request_header(1, "api_key").as_deref(), Some("backup"));
+
+        // the backup stays in use
+        let _: NextTrainsResponse = block_on(client.get(path)).unwrap();
+        assert_eq!(net.request_header(2, "api_key").as_deref(), Some("backup"));
+
+        // without a backup a 429 is returned as is, not retried
+        let net = MockNet::default();
+        net.reply("429 Too Many Requests", &[], "{\"statusCode\": 429}")
+            .reply_ok(PREDICTIONS);
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+        let res: Result<NextTrainsResponse, _> = block_on(client.get(path));
+        assert!(matches!(res, Err(Error::Status(429))));
+        assert_eq!(net.requests.borrow().len(), 1);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn next_trains_by_name_needs_one_station() {
+        let net = MockNet::default();
+        net.reply_ok(PREDICTIONS).reply_ok(PREDICTIONS);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains_by_name("ballston")).unwrap();
+        assert_eq!(res.trains.len(), 4);
+        assert_eq!(
+            net.request_path(0),
+            "/StationPrediction.svc/json/GetPrediction/K04"
+        );
+
+        // a transfer station's name asks for both its codes
+        block_on(client.next_trains_by_name("Metro Center")).unwrap();
+        assert_eq!(
+            net.request_path(1),
+            "/StationPrediction.svc/json/GetPrediction/A01,C01"
+        );
+
+        match block_on(client.next_trains_by_name("square")) {
+            Err(Error::AmbiguousStation(candidates)) => {
+                assert!(candidates.contains(&Station::B02));
+                assert!(candidates.contains(&Station::C02));
+            }
+            _ => panic!("\"square\" is in more than one name"),
+        }
+        assert!(matches!(
+            block_on(client.next_trains_by_name("nowhere")),
+            Err(Error::NoSuchStation)
+        ));
+        // neither got as far as a request
+        assert_eq!(net.requests.borrow().len(), 2);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn latest_falls_back_to_the_last_good_predictions() {
+        let net = MockNet::default();
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+        // every fetch that isn't given a reply fails, and without this would retry for a while
+        let soon = || Some(Instant::now() + Duration::from_millis(100));
+
+        // nothing to fall back to yet
+        client.set_deadline(soon());
+        assert!(block_on(client.latest(Station::K04)).is_err());
+
+        net.reply_ok(PREDICTIONS);
+        client.set_deadline(None);
+        let fresh = block_on(client.latest(Station::K04)).unwrap();
+        assert!(matches!(&fresh, Latest::Fresh(res) if res.trains.len() == 4));
+
+        client.set_deadline(soon());
+        let cached = block_on(client.latest(Station::K04)).unwrap();
+        assert!(matches!(&cached, Latest::Cached { age, .. } if *age < Duration::from_secs(1)));
+        assert!(cached.response().trains.len() == 4);
+
+        // the last good ones are K04's, no use for another station
+        client.set_deadline(soon());
+        assert!(block_on(client.latest(Station::A01)).is_err());
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn unreachable_destinations_are_only_dropped_when_asked() {
+        let unreachable = include_str!("../../test_data/get_prediction_unreachable.json");
+        let net = MockNet::default();
+        net.reply_ok(unreachable).reply_ok(unreachable);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+
+        let res = block_on(client.next_trains(Station::A01)).unwrap();
+        assert_eq!(res.trains.len(), 5);
+
+        client.set_drop_unreachable(true);
+        let res = block_on(client.next_trains(Station::A01)).unwrap();
+        assert_eq!(res.trains.len(), 3);
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn parse_tells_apart_empty_html_and_cut_off_bodies() {
+        const URL: &str = "https://api.wmata.com/StationPrediction.svc/json/GetPrediction/K04";
+        let parsed = |body: &str| parse::<MaybeNextTrainsResponse>(URL, body.as_bytes(), &[]);
+
+        assert!(matches!(parsed(""), Err(Error::EmptyResponse)));
+        assert!(matches!(parsed(" \r\n"), Err(Error::EmptyResponse)));
+        assert!(matches!(
+            parsed(include_str!("../../test_data/maintenance.html")),
+            Err(Error::NotJson)
+        ));
+        assert!(matches!(
+            parsed(include_str!(
+                "../../test_data/get_prediction_truncated.json"
+            )),
+            Err(Error::Truncated)
+        ));
+
+        // `null` trains are well-formed json, it's `next_trains` that finds nothing in them
+        let null_trains = include_str!("../../test_data/get_prediction_null_trains.json");
+        assert!(parsed(null_trains).unwrap().trains.is_none());
+        let net = MockNet::default();
+        net.reply_ok(null_trains);
+        let dns = MockDns::default();
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf);
+        assert!(matches!(
+            block_on(client.next_trains(Station::K04)),
+            Err(Error::EmptyResponse)
+        ));
+    }
+
+    #[test]
+    fn with_api_rejects_a_bad_base_url() {
+        let net = MockNet::default();
+        let dns = MockDns::default();
+        let long = alloc::format!("http://{}.example.com", "a".repeat(64));
+
+        for api in ["api.example.com", "http://", "http:///wmata", &long] {
+            let mut rx_buf = [0; 16];
+            let client = mock::client(&net, &dns, &mut rx_buf).with_api(api);
+            assert!(matches!(client, Err(Error::InvalidBaseUrl)), "{api}");
+        }
+        let mut rx_buf = [0; 2048];
+        let mut client = mock::client(&net, &dns, &mut rx_buf)
+            .with_api("http://localhost:8080/")
+            .unwrap();
+
+        net.reply_ok("{\"Lines\":[]}");
+        block_on(client.ping()).unwrap();
     }
 }
@@ -0,0 +1,254 @@
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use embedded_storage::{ReadStorage, Storage};
+use heapless::{String, Vec};
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    flash::FlashStorage,
+    partition::{STORAGE_SECTORS, storage_end},
+    types::Station,
+};
+
+/// Longest name an override can give a station, most of a 21 character row.
+pub const NAME_OVERRIDE_MAX_LEN: usize = 16;
+/// Bytes needed by [`NameOverrides::to_bytes`]: 20 per override, a count and the checksum, 165 in
+/// all.
+pub const NAME_OVERRIDES_SZ: usize = CHECKSUM_SZ + 1 + NameOverrides::MAX * STORED_OVERRIDE_SZ;
+
+const STORED_OVERRIDE_SZ: usize = 3 + 1 + NAME_OVERRIDE_MAX_LEN;
+
+#[derive(Encode, Decode)]
+struct StoredOverride {
+    station: [u8; 3],
+    len: u8,
+    name: [u8; NAME_OVERRIDE_MAX_LEN],
+}
+
+#[derive(Encode, Decode)]
+struct Stored {
+    len: u8,
+    overrides: [StoredOverride; NameOverrides::MAX],
+}
+
+/// Names to show for stations instead of their usual ones, e.g. a local house style, set on the
+/// device rather than built in like `ABBREVIATIONS`. Hand them to
+/// [`Abbreviations::with_saved`](crate::wmata::types::Abbreviations::with_saved), which looks
+/// here before anywhere else.
+///
+/// Kept in their own flash sector with a crc32 checksum, written only by
+/// [`NameOverrides::save`], so a handful of writes over the life of the device.
+#[derive(Clone, Debug, Default)]
+pub struct NameOverrides {
+    overrides: Vec<(Station, String<NAME_OVERRIDE_MAX_LEN>), { NameOverrides::MAX }>,
+}
+
+impl NameOverrides {
+    pub const MAX: usize = 8;
+
+    pub const fn new() -> Self {
+        Self {
+            overrides: Vec::new(),
+        }
+    }
+
+    /// The name set for `station`, `None` to use its usual one.
+    pub fn get(&self, station: Station) -> Option<&str> {
+        self.overrides
+            .iter()
+            .find(|(s, _)| *s == station)
+            .map(|(_, name)| name.as_str())
+    }
+
+    /// Show `station` as `name`, replacing any name already set for it. Errors without changing
+    /// anything if `name` is empty or longer than [`NAME_OVERRIDE_MAX_LEN`], if `station` isn't
+    /// one this firmware knows, or if there are already [`NameOverrides::MAX`] other overrides.
+    pub fn set(&mut self, station: Station, name: &str) -> Result<(), ConfigError> {
+        if name.is_empty() || station == Station::Unknown {
+            return Err(ConfigError::BadArgs);
+        }
+        let name = String::try_from(name).map_err(|_| ConfigError::BadArgs)?;
+
+        match self.overrides.iter_mut().find(|(s, _)| *s == station) {
+            Some((_, old)) => *old = name,
+            None => self
+                .overrides
+                .push((station, name))
+                .map_err(|_| ConfigError::BadArgs)?,
+        }
+        Ok(())
+    }
+
+    /// Go back to `station`'s usual name, returning whether it had an override.
+    pub fn clear(&mut self, station: Station) -> bool {
+        let len = self.overrides.len();
+        self.overrides.retain(|(s, _)| *s != station);
+        self.overrides.len() != len
+    }
+
+    /// Go back to every station's usual name.
+    pub fn clear_all(&mut self) {
+        self.overrides.clear();
+    }
+
+    /// Every override, in the order they were first set.
+    pub fn iter(&self) -> impl Iterator<Item = (Station, &str)> {
+        self.overrides
+            .iter()
+            .map(|(station, name)| (*station, name.as_str()))
+    }
+
+    /// Write the overrides to their flash sector.
+    pub fn save(&self, flash: &mut FlashStorage) -> Result<(), ConfigError> {
+        let mut bytes = [0u8; NAME_OVERRIDES_SZ];
+        self.to_bytes(&mut bytes)?;
+        let offset = Self::offset(flash)?;
+        flash.write(offset, &bytes)?;
+
+        Ok(())
+    }
+
+    /// Restore the overrides saved by [`NameOverrides::save`]. Errors on a board that never
+    /// saved any, fall back to [`NameOverrides::new`] then.
+    pub fn load(flash: &mut FlashStorage) -> Result<Self, ConfigError> {
+        let mut bytes = [0u8; NAME_OVERRIDES_SZ];
+        let offset = Self::offset(flash)?;
+        flash.read(offset, &mut bytes)?;
+
+        Self::from_bytes(&bytes)
+    }
+
+    /// Encode the overrides with a crc32 checksum.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<(), ConfigError> {
+        if buf.len() < NAME_OVERRIDES_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let mut stored = Stored {
+            len: self.overrides.len() as u8,
+            overrides: core::array::from_fn(|_| StoredOverride {
+                station: [0; 3],
+                len: 0,
+                name: [0; NAME_OVERRIDE_MAX_LEN],
+            }),
+        };
+        for (slot, (station, name)) in stored.overrides.iter_mut().zip(&self.overrides) {
+            slot.station.copy_from_slice(station.code().as_bytes());
+            slot.len = name.len() as u8;
+            slot.name[..name.len()].copy_from_slice(name.as_bytes());
+        }
+
+        let (crc32_bytes, payload) = buf[..NAME_OVERRIDES_SZ].split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Decode overrides written by [`NameOverrides::to_bytes`]. Any for a station this firmware
+    /// doesn't know, e.g. after an update removed it, are dropped.
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ConfigError> {
+        if buf.len() < NAME_OVERRIDES_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let (crc32_bytes, payload) = buf[..NAME_OVERRIDES_SZ].split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(payload) {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        let stored: Stored = decode_from_slice(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?
+        .0;
+
+        let len = (stored.len as usize).min(Self::MAX);
+        let mut overrides = Self::new();
+        for slot in &stored.overrides[..len] {
+            let name = slot
+                .name
+                .get(..slot.len as usize)
+                .and_then(|name| core::str::from_utf8(name).ok())
+                .ok_or(ConfigError::InvalidString)?;
+            let Some(station) = core::str::from_utf8(&slot.station)
+                .ok()
+                .and_then(Station::from_code)
+            else {
+                continue;
+            };
+            overrides.set(station, name)?;
+        }
+
+        Ok(overrides)
+    }
+
+    /// The sector below everything else in storage, see
+    /// [`Config::save`](crate::wmata::Config::save).
+    fn offset(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+        Ok(storage_end(flash)? - STORAGE_SECTORS * FlashStorage::SECTOR_SIZE)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+
+    use super::*;
+    use crate::wmata::types::Abbreviations;
+
+    #[test]
+    fn saved_names_come_before_the_rest() {
+        let mut names = NameOverrides::new();
+        names.set(Station::D13, "NewCarr").unwrap();
+        names.set(Station::A01, "Metro Ctr").unwrap();
+        names.set(Station::A01, "Metro Center").unwrap();
+        assert!(names.set(Station::K04, "").is_err());
+        assert!(names.set(Station::K04, "Ballston-MU Station").is_err());
+
+        let abbreviations = Abbreviations::new(&[(Station::D13, "Carrollton")]).with_saved(&names);
+        assert_eq!(abbreviations.get(Station::D13), Some("NewCarr"));
+        assert_eq!(abbreviations.get(Station::A01), Some("Metro Center"));
+        // stations without one keep the built-in abbreviation, or their full name
+        assert_eq!(abbreviations.get(Station::A15), Some("Shady Gr"));
+        assert_eq!(abbreviations.get(Station::K04), None);
+
+        assert!(names.clear(Station::D13));
+        assert!(!names.clear(Station::D13));
+        assert_eq!(names.get(Station::D13), None);
+    }
+
+    #[test]
+    fn names_read_back_as_saved() {
+        let mut flash = FlashStorage::new(4 * 1024 * 1024);
+        // never saved, so there's nothing to go on
+        assert!(NameOverrides::load(&mut flash).is_err());
+
+        let mut names = NameOverrides::new();
+        names.set(Station::K04, "Ballston").unwrap();
+        names.set(Station::C15, "Hunt").unwrap();
+        names.save(&mut flash).unwrap();
+        let loaded = NameOverrides::load(&mut flash).unwrap();
+        assert_eq!(
+            loaded.iter().collect::<Vec<_>>(),
+            [(Station::K04, "Ballston"), (Station::C15, "Hunt")]
+        );
+
+        names.clear_all();
+        names.save(&mut flash).unwrap();
+        assert_eq!(NameOverrides::load(&mut flash).unwrap().iter().count(), 0);
+
+        let mut bytes = [0u8; NAME_OVERRIDES_SZ];
+        loaded.to_bytes(&mut bytes).unwrap();
+        bytes[CHECKSUM_SZ + 5] ^= 1;
+        assert!(matches!(
+            NameOverrides::from_bytes(&bytes),
+            Err(ConfigError::BadChecksum)
+        ));
+    }
+}
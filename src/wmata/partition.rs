@@ -0,0 +1,242 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{CriticalSectionMutex, Mutex};
+use embedded_storage::ReadStorage;
+
+use crate::wmata::{config::ConfigError, flash::FlashStorage};
+
+/// Where the esp-idf bootloader expects the partition table.
+const TABLE_OFFSET: u32 = 0x8000;
+/// The table is one 0xC00 byte block, with room for 95 entries and the md5 entry after them.
+const MAX_ENTRIES: usize = 96;
+const ENTRY_SZ: usize = 32;
+const ENTRY_MAGIC: [u8; 2] = [0xAA, 0x50];
+const TYPE_APP: u8 = 0x00;
+const TYPE_DATA: u8 = 0x01;
+const SUBTYPE_NVS: u8 = 0x02;
+const STORAGE_LABEL: &str = "storage";
+/// Sectors the two config copies, the request counter, the event log, the power-on hours and,
+/// with the `history` and `name-overrides` features, the prediction history and the station name
+/// overrides take up together.
+pub(crate) const STORAGE_SECTORS: u32 = 6
+    + if cfg!(feature = "history") { 2 } else { 0 }
+    + if cfg!(feature = "name-overrides") {
+        1
+    } else {
+        0
+    };
+/// Where esp-idf layouts put the first app, with the bootloader and partition table below it.
+const APP_OFFSET: u32 = 0x10000;
+
+/// End of the storage region, once looked up, `Some(None)` if the table has nowhere big enough.
+/// Partitions don't move while running.
+static STORAGE_END: CriticalSectionMutex<Cell<Option<Option<u32>>>> = Mutex::new(Cell::new(None));
+
+/// One entry of an esp-idf partition table.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Partition {
+    /// `0` for an app, `1` for data.
+    pub kind: u8,
+    /// Meaning depends on `kind`, e.g. `2` is NVS for data.
+    pub subtype: u8,
+    pub offset: u32,
+    pub size: u32,
+    label: [u8; 16],
+}
+
+impl Partition {
+    /// Decode a 32 byte table entry, `None` past the last one (the md5 entry or blank flash).
+    pub fn parse(entry: &[u8]) -> Option<Self> {
+        let entry: &[u8; ENTRY_SZ] = entry.get(..ENTRY_SZ)?.try_into().ok()?;
+        if entry[..2] != ENTRY_MAGIC {
+            return None;
+        }
+
+        Some(Self {
+            kind: entry[2],
+            subtype: entry[3],
+            offset: u32::from_le_bytes(entry[4..8].try_into().ok()?),
+            size: u32::from_le_bytes(entry[8..12].try_into().ok()?),
+            label: entry[12..28].try_into().ok()?,
+        })
+    }
+
+    /// The name from the partition csv, e.g. `nvs`. Empty if it isn't valid utf8.
+    pub fn label(&self) -> &str {
+        let len = self
+            .label
+            .iter()
+            .position(|b| *b == 0)
+            .unwrap_or(self.label.len());
+        core::str::from_utf8(&self.label[..len]).unwrap_or_default()
+    }
+
+    /// The partition to keep the config in, out of a raw partition table: a data partition
+    /// labelled `storage`, otherwise the NVS partition. Either has to be sector aligned and big
+    /// enough for everything that's stored, or it's passed over.
+    pub fn find_storage(table: &[u8]) -> Option<Self> {
+        pick_storage(table.chunks_exact(ENTRY_SZ).map_while(Self::parse))
+    }
+
+    fn fits_storage(&self) -> bool {
+        self.kind == TYPE_DATA
+            && self.offset.is_multiple_of(FlashStorage::SECTOR_SIZE)
+            && self.size.is_multiple_of(FlashStorage::SECTOR_SIZE)
+            && self.size >= STORAGE_SECTORS * FlashStorage::SECTOR_SIZE
+    }
+}
+
+fn pick_storage(partitions: impl Iterator<Item = Partition>) -> Option<Partition> {
+    let mut nvs = None;
+    for partition in partitions.filter(Partition::fits_storage) {
+        if partition.label() == STORAGE_LABEL {
+            return Some(partition);
+        }
+        if partition.subtype == SUBTYPE_NVS && nvs.is_none() {
+            nvs = Some(partition);
+        }
+    }
+    nvs
+}
+
+/// Where the config, request counter, event log, power-on hours, prediction history and name
+/// overrides are stored, counting back sector by sector: the end of the partition from
+/// [`Partition::find_storage`], or the end of flash when the bootloader was flashed without a
+/// partition table.
+///
+/// With a table that has no partition big enough, e.g. the default layout's 24KB nvs with the
+/// `history` feature, it's [`ConfigError::NoStorage`] and nothing is saved. The end of flash is
+/// often taken by OTA or other partitions on esp-idf layouts, so it's never written once there's a
+/// table. A config older firmware saved there is only read, and copied into the partition, by
+/// [`Config::load`](crate::wmata::Config::load).
+pub(crate) fn storage_end(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+    if let Some(end) = STORAGE_END.lock(Cell::get) {
+        return end.ok_or(ConfigError::NoStorage);
+    }
+
+    let capacity = flash.capacity() as u32;
+    let end = end_in(partitions(flash), capacity);
+    if end.is_none() {
+        defmt::error!(
+            "no storage or nvs partition of at least {=u32}KB, nothing will be saved, see the README",
+            STORAGE_SECTORS * FlashStorage::SECTOR_SIZE / 1024
+        );
+    }
+
+    STORAGE_END.lock(|cell| cell.set(Some(end)));
+    end.ok_or(ConfigError::NoStorage)
+}
+
+/// [`storage_end`] for a table's `partitions` on flash of `capacity` bytes.
+fn end_in(mut partitions: impl Iterator<Item = Partition>, capacity: u32) -> Option<u32> {
+    let Some(first) = partitions.next() else {
+        return Some(capacity);
+    };
+    let storage = pick_storage(core::iter::once(first).chain(partitions))?;
+    Some(storage.offset + storage.size)
+}
+
+/// The start of the region [`storage_end`] ends, `None` if there isn't one or it would reach down
+/// into the bootloader, the partition table or an app partition, e.g. on a board whose flash size
+/// was misdetected as tiny. Writing there could leave the board unable to boot.
+///
+/// Without a partition table, the app's own size isn't known, so only the bootloader and table
+/// are kept clear of.
+pub(crate) fn storage_start(flash: &mut FlashStorage) -> Option<u32> {
+    let end = storage_end(flash).ok()?;
+    start_in(end, partitions(flash))
+}
+
+/// [`storage_start`] for storage ending at `end`, among a table's `partitions`.
+fn start_in(end: u32, mut partitions: impl Iterator<Item = Partition>) -> Option<u32> {
+    let start = end
+        .checked_sub(STORAGE_SECTORS * FlashStorage::SECTOR_SIZE)
+        .filter(|start| *start >= APP_OFFSET)?;
+    let overlaps_app = partitions.any(|partition| {
+        partition.kind == TYPE_APP
+            && start < partition.offset.saturating_add(partition.size)
+            && partition.offset < end
+    });
+
+    (!overlaps_app).then_some(start)
+}
+
+/// The entries of the partition table in flash, none if there isn't one.
+fn partitions(flash: &mut FlashStorage) -> impl Iterator<Item = Partition> + '_ {
+    let mut entry = [0u8; ENTRY_SZ];
+    (0..MAX_ENTRIES).map_while(move |i| {
+        let offset = TABLE_OFFSET + (i * ENTRY_SZ) as u32;
+        flash.read(offset, &mut entry).ok()?;
+        Partition::parse(&entry)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TABLE: &[u8] = include_bytes!("../../test_data/partition_table.bin");
+
+    #[test]
+    fn parses_a_real_table() {
+        let partitions: alloc::vec::Vec<_> = TABLE
+            .chunks_exact(ENTRY_SZ)
+            .map_while(Partition::parse)
+            .collect();
+        let labels: alloc::vec::Vec<_> = partitions.iter().map(Partition::label).collect();
+        assert_eq!(labels, ["nvs", "phy_init", "factory", "storage"]);
+
+        let nvs = partitions[0];
+        assert_eq!((nvs.kind, nvs.subtype), (TYPE_DATA, SUBTYPE_NVS));
+        assert_eq!((nvs.offset, nvs.size), (0x9000, 0x6000));
+        let factory = partitions[2];
+        assert_eq!(factory.kind, TYPE_APP);
+        assert_eq!((factory.offset, factory.size), (0x10000, 0x100000));
+    }
+
+    #[test]
+    fn storage_is_the_partition_labelled_so() {
+        let storage = Partition::find_storage(TABLE).unwrap();
+        assert_eq!(storage.label(), STORAGE_LABEL);
+        assert_eq!((storage.offset, storage.size), (0x110000, 0x10000));
+
+        // without it, nvs is used if it's big enough
+        let without_storage = &TABLE[..3 * ENTRY_SZ];
+        let fallback = Partition::find_storage(without_storage);
+        let nvs_fits = 0x6000 >= STORAGE_SECTORS * FlashStorage::SECTOR_SIZE;
+        assert_eq!(fallback.map(|p| p.offset), nvs_fits.then_some(0x9000));
+    }
+
+    #[test]
+    fn a_table_without_room_keeps_storage_off_the_end_of_flash() {
+        let parse = |table: &'static [u8]| table.chunks_exact(ENTRY_SZ).map_while(Partition::parse);
+        let capacity = 0x400000;
+
+        // no table at all, so the end of flash
+        assert_eq!(end_in(parse(&[]), capacity), Some(capacity));
+        assert_eq!(end_in(parse(TABLE), capacity), Some(0x120000));
+
+        // nvs alone is only 24KB, not enough with `history` or `name-overrides`
+        let nvs_fits = 0x6000 >= STORAGE_SECTORS * FlashStorage::SECTOR_SIZE;
+        let without_storage = &TABLE[..3 * ENTRY_SZ];
+        assert_eq!(
+            end_in(parse(without_storage), capacity),
+            nvs_fits.then_some(0xF000)
+        );
+    }
+
+    #[test]
+    fn storage_never_reaches_into_the_bootloader_or_an_app() {
+        let parse = |table: &'static [u8]| table.chunks_exact(ENTRY_SZ).map_while(Partition::parse);
+        let storage = STORAGE_SECTORS * FlashStorage::SECTOR_SIZE;
+
+        // flash misdetected as 32KB, or only just big enough, without a table
+        let tiny = end_in(parse(&[]), 0x8000).unwrap();
+        assert_eq!(start_in(tiny, parse(&[])), None);
+        assert_eq!(start_in(APP_OFFSET + storage, parse(&[])), Some(APP_OFFSET));
+
+        // the storage partition is clear of the factory app, the end of its range isn't
+        assert_eq!(start_in(0x120000, parse(TABLE)), Some(0x120000 - storage));
+        assert_eq!(start_in(0x100000, parse(TABLE)), None);
+    }
+}
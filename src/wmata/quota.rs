@@ -0,0 +1,180 @@
+use core::cell::Cell;
+
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use embassy_time::{Duration, Instant};
+use embedded_storage::{ReadStorage, Storage};
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    flash::FlashStorage,
+    partition::storage_end,
+};
+
+/// Bytes needed by [`RequestCounter::to_bytes`].
+pub const REQUEST_COUNTER_SZ: usize = CHECKSUM_SZ + 3 * core::mem::size_of::<u32>();
+
+/// Counts api requests per day, to keep an eye on WMATA's daily quota.
+///
+/// There's no wall clock, so a "day" is 24 hours of uptime from the first request rather than a
+/// calendar day, and won't line up with when WMATA resets the quota.
+/// The count is kept in RAM and written to its own flash sector every [`RequestCounter::SAVE_EVERY`]
+/// requests, so a reboot loses at most that many. Hand it to [`Client::with_request_counter`](crate::wmata::Client::with_request_counter).
+pub struct RequestCounter {
+    count: Cell<u32>,
+    day_ends: Cell<Option<Instant>>,
+    unsaved: Cell<u32>,
+}
+
+/// The count and how much of the day was left when it was encoded.
+#[derive(Encode, Decode)]
+struct Stored {
+    count: u32,
+    day_remaining_secs: u32,
+    /// Ignored when loading from flash, where there's nothing unsaved by definition.
+    unsaved: u32,
+}
+
+impl RequestCounter {
+    pub const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+    /// At one request every 10s, about 90 writes a day.
+    pub const SAVE_EVERY: u32 = 100;
+
+    pub const fn new() -> Self {
+        Self {
+            count: Cell::new(0),
+            day_ends: Cell::new(None),
+            unsaved: Cell::new(0),
+        }
+    }
+
+    /// Count one request.
+    pub fn record(&self) {
+        self.roll_over();
+        if self.day_ends.get().is_none() {
+            self.day_ends.set(Some(Instant::now() + Self::DAY));
+        }
+
+        self.count.set(self.count.get().saturating_add(1));
+        self.unsaved.set(self.unsaved.get() + 1);
+    }
+
+    /// Requests made so far today.
+    pub fn today(&self) -> u32 {
+        self.roll_over();
+        self.count.get()
+    }
+
+    /// Whether enough requests have happened since the last save to be worth a flash write.
+    pub fn needs_save(&self) -> bool {
+        self.unsaved.get() >= Self::SAVE_EVERY
+    }
+
+    /// Start a new day once the current one is over, skipping any whole days without requests.
+    fn roll_over(&self) {
+        let Some(ends) = self.day_ends.get() else {
+            return;
+        };
+
+        let now = Instant::now();
+        if now >= ends {
+            let days = (now - ends).as_ticks() / Self::DAY.as_ticks() + 1;
+            self.day_ends.set(Some(
+                ends + Duration::from_ticks(days * Self::DAY.as_ticks()),
+            ));
+            self.count.set(0);
+        }
+    }
+
+    /// Write the count to flash. Call it when [`RequestCounter::needs_save`] says so, not every request.
+    pub fn save(&self, flash: &mut FlashStorage) -> Result<(), ConfigError> {
+        let mut bytes = [0u8; REQUEST_COUNTER_SZ];
+        self.to_bytes(&mut bytes)?;
+        let offset = Self::offset(flash)?;
+        flash.write(offset, &bytes)?;
+        self.unsaved.set(0);
+
+        Ok(())
+    }
+
+    /// Restore the count saved by [`RequestCounter::save`].
+    /// The time spent rebooting isn't known, so the day carries on from where it was saved.
+    pub fn load(flash: &mut FlashStorage) -> Result<Self, ConfigError> {
+        let mut bytes = [0u8; REQUEST_COUNTER_SZ];
+        let offset = Self::offset(flash)?;
+        flash.read(offset, &mut bytes)?;
+
+        let counter = Self::from_bytes(&bytes)?;
+        // it's all in flash now
+        counter.unsaved.set(0);
+        Ok(counter)
+    }
+
+    /// Encode the counter with a crc32 checksum, including the requests not yet saved to flash.
+    /// For memory that outlives a reset but not power loss, like RTC memory through deep sleep,
+    /// where waking up and reloading from flash would lose them.
+    pub fn to_bytes(&self, buf: &mut [u8]) -> Result<(), ConfigError> {
+        if buf.len() < REQUEST_COUNTER_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        self.roll_over();
+        let day_remaining = self
+            .day_ends
+            .get()
+            .map(|ends| ends.saturating_duration_since(Instant::now()))
+            .unwrap_or(Self::DAY);
+        let stored = Stored {
+            count: self.count.get(),
+            day_remaining_secs: day_remaining.as_secs() as u32,
+            unsaved: self.unsaved.get(),
+        };
+
+        let (crc32_bytes, payload) = buf[..REQUEST_COUNTER_SZ].split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Decode a counter written by [`RequestCounter::to_bytes`].
+    pub fn from_bytes(buf: &[u8]) -> Result<Self, ConfigError> {
+        if buf.len() < REQUEST_COUNTER_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let (crc32_bytes, payload) = buf[..REQUEST_COUNTER_SZ].split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(payload) {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        let stored: Stored = decode_from_slice(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?
+        .0;
+        let day_remaining = Duration::from_secs(stored.day_remaining_secs.into()).min(Self::DAY);
+
+        Ok(Self {
+            count: Cell::new(stored.count),
+            day_ends: Cell::new(Some(Instant::now() + day_remaining)),
+            unsaved: Cell::new(stored.unsaved),
+        })
+    }
+
+    /// The sector before the two config copies, see [`Config::save`](crate::wmata::Config::save).
+    fn offset(flash: &mut FlashStorage) -> Result<u32, ConfigError> {
+        Ok(storage_end(flash)? - 3 * FlashStorage::SECTOR_SIZE)
+    }
+}
+
+impl Default for RequestCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,120 @@
+use heapless::Vec;
+
+use crate::wmata::types::{Eta, LineKind, NextTrainsResponse, StationName};
+
+/// Steadies the ETAs of a stream of responses so the display doesn't flicker.
+///
+/// Predictions near a minute boundary bounce between polls ("2m", "1m", "2m"), and ARR/BRD can toggle.
+/// A shown ETA is only replaced when the new one is sooner, or later by more than `threshold` steps
+/// (one step per minute, plus one each for ARR and BRD). Trains are tracked by their position in the
+/// list along with their line and destination, so a different train in a slot starts fresh.
+/// Up to `N` trains are tracked, any past that pass through unchanged.
+pub struct EtaSmoother<const N: usize> {
+    threshold: u8,
+    shown: Vec<Shown, N>,
+}
+
+/// What was last displayed for the train in one slot.
+struct Shown {
+    line: Option<LineKind>,
+    destination: StationName,
+    eta: Option<Eta>,
+}
+
+impl<const N: usize> EtaSmoother<N> {
+    /// Large enough to ignore the usual one-minute jitter, small enough to let a real delay through.
+    pub const DEFAULT_THRESHOLD: u8 = 2;
+
+    pub const fn new(threshold: u8) -> Self {
+        Self {
+            threshold,
+            shown: Vec::new(),
+        }
+    }
+
+    /// Replace the ETAs in `response` with the smoothed ones to display, and remember them for the next call.
+    pub fn smooth(&mut self, response: &mut NextTrainsResponse) {
+        for (i, train) in response.trains.iter_mut().enumerate().take(N) {
+            let prev = self
+                .shown
+                .get(i)
+                .filter(|shown| {
+                    shown.line == train.line && shown.destination.0 == train.destination.0
+                })
+                .and_then(|shown| shown.eta);
+
+            if let (Some(prev), Some(new)) = (prev, train.min)
+                && new > prev
+                && new.rank() - prev.rank() <= u16::from(self.threshold)
+            {
+                train.min = Some(prev);
+            }
+
+            let shown = Shown {
+                line: train.line,
+                destination: train.destination.clone(),
+                eta: train.min,
+            };
+            match self.shown.get_mut(i) {
+                Some(slot) => *slot = shown,
+                None => {
+                    // can't fail, `i < N` and slots are filled in order
+                    let _ = self.shown.push(shown);
+                }
+            }
+        }
+
+        self.shown.truncate(response.trains.len().min(N));
+    }
+
+    /// Forget everything shown so far, e.g. after switching stations.
+    pub fn reset(&mut self) {
+        self.shown.clear();
+    }
+}
+
+impl<const N: usize> Default for EtaSmoother<N> {
+    fn default() -> Self {
+        Self::new(Self::DEFAULT_THRESHOLD)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wmata::types::{NextTrain, Station};
+
+    fn shown(smoother: &mut EtaSmoother<4>, destination: &str, mins: u8) -> Option<Eta> {
+        let mut res = NextTrainsResponse::new(alloc::vec![NextTrain::new(
+            Station::K04,
+            LineKind::OR,
+            destination,
+            Eta::Minutes(mins),
+        )]);
+        smoother.smooth(&mut res);
+        res.trains[0].min
+    }
+
+    #[test]
+    fn a_bouncing_eta_does_not_bounce_back_up() {
+        let mut smoother = EtaSmoother::default();
+        let shown: alloc::vec::Vec<_> = [3, 4, 3, 2]
+            .into_iter()
+            .map(|mins| shown(&mut smoother, "Vienna", mins))
+            .collect();
+        assert!(shown == [3, 3, 3, 2].map(|m| Some(Eta::Minutes(m))));
+    }
+
+    #[test]
+    fn a_real_delay_or_another_train_comes_through() {
+        let mut smoother = EtaSmoother::default();
+        assert!(shown(&mut smoother, "Vienna", 3) == Some(Eta::Minutes(3)));
+        // past the threshold
+        assert!(shown(&mut smoother, "Vienna", 6) == Some(Eta::Minutes(6)));
+        // a different train in the slot
+        assert!(shown(&mut smoother, "N Carrollton", 7) == Some(Eta::Minutes(7)));
+
+        smoother.reset();
+        assert!(shown(&mut smoother, "N Carrollton", 8) == Some(Eta::Minutes(8)));
+    }
+}
@@ -0,0 +1,183 @@
+use bincode::{Decode, Encode, decode_from_slice, encode_into_slice};
+use heapless::String;
+
+use crate::wmata::{
+    config::{CHECKSUM_SZ, ConfigError},
+    types::{Eta, LineKind, NextTrain, NextTrainsResponse, Station, StationCode, StationName},
+};
+
+/// Most trains a snapshot keeps, from the front of the list. Enough to fill any panel we drive.
+pub const SNAPSHOT_MAX_TRAINS: usize = 8;
+/// Bytes needed for a snapshot: 42 per train, a count and the checksum, 341 in all.
+/// It's meant for RTC fast memory (8KB on the esp32), which survives deep sleep.
+pub const SNAPSHOT_SZ: usize = CHECKSUM_SZ + 1 + SNAPSHOT_MAX_TRAINS * STORED_TRAIN_SZ;
+
+const STORED_TRAIN_SZ: usize = 42;
+/// Marks a missing line, car count or ETA.
+pub(crate) const NONE: u8 = u8::MAX;
+/// Marks [`Eta::Unknown`], whose rank doesn't fit in a byte.
+const UNKNOWN_ETA: u8 = NONE - 1;
+/// [`Eta::Scheduled`] minutes, up to 99, are stored added to this, just short of [`UNKNOWN_ETA`].
+const SCHEDULED_ETA: u8 = UNKNOWN_ETA - 100;
+
+/// Just what's displayed of a [`NextTrain`], in fixed size fields.
+#[derive(Encode, Decode)]
+struct StoredTrain {
+    line: u8,
+    cars: u8,
+    /// See [`eta_to_byte`].
+    eta: u8,
+    location: [u8; 3],
+    /// Zeroes when there isn't a known code.
+    destination_code: [u8; 3],
+    destination_len: u8,
+    destination: [u8; 32],
+}
+
+#[derive(Encode, Decode)]
+struct Stored {
+    len: u8,
+    trains: [StoredTrain; SNAPSHOT_MAX_TRAINS],
+}
+
+impl NextTrainsResponse {
+    /// Write the first [`SNAPSHOT_MAX_TRAINS`] trains to `buf` with a crc32 checksum, e.g. to
+    /// show them straight away after waking from deep sleep. See [`NextTrainsResponse::from_snapshot`].
+    /// Only what's displayed is kept, the location and destination names are dropped.
+    pub fn write_snapshot(&self, buf: &mut [u8]) -> Result<(), ConfigError> {
+        if buf.len() < SNAPSHOT_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let mut stored = Stored {
+            len: 0,
+            trains: core::array::from_fn(|_| StoredTrain::empty()),
+        };
+        for (slot, train) in stored.trains.iter_mut().zip(&self.trains) {
+            *slot = StoredTrain::from(train);
+            stored.len += 1;
+        }
+
+        let (crc32_bytes, payload) = buf[..SNAPSHOT_SZ].split_at_mut(CHECKSUM_SZ);
+        let len = encode_into_slice(
+            &stored,
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?;
+        let crc32 = crc32fast::hash(&payload[..len]);
+        crc32_bytes.copy_from_slice(&crc32.to_le_bytes());
+
+        Ok(())
+    }
+
+    /// Read back a snapshot from [`NextTrainsResponse::write_snapshot`].
+    /// Memory that was never written, like RTC memory on a cold boot, fails the checksum.
+    /// The response has no fetch time, since it's as old as the snapshot.
+    pub fn from_snapshot(buf: &[u8]) -> Result<Self, ConfigError> {
+        if buf.len() < SNAPSHOT_SZ {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        let (crc32_bytes, payload) = buf[..SNAPSHOT_SZ].split_at(CHECKSUM_SZ);
+        let crc32 = u32::from_le_bytes(crc32_bytes.try_into().unwrap()); // this _should_ be infallible
+        if crc32 != crc32fast::hash(payload) {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        let stored: Stored = decode_from_slice(
+            payload,
+            bincode::config::standard().with_fixed_int_encoding(),
+        )?
+        .0;
+        let len = (stored.len as usize).min(SNAPSHOT_MAX_TRAINS);
+
+        Ok(Self {
+            trains: stored.trains[..len]
+                .iter()
+                .filter_map(StoredTrain::to_train)
+                .collect(),
+            fetched_at: None,
+        })
+    }
+}
+
+impl StoredTrain {
+    fn empty() -> Self {
+        Self {
+            line: NONE,
+            cars: NONE,
+            eta: NONE,
+            location: [0; 3],
+            destination_code: [0; 3],
+            destination_len: 0,
+            destination: [0; 32],
+        }
+    }
+
+    /// `None` if the location isn't a station this firmware knows, e.g. after an update removed it.
+    fn to_train(&self) -> Option<NextTrain> {
+        let location_code = Station::from_code(core::str::from_utf8(&self.location).ok()?)?;
+        let destination_code = core::str::from_utf8(&self.destination_code)
+            .ok()
+            .and_then(Station::from_code);
+        let destination_len = (self.destination_len as usize).min(self.destination.len());
+        let destination = core::str::from_utf8(&self.destination[..destination_len]).ok()?;
+
+        Some(NextTrain {
+            cars: (self.cars != NONE).then(|| self.cars.into()),
+            destination: StationName(String::try_from(destination).ok()?),
+            destination_code: destination_code.map(StationCode::from),
+            destination_name: None,
+            group: None,
+            line: LineKind::try_from(self.line).ok(),
+            location_code,
+            location_name: StationName(String::new()),
+            min: eta_from_byte(self.eta),
+        })
+    }
+}
+
+impl From<&NextTrain> for StoredTrain {
+    fn from(train: &NextTrain) -> Self {
+        let mut stored = Self::empty();
+        stored.line = train.line.map(u8::from).unwrap_or(NONE);
+        stored.cars = train.cars.map(u8::from).unwrap_or(NONE);
+        stored.eta = eta_to_byte(train.min);
+        stored
+            .location
+            .copy_from_slice(train.location_code.code().as_bytes());
+        if let Some(code) = train.destination_station() {
+            stored
+                .destination_code
+                .copy_from_slice(code.code().as_bytes());
+        }
+
+        let destination = train.destination_label().as_bytes();
+        stored.destination_len = destination.len() as u8;
+        stored.destination[..destination.len()].copy_from_slice(destination);
+        stored
+    }
+}
+
+/// An ETA in a byte: [`Eta::rank`], saturating just short of [`SCHEDULED_ETA`], then scheduled
+/// minutes from [`SCHEDULED_ETA`], then [`UNKNOWN_ETA`] and [`NONE`].
+pub(crate) fn eta_to_byte(eta: Option<Eta>) -> u8 {
+    eta.map(|eta| match eta {
+        Eta::Unknown => UNKNOWN_ETA,
+        Eta::Scheduled(m) => SCHEDULED_ETA + m.min(99),
+        eta => eta.rank().min(u16::from(SCHEDULED_ETA - 1)) as u8,
+    })
+    .unwrap_or(NONE)
+}
+
+/// Undo [`eta_to_byte`].
+pub(crate) fn eta_from_byte(byte: u8) -> Option<Eta> {
+    match byte {
+        NONE => None,
+        0 => Some(Eta::Boarding),
+        1 => Some(Eta::Arriving),
+        UNKNOWN_ETA => Some(Eta::Unknown),
+        m if m >= SCHEDULED_ETA => Some(Eta::Scheduled(m - SCHEDULED_ETA)),
+        m => Some(Eta::Minutes(m - 2)),
+    }
+}
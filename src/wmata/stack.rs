@@ -0,0 +1,119 @@
+use embassy_net::Stack;
+use embassy_net::dns::DnsSocket;
+use embassy_net::tcp::client::{TcpClient, TcpClientState};
+use embassy_time::Duration;
+use reqwless::client::HttpClient;
+
+use crate::wmata::{CachedDns, Client, DnsCache, Error, RetryDns};
+
+#[cfg(all(feature = "small-buffers", feature = "large-buffers"))]
+compile_error!("pick at most one of the `small-buffers` and `large-buffers` features");
+
+/// Size of the socket buffers each way, from the buffer profile: 1.5KB with `small-buffers`,
+/// 8KB with `large-buffers`, otherwise 4KB. They only bound how much is in flight at once, so
+/// even the smallest can read a response of any size, just over more round trips.
+#[cfg(feature = "small-buffers")]
+pub const TCP_BUFFER_SZ: usize = 1536;
+#[cfg(feature = "large-buffers")]
+pub const TCP_BUFFER_SZ: usize = 8192;
+#[cfg(not(any(feature = "small-buffers", feature = "large-buffers")))]
+pub const TCP_BUFFER_SZ: usize = 4096;
+
+/// Size of the `rx_buf` in [`ClientResources`], which has to hold a whole response, headers and
+/// all, see [`Client::new`]: 2KB with `small-buffers`, 16KB with `large-buffers`, otherwise 4KB.
+#[cfg(feature = "small-buffers")]
+pub const RX_BUF_SZ: usize = 2048;
+#[cfg(feature = "large-buffers")]
+pub const RX_BUF_SZ: usize = 16384;
+#[cfg(not(any(feature = "small-buffers", feature = "large-buffers")))]
+pub const RX_BUF_SZ: usize = 4096;
+
+/// The smallest `rx_buf` the enabled endpoint families are usable with, see [`Client::new`].
+/// `positions` isn't counted, its responses don't fit any profile.
+pub const MIN_RX_BUF_SZ: usize = if cfg!(any(feature = "bus", feature = "incidents")) {
+    4096
+} else {
+    2048
+};
+
+const _: () = assert!(
+    RX_BUF_SZ >= MIN_RX_BUF_SZ,
+    "rx_buf is too small for the enabled endpoints, drop `small-buffers`"
+);
+
+/// One connection at a time with [`TCP_BUFFER_SZ`] buffers each way.
+pub type StackTcpClient = TcpClient<'static, 1, TCP_BUFFER_SZ, TCP_BUFFER_SZ>;
+/// DNS through the stack, retried while the network settles and cached between requests.
+pub type StackDns = CachedDns<'static, RetryDns<DnsSocket<'static>>>;
+/// The [`Client`] [`Client::with_base_and_key`] builds.
+pub type StackClient = Client<'static, StackTcpClient, StackDns>;
+
+/// Everything a [`StackClient`] borrows for as long as it's alive: the socket buffers, the
+/// resolver and its cache, and the [`RX_BUF_SZ`] `rx_buf` bodies are read into. About 12KB in
+/// all with the default buffers, 5KB with `small-buffers` and 32KB with `large-buffers`.
+///
+/// The client holds on to these for good, so they have to be `'static`, which in practice
+/// means a `static_cell::StaticCell` (or `mk_static!`) filled once at boot. A stack local in
+/// `main` won't do, even though `main` never returns.
+pub struct ClientResources {
+    tcp_state: TcpClientState<1, TCP_BUFFER_SZ, TCP_BUFFER_SZ>,
+    tcp: Option<StackTcpClient>,
+    dns: Option<StackDns>,
+    dns_cache: DnsCache,
+    rx_buf: [u8; RX_BUF_SZ],
+}
+
+impl ClientResources {
+    pub const fn new() -> Self {
+        Self {
+            tcp_state: TcpClientState::new(),
+            tcp: None,
+            dns: None,
+            dns_cache: DnsCache::new(DnsCache::DEFAULT_TTL),
+            rx_buf: [0; RX_BUF_SZ],
+        }
+    }
+}
+
+impl Default for ClientResources {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl StackClient {
+    /// Socket inactivity timeout, on top of the client's own per-request one.
+    pub const SOCKET_TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// A client on `stack`, ready to send requests to `api` with `api_key`, e.g. [`API`](crate::wmata::API)
+    /// for the official api. Sets up the TCP client, DNS and `reqwless` that [`Client::new`] leaves
+    /// to the caller, and wires in the DNS cache like [`Client::with_dns_cache`].
+    ///
+    /// Use [`Client::new`] instead for other socket counts or buffer sizes.
+    pub fn with_base_and_key(
+        stack: Stack<'static>,
+        resources: &'static mut ClientResources,
+        api: &'static str,
+        api_key: &str,
+    ) -> Result<Self, Error> {
+        let ClientResources {
+            tcp_state,
+            tcp,
+            dns,
+            dns_cache,
+            rx_buf,
+        } = resources;
+        let dns_cache = &*dns_cache;
+
+        let tcp = tcp.insert(TcpClient::new(stack, tcp_state));
+        tcp.set_timeout(Some(Self::SOCKET_TIMEOUT));
+        let dns = dns.insert(CachedDns::new(
+            RetryDns::new(DnsSocket::new(stack)),
+            dns_cache,
+        ));
+
+        Client::new(HttpClient::new(&*tcp, &*dns), rx_buf, api_key)
+            .with_dns_cache(dns_cache)
+            .with_api(api)
+    }
+}
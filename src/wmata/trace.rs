@@ -0,0 +1,115 @@
+//! Request tracing for the `trace` feature: the request line, headers, status, timing and body
+//! size of every request the client makes, logged in the same shape each time. Without the
+//! feature [`Trace`] is empty and its methods do nothing, so none of it ends up in the build.
+
+#[cfg(feature = "trace")]
+use defmt::info;
+#[cfg(feature = "trace")]
+use embassy_time::Instant;
+
+use crate::wmata::Error;
+#[cfg(feature = "trace")]
+use crate::wmata::{API_KEY_HEADER, config::Secret};
+
+/// One request, from [`Trace::start`] until it's logged by [`Trace::end`].
+pub(crate) struct Trace {
+    #[cfg(feature = "trace")]
+    start: Instant,
+    #[cfg(feature = "trace")]
+    status: Option<u16>,
+    #[cfg(feature = "trace")]
+    body_size: Option<usize>,
+}
+
+impl Trace {
+    /// Log the request about to go out to `url` and start timing it.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn start(url: &str) -> Self {
+        #[cfg(feature = "trace")]
+        info!("trace: GET {=str}", url);
+
+        Trace {
+            #[cfg(feature = "trace")]
+            start: Instant::now(),
+            #[cfg(feature = "trace")]
+            status: None,
+            #[cfg(feature = "trace")]
+            body_size: None,
+        }
+    }
+
+    /// Log the headers sent with the request, with the api key redacted.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn headers(&self, headers: &[(&str, &str)]) {
+        #[cfg(feature = "trace")]
+        for &(name, value) in headers {
+            if name.eq_ignore_ascii_case(API_KEY_HEADER) {
+                info!("trace:   {=str}: {}", name, Secret::Redacted(value));
+            } else {
+                info!("trace:   {=str}: {=str}", name, value);
+            }
+        }
+    }
+
+    /// The response's status, once its headers are in.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn status(&mut self, status: u16) {
+        #[cfg(feature = "trace")]
+        {
+            self.status = Some(status);
+        }
+    }
+
+    /// How big the body was on the wire, before any decompression.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn body(&mut self, size: usize) {
+        #[cfg(feature = "trace")]
+        {
+            self.body_size = Some(size);
+        }
+    }
+
+    /// Log how the request went, including a timeout or anything else that cut it short before
+    /// there was a status or a body.
+    #[cfg_attr(not(feature = "trace"), allow(unused_variables))]
+    pub(crate) fn end<T>(&self, res: &Result<T, Error>) {
+        #[cfg(feature = "trace")]
+        {
+            let ms = self.start.elapsed().as_millis();
+            match res {
+                Ok(_) => info!(
+                    "trace: status={} body={}B time={=u64}ms ok",
+                    self.status, self.body_size, ms
+                ),
+                Err(e) => info!(
+                    "trace: status={} body={}B time={=u64}ms error={}",
+                    self.status, self.body_size, ms, e
+                ),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(not(feature = "trace"))]
+    #[test]
+    fn compiles_out_without_the_feature() {
+        assert_eq!(core::mem::size_of::<Trace>(), 0);
+    }
+
+    #[test]
+    fn traces_a_whole_request() {
+        let mut trace = Trace::start("http://api.wmata.com/Rail.svc/json/jLines");
+        trace.headers(&[
+            ("api_key", "0123456789abcdef"),
+            ("Accept", "application/json"),
+        ]);
+        trace.status(200);
+        trace.body(1234);
+        trace.end(&Ok::<(), Error>(()));
+        trace.end(&Err::<(), _>(Error::Timeout));
+    }
+}
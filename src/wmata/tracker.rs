@@ -0,0 +1,257 @@
+use embassy_time::Instant;
+
+use crate::wmata::types::{Eta, LineKind, NextTrain, NextTrainsResponse, StationName};
+
+/// A change in the train a [`TrainTracker`] is following.
+#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub enum TrackEvent {
+    /// It's now due at this ETA, first reported when the train is picked.
+    Updated(Eta),
+    /// It's shown ARR or BRD for the first time. Once it's gone after this, tracking just ends.
+    Arrived,
+    /// It went missing before arriving, and tracking ends.
+    Lost,
+}
+
+/// Follows one train across polls, e.g. for a platform announcement, reporting what changes as a
+/// [`TrackEvent`] from [`TrainTracker::update`].
+///
+/// Predictions have no trip id, so which train is the same one as last time is a guess:
+/// * The first response with a train `matches` accepts picks the soonest of them.
+/// * After that, a train has to have the same line and destination too, and be due no more than
+///   `tolerance` minutes later than the followed one should be by now, i.e. its last ETA less the
+///   minutes since. Of those, the one due closest to that is taken, so the train behind it can
+///   only be mistaken for it when they're within `tolerance` of each other.
+/// * A train missing from a response, e.g. one WMATA drops for a poll, gets
+///   [`TrainTracker::MAX_MISSES`] responses to come back before it's [`TrackEvent::Lost`].
+///
+/// Trains without a known ETA are never picked.
+pub struct TrainTracker<F> {
+    matches: F,
+    tolerance: u8,
+    state: State,
+}
+
+enum State {
+    /// No train picked yet.
+    Waiting,
+    Following(Followed),
+    /// Arrived and gone, or lost.
+    Done,
+}
+
+struct Followed {
+    line: Option<LineKind>,
+    destination: StationName,
+    eta: Eta,
+    /// When `eta` was last seen.
+    seen_at: Instant,
+    misses: u8,
+    arrived: bool,
+}
+
+impl<F: FnMut(&NextTrain) -> bool> TrainTracker<F> {
+    /// Enough for a train held a couple of minutes, without taking the one behind it on a
+    /// frequent line.
+    pub const DEFAULT_TOLERANCE: u8 = 3;
+    /// Responses in a row the followed train can be missing from before it's lost.
+    pub const MAX_MISSES: u8 = 2;
+
+    /// Follow the soonest train `matches` accepts, e.g. `|train| train.line == Some(LineKind::RD)`.
+    pub fn new(matches: F) -> Self {
+        Self {
+            matches,
+            tolerance: Self::DEFAULT_TOLERANCE,
+            state: State::Waiting,
+        }
+    }
+
+    /// How many minutes later than expected a train can be due and still be the followed one.
+    pub fn with_tolerance(mut self, minutes: u8) -> Self {
+        self.tolerance = minutes;
+        self
+    }
+
+    /// Find the followed train in `response`, fetched at `now`, and report what changed since
+    /// the last one, if anything. Nothing more is reported once it's arrived and gone, or lost.
+    pub fn update(&mut self, response: &NextTrainsResponse, now: Instant) -> Option<TrackEvent> {
+        match &mut self.state {
+            State::Done => None,
+            State::Waiting => {
+                let (train, eta) = response
+                    .trains
+                    .iter()
+                    .filter_map(|train| Some((train, known_eta(train)?)))
+                    .filter(|(train, _)| (self.matches)(train))
+                    .min_by_key(|(_, eta)| eta.rank())?;
+
+                let arrived = is_arrived(eta);
+                self.state = State::Following(Followed {
+                    line: train.line,
+                    destination: train.destination.clone(),
+                    eta,
+                    seen_at: now,
+                    misses: 0,
+                    arrived,
+                });
+                Some(if arrived {
+                    TrackEvent::Arrived
+                } else {
+                    TrackEvent::Updated(eta)
+                })
+            }
+            State::Following(followed) => {
+                let minutes = now.saturating_duration_since(followed.seen_at).as_secs() / 60;
+                let expected = followed
+                    .eta
+                    .rank()
+                    .saturating_sub(minutes.try_into().unwrap_or(u16::MAX));
+                let latest = expected.saturating_add(u16::from(self.tolerance));
+
+                let found = response
+                    .trains
+                    .iter()
+                    .filter(|train| {
+                        train.line == followed.line && train.destination.0 == followed.destination.0
+                    })
+                    .filter_map(known_eta)
+                    .filter(|eta| eta.rank() <= latest)
+                    .min_by_key(|eta| eta.rank().abs_diff(expected));
+                let matched = match found {
+                    Some(eta) => eta,
+                    None => {
+                        followed.misses += 1;
+                        if followed.arrived {
+                            self.state = State::Done;
+                            return None;
+                        }
+                        if followed.misses >= Self::MAX_MISSES {
+                            self.state = State::Done;
+                            return Some(TrackEvent::Lost);
+                        }
+                        return None;
+                    }
+                };
+
+                let previous = followed.eta;
+                followed.eta = matched;
+                followed.seen_at = now;
+                followed.misses = 0;
+                if is_arrived(matched) && !followed.arrived {
+                    followed.arrived = true;
+                    Some(TrackEvent::Arrived)
+                } else if matched != previous {
+                    Some(TrackEvent::Updated(matched))
+                } else {
+                    None
+                }
+            }
+        }
+    }
+
+    /// The followed train's last ETA, `None` before one's picked and after it's gone.
+    pub fn eta(&self) -> Option<Eta> {
+        match &self.state {
+            State::Following(followed) => Some(followed.eta),
+            State::Waiting | State::Done => None,
+        }
+    }
+
+    /// Whether the train arrived and left or was lost, so there's nothing more to report.
+    pub fn is_done(&self) -> bool {
+        matches!(self.state, State::Done)
+    }
+
+    /// Forget the followed train, picking a new one with the next response.
+    pub fn reset(&mut self) {
+        self.state = State::Waiting;
+    }
+}
+
+/// `train`'s ETA, unless it has none or it's unknown.
+fn known_eta(train: &NextTrain) -> Option<Eta> {
+    train.min.filter(|eta| *eta != Eta::Unknown)
+}
+
+fn is_arrived(eta: Eta) -> bool {
+    matches!(eta, Eta::Arriving | Eta::Boarding)
+}
+
+#[cfg(test)]
+mod tests {
+    use embassy_time::Duration;
+
+    use super::*;
+    use crate::wmata::json;
+
+    /// Red line trains to Shady Grove at Metro Center, due at each of `mins`.
+    fn response(mins: &[&str]) -> NextTrainsResponse {
+        let trains: alloc::vec::Vec<_> = mins
+            .iter()
+            .map(|min| {
+                alloc::format!(
+                    r#"{{"Car":"8","Destination":"Shady Grv","DestinationCode":"A15","DestinationName":"Shady Grove","Group":"2","Line":"RD","LocationCode":"A01","LocationName":"Metro Center","Min":"{min}"}}"#
+                )
+            })
+            .collect();
+        json::from_str(&alloc::format!(r#"{{"Trains":[{}]}}"#, trains.join(","))).unwrap()
+    }
+
+    fn minutes(n: u64) -> Instant {
+        Instant::from_secs(1000) + Duration::from_secs(60 * n)
+    }
+
+    #[test]
+    fn follows_a_train_until_it_arrives_and_leaves() {
+        let mut tracker = TrainTracker::new(|train: &NextTrain| train.line == Some(LineKind::RD));
+        assert!(
+            tracker.update(&response(&["9", "3"]), minutes(0))
+                == Some(TrackEvent::Updated(Eta::Minutes(3)))
+        );
+        // held at the same ETA is nothing new
+        assert!(tracker.update(&response(&["3", "9"]), minutes(0)).is_none());
+        assert!(
+            tracker.update(&response(&["2", "8"]), minutes(1))
+                == Some(TrackEvent::Updated(Eta::Minutes(2)))
+        );
+        assert!(tracker.update(&response(&["ARR", "7"]), minutes(2)) == Some(TrackEvent::Arrived));
+        assert!(
+            tracker.update(&response(&["BRD", "6"]), minutes(2))
+                == Some(TrackEvent::Updated(Eta::Boarding))
+        );
+        assert!(tracker.eta() == Some(Eta::Boarding));
+
+        // gone, and the one behind isn't taken for it
+        assert!(tracker.update(&response(&["5"]), minutes(3)).is_none());
+        assert!(tracker.is_done());
+        assert!(tracker.update(&response(&["4"]), minutes(4)).is_none());
+        assert!(tracker.eta().is_none());
+    }
+
+    #[test]
+    fn a_train_missing_too_long_is_lost() {
+        let mut tracker = TrainTracker::new(|_: &NextTrain| true);
+        assert!(tracker.update(&response(&[]), minutes(0)).is_none());
+        assert!(
+            tracker.update(&response(&["---", "4", "12"]), minutes(0))
+                == Some(TrackEvent::Updated(Eta::Minutes(4)))
+        );
+
+        // dropped for one poll is forgiven
+        assert!(tracker.update(&response(&["12"]), minutes(1)).is_none());
+        assert!(
+            tracker.update(&response(&["2", "11"]), minutes(2))
+                == Some(TrackEvent::Updated(Eta::Minutes(2)))
+        );
+
+        assert!(tracker.update(&response(&["10"]), minutes(3)).is_none());
+        assert!(tracker.update(&response(&["9"]), minutes(4)) == Some(TrackEvent::Lost));
+        assert!(tracker.is_done());
+
+        tracker.reset();
+        assert!(
+            tracker.update(&response(&["8"]), minutes(5))
+                == Some(TrackEvent::Updated(Eta::Minutes(8)))
+        );
+    }
+}
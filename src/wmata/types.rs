@@ -1,18 +1,53 @@
 use core::fmt::Write;
+#[cfg(feature = "rail")]
+use embassy_time::{Duration, Instant};
 use heapless::String;
-use miniserde::{Deserialize, de::Visitor, make_place};
+#[cfg(feature = "miniserde")]
+use miniserde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
 
-#[derive(Deserialize, defmt::Format)]
+use crate::wmata::Error;
+#[cfg(feature = "rail")]
+use crate::wmata::clock::LocalTime;
+#[cfg(feature = "rail")]
+use crate::wmata::config::NoPassengerPolicy;
+use crate::wmata::json::{FromJsonStr, deserialize_from_str};
+
+/// A single predicted arrival.
+///
+/// WMATA describes the destination three ways, which can disagree or go missing:
+/// `Destination` (abbreviated), `DestinationName` (full) and `DestinationCode`.
+/// Prefer the accessors over the raw fields:
+/// * [`NextTrain::destination_station`] for the station itself, from `DestinationCode` only,
+///   since the names aren't stable enough to look up.
+/// * [`NextTrain::destination_label`] for text, `Destination` first since it's sized for signs,
+///   falling back to `DestinationName`.
+#[cfg(feature = "rail")]
+#[derive(Clone, PartialEq, Eq, Deserialize, defmt::Format)]
 pub struct NextTrain {
+    /// `None` when WMATA leaves it out or sends something that isn't a number, like `""` or `"-"`.
     #[serde(rename = "Car")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::wmata::json::deserialize_lenient")
+    )]
     pub cars: Option<TrainCar>,
     #[serde(rename = "Destination")]
     pub destination: StationName,
     #[serde(rename = "DestinationCode")]
-    pub destination_code: Option<Station>,
+    pub destination_code: Option<StationCode>,
     #[serde(rename = "DestinationName")]
     pub destination_name: Option<StationName>,
+    /// Which track the train is on, see [`Group`].
+    #[serde(rename = "Group")]
+    pub group: Option<Group>,
+    /// `None` when WMATA leaves it out or sends something that isn't a line, like `""` or `"--"`.
     #[serde(rename = "Line")]
+    #[cfg_attr(
+        feature = "serde",
+        serde(default, deserialize_with = "crate::wmata::json::deserialize_lenient")
+    )]
     pub line: Option<LineKind>,
     #[serde(rename = "LocationCode")]
     pub location_code: Station,
@@ -22,336 +57,4064 @@ pub struct NextTrain {
     pub min: Option<Eta>,
 }
 
+#[cfg(feature = "rail")]
 impl NextTrain {
+    /// Shown in the ETA column when WMATA doesn't send a `Min` for a train.
+    pub const ETA_PLACEHOLDER: &'static str = "--";
+    /// Furthest a train can be from a scheduled one and still be matched to it by
+    /// [`NextTrain::schedule_deviation`], in minutes. Wide enough for a late first train, narrow
+    /// enough not to pair the last train with the one before it.
+    pub const SCHEDULE_MATCH_MINUTES: u8 = 15;
+    /// How early a train can be predicted against the last scheduled one and still be taken for
+    /// it by [`NextTrain::is_last_train`]. Trains rarely run early, and the one before the last is
+    /// usually a whole headway early, so this is much narrower than the late side.
+    pub const LAST_TRAIN_EARLY_MINUTES: u8 = 3;
+    /// What to mark a train with when [`NextTrain::is_last_train`].
+    pub const LAST_TRAIN_LABEL: &'static str = "LAST TRAIN";
+    /// The destination [`NoPassengerPolicy::Label`] gives trains that aren't taking passengers.
+    pub const NOT_IN_SERVICE: &'static str = "Not in Service";
+    /// Keys of every train in a prediction response, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &[
+        "Car",
+        "Destination",
+        "DestinationCode",
+        "DestinationName",
+        "Group",
+        "Line",
+        "LocationCode",
+        "LocationName",
+        "Min",
+    ];
+
+    /// A train from just what's displayed, for tests and made-up data rather than the api.
+    /// The car count and destination code start out unknown, see [`NextTrain::with_cars`] and
+    /// [`NextTrain::with_destination_code`], and the location name is left blank.
+    pub fn new(location: Station, line: LineKind, destination: &str, eta: Eta) -> Self {
+        Self {
+            cars: None,
+            destination: StationName::new(destination),
+            destination_code: None,
+            destination_name: None,
+            group: None,
+            line: Some(line),
+            location_code: location,
+            location_name: StationName(String::new()),
+            min: Some(eta),
+        }
+    }
+
+    pub fn with_cars(mut self, cars: u8) -> Self {
+        self.cars = Some(cars.into());
+        self
+    }
+
+    pub fn with_destination_code(mut self, destination: Station) -> Self {
+        self.destination_code = Some(destination.into());
+        self
+    }
+
+    pub fn with_group(mut self, group: u8) -> Self {
+        self.group = Some(group.into());
+        self
+    }
+
+    /// The ETA column for this train, falling back to [`NextTrain::ETA_PLACEHOLDER`] so every row has one.
+    pub fn eta_display(&self) -> String<4> {
+        match &self.min {
+            Some(min) => min.to_string(),
+            None => String::try_from(Self::ETA_PLACEHOLDER).expect("placeholder should always fit"),
+        }
+    }
+
+    /// Whether this train takes passengers. WMATA marks the ones that don't with line `NO` and
+    /// "No Passenger" for the destination name, and either is enough.
+    pub fn is_revenue(&self) -> bool {
+        let no_passenger = self
+            .destination_name
+            .as_ref()
+            .is_some_and(|name| name.0.eq_ignore_ascii_case("No Passenger"));
+        self.line != Some(LineKind::NO) && !no_passenger
+    }
+
+    /// The station this train terminates at, if WMATA sent a code we recognize.
+    pub fn destination_station(&self) -> Option<Station> {
+        self.destination_code.and_then(|code| code.station())
+    }
+
+    /// Whether the destination is on the line WMATA reported for this train, at either level of a
+    /// transfer station, so it could get there from here. Predictions don't say which way a train
+    /// is headed, so any destination on the line passes, and only one off it entirely, like a Red
+    /// Line train to Vienna, is caught.
+    ///
+    /// `true` when there's nothing to check against: no reported line, a No Passenger train, or a
+    /// location or destination that isn't known to be on the line.
+    pub fn is_destination_reachable(&self) -> bool {
+        let (Some(line), Some(destination)) = (self.line, self.destination_station()) else {
+            return true;
+        };
+        if line == LineKind::NO {
+            return true;
+        }
+        let on_line = |station: Station| {
+            station.line_index(line).is_some()
+                || station
+                    .other_level()
+                    .is_some_and(|other| other.line_index(line).is_some())
+        };
+        !on_line(self.location_code) || on_line(destination)
+    }
+
+    /// The stops after this one up to and including the destination, along the train's
+    /// [`NextTrain::line_or_inferred`], e.g. for a "via Metro Center, Gallery Place..." line.
+    /// Empty without a known line or destination, or if either station isn't on that line.
+    ///
+    /// It's a slice of [`LineKind::stations`], so it's in that order, which is backwards for a
+    /// train heading towards the line's first station. The destination is `last()` when it's
+    /// forwards and `first()` when it's backwards, so use `iter().rev()` when it's first.
+    pub fn stops_to_destination(&self) -> &'static [Station] {
+        let (Some(line), Some(destination)) = (self.line_or_inferred(), self.destination_station())
+        else {
+            return &[];
+        };
+        let (Some(here), Some(there)) = (
+            self.location_code.line_index(line),
+            destination.line_index(line),
+        ) else {
+            return &[];
+        };
+
+        let stations = line.stations();
+        match here.cmp(&there) {
+            core::cmp::Ordering::Less => &stations[here + 1..=there],
+            core::cmp::Ordering::Greater => &stations[there..here],
+            core::cmp::Ordering::Equal => &[],
+        }
+    }
+
+    /// How many minutes late this train is against `times`, the schedule of the station it's
+    /// predicted at, with `now` the local time the prediction was fetched. Negative is early.
+    /// Show it as e.g. `+3 late` or `2 early`.
+    ///
+    /// Best effort: predictions carry no trip id, and the api only publishes each day's first and
+    /// last trains per destination, so there's nothing to compare a train against in between.
+    /// A train is taken to be a scheduled one to the same destination when its predicted arrival,
+    /// `now` plus its ETA, is within [`NextTrain::SCHEDULE_MATCH_MINUTES`] of it, the nearest if
+    /// several are. So `None` for most of the day, for trains without a known destination or a
+    /// minute count, and whenever the nearest scheduled train is further off than that, which a
+    /// very late first train also is. Last trains after midnight are looked for under the day
+    /// before, where the api lists them.
+    pub fn schedule_deviation(&self, times: &StationTimes, now: LocalTime) -> Option<i8> {
+        let destination = self.destination_station()?;
+        // already the schedule, there's nothing to be late against
+        if matches!(self.min, Some(Eta::Scheduled(_))) {
+            return None;
+        }
+        let predicted = self.predicted_minute(now)?;
+
+        let deviation = scheduled_minutes(times, now, destination)
+            .map(|(_, scheduled)| predicted - scheduled)
+            .filter(|deviation| deviation.abs() <= i32::from(Self::SCHEDULE_MATCH_MINUTES))
+            .min_by_key(|deviation| deviation.abs())?;
+        i8::try_from(deviation).ok()
+    }
+
+    /// Whether this is the last train of the night to its destination going by `times`, the
+    /// schedule of the station it's predicted at, with `now` the local time the prediction was
+    /// fetched, e.g. to mark it [`NextTrain::LAST_TRAIN_LABEL`].
+    ///
+    /// Best effort, matched like [`NextTrain::schedule_deviation`]: its predicted arrival has to
+    /// be no more than [`NextTrain::LAST_TRAIN_EARLY_MINUTES`] before one of the day's last
+    /// trains to the same destination, or [`NextTrain::SCHEDULE_MATCH_MINUTES`] after it. So the
+    /// one before the last can only be taken for it when it's running most of a headway late,
+    /// and a last train later than that isn't recognized. A scheduled ETA counts as a prediction.
+    pub fn is_last_train(&self, times: &StationTimes, now: LocalTime) -> bool {
+        let (Some(destination), Some(predicted)) =
+            (self.destination_station(), self.predicted_minute(now))
+        else {
+            return false;
+        };
+
+        scheduled_minutes(times, now, destination)
+            .filter(|(last, _)| *last)
+            .any(|(_, scheduled)| {
+                let deviation = predicted - scheduled;
+                deviation >= -i32::from(Self::LAST_TRAIN_EARLY_MINUTES)
+                    && deviation <= i32::from(Self::SCHEDULE_MATCH_MINUTES)
+            })
+    }
+
+    /// When this train is due, in minutes from the midnight starting `now`'s day, `None` without
+    /// a minute count.
+    fn predicted_minute(&self, now: LocalTime) -> Option<i32> {
+        let eta = match self.min? {
+            Eta::Minutes(m) | Eta::Scheduled(m) => i32::from(m),
+            Eta::Arriving | Eta::Boarding => 0,
+            Eta::Unknown => return None,
+        };
+        Some(i32::from(now.minute_of_day()) + eta)
+    }
+
+    /// Destination text for display, see [`NextTrain`] for the precedence.
+    pub fn destination_label(&self) -> &str {
+        match &self.destination_name {
+            Some(name) if self.destination.0.is_empty() => &name.0,
+            _ => &self.destination.0,
+        }
+    }
+
+    /// The line to badge this train with, trying in order:
+    /// 1. [`NextTrain::line`], as WMATA reported it.
+    /// 2. The one line serving both the train's location and its destination, see
+    ///    [`LineKind::from_station_and_destination`]. This needs a `DestinationCode` we recognize.
+    /// 3. `None`, e.g. on a shared trunk where more than one line fits.
+    pub fn line_or_inferred(&self) -> Option<LineKind> {
+        self.line.or_else(|| {
+            LineKind::from_station_and_destination(self.location_code, self.destination_station()?)
+        })
+    }
+
+    /// Writes a sentence for text-to-speech, like "Red line train to Glenmont, 3 minutes." or
+    /// "Orange line train to Vienna/Fairfax-GMU, arriving.". It uses the full `DestinationName`
+    /// when there is one, since the sign-sized `Destination` abbreviates words.
+    /// A train without a known line is just "Train to ...".
+    pub fn announcement<const N: usize>(&self, buf: &mut String<N>) -> core::fmt::Result {
+        match self.line_or_inferred() {
+            Some(LineKind::NO) => write!(buf, "Train not taking passengers")?,
+            Some(line) => {
+                let mut name = line.name().chars();
+                if let Some(first) = name.next() {
+                    write!(buf, "{}", first.to_ascii_uppercase())?;
+                }
+                write!(buf, "{} line train to ", name.as_str())?;
+                self.write_spoken_destination(buf)?;
+            }
+            None => {
+                write!(buf, "Train to ")?;
+                self.write_spoken_destination(buf)?;
+            }
+        }
+
+        match self.min {
+            Some(Eta::Boarding) => write!(buf, ", boarding."),
+            Some(Eta::Arriving) => write!(buf, ", arriving."),
+            Some(Eta::Minutes(1)) => write!(buf, ", 1 minute."),
+            Some(Eta::Minutes(m)) => write!(buf, ", {m} minutes."),
+            Some(Eta::Scheduled(m)) => write!(buf, ", scheduled in about {m} minutes."),
+            Some(Eta::Unknown) | None => write!(buf, "."),
+        }
+    }
+
+    /// Writes which way this train is headed, like a platform sign: "Toward Glenmont" for a
+    /// destination in [`Station::direction_name`]'s table, which also sends short turns like
+    /// Silver Spring the same way as the terminal past them, and "To " and the
+    /// [`NextTrain::destination_label`] for anywhere else. Trains not taking passengers get just
+    /// the label.
+    pub fn direction_label<const N: usize>(&self, buf: &mut String<N>) -> core::fmt::Result {
+        if !self.is_revenue() {
+            return write!(buf, "{}", self.destination_label());
+        }
+        match self.destination_station().and_then(|s| s.direction_name()) {
+            Some(direction) => write!(buf, "{direction}"),
+            None => write!(buf, "To {}", self.destination_label()),
+        }
+    }
+
+    fn write_spoken_destination<const N: usize>(&self, buf: &mut String<N>) -> core::fmt::Result {
+        match &self.destination_name {
+            Some(name) if !name.0.is_empty() => write!(buf, "{}", name.0),
+            _ => write!(buf, "{}", self.destination_label()),
+        }
+    }
+
     pub fn write_debug_display<const N: usize>(&self, buf: &mut String<N>) -> core::fmt::Result {
-        if let Some(line) = &self.line {
+        self.write_debug_display_with(buf, &Abbreviations::NONE)
+    }
+
+    /// [`NextTrain::destination_label`], or the destination's entry in `abbreviations` if it has one.
+    pub fn short_destination<'s>(&'s self, abbreviations: &Abbreviations<'s>) -> &'s str {
+        self.destination_station()
+            .and_then(|station| abbreviations.get(station))
+            .unwrap_or_else(|| self.destination_label())
+    }
+
+    /// Same as [`NextTrain::write_debug_display`], with the destination shortened by `abbreviations`.
+    pub fn write_debug_display_with<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        abbreviations: &Abbreviations<'_>,
+    ) -> core::fmt::Result {
+        self.write_row(buf, abbreviations, false)
+    }
+
+    /// Same as [`NextTrain::write_debug_display_with`], with the train's platform after the line
+    /// at stations that have more than one, e.g. `[YL] L1 (6) Mt Vernon Sq - 3m` on L'Enfant Plaza's
+    /// lower level. See [`Station::platform_label`] for the labels. Elsewhere, or for a train
+    /// without a group, it's the same as the usual form.
+    pub fn write_platform_display_with<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        abbreviations: &Abbreviations<'_>,
+    ) -> core::fmt::Result {
+        self.write_row(buf, abbreviations, true)
+    }
+
+    /// One train of [`NextTrainsResponse::marquee`], like `OR Vienna 3m`.
+    fn write_marquee_entry<const N: usize>(&self, buf: &mut String<N>) -> core::fmt::Result {
+        if let Some(line) = self.line_or_inferred() {
+            write!(buf, "{} ", line.code())?;
+        }
+        write!(buf, "{} {}", self.destination_label(), self.eta_display())
+    }
+
+    fn write_row<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        abbreviations: &Abbreviations<'_>,
+        platform: bool,
+    ) -> core::fmt::Result {
+        if let Some(line) = self.line_or_inferred() {
             write!(buf, "[{}] ", line.code())?;
         } else {
             write!(buf, "[  ] ")?;
         }
 
+        let label = self
+            .group
+            .filter(|_| platform)
+            .and_then(|group| self.location_code.platform_label(group.into()));
+        if let Some(label) = label {
+            write!(buf, "{label} ")?;
+        }
+
         if let Some(cars) = &self.cars {
             write!(buf, "({}) ", cars.to_string())?;
         }
 
-        write!(buf, "{} ", self.destination.0)?;
+        write!(buf, "{} ", self.short_destination(abbreviations))?;
+        write!(buf, "- {}", self.eta_display())?;
+
+        Ok(())
+    }
+}
+
+/// Short names for destinations, for displays narrower than WMATA's own `Destination` text.
+///
+/// Looks in any names saved on the device first, see [`Abbreviations::with_saved`], then the
+/// installation's own `overrides`, then [`Abbreviations::BUILT_IN`], and anything in none of
+/// them keeps its usual label. Only trains with a `DestinationCode` we recognize can
+/// be looked up. [`Abbreviations::NONE`] leaves every destination as it is.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug)]
+pub struct Abbreviations<'a> {
+    overrides: &'a [(Station, &'a str)],
+    built_in: bool,
+    #[cfg(feature = "name-overrides")]
+    saved: Option<&'a crate::wmata::NameOverrides>,
+}
+
+#[cfg(feature = "rail")]
+impl<'a> Abbreviations<'a> {
+    /// Terminals and turnbacks whose names take up most of a 21 character row.
+    pub const BUILT_IN: &'static [(Station, &'static str)] = &[
+        (Station::A15, "Shady Gr"),
+        (Station::B08, "Silver Spr"),
+        (Station::D08, "Stadium"),
+        (Station::D13, "New Carr"),
+        (Station::E01, "Mt Vernon"),
+        (Station::G05, "Largo"),
+        (Station::J03, "Franconia"),
+        (Station::N06, "Wiehle"),
+    ];
+    pub const NONE: Abbreviations<'static> = Abbreviations {
+        overrides: &[],
+        built_in: false,
+        #[cfg(feature = "name-overrides")]
+        saved: None,
+    };
+
+    /// The built-in abbreviations, with `overrides` replacing or adding to them, e.g.
+    /// `&[(Station::C15, "Hunt")]`.
+    pub const fn new(overrides: &'a [(Station, &'a str)]) -> Self {
+        Self {
+            overrides,
+            built_in: true,
+            #[cfg(feature = "name-overrides")]
+            saved: None,
+        }
+    }
+
+    /// Names set on the device, looked up before `overrides` and the built-in ones.
+    #[cfg(feature = "name-overrides")]
+    pub const fn with_saved(mut self, saved: &'a crate::wmata::NameOverrides) -> Self {
+        self.saved = Some(saved);
+        self
+    }
 
-        if let Some(min) = &self.min {
-            write!(buf, "- {}", min.to_string())?;
+    /// The short name for `station`, `None` to use the full one.
+    pub fn get(&self, station: Station) -> Option<&'a str> {
+        #[cfg(feature = "name-overrides")]
+        if let Some(name) = self.saved.and_then(|saved| saved.get(station)) {
+            return Some(name);
         }
+        let built_in: &[(Station, &str)] = if self.built_in { Self::BUILT_IN } else { &[] };
+        self.overrides
+            .iter()
+            .chain(built_in)
+            .find(|(s, _)| *s == station)
+            .map(|(_, name)| *name)
+    }
+}
 
-        Ok(())
+#[cfg(feature = "rail")]
+impl Default for Abbreviations<'_> {
+    fn default() -> Self {
+        Self::new(&[])
     }
 }
 
-#[derive(Deserialize)]
+/// Plain one-line form, e.g. `RD Glenmont 3m`, leaving out the line when it can't be inferred either.
+/// See [`NextTrain::write_debug_display`] for the bracketed, aligned form.
+#[cfg(feature = "rail")]
+impl core::fmt::Display for NextTrain {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        if let Some(line) = self.line_or_inferred() {
+            write!(f, "{line} ")?;
+        }
+
+        write!(f, "{} {}", self.destination_label(), self.eta_display())
+    }
+}
+
+#[cfg(feature = "rail")]
+#[derive(Clone, Deserialize)]
 pub struct NextTrainsResponse {
     #[serde(rename = "Trains")]
     pub trains: alloc::vec::Vec<NextTrain>,
+    /// Set by the client when the response arrives. Never in the json, so decoding one directly leaves it `None`.
+    pub(crate) fetched_at: Option<FetchedAt>,
 }
 
-// make_place!(PlaceNextTrainsResponse);
+#[cfg(feature = "rail")]
+impl NextTrainsResponse {
+    /// How far ahead [`NextTrainsResponse::add_first_trains`] adds a scheduled train, in minutes.
+    pub const FIRST_TRAIN_MINUTES: u8 = 60;
+    /// Between trains in [`NextTrainsResponse::marquee`] unless the sign wants its own. The bullet
+    /// isn't ASCII, so a sign with an ASCII-only font needs something else, like `" - "`.
+    pub const DEFAULT_MARQUEE_SEPARATOR: &'static str = " \u{2022} ";
+
+    pub(crate) fn new(trains: alloc::vec::Vec<NextTrain>) -> Self {
+        Self {
+            trains,
+            fetched_at: Some(FetchedAt(Instant::now())),
+        }
+    }
+
+    /// When the client received this response, if it came from the client at all.
+    pub fn fetched_at(&self) -> Option<Instant> {
+        self.fetched_at.map(|at| at.0)
+    }
 
-// impl Deserialize for NextTrainsResponse {
-//     fn begin(out: &mut Option<Self>) -> &mut dyn Visitor {
-//         PlaceNextTrainsResponse::new(out)
-//     }
-// }
+    /// How long ago this response was received, e.g. to show "updated 8s ago" or gray out stale data.
+    pub fn age(&self) -> Option<Duration> {
+        self.fetched_at().map(|at| at.elapsed())
+    }
 
-// struct NextTrainsBuilder<'a> {
-//     out: &'a mut Option<NextTrainsResponse>,
-//     acc: Vec<NextTrain, MAX_TRAINS>,
-//     elem: Option<NextTrain>,
-// }
+    /// The `i`th train in the order WMATA sent them, `None` past the end, for a renderer that
+    /// fills fixed rows without indexing `trains` directly.
+    pub fn get(&self, i: usize) -> Option<&NextTrain> {
+        self.trains.get(i)
+    }
 
-// impl Visitor for PlaceNextTrainsResponse<NextTrainsResponse> {
-//     fn seq(&mut self) -> miniserde::Result<Box<dyn Seq + '_>> {
-//         Ok(Box::new(NextTrainsBuilder {
-//             out: &mut self.out,
-//             acc: Vec::new(),
-//             elem: None,
-//         }))
-//     }
-// }
+    /// The first `n` trains, or all of them if there are fewer, e.g. the top three rows of a board.
+    pub fn first_n(&self, n: usize) -> &[NextTrain] {
+        &self.trains[..n.min(self.trains.len())]
+    }
 
-impl<'a> IntoIterator for &'a NextTrainsResponse {
-    type Item = &'a NextTrain;
-    type IntoIter = core::slice::Iter<'a, NextTrain>;
-    fn into_iter(self) -> Self::IntoIter {
-        self.trains.iter()
+    /// The soonest train on each line, soonest first, e.g. for a display with one row per line.
+    /// Trains without a line and No Passenger trains are left out. A train with no ETA only
+    /// shows up if its line has nothing else.
+    pub fn next_per_line(&self) -> heapless::Vec<&NextTrain, 6> {
+        let mut next: heapless::Vec<&NextTrain, 6> = heapless::Vec::new();
+        for train in &self.trains {
+            let Some(line) = train.line.filter(|line| *line != LineKind::NO) else {
+                continue;
+            };
+
+            match next.iter_mut().find(|t| t.line == Some(line)) {
+                Some(t) if eta_key(train) < eta_key(t) => *t = train,
+                Some(_) => {}
+                // six revenue lines, so this can't be full
+                None => {
+                    let _ = next.push(train);
+                }
+            }
+        }
+
+        next.sort_unstable_by_key(|t| eta_key(t));
+        next
     }
-}
 
-// impl<'a> Seq for NextTrainsBuilder<'a> {
-//     fn element(&mut self) -> miniserde::Result<&mut dyn Visitor> {
-//         if let Some(v) = self.elem.take() {
-//             self.acc.push(v).map_err(|_| miniserde::Error)?;
-//         }
-//         Ok(Deserialize::begin(&mut self.elem))
-//     }
+    /// The soonest `n` trains on each line, so one busy line can't crowd the others off a board.
+    /// Each line's trains are together and soonest first, and the lines come in the order of
+    /// their soonest train, so `n = 1` picks the same trains as [`NextTrainsResponse::next_per_line`].
+    /// Trains without a line and No Passenger trains are left out, and trains with no ETA go last
+    /// on their line.
+    pub fn top_n_per_line(&self, n: usize) -> alloc::vec::Vec<&NextTrain> {
+        let mut shown: alloc::vec::Vec<&NextTrain> = self
+            .trains
+            .iter()
+            .filter(|train| train.line.is_some_and(|line| line != LineKind::NO))
+            .collect();
+        shown.sort_by_key(|train| eta_key(train));
 
-//     fn finish(&mut self) -> miniserde::Result<()> {
-//         if let Some(v) = self.elem.take() {
-//             self.acc.push(v).map_err(|_| miniserde::Error)?;
-//         }
-//         *self.out = Some(NextTrainsResponse(core::mem::take(&mut self.acc)));
-//         Ok(())
-//     }
-// }
+        // (line, trains kept so far), in the order each line's soonest train comes up
+        let mut lines: heapless::Vec<(Option<LineKind>, usize), 6> = heapless::Vec::new();
+        shown.retain(
+            |train| match lines.iter_mut().find(|(l, _)| *l == train.line) {
+                Some((_, kept)) if *kept < n => {
+                    *kept += 1;
+                    true
+                }
+                Some(_) => false,
+                // six revenue lines, so this can't be full
+                None => n > 0 && lines.push((train.line, 1)).is_ok(),
+            },
+        );
 
-#[derive(defmt::Format)]
-pub struct TrainCar(u8);
+        // stable, so each line keeps its trains soonest first
+        shown.sort_by_key(|train| lines.iter().position(|(l, _)| *l == train.line));
+        shown
+    }
 
-impl From<TrainCar> for u8 {
-    fn from(value: TrainCar) -> Self {
-        value.0
+    /// The train due first across every line, e.g. for a one-line marquee. BRD and ARR beat any
+    /// number of minutes, and trains with no ETA only come up if nothing else has one.
+    /// No Passenger trains are left out, `None` if that leaves nothing.
+    pub fn soonest(&self) -> Option<&NextTrain> {
+        self.trains
+            .iter()
+            .filter(|train| train.line != Some(LineKind::NO))
+            .min_by_key(|train| eta_key(train))
     }
-}
 
-impl TrainCar {
-    pub fn to_string(&self) -> String<1> {
-        let mut s = String::<1>::new();
-        write!(s, "{}", self.0).expect("to_string should always succeed");
-        s
+    /// Keep only trains terminating at one of `destinations`, doing nothing if it's empty.
+    /// Trains without a known destination code, like No Passenger trains, are dropped by any filter.
+    pub fn retain_destinations(&mut self, destinations: &[Station]) {
+        if destinations.is_empty() {
+            return;
+        }
+
+        self.trains.retain(|train| {
+            train
+                .destination_station()
+                .is_some_and(|station| destinations.contains(&station))
+        });
     }
-}
 
-make_place!(TrainCarPlace);
+    /// Keep only trains on a line in `mask`, a [`LineKind::bit`] per line, doing nothing if it's `0`.
+    /// The line is inferred where WMATA left it out, see [`NextTrain::line_or_inferred`], and
+    /// trains whose line still isn't known are dropped by any filter.
+    pub fn retain_lines(&mut self, mask: u8) {
+        if mask == 0 {
+            return;
+        }
 
-impl Deserialize for TrainCar {
-    fn begin(out: &mut Option<Self>) -> &mut dyn Visitor {
-        TrainCarPlace::new(out)
+        self.trains.retain(|train| {
+            train
+                .line_or_inferred()
+                .is_some_and(|line| mask & line.bit() != 0)
+        });
     }
-}
 
-impl Visitor for TrainCarPlace<TrainCar> {
-    fn string(&mut self, s: &str) -> miniserde::Result<()> {
-        let value = s.parse::<u8>().map_err(|_| miniserde::Error)?;
-        self.out = Some(TrainCar(value));
+    /// Drop trains due in under `minutes`, too soon to catch from wherever the display is, doing
+    /// nothing if it's `0`. ARR and BRD trains go too unless `keep_arriving`. Trains without an
+    /// ETA, or with an unknown one, stay, since they could be any time.
+    pub fn retain_catchable(&mut self, minutes: u8, keep_arriving: bool) {
+        if minutes == 0 {
+            return;
+        }
+
+        self.trains.retain(|train| match train.min {
+            Some(Eta::Minutes(m) | Eta::Scheduled(m)) => m >= minutes,
+            Some(Eta::Arriving | Eta::Boarding) => keep_arriving,
+            Some(Eta::Unknown) | None => true,
+        });
+    }
+
+    /// Drop trains headed somewhere their line doesn't go, see
+    /// [`NextTrain::is_destination_reachable`], logging each one. A glitch in the feed usually,
+    /// but a train rerouted around a closure can look the same, so nothing calls this by default.
+    pub fn retain_reachable(&mut self) {
+        self.trains.retain(|train| {
+            let reachable = train.is_destination_reachable();
+            if !reachable {
+                defmt::warn!(
+                    "dropping {} train at {} to {=str}, not on its line",
+                    train.line,
+                    train.location_code,
+                    train.destination_label()
+                );
+            }
+            reachable
+        });
+    }
+
+    /// Hide or relabel the trains that aren't taking passengers, see [`NoPassengerPolicy`].
+    /// With [`NoPassengerPolicy::Dim`] they're left alone, for the renderer to check
+    /// [`NextTrain::is_revenue`].
+    pub fn apply_no_passenger(&mut self, policy: NoPassengerPolicy) {
+        match policy {
+            NoPassengerPolicy::Hide => self.trains.retain(NextTrain::is_revenue),
+            NoPassengerPolicy::Dim => {}
+            NoPassengerPolicy::Label => {
+                for train in self.trains.iter_mut().filter(|train| !train.is_revenue()) {
+                    train.destination = StationName::new(NextTrain::NOT_IN_SERVICE);
+                }
+            }
+        }
+    }
+
+    /// Keep only `line` trains on track `group`, soonest first, i.e. what stops at one platform edge.
+    /// Trains with no ETA go last. See [`Group`] for which track is which.
+    pub fn retain_platform(&mut self, line: LineKind, group: u8) {
+        self.trains
+            .retain(|train| train.line == Some(line) && train.group.map(u8::from) == Some(group));
+        self.trains.sort_by_key(eta_key);
+    }
+
+    /// Add `other`'s trains to these, e.g. from the other platform code of a transfer station or
+    /// another chunk of stations, dropping any train both had and sorting the lot soonest first.
+    /// Trains with the same ETA keep their order, these before `other`'s.
+    ///
+    /// Trains have no id, so a duplicate is one matching on station, line, track, destination,
+    /// cars and ETA. The result counts as fetched when the older of the two was, so its
+    /// [`age`](Self::age) never makes stale trains look fresh.
+    pub fn merge(&mut self, other: NextTrainsResponse) {
+        for train in other.trains {
+            if !self.trains.iter().any(|t| same_prediction(t, &train)) {
+                self.trains.push(train);
+            }
+        }
+        self.trains.sort_by_key(eta_key);
+
+        self.fetched_at = match (self.fetched_at, other.fetched_at) {
+            (Some(a), Some(b)) => Some(FetchedAt(a.0.min(b.0))),
+            (a, b) => a.or(b),
+        };
+    }
+
+    /// Add the first trains of the day at `times`' station that are due within
+    /// [`NextTrainsResponse::FIRST_TRAIN_MINUTES`] of `now`, the local time, each with an
+    /// [`Eta::Scheduled`]. For the quiet before opening, when WMATA isn't tracking anything yet
+    /// and the board would otherwise be blank. Sorted soonest first afterwards, like
+    /// [`NextTrainsResponse::merge`].
+    ///
+    /// The schedule only lists each day's first and last trains, so these are the only ones there
+    /// are times for. A first train is left out if one to the same destination is already listed,
+    /// which is most likely it, being tracked.
+    pub fn add_first_trains(&mut self, times: &StationTimes, now: LocalTime) {
+        let now_minute = now.minute_of_day();
+        for first in &times.day(now.weekday).first_trains {
+            let Some(destination) = first.destination.station() else {
+                continue;
+            };
+            let Some(minutes) = first
+                .time
+                .minute_of_day()
+                .checked_sub(now_minute)
+                .filter(|m| *m <= u16::from(Self::FIRST_TRAIN_MINUTES))
+            else {
+                continue;
+            };
+            if self
+                .trains
+                .iter()
+                .any(|train| train.destination_station() == Some(destination))
+            {
+                continue;
+            }
+
+            self.trains.push(NextTrain {
+                cars: None,
+                destination: StationName::new(destination.name()),
+                destination_code: Some(destination.into()),
+                destination_name: None,
+                group: None,
+                line: LineKind::from_station_and_destination(times.code, destination),
+                location_code: times.code,
+                location_name: StationName::new(&times.station_name),
+                min: Some(Eta::Scheduled(minutes as u8)),
+            });
+        }
+        self.trains.sort_by_key(eta_key);
+    }
+
+    /// The soonest `max_rows` trains, soonest first, and how many more didn't fit.
+    /// Trains with the same ETA keep WMATA's order.
+    pub fn rows(&self, max_rows: usize) -> Rows<'_> {
+        let mut shown: alloc::vec::Vec<&NextTrain> = self.trains.iter().collect();
+        shown.sort_by_key(|train| eta_key(train));
+        let hidden = shown.len().saturating_sub(max_rows);
+        shown.truncate(max_rows);
+        Rows { shown, hidden }
+    }
+
+    /// Writes every train on one line for a scrolling sign, soonest first, with `separator`
+    /// between them, e.g. `OR Vienna 3m • SV Ashburn ARR • BL Largo 7m`. A train without a known
+    /// line is just its destination and ETA. Trains with the same ETA keep WMATA's order.
+    ///
+    /// Errors if `buf` fills up, leaving the trains that fit whole, so a sign never scrolls half
+    /// of one.
+    pub fn marquee<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        separator: &str,
+    ) -> core::fmt::Result {
+        for (i, train) in self.rows(usize::MAX).shown.iter().enumerate() {
+            let start = buf.len();
+            let separator = if i == 0 { "" } else { separator };
+            let written = buf
+                .push_str(separator)
+                .map_err(|_| core::fmt::Error)
+                .and_then(|()| train.write_marquee_entry(buf));
+            if written.is_err() {
+                buf.truncate(start);
+                return written;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Writes the soonest `max_rows` trains in the [`NextTrain::write_debug_display`] form, each
+    /// ending in `\n`, then a [`Rows::DEFAULT_OVERFLOW_FORMAT`] line if any didn't fit, e.g. to dump
+    /// a whole board over serial in one go. Errors if `buf` fills up, leaving what fit in it.
+    pub fn write_all<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        max_rows: usize,
+    ) -> core::fmt::Result {
+        let rows = self.rows(max_rows);
+        for train in &rows.shown {
+            train.write_debug_display(buf)?;
+            buf.push('\n').map_err(|_| core::fmt::Error)?;
+        }
+
+        if rows.hidden > 0 {
+            rows.write_overflow(buf, Rows::DEFAULT_OVERFLOW_FORMAT)?;
+            buf.push('\n').map_err(|_| core::fmt::Error)?;
+        }
+
         Ok(())
     }
 }
 
-#[derive(Deserialize)]
-pub struct Line {
-    pub kind: LineKind,
-    pub end_station_code: Station,
-    pub start_station_code: Station,
+/// The trains themselves, so slice methods like `len`, `first` and `iter` work on the response.
+/// Sorting and filtering go through the response's own methods, or `trains` directly.
+#[cfg(feature = "rail")]
+impl core::ops::Deref for NextTrainsResponse {
+    type Target = [NextTrain];
+
+    fn deref(&self) -> &Self::Target {
+        &self.trains
+    }
 }
 
-#[derive(Deserialize, defmt::Format)]
-pub enum LineKind {
-    GN,
-    BL,
-    SV,
-    RD,
-    OR,
-    YL,
-    NO,
+/// What fits on a board with a fixed number of rows, from [`NextTrainsResponse::rows`].
+#[cfg(feature = "rail")]
+pub struct Rows<'a> {
+    pub shown: alloc::vec::Vec<&'a NextTrain>,
+    /// Trains left off, to be summed up on an extra line after `shown`.
+    pub hidden: usize,
 }
 
-impl LineKind {
-    pub fn name(&self) -> &'static str {
-        match self {
-            LineKind::GN => "green",
-            LineKind::BL => "bue",
-            LineKind::SV => "silver",
-            LineKind::RD => "red",
-            LineKind::OR => "orange",
-            LineKind::YL => "yellow",
-            LineKind::NO => "no passengers",
+#[cfg(feature = "rail")]
+impl Rows<'_> {
+    /// Overflow line used unless the board wants its own wording.
+    pub const DEFAULT_OVERFLOW_FORMAT: &'static str = "+{} more";
+
+    /// Writes the overflow line, with the first `{}` in `format` replaced by the number of hidden trains.
+    /// Writes nothing when everything fit.
+    pub fn write_overflow<const N: usize>(
+        &self,
+        buf: &mut String<N>,
+        format: &str,
+    ) -> core::fmt::Result {
+        if self.hidden == 0 {
+            return Ok(());
+        }
+
+        match format.split_once("{}") {
+            Some((before, after)) => write!(buf, "{before}{}{after}", self.hidden),
+            None => buf.push_str(format).map_err(|_| core::fmt::Error),
         }
     }
 
-    pub fn code(&self) -> &'static str {
-        match self {
-            LineKind::GN => "GR",
-            LineKind::BL => "BL",
-            LineKind::SV => "SV",
-            LineKind::RD => "RD",
-            LineKind::OR => "OR",
-            LineKind::YL => "YL",
-            LineKind::NO => "NO",
+    /// Which rows differ from `previous`, e.g. the last rows drawn, for a slow panel to redraw
+    /// just those. Rows are the shown trains, then the overflow line if there is one. When the
+    /// two have a different number of rows, every row past the shorter one has changed too, to
+    /// be drawn or blanked.
+    pub fn changed_from(&self, previous: &Rows<'_>) -> alloc::vec::Vec<usize> {
+        (0..self.len().max(previous.len()))
+            .filter(|i| self.row(*i) != previous.row(*i))
+            .collect()
+    }
+
+    /// Shown trains plus the overflow line, if any.
+    fn len(&self) -> usize {
+        self.shown.len() + usize::from(self.hidden > 0)
+    }
+
+    fn row(&self, i: usize) -> Option<Row<'_>> {
+        match self.shown.get(i) {
+            Some(train) => Some(Row::Train(train)),
+            None if i == self.shown.len() && self.hidden > 0 => Some(Row::Overflow(self.hidden)),
+            None => None,
         }
     }
 }
 
-#[derive(defmt::Format)]
-pub enum Eta {
-    Minutes(u8),
-    Arriving, // ARR
-    Boarding, // BRD
+/// One row of [`Rows`], for [`Rows::changed_from`].
+#[cfg(feature = "rail")]
+#[derive(PartialEq)]
+enum Row<'a> {
+    Train(&'a NextTrain),
+    Overflow(usize),
 }
 
-impl Eta {
-    pub fn to_string(&self) -> String<4> {
-        let mut s: String<4> = String::new();
-        match self {
-            Eta::Minutes(m) => write!(s, "{}m", m).expect("to_string should always succeed"),
-            Eta::Arriving => write!(s, "ARR").expect("to_string should always succeed"),
-            Eta::Boarding => write!(s, "BRD").expect("to_string should always succeed"),
-        };
+/// Predictions for every station on a line, from [`Client::next_trains_for_line`](crate::wmata::Client::next_trains_for_line).
+#[cfg(feature = "rail")]
+pub struct LineTrains {
+    pub line: LineKind,
+    /// One entry per station, in track order, even when nothing is predicted there.
+    pub stations: alloc::vec::Vec<StationTrains>,
+}
 
-        s
-    }
+#[cfg(feature = "rail")]
+pub struct StationTrains {
+    pub station: Station,
+    /// Only trains on the line, soonest first.
+    pub trains: alloc::vec::Vec<NextTrain>,
 }
 
-make_place!(PlaceEta);
+#[cfg(feature = "rail")]
+impl LineTrains {
+    /// Sort `response` into `line`'s stations, dropping trains on other lines.
+    pub fn new(line: LineKind, response: NextTrainsResponse) -> Self {
+        let mut stations: alloc::vec::Vec<StationTrains> = line
+            .stations()
+            .iter()
+            .map(|station| StationTrains {
+                station: *station,
+                trains: alloc::vec::Vec::new(),
+            })
+            .collect();
+
+        for train in response.trains {
+            if train.line != Some(line) {
+                continue;
+            }
+            if let Some(entry) = stations
+                .iter_mut()
+                .find(|s| s.station == train.location_code)
+            {
+                entry.trains.push(train);
+            }
+        }
+
+        for entry in &mut stations {
+            entry.trains.sort_by_key(eta_key);
+        }
 
-impl Deserialize for Eta {
-    fn begin(out: &mut Option<Self>) -> &mut dyn Visitor {
-        PlaceEta::new(out)
+        Self { line, stations }
     }
 }
 
-impl Visitor for PlaceEta<Eta> {
-    fn string(&mut self, s: &str) -> miniserde::Result<()> {
-        let eta = match s {
-            "ARR" => Eta::Arriving,
-            "BRD" => Eta::Boarding,
-            _ => {
-                let m = s.parse::<u8>().map_err(|_| miniserde::Error)?;
-                Eta::Minutes(m)
-            }
+/// One station split in two for a side-by-side display, e.g. a board at a transfer station
+/// showing both levels at once. From [`Client::transfer_view`](crate::wmata::Client::transfer_view).
+#[cfg(feature = "rail")]
+pub struct TransferView {
+    pub left: PlatformTrains,
+    pub right: PlatformTrains,
+}
+
+/// One side of a [`TransferView`].
+#[cfg(feature = "rail")]
+pub struct PlatformTrains {
+    /// The code the trains are predicted at, which is the level at a station with two.
+    pub station: Station,
+    /// The track, or `None` when the side is a whole level with both its tracks.
+    pub group: Option<Group>,
+    /// Soonest first.
+    pub trains: alloc::vec::Vec<NextTrain>,
+}
+
+#[cfg(feature = "rail")]
+impl TransferView {
+    /// Split `response`, predictions for `station` and its [`Station::other_level`] if it has one.
+    ///
+    /// At a station with two levels each side is a level, `station`'s on the left, since that's
+    /// where the lines change. Otherwise each side is a track, group 1 on the left, so a single
+    /// level station still shows both directions. Trains at any other station, or without a group
+    /// at a single level one, are left out.
+    pub fn new(station: Station, response: NextTrainsResponse) -> Self {
+        let (mut left, mut right) = match station.other_level() {
+            Some(other) => (
+                PlatformTrains::new(station, None),
+                PlatformTrains::new(other, None),
+            ),
+            None => (
+                PlatformTrains::new(station, Some(Group(1))),
+                PlatformTrains::new(station, Some(Group(2))),
+            ),
         };
-        self.out = Some(eta);
-        Ok(())
+
+        for train in response.trains {
+            if left.takes(&train) {
+                left.trains.push(train);
+            } else if right.takes(&train) {
+                right.trains.push(train);
+            }
+        }
+        left.trains.sort_by_key(eta_key);
+        right.trains.sort_by_key(eta_key);
+
+        Self { left, right }
     }
 }
 
-#[derive(defmt::Format)]
-pub struct StationName(pub String<32>);
+#[cfg(feature = "rail")]
+impl PlatformTrains {
+    fn new(station: Station, group: Option<Group>) -> Self {
+        Self {
+            station,
+            group,
+            trains: alloc::vec::Vec::new(),
+        }
+    }
 
-make_place!(PlaceStationName);
-impl Deserialize for StationName {
-    fn begin(out: &mut Option<Self>) -> &mut dyn Visitor {
-        PlaceStationName::new(out)
+    fn takes(&self, train: &NextTrain) -> bool {
+        train.location_code == self.station && self.group.is_none_or(|g| train.group == Some(g))
     }
 }
 
-impl Visitor for PlaceStationName<StationName> {
-    fn string(&mut self, s: &str) -> miniserde::Result<()> {
-        let mut buf: String<32> = String::new();
-        buf.push_str(s).map_err(|_| miniserde::Error)?;
-        self.out = Some(StationName(buf));
-        Ok(())
+/// Sort key putting trains without an ETA after every train with one.
+#[cfg(feature = "rail")]
+fn eta_key(train: &NextTrain) -> (bool, Option<Eta>) {
+    (train.min.is_none(), train.min)
+}
+
+/// The trains in `times` to `destination` around `now`'s day, each with whether it's a last
+/// train, and when it's due in minutes from the midnight starting `now`'s day. So yesterday's
+/// last trains that left after midnight are early on today, and today's are past 24 hours.
+#[cfg(feature = "rail")]
+fn scheduled_minutes(
+    times: &StationTimes,
+    now: LocalTime,
+    destination: Station,
+) -> impl Iterator<Item = (bool, i32)> + '_ {
+    let today = times.day(now.weekday);
+    let yesterday = times.day(now.weekday.prev());
+    today
+        .first_trains
+        .iter()
+        .map(|t| (t, false, 0))
+        .chain(
+            today
+                .last_trains
+                .iter()
+                .map(|t| (t, true, today.after_midnight(t))),
+        )
+        .chain(
+            yesterday
+                .last_trains
+                .iter()
+                .filter(|t| yesterday.after_midnight(t) > 0)
+                .map(|t| (t, true, 0)),
+        )
+        .filter(move |(t, _, _)| t.destination.station() == Some(destination))
+        .map(|(t, last, day_offset)| (last, i32::from(t.time.minute_of_day()) + day_offset))
+}
+
+/// Whether `a` and `b` are the same prediction, e.g. from two overlapping responses.
+#[cfg(feature = "rail")]
+fn same_prediction(a: &NextTrain, b: &NextTrain) -> bool {
+    a.location_code == b.location_code
+        && a.line == b.line
+        && a.group == b.group
+        && a.destination_code == b.destination_code
+        && a.destination.0 == b.destination.0
+        && a.cars.map(|c| c.0) == b.cars.map(|c| c.0)
+        && a.min == b.min
+}
+
+/// Fetch time of a response. Only here so it can sit in a derived `Deserialize` struct, it never decodes.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, defmt::Format)]
+pub(crate) struct FetchedAt(Instant);
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for FetchedAt {
+    fn from_json_str(_: &str) -> Option<Self> {
+        None
     }
 }
 
-macro_rules! stations {
-    ($($v:ident),* $(,)?) => {
-        #[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, defmt::Format)]
-        pub enum Station { $( $v ),* }
+#[cfg(feature = "rail")]
+deserialize_from_str!(FetchedAt);
 
-        impl Station {
-            pub fn code(&self) -> &str {
-                match self { $( Station::$v => stringify!($v), )* }
-            }
-        }
-    };
+/// What the api actually sends, since `Trains` is sometimes `null` or missing entirely.
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub(crate) struct MaybeNextTrainsResponse {
+    #[serde(rename = "Trains")]
+    pub trains: Option<alloc::vec::Vec<NextTrain>>,
 }
 
-stations! {
-    A01,
-    A02,
-    A03,
-    A04,
-    A05,
-    A06,
-    A07,
-    A08,
-    A09,
-    A10,
-    A11,
-    A12,
-    A13,
-    A14,
-    A15,
-    B01,
-    B02,
-    B03,
-    B04,
-    B05,
-    B06,
-    B07,
-    B08,
-    B09,
-    B10,
-    B11,
-    B35,
-    C01,
-    C02,
-    C03,
-    C04,
-    C05,
-    C06,
-    C07,
-    C08,
-    C09,
-    C10,
-    C11,
-    C12,
-    C13,
-    C14,
-    C15,
-    D01,
-    D02,
-    D03,
-    D04,
-    D05,
-    D06,
-    D07,
-    D08,
-    D09,
-    D10,
-    D11,
-    D12,
-    D13,
-    E01,
-    E02,
-    E03,
-    E04,
-    E05,
-    E06,
-    E07,
-    E08,
-    E09,
-    E10,
-    F01,
-    F02,
-    F03,
-    F04,
-    F05,
-    F06,
-    F07,
-    F08,
-    F09,
-    F10,
-    F11,
-    G01,
-    G02,
-    G03,
-    G04,
-    G05,
-    J02,
-    J03,
-    K01,
-    K02,
-    K03,
-    K04,
-    K05,
-    K06,
-    K07,
-    K08,
-    N01,
-    N02,
-    N03,
-    N04,
-    N06,
-    N07,
-    N08,
-    N09,
-    N10,
-    N11,
-    N12,
+#[cfg(feature = "rail")]
+impl<'a> IntoIterator for &'a NextTrainsResponse {
+    type Item = &'a NextTrain;
+    type IntoIter = core::slice::Iter<'a, NextTrain>;
+    fn into_iter(self) -> Self::IntoIter {
+        self.trains.iter()
+    }
+}
+
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
+pub struct TrainCar(u8);
+
+#[cfg(feature = "rail")]
+impl From<TrainCar> for u8 {
+    fn from(value: TrainCar) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "rail")]
+impl From<u8> for TrainCar {
+    fn from(value: u8) -> Self {
+        TrainCar(value)
+    }
+}
+
+#[cfg(feature = "rail")]
+impl TrainCar {
+    pub fn to_string(&self) -> String<1> {
+        let mut s = String::<1>::new();
+        write!(s, "{self}").expect("to_string should always succeed");
+        s
+    }
+}
+
+/// The number of cars, e.g. `8`.
+#[cfg(feature = "rail")]
+impl core::fmt::Display for TrainCar {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for TrainCar {
+    fn from_json_str(s: &str) -> Option<Self> {
+        s.parse::<u8>().ok().map(TrainCar)
+    }
+
+    #[cfg(feature = "miniserde")]
+    fn from_json_u64(n: u64) -> Option<Self> {
+        u8::try_from(n).ok().map(TrainCar)
+    }
+}
+
+// an empty or dashed `Car` just means the count isn't known yet
+#[cfg(feature = "rail")]
+deserialize_from_str!(TrainCar, lenient);
+
+/// The track a train is on at its station, `1` or `2`.
+///
+/// Which is which depends on the station, there's no rule like "1 is inbound" that holds
+/// across the system. At K04 (Ballston-MU), for example, group 1 is towards New Carrollton and
+/// 2 towards Vienna and Ashburn. [`Station::group_direction`] has the busiest stations, for
+/// anywhere else check the api's output at the station in question, or its track layout,
+/// before hardcoding a group. Stations with two levels, like Metro Center
+/// (A01 and C01), have a code per level, each with its own groups 1 and 2.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct Group(u8);
+
+#[cfg(feature = "rail")]
+impl From<Group> for u8 {
+    fn from(value: Group) -> Self {
+        value.0
+    }
+}
+
+#[cfg(feature = "rail")]
+impl From<u8> for Group {
+    fn from(value: u8) -> Self {
+        Group(value)
+    }
+}
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for Group {
+    fn from_json_str(s: &str) -> Option<Self> {
+        s.parse::<u8>().ok().map(Group)
+    }
+}
+
+#[cfg(feature = "rail")]
+deserialize_from_str!(Group);
+
+#[derive(Deserialize)]
+pub struct Line {
+    pub kind: LineKind,
+    pub end_station_code: Station,
+    pub start_station_code: Station,
+}
+
+/// The discriminants are how lines are stored as bytes, e.g. in flash, so they must never change.
+/// New lines get the next free number. Decodes from the api's codes, see [`LineKind::from_code`].
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+#[repr(u8)]
+#[non_exhaustive]
+pub enum LineKind {
+    GN = 0,
+    BL = 1,
+    SV = 2,
+    RD = 3,
+    OR = 4,
+    YL = 5,
+    NO = 6,
+}
+
+impl From<LineKind> for u8 {
+    fn from(value: LineKind) -> Self {
+        value as u8
+    }
+}
+
+impl TryFrom<u8> for LineKind {
+    /// The byte that isn't a line.
+    type Error = u8;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(LineKind::GN),
+            1 => Ok(LineKind::BL),
+            2 => Ok(LineKind::SV),
+            3 => Ok(LineKind::RD),
+            4 => Ok(LineKind::OR),
+            5 => Ok(LineKind::YL),
+            6 => Ok(LineKind::NO),
+            _ => Err(value),
+        }
+    }
+}
+
+impl LineKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineKind::GN => "green",
+            LineKind::BL => "blue",
+            LineKind::SV => "silver",
+            LineKind::RD => "red",
+            LineKind::OR => "orange",
+            LineKind::YL => "yellow",
+            LineKind::NO => "no passengers",
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            LineKind::GN => "GR",
+            LineKind::BL => "BL",
+            LineKind::SV => "SV",
+            LineKind::RD => "RD",
+            LineKind::OR => "OR",
+            LineKind::YL => "YL",
+            LineKind::NO => "NO",
+        }
+    }
+
+    /// This line's bit in a set of lines packed into a byte, like [`Config::lines_mask`],
+    /// from the discriminant, so it's as stable as they are. A ninth line would need a wider mask.
+    ///
+    /// [`Config::lines_mask`]: crate::wmata::Config::lines_mask
+    pub fn bit(&self) -> u8 {
+        1 << u8::from(*self)
+    }
+
+    /// A single letter for displays too narrow for [`LineKind::code`], e.g. `O` for orange.
+    /// No Passenger trains get `-`, since `N` would look like a line.
+    pub fn abbrev(&self) -> char {
+        match self {
+            LineKind::GN => 'G',
+            LineKind::BL => 'B',
+            LineKind::SV => 'S',
+            LineKind::RD => 'R',
+            LineKind::OR => 'O',
+            LineKind::YL => 'Y',
+            LineKind::NO => '-',
+        }
+    }
+
+    /// Parse a line code as the api writes it in free-text fields like `LinesAffected`.
+    pub fn from_code(code: &str) -> Option<Self> {
+        match code {
+            "GR" => Some(LineKind::GN),
+            "BL" => Some(LineKind::BL),
+            "SV" => Some(LineKind::SV),
+            "RD" => Some(LineKind::RD),
+            "OR" => Some(LineKind::OR),
+            "YL" => Some(LineKind::YL),
+            "NO" => Some(LineKind::NO),
+            _ => None,
+        }
+    }
+
+    /// Stations served by this line in track order, from one terminal to the other.
+    /// Stations on a shared trunk appear in every line that runs through them.
+    /// Transfer stations use the platform code for this line (e.g. `A01` on Red, `C01` on Blue).
+    pub fn stations(&self) -> &'static [Station] {
+        use Station::*;
+        match self {
+            LineKind::RD => &[
+                A15, A14, A13, A12, A11, A10, A09, A08, A07, A06, A05, A04, A03, A02, A01, B01,
+                B02, B03, B35, B04, B05, B06, B07, B08, B09, B10, B11,
+            ],
+            LineKind::BL => &[
+                J03, J02, C13, C12, C11, C10, C09, C08, C07, C06, C05, C04, C03, C02, C01, D01,
+                D02, D03, D04, D05, D06, D07, D08, G01, G02, G03, G04, G05,
+            ],
+            LineKind::YL => &[
+                C15, C14, C13, C12, C11, C10, C09, C08, C07, F03, F02, F01, E01,
+            ],
+            LineKind::GN => &[
+                F11, F10, F09, F08, F07, F06, F05, F04, F03, F02, F01, E01, E02, E03, E04, E05,
+                E06, E07, E08, E09, E10,
+            ],
+            LineKind::OR => &[
+                K08, K07, K06, K05, K04, K03, K02, K01, C05, C04, C03, C02, C01, D01, D02, D03,
+                D04, D05, D06, D07, D08, D09, D10, D11, D12, D13,
+            ],
+            LineKind::SV => &[
+                N12, N11, N10, N09, N08, N07, N06, N04, N03, N02, N01, K05, K04, K03, K02, K01,
+                C05, C04, C03, C02, C01, D01, D02, D03, D04, D05, D06, D07, D08, D09, D10, D11,
+                D12, D13,
+            ],
+            LineKind::NO => &[],
+        }
+    }
+
+    /// Best-effort guess at the line a train is running on, for when WMATA omits the `Line` field.
+    /// Only returns a line when exactly one line serves both the train's location and its destination.
+    /// Ambiguous cases (e.g. an Orange/Silver train on the shared trunk headed to New Carrollton) return `None`.
+    pub fn from_station_and_destination(location: Station, destination: Station) -> Option<Self> {
+        let mut candidates = location
+            .lines()
+            .iter()
+            .filter(|line| destination.lines().contains(line));
+
+        match (candidates.next(), candidates.next()) {
+            (Some(line), None) => Some(*line),
+            _ => None,
+        }
+    }
+}
+
+/// The api's codes, see [`LineKind::from_code`], and two more spellings: `GN`, the variant's
+/// own name, which older firmware expected for green, and `No`, how predictions write No
+/// Passenger trains.
+impl FromJsonStr for LineKind {
+    fn from_json_str(s: &str) -> Option<Self> {
+        match s {
+            "GN" => Some(LineKind::GN),
+            "No" => Some(LineKind::NO),
+            s => LineKind::from_code(s),
+        }
+    }
+}
+
+deserialize_from_str!(LineKind, lenient);
+
+/// The two-letter api code, e.g. `GR`, which round-trips through [`LineKind::from_code`].
+/// Use [`LineKind::name`] for the spelled out name.
+impl core::fmt::Display for LineKind {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// Ordered by how soon the train is here: BRD, then ARR, then minutes ascending, then unknown.
+/// A [`Eta::Scheduled`] goes by its minutes, after a live prediction of the same minutes.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, PartialEq, Eq, defmt::Format)]
+#[non_exhaustive]
+pub enum Eta {
+    Minutes(u8),
+    Arriving, // ARR
+    Boarding, // BRD
+    /// `---` or blank, a scheduled train that isn't being tracked yet.
+    Unknown,
+    /// Minutes until a train is due by the timetable, not by live tracking, shown as e.g. `~5m`.
+    ///
+    /// The prediction feed never sends these, a train it isn't tracking is just `---`, see
+    /// [`Eta::Unknown`]. They only come from schedule data, see
+    /// [`NextTrainsResponse::add_first_trains`]. Shown as `~99m` past 99 minutes.
+    Scheduled(u8),
+}
+
+#[cfg(feature = "rail")]
+impl Eta {
+    /// Reads an eta as the api writes it: `ARR`, `BRD`, whole minutes, or `---`/blank for
+    /// [`Eta::Unknown`]. Anything else, like a negative or out of range number, is an error.
+    #[allow(
+        clippy::result_unit_err,
+        reason = "there's only one way for it to fail"
+    )]
+    pub fn parse(s: &str) -> Result<Eta, ()> {
+        match s {
+            "ARR" => Ok(Eta::Arriving),
+            "BRD" => Ok(Eta::Boarding),
+            "---" | "" => Ok(Eta::Unknown),
+            _ => s.parse::<u8>().map(Eta::Minutes).map_err(|_| ()),
+        }
+    }
+
+    /// Whether this is a timetable estimate rather than a live prediction, see [`Eta::Scheduled`].
+    pub fn is_scheduled(&self) -> bool {
+        matches!(self, Eta::Scheduled(_))
+    }
+
+    pub fn to_string(&self) -> String<4> {
+        let mut s: String<4> = String::new();
+        write!(s, "{self}").expect("to_string should always succeed");
+        s
+    }
+
+    /// Like `to_string`, but with anything under `arr_below` minutes shown as `ARR` too, for
+    /// signs that call a train arriving a minute or so out, e.g. `2` for `ARR` up to 1 minute.
+    /// `0` is the same as `to_string`.
+    pub fn display_with_window(&self, arr_below: u8) -> String<4> {
+        match self {
+            Eta::Minutes(m) if *m < arr_below => Eta::Arriving.to_string(),
+            _ => self.to_string(),
+        }
+    }
+
+    /// Steps away from boarding, one for ARR plus one per minute, and unknown after everything. Backs the ordering.
+    pub(crate) fn rank(&self) -> u16 {
+        match self {
+            Eta::Boarding => 0,
+            Eta::Arriving => 1,
+            Eta::Minutes(m) | Eta::Scheduled(m) => u16::from(*m) + 2,
+            Eta::Unknown => u16::MAX,
+        }
+    }
+
+    /// How soon this train is due, with anything up to [`EtaClass::SOON_MINUTES`] counting as soon.
+    pub fn class(&self) -> EtaClass {
+        self.class_within(EtaClass::SOON_MINUTES)
+    }
+
+    /// Same as [`Eta::class`], counting anything up to `soon_minutes` as soon.
+    pub fn class_within(&self, soon_minutes: u8) -> EtaClass {
+        match self {
+            Eta::Arriving | Eta::Boarding => EtaClass::Now,
+            Eta::Minutes(m) | Eta::Scheduled(m) if *m <= soon_minutes => EtaClass::Soon,
+            Eta::Minutes(_) | Eta::Scheduled(_) | Eta::Unknown => EtaClass::Later,
+        }
+    }
+
+    /// How urgently to show this ETA, e.g. in a renderer's colors: [`EtaClass::Now`] for ARR and
+    /// BRD, [`EtaClass::Soon`] up to [`EtaClass::URGENT_MINUTES`], and [`EtaClass::Later`] for the
+    /// rest, unknown ETAs included. Use [`Eta::class_within`] for a different cut-off.
+    pub fn urgency(&self) -> EtaClass {
+        self.class_within(EtaClass::URGENT_MINUTES)
+    }
+
+    /// Like `to_string`, but without the "m" suffix, for fixed three-character displays.
+    pub fn to_compact(&self) -> String<3> {
+        let mut s: String<3> = String::new();
+        match self {
+            Eta::Minutes(m) => write!(s, "{}", m).expect("to_compact should always succeed"),
+            Eta::Arriving => write!(s, "ARR").expect("to_compact should always succeed"),
+            Eta::Boarding => write!(s, "BRD").expect("to_compact should always succeed"),
+            Eta::Unknown => write!(s, "{}", NextTrain::ETA_PLACEHOLDER)
+                .expect("to_compact should always succeed"),
+            Eta::Scheduled(m) => {
+                write!(s, "~{}", m.min(&99)).expect("to_compact should always succeed")
+            }
+        };
+
+        s
+    }
+}
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for Eta {
+    fn from_json_str(s: &str) -> Option<Self> {
+        Eta::parse(s).ok()
+    }
+
+    #[cfg(feature = "miniserde")]
+    fn from_json_u64(n: u64) -> Option<Self> {
+        u8::try_from(n).ok().map(Eta::Minutes)
+    }
+}
+
+#[cfg(feature = "rail")]
+impl Ord for Eta {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.rank()
+            .cmp(&other.rank())
+            .then(self.is_scheduled().cmp(&other.is_scheduled()))
+    }
+}
+
+#[cfg(feature = "rail")]
+impl PartialOrd for Eta {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+/// Coarse timing of an [`Eta`], for announcements and alerts. See [`Eta::class`].
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+#[non_exhaustive]
+pub enum EtaClass {
+    /// ARR or BRD, the train is at or pulling into the platform.
+    Now,
+    /// A few minutes out, e.g. time to head for the platform.
+    Soon,
+    Later,
+}
+
+#[cfg(feature = "rail")]
+impl EtaClass {
+    /// Default cut-off between [`EtaClass::Soon`] and [`EtaClass::Later`], in minutes.
+    pub const SOON_MINUTES: u8 = 3;
+    /// Default cut-off for [`Eta::urgency`], tighter than [`EtaClass::SOON_MINUTES`] so only a
+    /// train it's time to run for stands out on a display.
+    pub const URGENT_MINUTES: u8 = 2;
+}
+
+/// Same as [`Eta::to_string`], e.g. `3m`, `ARR`, `BRD`, `~3m` when scheduled or `--` when unknown.
+#[cfg(feature = "rail")]
+impl core::fmt::Display for Eta {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Eta::Minutes(m) => write!(f, "{m}m"),
+            Eta::Arriving => f.write_str("ARR"),
+            Eta::Boarding => f.write_str("BRD"),
+            Eta::Unknown => f.write_str(NextTrain::ETA_PLACEHOLDER),
+            Eta::Scheduled(m) => write!(f, "~{}m", m.min(&99)),
+        }
+    }
+}
+
+#[cfg(feature = "rail")]
+deserialize_from_str!(Eta);
+
+#[cfg(feature = "rail")]
+#[derive(Clone, PartialEq, Eq, defmt::Format)]
+pub struct StationName(pub String<32>);
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for StationName {
+    /// Never `None`, a name too long to keep is cut short rather than losing the whole response.
+    fn from_json_str(s: &str) -> Option<Self> {
+        let name = StationName::new(s);
+        if name.0.len() < s.len() {
+            defmt::warn!("station name cut short: {=str}", s);
+        }
+        Some(name)
+    }
+}
+
+#[cfg(feature = "rail")]
+deserialize_from_str!(StationName);
+
+#[cfg(feature = "rail")]
+impl StationName {
+    /// Cuts `name` short at a character boundary if it's longer than fits.
+    pub fn new(name: &str) -> Self {
+        let mut end = name.len().min(32);
+        while !name.is_char_boundary(end) {
+            end -= 1;
+        }
+        StationName(String::try_from(&name[..end]).expect("truncated name should fit"))
+    }
+}
+
+#[cfg(feature = "rail")]
+impl core::fmt::Display for StationName {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+/// A station code as sent by the api, which isn't always one we know about.
+/// Unknown codes decode to `station() == None` rather than failing the whole response.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct StationCode(Option<Station>);
+
+#[cfg(feature = "rail")]
+impl From<Station> for StationCode {
+    fn from(value: Station) -> Self {
+        StationCode(Some(value))
+    }
+}
+
+#[cfg(feature = "rail")]
+impl StationCode {
+    pub fn station(&self) -> Option<Station> {
+        self.0
+    }
+}
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for StationCode {
+    fn from_json_str(s: &str) -> Option<Self> {
+        Some(StationCode(Station::from_code(s)))
+    }
+}
+
+#[cfg(feature = "rail")]
+deserialize_from_str!(StationCode);
+
+/// Scheduled service at a station for each day of the week, from `jStationTimes`.
+/// Unlike predictions these are always there, so they can fill in when predictions dry up late at night.
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub struct StationTimes {
+    #[serde(rename = "Code")]
+    pub code: Station,
+    #[serde(rename = "StationName")]
+    pub station_name: alloc::string::String,
+    #[serde(rename = "Monday")]
+    pub monday: DayTimes,
+    #[serde(rename = "Tuesday")]
+    pub tuesday: DayTimes,
+    #[serde(rename = "Wednesday")]
+    pub wednesday: DayTimes,
+    #[serde(rename = "Thursday")]
+    pub thursday: DayTimes,
+    #[serde(rename = "Friday")]
+    pub friday: DayTimes,
+    #[serde(rename = "Saturday")]
+    pub saturday: DayTimes,
+    #[serde(rename = "Sunday")]
+    pub sunday: DayTimes,
+}
+
+#[cfg(feature = "rail")]
+impl StationTimes {
+    /// Keys of every station in a `jStationTimes` response, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &[
+        "Code",
+        "StationName",
+        "Monday",
+        "Tuesday",
+        "Wednesday",
+        "Thursday",
+        "Friday",
+        "Saturday",
+        "Sunday",
+    ];
+
+    /// There's no wall clock on the device, so which day it is has to come from the caller.
+    pub fn day(&self, day: Weekday) -> &DayTimes {
+        match day {
+            Weekday::Monday => &self.monday,
+            Weekday::Tuesday => &self.tuesday,
+            Weekday::Wednesday => &self.wednesday,
+            Weekday::Thursday => &self.thursday,
+            Weekday::Friday => &self.friday,
+            Weekday::Saturday => &self.saturday,
+            Weekday::Sunday => &self.sunday,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub enum Weekday {
+    Monday,
+    Tuesday,
+    Wednesday,
+    Thursday,
+    Friday,
+    Saturday,
+    Sunday,
+}
+
+impl Weekday {
+    const ALL: [Weekday; 7] = [
+        Weekday::Monday,
+        Weekday::Tuesday,
+        Weekday::Wednesday,
+        Weekday::Thursday,
+        Weekday::Friday,
+        Weekday::Saturday,
+        Weekday::Sunday,
+    ];
+
+    /// The day `days` after this one, wrapping round the week.
+    pub fn plus(self, days: u32) -> Weekday {
+        Self::ALL[(self as usize + days as usize % 7) % 7]
+    }
+
+    pub fn next(self) -> Weekday {
+        self.plus(1)
+    }
+
+    pub fn prev(self) -> Weekday {
+        self.plus(6)
+    }
+
+    pub fn is_weekend(self) -> bool {
+        matches!(self, Weekday::Saturday | Weekday::Sunday)
+    }
+}
+
+/// One day's schedule at a station. The first and last trains are listed once per destination,
+/// so a station on two lines has up to four of each.
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub struct DayTimes {
+    #[serde(rename = "OpeningTime")]
+    pub opening_time: ScheduleTime,
+    #[serde(rename = "FirstTrains")]
+    pub first_trains: alloc::vec::Vec<ScheduledTrain>,
+    #[serde(rename = "LastTrains")]
+    pub last_trains: alloc::vec::Vec<ScheduledTrain>,
+}
+
+#[cfg(feature = "rail")]
+impl DayTimes {
+    /// First trains running on `line`, one per direction.
+    pub fn first_trains_on(&self, line: LineKind) -> impl Iterator<Item = &ScheduledTrain> {
+        self.first_trains.iter().filter(move |t| t.runs_on(line))
+    }
+
+    /// Last trains running on `line`, one per direction, e.g. for "last train at 23:47".
+    pub fn last_trains_on(&self, line: LineKind) -> impl Iterator<Item = &ScheduledTrain> {
+        self.last_trains.iter().filter(move |t| t.runs_on(line))
+    }
+
+    /// Minutes to add to `last`'s time to count it from this day's midnight, a day's worth if it
+    /// leaves after midnight, which going by the clock is before the station opens.
+    fn after_midnight(&self, last: &ScheduledTrain) -> i32 {
+        if last.time.minute_of_day() < self.opening_time.minute_of_day() {
+            24 * 60
+        } else {
+            0
+        }
+    }
+}
+
+#[cfg(feature = "rail")]
+#[derive(Clone, Deserialize, defmt::Format)]
+pub struct ScheduledTrain {
+    #[serde(rename = "Time")]
+    pub time: ScheduleTime,
+    #[serde(rename = "DestinationStation")]
+    pub destination: StationCode,
+}
+
+#[cfg(feature = "rail")]
+impl ScheduledTrain {
+    /// Whether the train's destination is on `line`. Trains headed down a shared stretch, e.g. to
+    /// Downtown Largo, count for every line that goes there, since riders on any of them can take it.
+    pub fn runs_on(&self, line: LineKind) -> bool {
+        self.destination
+            .station()
+            .is_some_and(|station| station.lines().contains(&line))
+    }
+}
+
+/// A scheduled time as sent by the api, `HH:MM` on a 24 hour clock.
+/// Last trains after midnight are listed under the day they left on, e.g. `00:22` on Friday is early Saturday.
+#[cfg(feature = "rail")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+pub struct ScheduleTime {
+    pub hour: u8,
+    pub minute: u8,
+}
+
+#[cfg(feature = "rail")]
+impl FromJsonStr for ScheduleTime {
+    fn from_json_str(s: &str) -> Option<Self> {
+        let (hour, minute) = s.split_once(':')?;
+        let hour: u8 = hour.parse().ok()?;
+        let minute: u8 = minute.parse().ok()?;
+        (hour < 24 && minute < 60).then_some(ScheduleTime { hour, minute })
+    }
+}
+
+#[cfg(feature = "rail")]
+deserialize_from_str!(ScheduleTime);
+
+#[cfg(feature = "rail")]
+impl ScheduleTime {
+    pub fn minute_of_day(&self) -> u16 {
+        u16::from(self.hour) * 60 + u16::from(self.minute)
+    }
+}
+
+#[cfg(feature = "rail")]
+impl core::fmt::Display for ScheduleTime {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        write!(f, "{:02}:{:02}", self.hour, self.minute)
+    }
+}
+
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub(crate) struct StationTimesResponse {
+    #[serde(rename = "StationTimes")]
+    pub stations: alloc::vec::Vec<StationTimes>,
+}
+
+/// A station as `jStations` lists it. The api sends a lot more, like its address, location and
+/// lines, which isn't kept.
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub struct StationInfo {
+    #[serde(rename = "Code")]
+    pub code: Station,
+    /// WMATA's current name for the station, in full, unlike the sign-sized [`StationName`].
+    #[serde(rename = "Name")]
+    pub name: alloc::string::String,
+}
+
+#[cfg(feature = "rail")]
+impl StationInfo {
+    /// Keys of every station in a `jStations` response, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &["Code", "Name"];
+}
+
+#[cfg(feature = "rail")]
+#[derive(Deserialize)]
+pub struct StationListResponse {
+    #[serde(rename = "Stations")]
+    pub stations: alloc::vec::Vec<StationInfo>,
+}
+
+/// Station names as WMATA has them now, for stations renamed since this firmware was built.
+///
+/// Starts out empty, so [`StationNames::name`] is just [`Station::name`]. After
+/// [`StationNames::update`] with a station list it has the live name for every station whose
+/// name changed, and only those, so it stays small. Offline, or before the first list comes in,
+/// the built-in names are used.
+#[cfg(feature = "rail")]
+#[derive(Clone, Debug, Default)]
+pub struct StationNames {
+    renamed: alloc::vec::Vec<(Station, alloc::string::String)>,
+}
+
+#[cfg(feature = "rail")]
+impl StationNames {
+    pub const fn new() -> Self {
+        Self {
+            renamed: alloc::vec::Vec::new(),
+        }
+    }
+
+    /// Take the names in `list`, e.g. from [`Client::station_list`]. Stations missing from it,
+    /// like those on other lines when it was fetched for one line, keep what they had.
+    ///
+    /// [`Client::station_list`]: crate::wmata::Client::station_list
+    pub fn update(&mut self, list: &StationListResponse) {
+        for info in &list.stations {
+            if info.code == Station::Unknown {
+                continue;
+            }
+
+            self.renamed.retain(|(station, _)| *station != info.code);
+            if info.name != info.code.name() {
+                self.renamed.push((info.code, info.name.clone()));
+            }
+        }
+    }
+
+    /// The live name for `station` if it was renamed, otherwise [`Station::name`].
+    pub fn name(&self, station: Station) -> &str {
+        self.renamed
+            .iter()
+            .find(|(renamed, _)| *renamed == station)
+            .map_or_else(|| station.name(), |(_, name)| name.as_str())
+    }
+}
+
+#[cfg(feature = "incidents")]
+#[derive(Deserialize)]
+pub struct RailIncident {
+    #[serde(rename = "IncidentID")]
+    pub id: alloc::string::String,
+    #[serde(rename = "IncidentType")]
+    pub kind: alloc::string::String,
+    #[serde(rename = "Description")]
+    pub description: alloc::string::String,
+    /// Semicolon separated line codes, like `"RD; GR;"`. Use `lines()` to iterate them.
+    #[serde(rename = "LinesAffected")]
+    pub lines_affected: alloc::string::String,
+    #[serde(rename = "DateUpdated")]
+    pub date_updated: alloc::string::String,
+}
+
+#[cfg(feature = "incidents")]
+impl RailIncident {
+    /// Keys of every incident, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &[
+        "IncidentID",
+        "IncidentType",
+        "Description",
+        "LinesAffected",
+        "DateUpdated",
+    ];
+
+    pub fn lines(&self) -> impl Iterator<Item = LineKind> + '_ {
+        self.lines_affected
+            .split(';')
+            .filter_map(|code| LineKind::from_code(code.trim()))
+    }
+
+    pub fn affects(&self, line: LineKind) -> bool {
+        self.lines().any(|l| l == line)
+    }
+
+    /// How bad this incident is for the lines it affects. The feed has no severity of its own,
+    /// just a `"Delay"` or `"Alert"` type and free text, so this goes by the wording: a
+    /// suspension or no service is [`LineStatus::Suspended`], major or significant delays or a
+    /// shuttle bus are [`LineStatus::MajorDelays`], and anything else is
+    /// [`LineStatus::MinorDelays`].
+    pub fn status(&self) -> LineStatus {
+        let says = |words: &[&str]| {
+            words
+                .iter()
+                .any(|word| contains_ignore_case(&self.description, word))
+        };
+
+        if says(&["suspend", "no service", "no train service"]) {
+            LineStatus::Suspended
+        } else if says(&["major", "significant", "shuttle"]) {
+            LineStatus::MajorDelays
+        } else {
+            LineStatus::MinorDelays
+        }
+    }
+
+    /// Whether this is planned work, like weekend single tracking or track maintenance, rather
+    /// than something that went wrong. The feed mixes both into the same list with nothing but
+    /// the wording to tell them apart. Anything that says it's unplanned or unscheduled isn't,
+    /// even single tracking, which is also how trains get round a breakdown.
+    pub fn is_planned_work(&self) -> bool {
+        let says = |words: &[&str]| {
+            words
+                .iter()
+                .any(|word| starts_word_ignore_case(&self.description, word))
+        };
+        if says(&["unplanned", "un-planned", "unscheduled", "un-scheduled"]) {
+            return false;
+        }
+        says(&[
+            "single track",
+            "single-track",
+            "track work",
+            "trackwork",
+            "maintenance",
+            "scheduled",
+            "planned",
+        ])
+    }
+}
+
+/// How a line is running, worst last, for a header like "Red: Good Service". See
+/// [`RailIncidentsResponse::line_statuses`].
+#[cfg(feature = "incidents")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, defmt::Format)]
+pub enum LineStatus {
+    GoodService,
+    MinorDelays,
+    MajorDelays,
+    Suspended,
+}
+
+#[cfg(feature = "incidents")]
+impl LineStatus {
+    /// Title case, as a header would show it, e.g. `"Good Service"`.
+    pub fn label(&self) -> &'static str {
+        match self {
+            LineStatus::GoodService => "Good Service",
+            LineStatus::MinorDelays => "Minor Delays",
+            LineStatus::MajorDelays => "Major Delays",
+            LineStatus::Suspended => "Suspended",
+        }
+    }
+}
+
+#[cfg(feature = "incidents")]
+#[derive(Deserialize)]
+pub struct RailIncidentsResponse {
+    #[serde(rename = "Incidents")]
+    pub incidents: alloc::vec::Vec<RailIncident>,
+}
+
+#[cfg(feature = "incidents")]
+impl RailIncidentsResponse {
+    /// Every passenger line, in the order WMATA's map lists them, with the worst
+    /// [`RailIncident::status`] among the incidents affecting it, or
+    /// [`LineStatus::GoodService`] if there are none.
+    pub fn line_statuses(&self) -> heapless::Vec<(LineKind, LineStatus), 6> {
+        use LineKind::*;
+        [RD, OR, SV, BL, YL, GN]
+            .into_iter()
+            .map(|line| {
+                let status = self
+                    .incidents
+                    .iter()
+                    .filter(|incident| incident.affects(line))
+                    .map(RailIncident::status)
+                    .max()
+                    .unwrap_or(LineStatus::GoodService);
+                (line, status)
+            })
+            .collect()
+    }
+
+    /// The description of the first planned work notice, see [`RailIncident::is_planned_work`],
+    /// on a line serving `station`, to show as a banner above its predictions. Single tracking
+    /// leaves long gaps that the countdowns alone don't explain.
+    pub fn maintenance_notice(&self, station: Station) -> Option<&str> {
+        self.incidents
+            .iter()
+            .filter(|incident| incident.is_planned_work())
+            .find(|incident| station.lines().iter().any(|l| incident.affects(*l)))
+            .map(|incident| incident.description.as_str())
+    }
+}
+
+#[cfg(feature = "incidents")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, defmt::Format)]
+#[non_exhaustive]
+pub enum UnitType {
+    #[serde(rename = "ELEVATOR")]
+    Elevator,
+    #[serde(rename = "ESCALATOR")]
+    Escalator,
+}
+
+#[cfg(feature = "incidents")]
+#[derive(Deserialize)]
+pub struct UnitOutage {
+    #[serde(rename = "StationCode")]
+    pub station_code: Station,
+    #[serde(rename = "StationName")]
+    pub station_name: alloc::string::String,
+    #[serde(rename = "UnitName")]
+    pub unit_name: alloc::string::String,
+    #[serde(rename = "UnitType")]
+    pub unit_type: UnitType,
+    #[serde(rename = "LocationDescription")]
+    pub location_description: alloc::string::String,
+    #[serde(rename = "SymptomDescription")]
+    pub symptom_description: Option<alloc::string::String>,
+    #[serde(rename = "EstimatedReturnToService")]
+    pub estimated_return: Option<alloc::string::String>,
+}
+
+#[cfg(feature = "incidents")]
+impl UnitOutage {
+    /// Keys of every outage, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &[
+        "StationCode",
+        "StationName",
+        "UnitName",
+        "UnitType",
+        "LocationDescription",
+        "SymptomDescription",
+        "EstimatedReturnToService",
+    ];
+}
+
+#[cfg(feature = "incidents")]
+#[derive(Deserialize)]
+pub struct UnitOutagesResponse {
+    #[serde(rename = "ElevatorIncidents")]
+    pub outages: alloc::vec::Vec<UnitOutage>,
+}
+
+#[cfg(feature = "bus")]
+#[derive(Deserialize)]
+pub struct BusPosition {
+    #[serde(rename = "VehicleID")]
+    pub vehicle_id: alloc::string::String,
+    #[serde(rename = "Lat")]
+    pub lat: f64,
+    #[serde(rename = "Lon")]
+    pub lon: f64,
+    /// Minutes off schedule. Positive is late, negative is early.
+    #[serde(rename = "Deviation")]
+    pub deviation: f64,
+    /// e.g. `"NORTH"`, `"SOUTH"`, `"CLOCKWISE"`.
+    #[serde(rename = "DirectionText")]
+    pub direction_text: alloc::string::String,
+}
+
+#[cfg(feature = "bus")]
+impl BusPosition {
+    /// Keys of every bus, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &["VehicleID", "Lat", "Lon", "Deviation", "DirectionText"];
+}
+
+#[cfg(feature = "bus")]
+#[derive(Deserialize)]
+pub struct BusPositionsResponse {
+    #[serde(rename = "BusPositions")]
+    pub positions: alloc::vec::Vec<BusPosition>,
+}
+
+/// A bus stop from [`Client::bus_stops`](crate::wmata::Client::bus_stops).
+#[cfg(feature = "bus")]
+#[derive(Deserialize)]
+pub struct BusStop {
+    /// e.g. `"1001195"`, which is what next bus predictions are asked for by.
+    /// `"0"` for stops WMATA has no predictions for.
+    #[serde(rename = "StopID")]
+    pub stop_id: alloc::string::String,
+    #[serde(rename = "Name")]
+    pub name: alloc::string::String,
+    #[serde(rename = "Lat")]
+    pub lat: f64,
+    #[serde(rename = "Lon")]
+    pub lon: f64,
+    #[serde(rename = "Routes")]
+    pub routes: RouteIds,
+}
+
+#[cfg(feature = "bus")]
+impl BusStop {
+    /// Keys of every stop, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &["StopID", "Name", "Lat", "Lon", "Routes"];
+}
+
+#[cfg(feature = "bus")]
+#[derive(Deserialize)]
+pub struct BusStopsResponse {
+    #[serde(rename = "Stops")]
+    pub stops: alloc::vec::Vec<BusStop>,
+}
+
+/// Routes serving a stop, e.g. `"70"` or `"10A"`, as WMATA lists them.
+/// Only the first [`RouteIds::MAX`] are kept, and an id longer than 8 bytes is skipped,
+/// so a busy transit center doesn't grow every stop in the response.
+#[cfg(feature = "bus")]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RouteIds(pub heapless::Vec<String<8>, { RouteIds::MAX }>);
+
+#[cfg(feature = "bus")]
+impl RouteIds {
+    pub const MAX: usize = 16;
+
+    /// Keep `id` if there's room and it fits, see [`RouteIds`].
+    pub(crate) fn push(&mut self, id: &str) {
+        if let Ok(id) = String::try_from(id) {
+            let _ = self.0.push(id);
+        }
+    }
+}
+
+#[cfg(feature = "bus")]
+impl core::ops::Deref for RouteIds {
+    type Target = [String<8>];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// One line's route along one track, as the track circuits a train runs over from end to end,
+/// from [`Client::standard_routes`](crate::wmata::Client::standard_routes).
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct StandardRoute {
+    /// As WMATA sends it, e.g. `"RD"`, or `"YLRP"` for rush hour Yellow Line service. Use
+    /// [`StandardRoute::line`] for a [`LineKind`].
+    #[serde(rename = "LineCode")]
+    pub line_code: alloc::string::String,
+    /// `1` or `2`, the two tracks of the line.
+    #[serde(rename = "TrackNum")]
+    pub track: u8,
+    /// In order along the route.
+    #[serde(rename = "TrackCircuits")]
+    pub circuits: alloc::vec::Vec<RouteCircuit>,
+}
+
+#[cfg(feature = "positions")]
+impl StandardRoute {
+    /// Keys of every route, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &["LineCode", "TrackNum", "TrackCircuits"];
+
+    /// The line the route is for, `None` for a code that isn't one of [`LineKind`]'s.
+    pub fn line(&self) -> Option<LineKind> {
+        LineKind::from_code(&self.line_code)
+    }
+}
+
+/// A track circuit on a [`StandardRoute`].
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct RouteCircuit {
+    /// Position along the route, from `0`.
+    #[serde(rename = "SeqNum")]
+    pub seq: u16,
+    #[serde(rename = "CircuitId")]
+    pub circuit_id: u16,
+    /// The station whose platform the circuit runs along, `None` between stations.
+    #[serde(rename = "StationCode")]
+    pub station: Option<Station>,
+}
+
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct StandardRoutesResponse {
+    #[serde(rename = "StandardRoutes")]
+    pub routes: alloc::vec::Vec<StandardRoute>,
+}
+
+/// A track circuit and the ones next to it, from
+/// [`Client::track_circuits`](crate::wmata::Client::track_circuits), the edges of the
+/// network's track graph.
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct TrackCircuit {
+    /// `1` or `2` on a line's main tracks, anything else for pocket tracks, yards and the like.
+    #[serde(rename = "Track")]
+    pub track: u8,
+    #[serde(rename = "CircuitId")]
+    pub circuit_id: u16,
+    /// One entry per side with neighbors, more than one circuit on a side at a switch.
+    #[serde(rename = "Neighbors")]
+    pub neighbors: alloc::vec::Vec<CircuitNeighbors>,
+}
+
+#[cfg(feature = "positions")]
+impl TrackCircuit {
+    /// Keys of every circuit, see the `strict` feature.
+    pub(crate) const FIELDS: &[&str] = &["Track", "CircuitId", "Neighbors"];
+}
+
+/// The circuits next to a [`TrackCircuit`] on one side.
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct CircuitNeighbors {
+    #[serde(rename = "NeighborType")]
+    pub side: NeighborSide,
+    #[serde(rename = "CircuitIds")]
+    pub circuit_ids: alloc::vec::Vec<u16>,
+}
+
+/// Which way along the track a neighbor is. WMATA doesn't tie these to a compass direction,
+/// only to each other: a circuit's right neighbor has it as its left one.
+#[cfg(feature = "positions")]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize, defmt::Format)]
+pub enum NeighborSide {
+    Left,
+    Right,
+}
+
+#[cfg(feature = "positions")]
+#[derive(Deserialize)]
+pub struct TrackCircuitsResponse {
+    #[serde(rename = "TrackCircuits")]
+    pub circuits: alloc::vec::Vec<TrackCircuit>,
+}
+
+/// Everything a single station's display needs to warn riders about.
+/// Each half is fetched separately, so one failing doesn't hide the other.
+#[cfg(feature = "incidents")]
+pub struct StationDisruptions {
+    pub station: Station,
+    /// Rail incidents on any line serving `station`.
+    pub incidents: Result<alloc::vec::Vec<RailIncident>, Error>,
+    /// Elevator and escalator outages reported under `station`'s code.
+    pub outages: Result<alloc::vec::Vec<UnitOutage>, Error>,
+}
+
+/// WMATA station codes are a letter for the original line segment followed by two digits, e.g. `K04`.
+const fn is_station_code(code: &str) -> bool {
+    let b = code.as_bytes();
+    b.len() == 3 && b[0].is_ascii_uppercase() && b[1].is_ascii_digit() && b[2].is_ascii_digit()
+}
+
+/// Station codes to keep names for, set with e.g. `STATIONS=K04,K05` when building. The names are
+/// most of the station tables, so a display that only ever shows a few stations can leave the
+/// rest out of flash. Every other station still decodes and works as usual, it's just
+/// [`Station::name`] that falls back to the code. Unset keeps every name.
+const STATION_SUBSET: Option<&str> = option_env!("STATIONS");
+
+/// Whether [`Station::name`] has the name for `code` in this build, see [`STATION_SUBSET`].
+const fn keeps_name(code: &str) -> bool {
+    match STATION_SUBSET {
+        Some(subset) => list_contains(subset, code),
+        None => true,
+    }
+}
+
+/// Whether `code` is one of the comma separated entries in `list`, ignoring spaces around them.
+const fn list_contains(list: &str, code: &str) -> bool {
+    let list = list.as_bytes();
+    let mut start = 0;
+    while start < list.len() {
+        let (entry, next) = list_entry(list, start);
+        if bytes_eq(list, entry, code.as_bytes()) {
+            return true;
+        }
+        start = next;
+    }
+    false
+}
+
+/// Whether every entry in the comma separated `list` is one of `codes`, so a typo in `STATIONS`
+/// fails the build instead of quietly dropping a name. Empty entries are skipped.
+const fn list_within(list: &str, codes: &[&str]) -> bool {
+    let list = list.as_bytes();
+    let mut start = 0;
+    while start < list.len() {
+        let (entry, next) = list_entry(list, start);
+        if entry.0 < entry.1 {
+            let mut i = 0;
+            while i < codes.len() && !bytes_eq(list, entry, codes[i].as_bytes()) {
+                i += 1;
+            }
+            if i == codes.len() {
+                return false;
+            }
+        }
+        start = next;
+    }
+    true
+}
+
+/// The bounds of the entry in `list` starting at `start`, trimmed of spaces, and where the one
+/// after it starts.
+const fn list_entry(list: &[u8], start: usize) -> ((usize, usize), usize) {
+    let mut end = start;
+    while end < list.len() && list[end] != b',' {
+        end += 1;
+    }
+
+    let (mut from, mut to) = (start, end);
+    while from < to && list[from] == b' ' {
+        from += 1;
+    }
+    while to > from && list[to - 1] == b' ' {
+        to -= 1;
+    }
+    ((from, to), end + 1)
+}
+
+const fn bytes_eq(list: &[u8], (from, to): (usize, usize), code: &[u8]) -> bool {
+    if to - from != code.len() {
+        return false;
+    }
+
+    let mut i = 0;
+    while i < code.len() {
+        if list[from + i] != code[i] {
+            return false;
+        }
+        i += 1;
+    }
+    true
+}
+
+macro_rules! stations {
+    ($($v:ident => $name:literal),* $(,)?) => {
+        /// Declared in code order, `A01` before `A02` before `B01`, which isn't where stations are
+        /// along a line. Use [`Station::line_index`] to put stations in track order.
+        #[derive(Copy, Clone, Debug, PartialEq, Eq, defmt::Format)]
+        #[non_exhaustive]
+        pub enum Station {
+            $( $v, )*
+            /// A code in an api response that isn't one of the above, e.g. a station opened since
+            /// this was built. Decoding falls back to it instead of failing the whole response.
+            /// Every unknown code is the same `Unknown`, so two of them aren't necessarily the same
+            /// station. It's on no line and isn't in [`Station::ALL`], and there's nothing to send
+            /// back to the api for it, so don't request or save it.
+            Unknown,
+        }
+
+        // variant names double as the codes sent to the api, so reject malformed ones at compile time
+        const _: () = {
+            $(
+                assert!(
+                    is_station_code(stringify!($v)),
+                    concat!("malformed station code: ", stringify!($v))
+                );
+            )*
+            if let Some(subset) = STATION_SUBSET {
+                assert!(
+                    list_within(subset, &[ $( stringify!($v) ),* ]),
+                    "STATIONS has a code that isn't a station"
+                );
+            }
+        };
+
+        impl Station {
+            /// Every station, in code order. Doesn't include [`Station::Unknown`].
+            pub const ALL: &[Station] = &[ $( Station::$v ),* ];
+
+            /// The station code, or `"???"` for [`Station::Unknown`].
+            pub fn code(&self) -> &'static str {
+                match self {
+                    $( Station::$v => stringify!($v), )*
+                    Station::Unknown => "???",
+                }
+            }
+
+            /// WMATA's name for the station, e.g. `"Ballston-MU"`, as of this build. Both platform codes
+            /// of a transfer station have the same name. `"Unknown"` for [`Station::Unknown`].
+            /// Just the code, like [`Station::code`], for a station left out of a `STATIONS`
+            /// subset at build time.
+            pub fn name(&self) -> &'static str {
+                match self {
+                    // decided at compile time, so names left out never make it into the binary
+                    $(
+                        Station::$v => if const { keeps_name(stringify!($v)) } {
+                            $name
+                        } else {
+                            stringify!($v)
+                        },
+                    )*
+                    Station::Unknown => "Unknown",
+                }
+            }
+
+            /// Parse a station code like `"K04"`, returning `None` for codes we don't know, never
+            /// [`Station::Unknown`].
+            pub fn from_code(code: &str) -> Option<Self> {
+                match code {
+                    $( stringify!($v) => Some(Station::$v), )*
+                    _ => None,
+                }
+            }
+        }
+    };
+}
+
+stations! {
+    A01 => "Metro Center",
+    A02 => "Farragut North",
+    A03 => "Dupont Circle",
+    A04 => "Woodley Park-Zoo/Adams Morgan",
+    A05 => "Cleveland Park",
+    A06 => "Van Ness-UDC",
+    A07 => "Tenleytown-AU",
+    A08 => "Friendship Heights",
+    A09 => "Bethesda",
+    A10 => "Medical Center",
+    A11 => "Grosvenor-Strathmore",
+    A12 => "North Bethesda",
+    A13 => "Twinbrook",
+    A14 => "Rockville",
+    A15 => "Shady Grove",
+    B01 => "Gallery Pl-Chinatown",
+    B02 => "Judiciary Square",
+    B03 => "Union Station",
+    B04 => "Rhode Island Ave-Brentwood",
+    B05 => "Brookland-CUA",
+    B06 => "Fort Totten",
+    B07 => "Takoma",
+    B08 => "Silver Spring",
+    B09 => "Forest Glen",
+    B10 => "Wheaton",
+    B11 => "Glenmont",
+    B35 => "NoMa-Gallaudet U",
+    C01 => "Metro Center",
+    C02 => "McPherson Square",
+    C03 => "Farragut West",
+    C04 => "Foggy Bottom-GWU",
+    C05 => "Rosslyn",
+    C06 => "Arlington Cemetery",
+    C07 => "Pentagon",
+    C08 => "Pentagon City",
+    C09 => "Crystal City",
+    C10 => "Ronald Reagan Washington National Airport",
+    C11 => "Potomac Yard",
+    C12 => "Braddock Road",
+    C13 => "King St-Old Town",
+    C14 => "Eisenhower Avenue",
+    C15 => "Huntington",
+    D01 => "Federal Triangle",
+    D02 => "Smithsonian",
+    D03 => "L'Enfant Plaza",
+    D04 => "Federal Center SW",
+    D05 => "Capitol South",
+    D06 => "Eastern Market",
+    D07 => "Potomac Ave",
+    D08 => "Stadium-Armory",
+    D09 => "Minnesota Ave",
+    D10 => "Deanwood",
+    D11 => "Cheverly",
+    D12 => "Landover",
+    D13 => "New Carrollton",
+    E01 => "Mt Vernon Sq 7th St-Convention Center",
+    E02 => "Shaw-Howard U",
+    E03 => "U Street/African-Amer Civil War Memorial/Cardozo",
+    E04 => "Columbia Heights",
+    E05 => "Georgia Ave-Petworth",
+    E06 => "Fort Totten",
+    E07 => "West Hyattsville",
+    E08 => "Hyattsville Crossing",
+    E09 => "College Park-U of Md",
+    E10 => "Greenbelt",
+    F01 => "Gallery Pl-Chinatown",
+    F02 => "Archives-Navy Memorial-Penn Quarter",
+    F03 => "L'Enfant Plaza",
+    F04 => "Waterfront",
+    F05 => "Navy Yard-Ballpark",
+    F06 => "Anacostia",
+    F07 => "Congress Heights",
+    F08 => "Southern Avenue",
+    F09 => "Naylor Road",
+    F10 => "Suitland",
+    F11 => "Branch Ave",
+    G01 => "Benning Road",
+    G02 => "Capitol Heights",
+    G03 => "Addison Road-Seat Pleasant",
+    G04 => "Morgan Boulevard",
+    G05 => "Downtown Largo",
+    J02 => "Van Dorn Street",
+    J03 => "Franconia-Springfield",
+    K01 => "Court House",
+    K02 => "Clarendon",
+    K03 => "Virginia Square-GMU",
+    K04 => "Ballston-MU",
+    K05 => "East Falls Church",
+    K06 => "West Falls Church",
+    K07 => "Dunn Loring-Merrifield",
+    K08 => "Vienna/Fairfax-GMU",
+    N01 => "McLean",
+    N02 => "Tysons",
+    N03 => "Greensboro",
+    N04 => "Spring Hill",
+    N06 => "Wiehle-Reston East",
+    N07 => "Reston Town Center",
+    N08 => "Herndon",
+    N09 => "Innovation Center",
+    N10 => "Washington Dulles International Airport",
+    N11 => "Loudoun Gateway",
+    N12 => "Ashburn",
+}
+
+impl FromJsonStr for Station {
+    fn from_json_str(s: &str) -> Option<Self> {
+        Some(Station::from_code(s).unwrap_or(Station::Unknown))
+    }
+}
+
+deserialize_from_str!(Station);
+
+/// The station code, e.g. `K04`, which fits any display. See [`Station::name`] for the name.
+impl core::fmt::Display for Station {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+/// The station code, same as [`Station::code`].
+impl From<Station> for &'static str {
+    fn from(station: Station) -> Self {
+        station.code()
+    }
+}
+
+/// A station code typed in by someone, e.g. while provisioning, so surrounding whitespace and
+/// lowercase are fine: `" k04 "` is [`Station::K04`]. Anything else that isn't a code we know,
+/// including `"???"`, is [`Error::NoSuchStation`]. Use [`Station::from_code`] for codes from the
+/// api, which are exact.
+impl core::str::FromStr for Station {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = s.trim();
+        let mut upper = [0u8; 3];
+        if code.len() != upper.len() {
+            return Err(Error::NoSuchStation);
+        }
+        for (out, b) in upper.iter_mut().zip(code.bytes()) {
+            *out = b.to_ascii_uppercase();
+        }
+
+        core::str::from_utf8(&upper)
+            .ok()
+            .and_then(Station::from_code)
+            .ok_or(Error::NoSuchStation)
+    }
+}
+
+/// Same as [`Station::from_str`](core::str::FromStr::from_str).
+impl TryFrom<&str> for Station {
+    type Error = Error;
+
+    fn try_from(s: &str) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl Station {
+    /// Append the codes of `stations` to `buf` separated by commas, e.g. `A01,C01`, the list the
+    /// predictions endpoint takes. Nothing is written for no stations. A list that doesn't fit
+    /// is an error and leaves `buf` as it was, never with part of the list.
+    pub fn codes_into<const N: usize>(
+        stations: &[Station],
+        buf: &mut String<N>,
+    ) -> core::fmt::Result {
+        let start = buf.len();
+        for (i, station) in stations.iter().enumerate() {
+            let separator = if i == 0 { "" } else { "," };
+            if write!(buf, "{separator}{station}").is_err() {
+                buf.truncate(start);
+                return Err(core::fmt::Error);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// How far along `line` this station is, counting from the terminal [`LineKind::stations`]
+    /// starts at, or `None` if `line` doesn't stop here. Sort by it to draw a line's stations in
+    /// track order, e.g. `stations.sort_by_key(|s| s.line_index(LineKind::RD))`.
+    pub fn line_index(&self, line: LineKind) -> Option<usize> {
+        line.stations().iter().position(|station| station == self)
+    }
+
+    /// Lines that stop at this station under the current service pattern.
+    /// Transfer stations with two platform codes (e.g. `A01`/`C01`) only list the lines for that platform.
+    pub fn lines(&self) -> &'static [LineKind] {
+        use LineKind::*;
+        match self {
+            Station::A01
+            | Station::A02
+            | Station::A03
+            | Station::A04
+            | Station::A05
+            | Station::A06
+            | Station::A07
+            | Station::A08
+            | Station::A09
+            | Station::A10
+            | Station::A11
+            | Station::A12
+            | Station::A13
+            | Station::A14
+            | Station::A15
+            | Station::B01
+            | Station::B02
+            | Station::B03
+            | Station::B04
+            | Station::B05
+            | Station::B06
+            | Station::B07
+            | Station::B08
+            | Station::B09
+            | Station::B10
+            | Station::B11
+            | Station::B35 => &[RD],
+            Station::C01
+            | Station::C02
+            | Station::C03
+            | Station::C04
+            | Station::C05
+            | Station::D01
+            | Station::D02
+            | Station::D03
+            | Station::D04
+            | Station::D05
+            | Station::D06
+            | Station::D07
+            | Station::D08 => &[BL, OR, SV],
+            Station::C06
+            | Station::G01
+            | Station::G02
+            | Station::G03
+            | Station::G04
+            | Station::G05
+            | Station::J02
+            | Station::J03 => &[BL],
+            Station::C07
+            | Station::C08
+            | Station::C09
+            | Station::C10
+            | Station::C11
+            | Station::C12
+            | Station::C13 => &[BL, YL],
+            Station::C14 | Station::C15 => &[YL],
+            Station::D09
+            | Station::D10
+            | Station::D11
+            | Station::D12
+            | Station::D13
+            | Station::K01
+            | Station::K02
+            | Station::K03
+            | Station::K04
+            | Station::K05 => &[OR, SV],
+            Station::K06 | Station::K07 | Station::K08 => &[OR],
+            Station::E01 | Station::F01 | Station::F02 | Station::F03 => &[GN, YL],
+            Station::E02
+            | Station::E03
+            | Station::E04
+            | Station::E05
+            | Station::E06
+            | Station::E07
+            | Station::E08
+            | Station::E09
+            | Station::E10
+            | Station::F04
+            | Station::F05
+            | Station::F06
+            | Station::F07
+            | Station::F08
+            | Station::F09
+            | Station::F10
+            | Station::F11 => &[GN],
+            Station::N01
+            | Station::N02
+            | Station::N03
+            | Station::N04
+            | Station::N06
+            | Station::N07
+            | Station::N08
+            | Station::N09
+            | Station::N10
+            | Station::N11
+            | Station::N12 => &[SV],
+            Station::Unknown => &[],
+        }
+    }
+
+    /// Which way trains on track `group` are headed at this station, e.g. `"To Glenmont"`, for
+    /// labelling a platform edge. `None` for a group other than `1` or `2`, or a station that
+    /// isn't in the table yet, see [`Group`] for why there's no general rule.
+    ///
+    /// Covers the busiest stations: Red between Bethesda (A09) and Silver Spring (B08), the
+    /// Blue/Orange/Silver trunk from Rosslyn (C05) to Stadium-Armory (D08), Ballston (K04), and
+    /// Green/Yellow from Navy Yard (F05) to Columbia Heights (E04). Labels name every line's
+    /// terminal in that direction, so they read the same whichever line a train is on.
+    pub fn group_direction(&self, group: u8) -> Option<&'static str> {
+        let (one, two) = match self {
+            Station::A01
+            | Station::A02
+            | Station::A03
+            | Station::A04
+            | Station::A05
+            | Station::A06
+            | Station::A07
+            | Station::A08
+            | Station::A09
+            | Station::B01
+            | Station::B02
+            | Station::B03
+            | Station::B35
+            | Station::B04
+            | Station::B05
+            | Station::B06
+            | Station::B07
+            | Station::B08 => ("To Glenmont", "To Shady Grove"),
+            Station::C01
+            | Station::C02
+            | Station::C03
+            | Station::C04
+            | Station::C05
+            | Station::D01
+            | Station::D02
+            | Station::D03
+            | Station::D04
+            | Station::D05
+            | Station::D06
+            | Station::D07
+            | Station::D08 => ("To Largo/New Carrollton", "To Vienna/Ashburn/Franconia"),
+            Station::K04 => ("To Largo/New Carrollton", "To Vienna/Ashburn"),
+            Station::F01 | Station::F02 | Station::F03 => {
+                ("To Greenbelt/Mt Vernon Sq", "To Branch Ave/Huntington")
+            }
+            Station::E01 => ("To Greenbelt", "To Branch Ave/Huntington"),
+            Station::E02 | Station::E03 | Station::E04 | Station::F04 | Station::F05 => {
+                ("To Greenbelt", "To Branch Ave")
+            }
+            _ => return None,
+        };
+
+        match group {
+            1 => Some(one),
+            2 => Some(two),
+            _ => None,
+        }
+    }
+
+    /// How riders name the direction of a train terminating here, e.g. `"Toward Largo"`, for
+    /// [`NextTrain::direction_label`]. Covers every terminal and the regular short turns:
+    /// Grosvenor and Twinbrook, Silver Spring, West Falls Church and Wiehle-Reston East.
+    /// `None` anywhere else, including Fort Totten and National Airport, where it depends on the
+    /// line.
+    pub fn direction_name(&self) -> Option<&'static str> {
+        Some(match self {
+            Station::A15 | Station::A13 | Station::A11 => "Toward Shady Grove",
+            Station::B11 | Station::B08 => "Toward Glenmont",
+            Station::K08 | Station::K06 => "Toward Vienna",
+            Station::N12 | Station::N06 => "Toward Ashburn",
+            Station::D13 => "Toward New Carrollton",
+            Station::G05 => "Toward Largo",
+            Station::J03 => "Toward Franconia",
+            Station::C15 => "Toward Huntington",
+            Station::F11 => "Toward Branch Ave",
+            Station::E10 => "Toward Greenbelt",
+            Station::E01 => "Toward Mt Vernon Sq",
+            _ => return None,
+        })
+    }
+
+    /// The stations either side of this one on `line`, in the order of [`LineKind::stations`].
+    /// Terminals have `None` on their outer side. Both are `None` if `line` doesn't stop here,
+    /// including the other platform code of a transfer station (e.g. `C01` on Red).
+    pub fn neighbors(&self, line: LineKind) -> (Option<Station>, Option<Station>) {
+        let stations = line.stations();
+        let Some(i) = stations.iter().position(|s| s == self) else {
+            return (None, None);
+        };
+
+        let prev = i.checked_sub(1).map(|prev| stations[prev]);
+        (prev, stations.get(i + 1).copied())
+    }
+    /// A short label for track `group` at a station with more than one level: `U` for the upper
+    /// level or `L` for the lower, then the group, e.g. `"L1"`. Each level has its own code, so
+    /// it's the code that says which level, e.g. `A01` is Metro Center's upper, Red line level
+    /// and `C01` its lower one. `None` for a group other than `1` or `2`, or a station with just
+    /// the one level, where the group alone, or [`Station::group_direction`], says enough.
+    pub fn platform_label(&self, group: u8) -> Option<&'static str> {
+        let (one, two) = match self {
+            // Metro Center, Gallery Place, L'Enfant Plaza and Fort Totten
+            Station::A01 | Station::B01 | Station::D03 | Station::B06 => ("U1", "U2"),
+            Station::C01 | Station::F01 | Station::F03 | Station::E06 => ("L1", "L2"),
+            _ => return None,
+        };
+
+        match group {
+            1 => Some(one),
+            2 => Some(two),
+            _ => None,
+        }
+    }
+
+    /// The code for the station's other level, e.g. `C01` for `A01`, both Metro Center. `None`
+    /// for a station with just the one level.
+    pub fn other_level(&self) -> Option<Station> {
+        match self {
+            Station::A01 => Some(Station::C01),
+            Station::C01 => Some(Station::A01),
+            Station::B01 => Some(Station::F01),
+            Station::F01 => Some(Station::B01),
+            Station::D03 => Some(Station::F03),
+            Station::F03 => Some(Station::D03),
+            Station::B06 => Some(Station::E06),
+            Station::E06 => Some(Station::B06),
+            _ => None,
+        }
+    }
+
+    /// Stations whose [`Station::name`] contains `query`, ignoring case, e.g. `"vienna"` for
+    /// `K08`, in code order. Both codes of a transfer station match. Up to the first 8, and none
+    /// for a blank query, since it'd match everything.
+    pub fn search(query: &str) -> heapless::Vec<Station, 8> {
+        let query = query.trim();
+        let mut found = heapless::Vec::new();
+        if query.is_empty() {
+            return found;
+        }
+
+        for station in Station::ALL {
+            if contains_ignore_case(station.name(), query) && found.push(*station).is_err() {
+                break;
+            }
+        }
+        found
+    }
+
+    /// The station called `name`, or the closest thing to it, for typing a name rather than a
+    /// code. A name that's exactly a station's, ignoring case, picks it even if it's part of
+    /// others too, so `"pentagon"` isn't mistaken for Pentagon City. Otherwise it has to be
+    /// part of just one station's name, see [`Station::search`]. Both codes of a transfer
+    /// station come back, since predictions need both.
+    ///
+    /// [`Error::NoSuchStation`] if nothing matches, [`Error::AmbiguousStation`] with the
+    /// candidates if more than one station does.
+    pub fn from_name(name: &str) -> Result<heapless::Vec<Station, 2>, Error> {
+        let name = name.trim();
+        let exact = Station::ALL
+            .iter()
+            .copied()
+            .filter(|station| !name.is_empty() && station.name().eq_ignore_ascii_case(name));
+        let candidates: heapless::Vec<Station, 8> = match exact.collect::<heapless::Vec<_, 8>>() {
+            exact if !exact.is_empty() => exact,
+            _ => Station::search(name),
+        };
+
+        let Some(first) = candidates.first() else {
+            return Err(Error::NoSuchStation);
+        };
+        if candidates.iter().any(|s| s.name() != first.name()) {
+            return Err(Error::AmbiguousStation(candidates));
+        }
+
+        Ok(candidates.iter().copied().take(2).collect())
+    }
+}
+
+/// Whether `needle` is in `haystack`, ignoring ASCII case.
+fn contains_ignore_case(haystack: &str, needle: &str) -> bool {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .any(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// Whether `needle` is in `haystack` at the start of a word, ignoring ASCII case, so "planned"
+/// isn't found in "unplanned".
+#[cfg(feature = "incidents")]
+fn starts_word_ignore_case(haystack: &str, needle: &str) -> bool {
+    let bytes = haystack.as_bytes();
+    bytes.windows(needle.len()).enumerate().any(|(i, window)| {
+        window.eq_ignore_ascii_case(needle.as_bytes())
+            && !i
+                .checked_sub(1)
+                .is_some_and(|before| bytes[before].is_ascii_alphanumeric())
+    })
+}
+
+#[cfg(all(test, feature = "rail"))]
+mod tests {
+    use super::*;
+    use crate::wmata::json;
+
+    fn response(json: &str) -> NextTrainsResponse {
+        json::from_str(json).expect("fixture should decode")
+    }
+
+    #[test]
+    fn every_prediction_field_decodes() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        assert_eq!(res.trains.len(), 4);
+
+        let train = &res.trains[0];
+        assert_eq!(train.cars.map(u8::from), Some(8));
+        assert_eq!(train.destination.0, "Ashburn");
+        assert_eq!(
+            train.destination_code.and_then(|code| code.station()),
+            Some(Station::N12)
+        );
+        assert_eq!(
+            train.destination_name.as_ref().map(|name| name.0.as_str()),
+            Some("Ashburn")
+        );
+        assert_eq!(train.group, Some(Group::from(2)));
+        assert_eq!(train.line, Some(LineKind::SV));
+        assert_eq!(train.location_code, Station::K04);
+        assert_eq!(train.location_name.0, "Ballston-MU");
+        assert!(train.min == Some(Eta::Minutes(4)));
+
+        assert!(res.trains[2].min == Some(Eta::Arriving));
+        assert!(res.trains[3].min == Some(Eta::Boarding));
+    }
+
+    #[test]
+    fn compact_eta_drops_the_minutes_suffix() {
+        assert_eq!(Eta::Minutes(3).to_compact(), "3");
+        assert_eq!(Eta::Minutes(12).to_compact(), "12");
+        assert_eq!(Eta::Arriving.to_compact(), "ARR");
+        assert_eq!(Eta::Boarding.to_compact(), "BRD");
+        assert_eq!(Eta::Minutes(12).to_string(), "12m");
+    }
+
+    #[test]
+    fn missing_min_shows_the_placeholder() {
+        let res = response(include_str!("../../test_data/get_prediction_no_min.json"));
+        assert!(res.trains[0].min.is_none());
+        assert!(res.trains[1].min.is_none());
+        assert_eq!(res.trains[0].eta_display(), NextTrain::ETA_PLACEHOLDER);
+        assert_eq!(res.trains[2].eta_display(), "7m");
+
+        let mut row: String<48> = String::new();
+        res.trains[1].write_debug_display(&mut row).unwrap();
+        assert_eq!(row, "[OR] (6) Vienna - --");
+    }
+
+    #[test]
+    fn every_station_code_is_well_formed() {
+        assert!(!Station::ALL.is_empty());
+        for station in Station::ALL {
+            assert!(is_station_code(station.code()), "{}", station.code());
+            assert_eq!(Station::from_code(station.code()), Some(*station));
+        }
+        assert!(!Station::ALL.contains(&Station::Unknown));
+        assert!(!is_station_code("N5"));
+        assert!(!is_station_code("n05"));
+        assert!(!is_station_code("NO5"));
+    }
+
+    #[test]
+    fn display_matches_the_short_forms() {
+        let train =
+            NextTrain::new(Station::K04, LineKind::OR, "Vienna", Eta::Minutes(3)).with_cars(6);
+        assert_eq!(format!("{train}"), "OR Vienna 3m");
+        assert_eq!(format!("{}", Eta::Minutes(12)), "12m");
+        assert_eq!(format!("{}", Eta::Arriving), "ARR");
+        assert_eq!(format!("{}", Eta::Boarding), "BRD");
+        assert_eq!(format!("{}", LineKind::GN), "GR");
+        assert_eq!(format!("{}", TrainCar::from(8)), "8");
+        assert_eq!(
+            format!("{}", StationName::new("Ballston-MU")),
+            "Ballston-MU"
+        );
+        assert_eq!(format!("{}", Station::K04), "K04");
+    }
+
+    #[test]
+    fn eta_class_splits_at_the_cut_off() {
+        assert_eq!(Eta::Arriving.class(), EtaClass::Now);
+        assert_eq!(Eta::Boarding.class(), EtaClass::Now);
+        assert_eq!(Eta::Minutes(0).class(), EtaClass::Soon);
+        assert_eq!(Eta::Minutes(EtaClass::SOON_MINUTES).class(), EtaClass::Soon);
+        assert_eq!(
+            Eta::Minutes(EtaClass::SOON_MINUTES + 1).class(),
+            EtaClass::Later
+        );
+        assert_eq!(
+            Eta::Scheduled(EtaClass::SOON_MINUTES).class(),
+            EtaClass::Soon
+        );
+        assert_eq!(Eta::Unknown.class(), EtaClass::Later);
+
+        assert_eq!(Eta::Minutes(5).class_within(5), EtaClass::Soon);
+        assert_eq!(Eta::Minutes(6).class_within(5), EtaClass::Later);
+        assert_eq!(Eta::Boarding.class_within(0), EtaClass::Now);
+    }
+
+    #[test]
+    fn line_kind_bytes_are_stable() {
+        use LineKind::*;
+        // stored in flash, so these must never change
+        let lines = [GN, BL, SV, RD, OR, YL, NO];
+        for (byte, line) in lines.into_iter().enumerate() {
+            assert_eq!(u8::from(line), byte as u8);
+            assert_eq!(LineKind::try_from(byte as u8), Ok(line));
+        }
+        assert_eq!(LineKind::try_from(7), Err(7));
+        assert_eq!(LineKind::try_from(0xFF), Err(0xFF));
+    }
+
+    #[test]
+    fn next_per_line_keeps_the_soonest_of_each_line() {
+        use LineKind::*;
+        let res = NextTrainsResponse::new(alloc::vec![
+            NextTrain::new(Station::A01, RD, "Glenmont", Eta::Minutes(7)),
+            NextTrain::new(Station::A01, RD, "Shady Grove", Eta::Minutes(2)),
+            NextTrain::new(Station::C01, OR, "Vienna", Eta::Minutes(5)),
+            NextTrain::new(Station::C01, NO, "No Passenger", Eta::Arriving),
+            NextTrain::new(Station::C01, OR, "New Carrollton", Eta::Unknown),
+        ]);
+
+        let next = res.next_per_line();
+        assert_eq!(next.len(), 2);
+        assert_eq!(next[0].line, Some(RD));
+        assert!(next[0].min == Some(Eta::Minutes(2)));
+        assert_eq!(next[1].line, Some(OR));
+        assert!(next[1].min == Some(Eta::Minutes(5)));
+    }
+
+    #[test]
+    fn built_train_fills_what_it_was_given() {
+        let train = NextTrain::new(Station::K04, LineKind::SV, "Ashburn", Eta::Minutes(4))
+            .with_cars(8)
+            .with_destination_code(Station::N12)
+            .with_group(2);
+        assert_eq!(train.cars.map(u8::from), Some(8));
+        assert_eq!(
+            train.destination_code.and_then(|code| code.station()),
+            Some(Station::N12)
+        );
+        assert_eq!(train.group, Some(Group::from(2)));
+        assert_eq!(train.line, Some(LineKind::SV));
+        assert_eq!(train.location_code, Station::K04);
+        assert!(train.destination_name.is_none());
+
+        let mut row: String<48> = String::new();
+        train
+            .write_debug_display_with(&mut row, &Abbreviations::NONE)
+            .unwrap();
+        assert_eq!(row, "[SV] (8) Ashburn - 4m");
+    }
+
+    #[test]
+    fn soonest_prefers_arr_over_any_minutes() {
+        use LineKind::*;
+        let res = NextTrainsResponse::new(alloc::vec![
+            NextTrain::new(Station::A01, RD, "Glenmont", Eta::Minutes(1)),
+            NextTrain::new(Station::A01, NO, "No Passenger", Eta::Boarding),
+            NextTrain::new(Station::A01, RD, "Shady Grove", Eta::Arriving),
+            NextTrain::new(Station::A01, RD, "Grosvenor", Eta::Minutes(0)),
+        ]);
+
+        let soonest = res.soonest().unwrap();
+        assert_eq!(soonest.destination.0, "Shady Grove");
+        assert!(NextTrainsResponse::new(alloc::vec![]).soonest().is_none());
+    }
+
+    #[test]
+    fn cloned_response_keeps_every_train() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        let copy = res.clone();
+        assert_eq!(copy.trains.len(), res.trains.len());
+        assert!(copy.trains.iter().zip(&res.trains).all(|(a, b)| a == b));
+    }
+
+    #[test]
+    fn rows_count_what_didnt_fit() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_two_platforms.json"
+        ));
+        let rows = res.rows(4);
+        assert_eq!(rows.shown.len(), 4);
+        assert_eq!(rows.hidden, 3);
+        assert!(rows.shown[0].min == Some(Eta::Boarding));
+        assert!(rows.shown[3].min == Some(Eta::Minutes(5)));
+
+        let mut line: String<16> = String::new();
+        rows.write_overflow(&mut line, Rows::DEFAULT_OVERFLOW_FORMAT)
+            .unwrap();
+        assert_eq!(line, "+3 more");
+        line.clear();
+        rows.write_overflow(&mut line, "and {} later").unwrap();
+        assert_eq!(line, "and 3 later");
+
+        line.clear();
+        let rows = res.rows(7);
+        assert_eq!(rows.hidden, 0);
+        rows.write_overflow(&mut line, Rows::DEFAULT_OVERFLOW_FORMAT)
+            .unwrap();
+        assert!(line.is_empty());
+    }
+
+    #[test]
+    fn each_line_has_its_letter() {
+        use LineKind::*;
+        let letters: String<7> = [GN, BL, SV, RD, OR, YL, NO]
+            .iter()
+            .map(LineKind::abbrev)
+            .collect();
+        assert_eq!(letters, "GBSROY-");
+    }
+
+    #[test]
+    fn eta_parses_what_the_api_sends() {
+        assert!(Eta::parse("ARR") == Ok(Eta::Arriving));
+        assert!(Eta::parse("BRD") == Ok(Eta::Boarding));
+        assert!(Eta::parse("0") == Ok(Eta::Minutes(0)));
+        assert!(Eta::parse("12") == Ok(Eta::Minutes(12)));
+        assert!(Eta::parse("255") == Ok(Eta::Minutes(255)));
+        assert!(Eta::parse("---") == Ok(Eta::Unknown));
+        assert!(Eta::parse("") == Ok(Eta::Unknown));
+
+        for bad in ["256", "-1", "3m", "arr", " 4", "1.5"] {
+            assert!(Eta::parse(bad).is_err(), "{bad:?} should be rejected");
+        }
+    }
+
+    #[test]
+    fn line_index_sorts_in_track_order() {
+        use Station::*;
+        // code order would put B35 (NoMa) last and A01 first
+        let mut stations = [B11, A01, B35, A15, B01];
+        stations.sort_by_key(|s| s.line_index(LineKind::RD));
+        assert_eq!(stations, [A15, A01, B01, B35, B11]);
+
+        assert_eq!(A15.line_index(LineKind::RD), Some(0));
+        assert_eq!(K04.line_index(LineKind::RD), None);
+    }
+
+    #[test]
+    fn announcements_read_out_the_eta() {
+        let spoken = |line, eta| {
+            let mut buf: String<96> = String::new();
+            NextTrain::new(Station::A01, line, "Glenmont", eta)
+                .announcement(&mut buf)
+                .unwrap();
+            buf
+        };
+        assert_eq!(
+            spoken(LineKind::RD, Eta::Arriving),
+            "Red line train to Glenmont, arriving."
+        );
+        assert_eq!(
+            spoken(LineKind::RD, Eta::Boarding),
+            "Red line train to Glenmont, boarding."
+        );
+        assert_eq!(
+            spoken(LineKind::RD, Eta::Minutes(1)),
+            "Red line train to Glenmont, 1 minute."
+        );
+        assert_eq!(
+            spoken(LineKind::RD, Eta::Minutes(3)),
+            "Red line train to Glenmont, 3 minutes."
+        );
+
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        let mut buf: String<96> = String::new();
+        res.trains[1].announcement(&mut buf).unwrap();
+        assert_eq!(buf, "Orange line train to Vienna/Fairfax-GMU, 10 minutes.");
+    }
+
+    #[test]
+    fn group_directions_match_where_trains_go() {
+        assert_eq!(Station::A01.group_direction(1), Some("To Glenmont"));
+        assert_eq!(Station::A01.group_direction(2), Some("To Shady Grove"));
+        assert_eq!(Station::A01.group_direction(3), None);
+        assert_eq!(Station::A15.group_direction(1), None);
+
+        // every fixture train's terminal is named on its track's label
+        let fixtures = [
+            include_str!("../../test_data/get_prediction.json"),
+            include_str!("../../test_data/get_prediction_two_platforms.json"),
+        ];
+        for fixture in fixtures {
+            for train in response(fixture).trains {
+                let group = u8::from(train.group.unwrap());
+                let label = train.location_code.group_direction(group).unwrap();
+                let toward = train
+                    .destination_station()
+                    .unwrap()
+                    .direction_name()
+                    .unwrap();
+                let terminal = toward.strip_prefix("Toward ").unwrap();
+                assert!(label.contains(terminal), "{label} for {terminal}");
+            }
+        }
+    }
+
+    #[test]
+    fn write_all_writes_a_line_per_row() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        let mut buf: String<256> = String::new();
+        res.write_all(&mut buf, 3).unwrap();
+        assert_eq!(
+            buf,
+            "[SV] (8) N Carrollton - BRD\n\
+             [OR] (8) N Carrollton - ARR\n\
+             [SV] (8) Ashburn - 4m\n\
+             +1 more\n"
+        );
+
+        let mut small: String<16> = String::new();
+        assert!(res.write_all(&mut small, 3).is_err());
+    }
+
+    #[test]
+    fn merge_drops_the_overlap_and_sorts() {
+        use LineKind::*;
+        let shared = NextTrain::new(Station::A01, RD, "Glenmont", Eta::Minutes(4)).with_cars(8);
+        let mut upper = NextTrainsResponse::new(alloc::vec![
+            shared.clone(),
+            NextTrain::new(Station::A01, RD, "Shady Grove", Eta::Minutes(9)),
+        ]);
+        let lower = NextTrainsResponse::new(alloc::vec![
+            NextTrain::new(Station::C01, BL, "Franconia", Eta::Arriving),
+            shared,
+            // same train but a minute later is a different prediction
+            NextTrain::new(Station::A01, RD, "Glenmont", Eta::Minutes(5)).with_cars(8),
+        ]);
+
+        upper.merge(lower);
+        let order: alloc::vec::Vec<_> = upper.trains.iter().map(|t| format!("{t}")).collect();
+        assert_eq!(
+            order,
+            [
+                "BL Franconia ARR",
+                "RD Glenmont 4m",
+                "RD Glenmont 5m",
+                "RD Shady Grove 9m"
+            ]
+        );
+    }
+
+    #[test]
+    fn abbreviations_apply_or_fall_through() {
+        let abbreviations = Abbreviations::new(&[(Station::C15, "Hunt"), (Station::G05, "Lrgo")]);
+        assert_eq!(abbreviations.get(Station::C15), Some("Hunt"));
+        // overrides win over the built-in ones
+        assert_eq!(abbreviations.get(Station::G05), Some("Lrgo"));
+        assert_eq!(abbreviations.get(Station::A15), Some("Shady Gr"));
+        assert_eq!(abbreviations.get(Station::K08), None);
+        assert_eq!(Abbreviations::NONE.get(Station::A15), None);
+
+        let train = NextTrain::new(Station::C01, LineKind::BL, "Largo", Eta::Minutes(6))
+            .with_destination_code(Station::G05);
+        let mut row: String<48> = String::new();
+        train
+            .write_debug_display_with(&mut row, &abbreviations)
+            .unwrap();
+        assert_eq!(row, "[BL] Lrgo - 6m");
+
+        let train = NextTrain::new(Station::C01, LineKind::OR, "Vienna", Eta::Minutes(6))
+            .with_destination_code(Station::K08);
+        row.clear();
+        train
+            .write_debug_display_with(&mut row, &abbreviations)
+            .unwrap();
+        assert_eq!(row, "[OR] Vienna - 6m");
+    }
+
+    #[test]
+    fn unknown_station_code_decodes_as_unknown() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_unknown_station.json"
+        ));
+        assert_eq!(res.trains.len(), 2);
+        assert_eq!(res.trains[0].location_code, Station::Unknown);
+        assert_eq!(res.trains[0].location_code.code(), "???");
+        assert_eq!(res.trains[1].location_code, Station::K04);
+        assert_eq!(Station::from_code("N13"), None);
+    }
+
+    #[test]
+    fn trains_too_soon_to_catch_are_dropped() {
+        let mins = |minutes, keep_arriving| {
+            let mut res = response(include_str!("../../test_data/get_prediction.json"));
+            res.retain_catchable(minutes, keep_arriving);
+            res.trains
+                .iter()
+                .map(|train| train.min.unwrap().to_string())
+                .collect::<alloc::vec::Vec<_>>()
+        };
+
+        assert_eq!(mins(0, false), ["4m", "10m", "ARR", "BRD"]);
+        assert_eq!(mins(4, false), ["4m", "10m"]);
+        assert_eq!(mins(5, false), ["10m"]);
+        assert_eq!(mins(5, true), ["10m", "ARR", "BRD"]);
+    }
+
+    #[test]
+    fn transfer_view_splits_by_level_or_else_by_track() {
+        let mins = |side: &PlatformTrains| {
+            side.trains
+                .iter()
+                .map(|train| train.min.unwrap().to_string())
+                .collect::<alloc::vec::Vec<_>>()
+        };
+
+        let two_levels = response(include_str!(
+            "../../test_data/get_prediction_two_platforms.json"
+        ));
+        let view = TransferView::new(Station::A01, two_levels);
+        assert_eq!((view.left.station, view.left.group), (Station::A01, None));
+        assert_eq!(mins(&view.left), ["BRD", "2m", "12m"]);
+        assert_eq!((view.right.station, view.right.group), (Station::C01, None));
+        assert_eq!(mins(&view.right), ["ARR", "5m", "7m", "15m"]);
+
+        // one level, so one track a side
+        let one_level = response(include_str!("../../test_data/get_prediction.json"));
+        let view = TransferView::new(Station::K04, one_level);
+        assert_eq!(view.left.group, Some(Group(1)));
+        assert_eq!(mins(&view.left), ["BRD", "ARR"]);
+        assert_eq!(view.right.group, Some(Group(2)));
+        assert_eq!(mins(&view.right), ["4m", "10m"]);
+    }
+
+    #[test]
+    fn no_passenger_trains_are_hidden_dimmed_or_labelled() {
+        let destinations = |policy| {
+            let mut res = response(include_str!(
+                "../../test_data/get_prediction_all_lines.json"
+            ));
+            res.apply_no_passenger(policy);
+            res.trains
+                .iter()
+                .map(|train| (train.destination.0.as_str().into(), train.is_revenue()))
+                .collect::<alloc::vec::Vec<(alloc::string::String, bool)>>()
+        };
+        let revenue = destinations(NoPassengerPolicy::Dim);
+        assert_eq!(revenue.len(), 9);
+        assert_eq!(revenue[6], ("ssenger".into(), false));
+
+        let hidden = destinations(NoPassengerPolicy::Hide);
+        assert_eq!(hidden.len(), 8);
+        assert!(hidden.iter().all(|(_, revenue)| *revenue));
+        assert_eq!(NoPassengerPolicy::default(), NoPassengerPolicy::Hide);
+
+        let labelled = destinations(NoPassengerPolicy::Label);
+        assert_eq!(labelled[6], (NextTrain::NOT_IN_SERVICE.into(), false));
+        assert_eq!(labelled[..6], revenue[..6]);
+    }
+
+    #[test]
+    fn station_codes_join_with_commas() {
+        let mut buf: String<16> = String::new();
+        Station::codes_into(&[], &mut buf).unwrap();
+        assert_eq!(buf, "");
+        Station::codes_into(&[Station::K04], &mut buf).unwrap();
+        assert_eq!(buf, "K04");
+        buf.clear();
+        Station::codes_into(&[Station::A01, Station::C01, Station::K04], &mut buf).unwrap();
+        assert_eq!(buf, "A01,C01,K04");
+        assert_eq!(alloc::format!("{}", Station::C01), "C01");
+
+        // five codes take 19 bytes, more than the 15 left after the `/`, so none are added
+        buf.clear();
+        buf.push_str("/").unwrap();
+        let five = [
+            Station::A01,
+            Station::A02,
+            Station::A03,
+            Station::A04,
+            Station::A05,
+        ];
+        assert_eq!(Station::codes_into(&five, &mut buf), Err(core::fmt::Error));
+        assert_eq!(buf, "/");
+        Station::codes_into(&five[..3], &mut buf).unwrap();
+        assert_eq!(buf, "/A01,A02,A03");
+    }
+
+    #[test]
+    fn marquee_joins_every_train_soonest_first() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        let mut buf: String<128> = String::new();
+        res.marquee(&mut buf, " • ").unwrap();
+        assert_eq!(
+            buf,
+            "SV N Carrollton BRD • OR N Carrollton ARR • SV Ashburn 4m • OR Vienna 10m"
+        );
+
+        // only whole trains when it doesn't all fit
+        let mut short: String<48> = String::new();
+        assert!(res.marquee(&mut short, " | ").is_err());
+        assert_eq!(short, "SV N Carrollton BRD | OR N Carrollton ARR");
+    }
+
+    #[test]
+    fn typed_station_codes_ignore_case_and_surrounding_space() {
+        assert_eq!("k04".parse::<Station>().unwrap(), Station::K04);
+        assert_eq!(" K04 ".parse::<Station>().unwrap(), Station::K04);
+        assert_eq!("\tc01\r\n".parse::<Station>().unwrap(), Station::C01);
+        assert_eq!(Station::try_from("a01").unwrap(), Station::A01);
+
+        for bad in ["K99", "k0", "K 04", "K044", "", "ü01"] {
+            assert!(
+                matches!(bad.parse::<Station>(), Err(Error::NoSuchStation)),
+                "{bad:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn every_line_code_decodes_and_a_blank_one_is_none() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_all_lines.json"
+        ));
+        let lines: alloc::vec::Vec<_> = res.trains.iter().map(|train| train.line).collect();
+        assert_eq!(
+            lines,
+            [
+                Some(LineKind::RD),
+                Some(LineKind::BL),
+                Some(LineKind::OR),
+                Some(LineKind::SV),
+                Some(LineKind::YL),
+                Some(LineKind::GN),
+                Some(LineKind::NO),
+                None,
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn arriving_window_covers_the_minutes_under_it() {
+        assert_eq!(Eta::Minutes(1).display_with_window(2), "ARR");
+        assert_eq!(Eta::Minutes(2).display_with_window(2), "2m");
+        assert_eq!(Eta::Minutes(0).display_with_window(0), "0m");
+        assert_eq!(
+            Eta::Minutes(5).display_with_window(0),
+            Eta::Minutes(5).to_string()
+        );
+
+        assert_eq!(Eta::Arriving.display_with_window(2), "ARR");
+        assert_eq!(Eta::Boarding.display_with_window(2), "BRD");
+        assert_eq!(
+            Eta::Unknown.display_with_window(2),
+            Eta::Unknown.to_string()
+        );
+        assert_eq!(Eta::Scheduled(1).display_with_window(2), "~1m");
+    }
+
+    #[test]
+    fn search_matches_part_of_a_name() {
+        assert_eq!(Station::search("vienna").as_slice(), &[Station::K08]);
+        assert_eq!(Station::search(" COURT ").as_slice(), &[Station::K01]);
+        assert_eq!(
+            Station::search("pentagon").as_slice(),
+            &[Station::C07, Station::C08]
+        );
+        assert_eq!(
+            Station::search("metro center").as_slice(),
+            &[Station::A01, Station::C01]
+        );
+        assert_eq!(Station::search("a").len(), 8);
+        assert!(Station::search("nowhere").is_empty());
+        assert!(Station::search("  ").is_empty());
+    }
+
+    #[test]
+    fn top_n_per_line_caps_each_line() {
+        use LineKind::*;
+        let res = NextTrainsResponse::new(alloc::vec![
+            NextTrain::new(Station::K04, OR, "Vienna", Eta::Minutes(9)),
+            NextTrain::new(Station::K04, SV, "Ashburn", Eta::Minutes(4)),
+            NextTrain::new(Station::K04, OR, "New Carrollton", Eta::Arriving),
+            NextTrain::new(Station::K04, OR, "Vienna", Eta::Minutes(3)),
+        ]);
+
+        let top = res.top_n_per_line(2);
+        let etas: alloc::vec::Vec<_> = top.iter().map(|train| (train.line, train.min)).collect();
+        assert!(
+            etas == [
+                (Some(OR), Some(Eta::Arriving)),
+                (Some(OR), Some(Eta::Minutes(3))),
+                (Some(SV), Some(Eta::Minutes(4))),
+            ]
+        );
+
+        let next: alloc::vec::Vec<_> = res.next_per_line().into_iter().collect();
+        assert!(res.top_n_per_line(1) == next);
+        assert!(res.top_n_per_line(0).is_empty());
+    }
+
+    /// Metro Center's schedule from `station_times.json`.
+    fn metro_center_times() -> StationTimes {
+        let res: StationTimesResponse =
+            json::from_str(include_str!("../../test_data/station_times.json")).unwrap();
+        res.stations.into_iter().next().unwrap()
+    }
+
+    fn monday_at(hour: u8, minute: u8) -> LocalTime {
+        LocalTime {
+            weekday: Weekday::Monday,
+            hour,
+            minute,
+        }
+    }
+
+    #[test]
+    fn schedule_deviation_matches_the_nearest_scheduled_train() {
+        let times = metro_center_times();
+        // Monday's last train to Vienna leaves at 23:38
+        let vienna = |eta| {
+            NextTrain::new(Station::C01, LineKind::OR, "Vienna", eta)
+                .with_destination_code(Station::K08)
+        };
+        assert_eq!(
+            vienna(Eta::Minutes(5)).schedule_deviation(&times, monday_at(23, 36)),
+            Some(3)
+        );
+        assert_eq!(
+            vienna(Eta::Arriving).schedule_deviation(&times, monday_at(23, 36)),
+            Some(-2)
+        );
+        // and the first at 05:10
+        assert_eq!(
+            vienna(Eta::Minutes(8)).schedule_deviation(&times, monday_at(5, 0)),
+            Some(-2)
+        );
+
+        // nothing scheduled near midday, and nothing to match without a destination
+        assert_eq!(
+            vienna(Eta::Minutes(5)).schedule_deviation(&times, monday_at(12, 0)),
+            None
+        );
+        let no_destination = NextTrain::new(Station::C01, LineKind::OR, "Vienna", Eta::Minutes(5));
+        assert_eq!(
+            no_destination.schedule_deviation(&times, monday_at(23, 36)),
+            None
+        );
+    }
+
+    #[test]
+    fn last_train_is_the_one_due_around_the_last_scheduled() {
+        let times = metro_center_times();
+        // Monday's last train to Vienna leaves at 23:38
+        let vienna = |eta| {
+            NextTrain::new(Station::C01, LineKind::OR, "Vienna", eta)
+                .with_destination_code(Station::K08)
+        };
+        assert!(vienna(Eta::Minutes(8)).is_last_train(&times, monday_at(23, 30)));
+        assert!(vienna(Eta::Minutes(5)).is_last_train(&times, monday_at(23, 30)));
+        assert!(vienna(Eta::Arriving).is_last_train(&times, monday_at(23, 45)));
+
+        // too early for it, so the one before
+        assert!(!vienna(Eta::Minutes(4)).is_last_train(&times, monday_at(23, 30)));
+        assert!(!vienna(Eta::Minutes(5)).is_last_train(&times, monday_at(23, 20)));
+        // the first train of the day isn't a last one, nor is one without a minute count
+        assert!(!vienna(Eta::Minutes(8)).is_last_train(&times, monday_at(5, 0)));
+        assert!(!vienna(Eta::Unknown).is_last_train(&times, monday_at(23, 36)));
+    }
+
+    #[test]
+    fn platform_display_labels_each_level() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_multi_level.json"
+        ));
+        let rows: alloc::vec::Vec<_> = res
+            .trains
+            .iter()
+            .map(|train| {
+                let mut row: String<48> = String::new();
+                train
+                    .write_platform_display_with(&mut row, &Abbreviations::NONE)
+                    .unwrap();
+                row
+            })
+            .collect();
+        assert_eq!(rows[0], "[YL] L1 (6) Mt Vernon Sq - 3m");
+        assert_eq!(rows[1], "[YL] L2 (6) Huntington - ARR");
+        assert_eq!(rows[2], "[SV] U1 (8) Downtown Largo - 5m");
+        assert_eq!(rows[3], "[OR] U2 (6) Vienna - BRD");
+
+        // the usual form leaves the platform out
+        let mut row: String<48> = String::new();
+        res.trains[0].write_debug_display(&mut row).unwrap();
+        assert_eq!(row, "[YL] (6) Mt Vernon Sq - 3m");
+        assert_eq!(Station::K04.platform_label(1), None);
+        assert_eq!(Station::F03.platform_label(3), None);
+    }
+
+    #[cfg(feature = "incidents")]
+    #[test]
+    fn line_statuses_take_the_worst_incident() {
+        use LineKind::*;
+        let res: RailIncidentsResponse =
+            json::from_str(include_str!("../../test_data/rail_incidents.json")).unwrap();
+        assert_eq!(
+            res.line_statuses().as_slice(),
+            &[
+                (RD, LineStatus::MinorDelays),
+                (OR, LineStatus::MajorDelays),
+                (SV, LineStatus::Suspended),
+                (BL, LineStatus::GoodService),
+                (YL, LineStatus::GoodService),
+                (GN, LineStatus::GoodService),
+            ]
+        );
+    }
+
+    #[test]
+    fn stops_to_destination_follow_the_line() {
+        use LineKind::*;
+        // Orange runs from Vienna, so heading there the destination comes first
+        let west = NextTrain::new(Station::K04, OR, "Vienna", Eta::Minutes(3))
+            .with_destination_code(Station::K08);
+        assert_eq!(
+            west.stops_to_destination(),
+            &[Station::K08, Station::K07, Station::K06, Station::K05]
+        );
+        let east = NextTrain::new(Station::K04, OR, "New Carrollton", Eta::Minutes(3))
+            .with_destination_code(Station::D13);
+        let stops = east.stops_to_destination();
+        assert_eq!(stops.first(), Some(&Station::K03));
+        assert_eq!(stops.last(), Some(&Station::D13));
+        assert!(stops.contains(&Station::C01));
+
+        let no_destination = NextTrain::new(Station::K04, OR, "Vienna", Eta::Minutes(3));
+        assert!(no_destination.stops_to_destination().is_empty());
+        let off_the_line = NextTrain::new(Station::K04, OR, "Glenmont", Eta::Minutes(3))
+            .with_destination_code(Station::B11);
+        assert!(off_the_line.stops_to_destination().is_empty());
+    }
+
+    #[test]
+    fn live_names_override_the_built_in_ones() {
+        let list: StationListResponse =
+            json::from_str(include_str!("../../test_data/station_list_renamed.json")).unwrap();
+        let mut names = StationNames::new();
+        assert_eq!(
+            names.name(Station::C10),
+            "Ronald Reagan Washington National Airport"
+        );
+
+        names.update(&list);
+        assert_eq!(names.name(Station::C10), "Reagan National Airport");
+        assert_eq!(names.name(Station::K04), "Ballston-MU");
+        // stations the list left out keep the built-in name
+        assert_eq!(names.name(Station::A01), Station::A01.name());
+    }
+
+    #[cfg(feature = "incidents")]
+    #[test]
+    fn maintenance_notice_is_only_planned_work_on_the_station_lines() {
+        let res: RailIncidentsResponse = json::from_str(include_str!(
+            "../../test_data/rail_incidents_single_tracking.json"
+        ))
+        .unwrap();
+        assert!(!res.incidents[0].is_planned_work());
+        assert!(res.incidents[1].is_planned_work());
+
+        // Metro Center's upper level is on the Red line
+        let notice = res.maintenance_notice(Station::A01).unwrap();
+        assert!(notice.starts_with("Red Line: Trains single tracking"));
+        // the Orange line's delay isn't planned work, so it's no notice
+        assert_eq!(res.maintenance_notice(Station::K04), None);
+    }
+
+    #[cfg(feature = "incidents")]
+    #[test]
+    fn unplanned_and_unscheduled_work_is_not_planned() {
+        let incident = |description: &str| RailIncident {
+            id: "1".into(),
+            kind: "Delay".into(),
+            description: description.into(),
+            lines_affected: "RD;".into(),
+            date_updated: "2025-03-14T08:41:29".into(),
+        };
+
+        for description in [
+            "Red Line: Unplanned single tracking due to a disabled train.",
+            "Red Line: Delays due to unscheduled maintenance at Farragut North.",
+            "Red Line: Un-planned track work between Glenmont and Silver Spring.",
+            "Red Line: Delays after an unscheduled stop at Dupont Circle.",
+        ] {
+            assert!(!incident(description).is_planned_work(), "{description}");
+        }
+        assert!(incident("Red Line: Scheduled single tracking this weekend.").is_planned_work());
+        assert!(incident("Planned track work, trains every 20 minutes.").is_planned_work());
+    }
+
+    #[test]
+    fn get_and_first_n_stop_at_the_last_train() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        assert!(res.get(0) == Some(&res.trains[0]));
+        assert!(res.get(3) == Some(&res.trains[3]));
+        assert!(res.get(4).is_none());
+        assert!(res.first_n(3) == &res.trains[..3]);
+        assert_eq!(res.first_n(10).len(), 4);
+        assert!(res.first_n(0).is_empty());
+
+        let empty = NextTrainsResponse::new(alloc::vec::Vec::new());
+        assert!(empty.get(0).is_none());
+        assert!(empty.first_n(3).is_empty());
+    }
+
+    #[test]
+    fn only_the_rows_that_differ_are_redrawn() {
+        let json = include_str!("../../test_data/get_prediction.json");
+        let before = response(json);
+        // the 10 minute Vienna train, last of the four once sorted, gets two more cars
+        let after = response(&json.replace(r#""Car":"6""#, r#""Car":"8""#));
+        assert_eq!(after.rows(4).changed_from(&before.rows(4)), [3]);
+        assert!(before.rows(4).changed_from(&before.rows(4)).is_empty());
+
+        // two trains and "+2 more" against all four: the overflow line becomes a train and a
+        // fourth row appears, and back again the fourth one is blanked
+        assert_eq!(before.rows(4).changed_from(&before.rows(2)), [2, 3]);
+        assert_eq!(before.rows(2).changed_from(&before.rows(4)), [2, 3]);
+        let empty = NextTrainsResponse::new(alloc::vec::Vec::new());
+        assert_eq!(empty.rows(4).changed_from(&before.rows(2)), [0, 1, 2]);
+    }
+
+    #[test]
+    fn trains_headed_off_their_line_are_dropped() {
+        let mut res = response(include_str!(
+            "../../test_data/get_prediction_unreachable.json"
+        ));
+        let reachable: alloc::vec::Vec<_> = res
+            .trains
+            .iter()
+            .map(NextTrain::is_destination_reachable)
+            .collect();
+        // a Red Line train to Vienna and a Silver Line one to Shady Grove can't get there, the
+        // Blue Line one stops downstairs, so Metro Center is still on its line
+        assert_eq!(reachable, [true, false, true, false, true]);
+
+        res.retain_reachable();
+        let left: alloc::vec::Vec<_> = res
+            .trains
+            .iter()
+            .map(NextTrain::destination_station)
+            .collect();
+        assert_eq!(
+            left,
+            [Some(Station::B11), Some(Station::J03), Some(Station::A15)]
+        );
+    }
+
+    #[test]
+    fn urgency_bands() {
+        assert_eq!(Eta::Arriving.urgency(), EtaClass::Now);
+        assert_eq!(Eta::Boarding.urgency(), EtaClass::Now);
+        assert_eq!(Eta::Minutes(1).urgency(), EtaClass::Soon);
+        assert_eq!(
+            Eta::Minutes(EtaClass::URGENT_MINUTES).urgency(),
+            EtaClass::Soon
+        );
+        // soon enough for `class`, not to stand out on a display
+        assert_eq!(
+            Eta::Minutes(EtaClass::SOON_MINUTES).urgency(),
+            EtaClass::Later
+        );
+        assert_eq!(Eta::Minutes(EtaClass::SOON_MINUTES).class(), EtaClass::Soon);
+        assert_eq!(Eta::Scheduled(2).urgency(), EtaClass::Soon);
+        assert_eq!(Eta::Scheduled(12).urgency(), EtaClass::Later);
+        assert_eq!(Eta::Unknown.urgency(), EtaClass::Later);
+    }
+
+    #[test]
+    fn direction_labels_name_the_terminal_or_fall_back_to_the_destination() {
+        let labels = |res: &NextTrainsResponse| -> alloc::vec::Vec<String<32>> {
+            res.trains
+                .iter()
+                .map(|train| {
+                    let mut label = String::new();
+                    train.direction_label(&mut label).unwrap();
+                    label
+                })
+                .collect()
+        };
+
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        assert_eq!(
+            labels(&res),
+            [
+                "Toward Ashburn",
+                "Toward Vienna",
+                "Toward New Carrollton",
+                "Toward New Carrollton"
+            ]
+        );
+
+        // no code, no destination either, or one we don't know
+        let res = response(include_str!(
+            "../../test_data/get_prediction_destination_codes.json"
+        ));
+        assert_eq!(
+            labels(&res),
+            [
+                "Toward Ashburn",
+                "To N Carrollton",
+                "To New Carrollton",
+                "To Silver Spr"
+            ]
+        );
+
+        // a short turn goes the same way as the terminal, a train out of service just says so
+        let json = include_str!("../../test_data/get_prediction.json");
+        let res = response(&json.replacen(r#""N12""#, r#""N06""#, 1));
+        assert_eq!(labels(&res)[0], "Toward Ashburn");
+        let res = response(&json.replacen(r#""Line":"SV""#, r#""Line":"No""#, 1));
+        assert_eq!(labels(&res)[0], "Ashburn");
+    }
+
+    #[cfg(feature = "positions")]
+    #[test]
+    fn standard_routes_decode_in_circuit_order() {
+        let res: StandardRoutesResponse =
+            json::from_str(include_str!("../../test_data/standard_routes.json")).unwrap();
+        assert_eq!(res.routes.len(), 2);
+
+        let green = &res.routes[0];
+        assert_eq!(green.line(), Some(LineKind::GN));
+        assert_eq!(green.track, 1);
+        let seqs: alloc::vec::Vec<_> = green.circuits.iter().map(|c| c.seq).collect();
+        assert_eq!(seqs, [0, 1, 2, 3]);
+        let ids: alloc::vec::Vec<_> = green.circuits.iter().map(|c| c.circuit_id).collect();
+        assert_eq!(ids, [2956, 2957, 2958, 2959]);
+        let stations: alloc::vec::Vec<_> = green.circuits.iter().map(|c| c.station).collect();
+        assert_eq!(
+            stations,
+            [None, Some(Station::F11), Some(Station::F11), None]
+        );
+
+        // rush hour Yellow isn't one of `LineKind`'s
+        let rush_yellow = &res.routes[1];
+        assert_eq!(rush_yellow.line_code, "YLRP");
+        assert_eq!(rush_yellow.line(), None);
+        assert_eq!(rush_yellow.track, 2);
+        assert_eq!(rush_yellow.circuits[0].station, Some(Station::C15));
+    }
+
+    #[cfg(feature = "positions")]
+    #[test]
+    fn track_circuits_decode_with_their_neighbors() {
+        let res: TrackCircuitsResponse =
+            json::from_str(include_str!("../../test_data/track_circuits.json")).unwrap();
+        let ids: alloc::vec::Vec<_> = res.circuits.iter().map(|c| c.circuit_id).collect();
+        assert_eq!(ids, [1, 2, 1402]);
+
+        // a switch, two circuits on its right
+        let switch = &res.circuits[1];
+        assert_eq!(switch.track, 1);
+        let sides: alloc::vec::Vec<_> = switch.neighbors.iter().map(|n| n.side).collect();
+        assert_eq!(sides, [NeighborSide::Left, NeighborSide::Right]);
+        assert_eq!(switch.neighbors[0].circuit_ids, [1]);
+        assert_eq!(switch.neighbors[1].circuit_ids, [3, 1402]);
+
+        // the end of a pocket track
+        let pocket = &res.circuits[2];
+        assert_eq!(pocket.track, 0);
+        assert!(pocket.neighbors.is_empty());
+    }
+
+    #[test]
+    fn a_missing_line_is_none_and_inferred_where_only_one_fits() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_missing_line.json"
+        ));
+        let lines: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.line).collect();
+        assert_eq!(lines, [Some(LineKind::RD), None, None, None]);
+
+        let inferred: alloc::vec::Vec<_> =
+            res.trains.iter().map(NextTrain::line_or_inferred).collect();
+        assert_eq!(
+            inferred,
+            [
+                // as reported
+                Some(LineKind::RD),
+                // only Blue runs from Rosslyn to Franconia-Springfield
+                Some(LineKind::BL),
+                // Orange and Silver both run from there to New Carrollton
+                None,
+                // no destination code to go by
+                None,
+            ]
+        );
+    }
+
+    #[test]
+    fn an_unrecognised_car_count_is_none_without_failing_the_response() {
+        let res = response(include_str!(
+            "../../test_data/get_prediction_unknown_cars.json"
+        ));
+        // `""`, `"-"` and `null`, then a real count
+        let cars: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.cars.map(u8::from)).collect();
+        assert_eq!(cars, [None, None, None, Some(8)]);
+        // the rest of each train still came through
+        let mins: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.min).collect();
+        assert!(mins == [3, 5, 7, 12].map(|m| Some(Eta::Minutes(m))));
+    }
+
+    #[test]
+    fn untracked_etas_decode_as_unknown_and_sort_last() {
+        let mut res = response(include_str!(
+            "../../test_data/get_prediction_unknown_min.json"
+        ));
+        // `"---"` and `""` alike
+        let mins: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.min).collect();
+        assert!(
+            mins == [
+                Some(Eta::Minutes(3)),
+                Some(Eta::Unknown),
+                Some(Eta::Unknown),
+                Some(Eta::Boarding),
+            ]
+        );
+        assert_eq!(Eta::Unknown.to_string(), "--");
+
+        // after every live and scheduled minute count
+        assert!(Eta::Unknown > Eta::Minutes(u8::MAX));
+        assert!(Eta::Unknown > Eta::Scheduled(u8::MAX));
+        assert!(Eta::Boarding < Eta::Minutes(0));
+
+        res.trains.sort_by_key(eta_key);
+        let order: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.destination_label()).collect();
+        assert_eq!(order[..2], ["Largo", "Ashburn"]);
+        assert!(res.trains[2..].iter().all(|t| t.min == Some(Eta::Unknown)));
+    }
+
+    /// Only `miniserde` offers a bare number to a field it asked for a string, see
+    /// [`FromJsonStr::from_json_u64`](crate::wmata::json::FromJsonStr::from_json_u64).
+    #[cfg(feature = "miniserde")]
+    #[test]
+    fn numeric_car_and_min_decode_like_strings() {
+        let res = response(include_str!("../../test_data/get_prediction_numeric.json"));
+        let cars: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.cars.map(u8::from)).collect();
+        assert_eq!(cars, [Some(8), Some(6), Some(8), Some(8)]);
+        let mins: alloc::vec::Vec<_> = res.trains.iter().map(|t| t.min).collect();
+        assert!(
+            mins == [
+                Some(Eta::Minutes(4)),
+                Some(Eta::Minutes(10)),
+                Some(Eta::Arriving),
+                Some(Eta::Minutes(0)),
+            ]
+        );
+    }
+
+    /// `serde-json-core` only ever asks for a string, so a number where one's expected fails the
+    /// response rather than being read as one.
+    #[cfg(feature = "serde")]
+    #[test]
+    fn numeric_min_fails_the_response_under_serde() {
+        let res: Result<NextTrainsResponse, _> =
+            json::from_str(include_str!("../../test_data/get_prediction_numeric.json"));
+        assert!(res.is_err());
+    }
+
+    /// Built with the same code under either json backend, so both have to decode the fixture to
+    /// exactly these trains. Run with `--no-default-features --features serde,...` for `serde`.
+    #[test]
+    fn both_backends_decode_the_fixture_alike() {
+        let train = |dest: &str, code, name: &str, group, line, cars, min| NextTrain {
+            destination_name: Some(StationName::new(name)),
+            location_name: StationName::new("Ballston-MU"),
+            ..NextTrain::new(Station::K04, line, dest, min)
+                .with_cars(cars)
+                .with_destination_code(code)
+                .with_group(group)
+        };
+        let expected = NextTrainsResponse::new(alloc::vec![
+            train(
+                "Ashburn",
+                Station::N12,
+                "Ashburn",
+                2,
+                LineKind::SV,
+                8,
+                Eta::Minutes(4)
+            ),
+            train(
+                "Vienna",
+                Station::K08,
+                "Vienna/Fairfax-GMU",
+                2,
+                LineKind::OR,
+                6,
+                Eta::Minutes(10),
+            ),
+            train(
+                "N Carrollton",
+                Station::D13,
+                "New Carrollton",
+                1,
+                LineKind::OR,
+                8,
+                Eta::Arriving,
+            ),
+            train(
+                "N Carrollton",
+                Station::D13,
+                "New Carrollton",
+                1,
+                LineKind::SV,
+                8,
+                Eta::Boarding,
+            ),
+        ]);
+
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        assert!(res.trains == expected.trains);
+    }
+
+    #[test]
+    fn a_shared_trunk_station_infers_the_line_from_its_terminal() {
+        // Rosslyn, on Blue, Orange and Silver
+        let infer = |destination| LineKind::from_station_and_destination(Station::C05, destination);
+        assert_eq!(infer(Station::J03), Some(LineKind::BL));
+        assert_eq!(infer(Station::G05), Some(LineKind::BL));
+        assert_eq!(infer(Station::K08), Some(LineKind::OR));
+        assert_eq!(infer(Station::N12), Some(LineKind::SV));
+
+        // Orange and Silver both run on to New Carrollton
+        assert_eq!(infer(Station::D13), None);
+        // and no line from Rosslyn reaches Shady Grove
+        assert_eq!(infer(Station::A15), None);
+    }
+
+    #[test]
+    fn neighbors_are_the_stations_either_side_on_the_line() {
+        use Station::*;
+        // Ballston, mid-line
+        assert_eq!(K04.neighbors(LineKind::OR), (Some(K05), Some(K03)));
+        // Shady Grove and Glenmont, the ends of Red
+        assert_eq!(A15.neighbors(LineKind::RD), (None, Some(A14)));
+        assert_eq!(B11.neighbors(LineKind::RD), (Some(B10), None));
+
+        // Rosslyn, where Blue comes in from the south and Orange and Silver from the west
+        assert_eq!(C05.neighbors(LineKind::BL), (Some(C06), Some(C04)));
+        assert_eq!(C05.neighbors(LineKind::OR), (Some(K01), Some(C04)));
+        assert_eq!(C05.neighbors(LineKind::SV), (Some(K01), Some(C04)));
+        // a line that doesn't stop there, or the other platform code of a transfer station
+        assert_eq!(C05.neighbors(LineKind::RD), (None, None));
+        assert_eq!(A01.neighbors(LineKind::BL), (None, None));
+    }
+
+    #[test]
+    fn retain_destinations_keeps_only_the_listed_ones() {
+        use LineKind::*;
+        let mixed = || {
+            NextTrainsResponse::new(alloc::vec![
+                NextTrain::new(Station::K04, SV, "Ashburn", Eta::Minutes(4))
+                    .with_destination_code(Station::N12),
+                NextTrain::new(Station::K04, OR, "Vienna", Eta::Minutes(6))
+                    .with_destination_code(Station::K08),
+                NextTrain::new(Station::K04, OR, "N Carrollton", Eta::Minutes(9))
+                    .with_destination_code(Station::D13),
+                // no destination code to match on
+                NextTrain::new(Station::K04, NO, "No Passenger", Eta::Minutes(12)),
+            ])
+        };
+
+        let mut res = mixed();
+        res.retain_destinations(&[Station::D13, Station::N12]);
+        let kept: alloc::vec::Vec<_> = res.iter().filter_map(|t| t.destination_station()).collect();
+        assert_eq!(kept, [Station::N12, Station::D13]);
+
+        let mut res = mixed();
+        res.retain_destinations(&[]);
+        assert!(res.trains == mixed().trains);
+    }
+
+    #[test]
+    fn each_line_runs_between_its_terminals() {
+        use Station::*;
+        for (line, first, last) in [
+            (LineKind::RD, A15, B11),
+            (LineKind::BL, J03, G05),
+            (LineKind::YL, C15, E01),
+            (LineKind::GN, F11, E10),
+            (LineKind::OR, K08, D13),
+            (LineKind::SV, N12, D13),
+        ] {
+            let stations = line.stations();
+            assert_eq!(
+                (stations.first(), stations.last()),
+                (Some(&first), Some(&last)),
+                "{line}"
+            );
+        }
+        assert!(LineKind::NO.stations().is_empty());
+    }
+
+    #[test]
+    fn the_response_derefs_to_its_trains() {
+        let res = response(include_str!("../../test_data/get_prediction.json"));
+        assert_eq!(res.len(), 4);
+        assert!(!res.is_empty());
+        assert_eq!(res.first().map(|t| t.destination_label()), Some("Ashburn"));
+        assert!(res.iter().eq(res.trains.iter()));
+        assert!(res[3].min == Some(Eta::Boarding));
+
+        let empty = NextTrainsResponse::new(alloc::vec::Vec::new());
+        assert!(empty.is_empty());
+        assert_eq!(empty.first().map(|t| t.destination_label()), None);
+    }
 }
@@ -1,19 +1,422 @@
 use core::fmt::Write;
 use heapless::String;
 
-use crate::wmata::{API, types::Station};
+#[cfg(feature = "rail")]
+use crate::wmata::types::LineKind;
+#[cfg(any(feature = "rail", feature = "incidents"))]
+use crate::wmata::types::Station;
 
-/// We can't use `format!()` so we're stuck with this
-pub(super) fn build_next_trains_url(
+// of the endpoints wrapped here only `jBusPositions` (`RouteID`), `ElevatorIncidents`
+// (`StationCode`) and `jStations` (`LineCode`) filter server-side. predictions and rail incidents take no filter at all,
+// so those are filtered after decoding
+
+/// Takes no parameters, every current incident comes back.
+#[cfg(feature = "incidents")]
+pub(super) const RAIL_INCIDENTS_PATH: &str = "/Incidents.svc/json/Incidents";
+
+/// The route of every line over each track, see [`StandardRoutesResponse`]. Takes no parameters.
+///
+/// [`StandardRoutesResponse`]: crate::wmata::types::StandardRoutesResponse
+#[cfg(feature = "positions")]
+pub(super) const STANDARD_ROUTES_PATH: &str = "/TrainPositions/StandardRoutes?contentType=json";
+
+/// Every track circuit and its neighbors. Takes no parameters.
+#[cfg(feature = "positions")]
+pub(super) const TRACK_CIRCUITS_PATH: &str = "/TrainPositions/TrackCircuits?contentType=json";
+
+/// Size of the buffer a request's url is built in, the base url and path together.
+pub(super) const URL_LEN: usize = 128;
+
+/// Room the base url has to leave for the path: the longest fixed path, a bus stop search or
+/// predictions for a few stations at once all fit. Longer paths fail like any too long for `buf`.
+pub(super) const PATH_ROOM: usize = 64;
+
+/// Builds a url or path into a caller's buffer, since we can't use `format!()`.
+/// Anything that doesn't fit in `N` bytes is a [`core::fmt::Error`], same as `write!`.
+///
+/// [`UrlBuilder::raw`] is for text we control, like the base url and fixed paths. Everything
+/// added with [`UrlBuilder::query`] is percent-encoded, so free text like a bus route can't break
+/// the url. Station codes from [`UrlBuilder::stations_segment`] are letters and digits already.
+pub(super) struct UrlBuilder<'b, const N: usize> {
+    buf: &'b mut String<N>,
+    has_query: bool,
+}
+
+#[cfg_attr(
+    not(all(feature = "rail", feature = "incidents", feature = "bus")),
+    allow(
+        dead_code,
+        reason = "which methods are used depends on the endpoints enabled"
+    )
+)]
+impl<'b, const N: usize> UrlBuilder<'b, N> {
+    /// Starts over with an empty `buf`.
+    pub(super) fn new(buf: &'b mut String<N>) -> Self {
+        buf.clear();
+        Self {
+            buf,
+            has_query: false,
+        }
+    }
+
+    /// Appends `s` as is.
+    pub(super) fn raw(self, s: &str) -> Result<Self, core::fmt::Error> {
+        self.buf.push_str(s).map_err(|_| core::fmt::Error)?;
+        Ok(self)
+    }
+
+    /// Appends `/` and the codes of `stations` separated by commas, see [`Station::codes_into`].
+    #[cfg(feature = "rail")]
+    pub(super) fn stations_segment(self, stations: &[Station]) -> Result<Self, core::fmt::Error> {
+        self.buf.push('/').map_err(|_| core::fmt::Error)?;
+        Station::codes_into(stations, self.buf)?;
+
+        Ok(self)
+    }
+
+    /// Appends `key=value`, after a `?` for the first parameter and `&` for the rest.
+    pub(super) fn query(mut self, key: &str, value: &str) -> Result<Self, core::fmt::Error> {
+        let separator = if self.has_query { '&' } else { '?' };
+        self.buf.push(separator).map_err(|_| core::fmt::Error)?;
+        self.encode(key)?;
+        self.buf.push('=').map_err(|_| core::fmt::Error)?;
+        self.encode(value)?;
+        self.has_query = true;
+
+        Ok(self)
+    }
+
+    /// Same as [`UrlBuilder::query`], leaving the parameter out when `value` is `None`.
+    pub(super) fn query_opt(
+        self,
+        key: &str,
+        value: Option<&str>,
+    ) -> Result<Self, core::fmt::Error> {
+        match value {
+            Some(value) => self.query(key, value),
+            None => Ok(self),
+        }
+    }
+
+    pub(super) fn finish(self) -> &'b str {
+        self.buf
+    }
+
+    /// Unreserved characters (RFC 3986) go in as they are, every other byte as `%XX`.
+    fn encode(&mut self, s: &str) -> core::fmt::Result {
+        for b in s.bytes() {
+            if b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~') {
+                self.buf.push(b as char).map_err(|_| core::fmt::Error)?;
+            } else {
+                write!(self.buf, "%{b:02X}")?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub(super) fn build_url<'b>(
+    buf: &'b mut String<URL_LEN>,
+    api: &str,
+    path: &str,
+) -> Result<&'b str, core::fmt::Error> {
+    Ok(UrlBuilder::new(buf).raw(api)?.raw(path)?.finish())
+}
+
+/// The api takes several stations at once as a comma separated list. A list too long for `buf`
+/// is an error, never a path cut off partway through, which would ask for the wrong stations.
+#[cfg(feature = "rail")]
+pub(super) fn build_next_trains_path<'b>(
+    buf: &'b mut String<128>,
+    stations: &[Station],
+) -> Result<&'b str, core::fmt::Error> {
+    Ok(UrlBuilder::new(buf)
+        .raw("/StationPrediction.svc/json/GetPrediction")?
+        .stations_segment(stations)?
+        .finish())
+}
+
+#[cfg(feature = "rail")]
+pub(super) fn build_station_times_path(
     buf: &mut String<128>,
     station: Station,
 ) -> Result<&str, core::fmt::Error> {
-    buf.clear();
-    write!(
-        buf,
-        "{API}/StationPrediction.svc/json/GetPrediction/{}",
-        station.code()
-    )?;
-
-    Ok(buf)
+    Ok(UrlBuilder::new(buf)
+        .raw("/Rail.svc/json/jStationTimes")?
+        .query("StationCode", station.code())?
+        .finish())
+}
+
+/// `line` filters server-side, `None` asks for every station.
+#[cfg(feature = "rail")]
+pub(super) fn build_station_list_path(
+    buf: &mut String<128>,
+    line: Option<LineKind>,
+) -> Result<&str, core::fmt::Error> {
+    Ok(UrlBuilder::new(buf)
+        .raw("/Rail.svc/json/jStations")?
+        .query_opt("LineCode", line.as_ref().map(LineKind::code))?
+        .finish())
+}
+
+/// `station` filters server-side, `None` asks for every station.
+#[cfg(feature = "incidents")]
+pub(super) fn build_unit_outages_path(
+    buf: &mut String<128>,
+    station: Option<Station>,
+) -> Result<&str, core::fmt::Error> {
+    Ok(UrlBuilder::new(buf)
+        .raw("/Incidents.svc/json/ElevatorIncidents")?
+        .query_opt("StationCode", station.as_ref().map(Station::code))?
+        .finish())
+}
+
+#[cfg(feature = "bus")]
+pub(super) fn build_bus_positions_path<'b>(
+    buf: &'b mut String<128>,
+    route_id: Option<&str>,
+) -> Result<&'b str, core::fmt::Error> {
+    Ok(UrlBuilder::new(buf)
+        .raw("/Bus.svc/json/jBusPositions")?
+        .query_opt("RouteID", route_id)?
+        .finish())
+}
+
+/// Coordinates to 6 decimal places, about 10cm, which is as precise as WMATA's own.
+#[cfg(feature = "bus")]
+pub(super) fn build_bus_stops_path(
+    buf: &mut String<128>,
+    lat: f64,
+    lon: f64,
+    radius: u32,
+) -> Result<&str, core::fmt::Error> {
+    let mut lat_buf: String<16> = String::new();
+    let mut lon_buf: String<16> = String::new();
+    let mut radius_buf: String<10> = String::new();
+    write!(lat_buf, "{lat:.6}")?;
+    write!(lon_buf, "{lon:.6}")?;
+    write!(radius_buf, "{radius}")?;
+
+    Ok(UrlBuilder::new(buf)
+        .raw("/Bus.svc/json/jStops")?
+        .query("Lat", &lat_buf)?
+        .query("Lon", &lon_buf)?
+        .query("Radius", &radius_buf)?
+        .finish())
+}
+
+/// Whether every object and array opened in `json` is closed again, ignoring brackets inside
+/// strings. Doesn't check anything else, that's left to the json backend.
+pub(super) fn is_complete_json(json: &str) -> bool {
+    let mut depth = 0usize;
+    let mut in_string = false;
+    let mut escaped = false;
+
+    for b in json.bytes() {
+        if in_string {
+            match b {
+                _ if escaped => escaped = false,
+                b'\\' => escaped = true,
+                b'"' => in_string = false,
+                _ => {}
+            }
+            continue;
+        }
+
+        match b {
+            b'"' => in_string = true,
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' => match depth.checked_sub(1) {
+                Some(d) => depth = d,
+                None => return false,
+            },
+            _ => {}
+        }
+    }
+
+    !in_string && depth == 0
+}
+
+/// Everything after the host, e.g. to log which endpoint a request was for without the base url
+/// that's the same for all of them. `url` itself if it has no scheme.
+pub(super) fn url_path(url: &str) -> &str {
+    url.split_once("://")
+        .and_then(|(_, rest)| rest.find('/').map(|i| &rest[i..]))
+        .unwrap_or(url)
+}
+
+/// Check `url` is `http://` or `https://` followed by a non-empty host, and short enough to leave
+/// [`PATH_ROOM`] in a url, and strip any trailing `/`.
+pub(super) fn validate_base_url(url: &str) -> Option<&str> {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))?;
+    let host = rest.split(['/', ':']).next()?;
+    if host.is_empty() {
+        return None;
+    }
+
+    let url = url.trim_end_matches('/');
+    (url.len() <= URL_LEN - PATH_ROOM).then_some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn station_list_filters_by_line_in_the_query() {
+        let mut buf = String::new();
+        assert_eq!(
+            build_station_list_path(&mut buf, Some(LineKind::GN)).unwrap(),
+            "/Rail.svc/json/jStations?LineCode=GR"
+        );
+        assert_eq!(
+            build_station_list_path(&mut buf, None).unwrap(),
+            "/Rail.svc/json/jStations"
+        );
+    }
+
+    #[cfg(feature = "incidents")]
+    #[test]
+    fn unit_outages_filter_by_station_in_the_query() {
+        let mut buf = String::new();
+        assert_eq!(
+            build_unit_outages_path(&mut buf, Some(Station::K04)).unwrap(),
+            "/Incidents.svc/json/ElevatorIncidents?StationCode=K04"
+        );
+        assert_eq!(
+            build_unit_outages_path(&mut buf, None).unwrap(),
+            "/Incidents.svc/json/ElevatorIncidents"
+        );
+    }
+
+    #[cfg(feature = "bus")]
+    #[test]
+    fn bus_positions_filter_by_route_in_the_query() {
+        let mut buf = String::new();
+        assert_eq!(
+            build_bus_positions_path(&mut buf, Some("38B")).unwrap(),
+            "/Bus.svc/json/jBusPositions?RouteID=38B"
+        );
+        assert_eq!(
+            build_bus_positions_path(&mut buf, None).unwrap(),
+            "/Bus.svc/json/jBusPositions"
+        );
+    }
+
+    #[test]
+    fn builder_joins_segments_and_encodes_queries() {
+        let mut buf: String<128> = String::new();
+        let url = UrlBuilder::new(&mut buf)
+            .raw("http://api.example.com")
+            .and_then(|b| b.raw("/Bus.svc/json/jStops"))
+            .and_then(|b| b.query("Route", "S2 & S9/x"))
+            .and_then(|b| b.query_opt("Skipped", None))
+            .and_then(|b| b.query("n", "1~2"))
+            .unwrap()
+            .finish();
+        assert_eq!(
+            url,
+            "http://api.example.com/Bus.svc/json/jStops?Route=S2%20%26%20S9%2Fx&n=1~2"
+        );
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn builder_joins_station_codes() {
+        let mut buf: String<128> = String::new();
+        let path = build_next_trains_path(&mut buf, &[Station::K04, Station::A01]).unwrap();
+        assert_eq!(path, "/StationPrediction.svc/json/GetPrediction/K04,A01");
+    }
+
+    #[cfg(feature = "rail")]
+    #[test]
+    fn too_many_stations_is_an_error_not_a_cut_off_path() {
+        let stations = LineKind::OR.stations();
+        let mut buf: String<128> = String::new();
+
+        // 41 bytes of path, then 4 per code with its separator, less the first's
+        let path = build_next_trains_path(&mut buf, &stations[..21]).unwrap();
+        assert_eq!(path.len(), 125);
+        assert!(path.ends_with(
+            "/K08,K07,K06,K05,K04,K03,K02,K01,C05,C04,C03,C02,C01,D01,D02,D03,D04,D05,D06,D07,D08"
+        ));
+
+        assert_eq!(
+            build_next_trains_path(&mut buf, &stations[..22]),
+            Err(core::fmt::Error)
+        );
+        assert_eq!(
+            build_next_trains_path(&mut buf, stations),
+            Err(core::fmt::Error)
+        );
+    }
+
+    #[test]
+    fn builder_overflow_is_an_error() {
+        let mut buf: String<16> = String::new();
+        assert!(UrlBuilder::new(&mut buf).raw("/0123456789abcdef").is_err());
+        assert!(
+            UrlBuilder::new(&mut buf)
+                .raw("/jStops")
+                .and_then(|b| b.query("q", "a b c"))
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn complete_json_is_balanced_outside_strings() {
+        assert!(is_complete_json(r#"{"Trains":[{"Min":"3"}]}"#));
+        assert!(is_complete_json(r#"{"Name":"a } ] \" {"}"#));
+        assert!(!is_complete_json(r#"{"Trains":[{"Min":"3"}"#));
+        assert!(!is_complete_json(r#"{"Trains":[{"Min":"3"#));
+        assert!(!is_complete_json(r#"{"Name":"a \"}"#));
+        assert!(!is_complete_json("}{"));
+    }
+
+    #[test]
+    fn a_base_url_needs_a_scheme_a_host_and_room_for_a_path() {
+        assert_eq!(
+            validate_base_url("http://localhost:8080/"),
+            Some("http://localhost:8080")
+        );
+        assert_eq!(validate_base_url("api.wmata.com"), None);
+        assert_eq!(validate_base_url("https://"), None);
+        assert_eq!(validate_base_url("http://:8080/wmata"), None);
+
+        let mut long: String<URL_LEN> = String::new();
+        long.push_str("https://").unwrap();
+        while long.len() < URL_LEN - PATH_ROOM {
+            long.push('a').unwrap();
+        }
+        assert_eq!(validate_base_url(&long), Some(long.as_str()));
+        long.push('a').unwrap();
+        assert_eq!(validate_base_url(&long), None);
+    }
+
+    #[test]
+    fn fixed_paths_fit_in_the_room_left_for_them() {
+        let paths = [
+            "/Rail.svc/json/jLines",
+            #[cfg(feature = "positions")]
+            STANDARD_ROUTES_PATH,
+            #[cfg(feature = "positions")]
+            TRACK_CIRCUITS_PATH,
+            #[cfg(feature = "incidents")]
+            RAIL_INCIDENTS_PATH,
+        ];
+        for path in paths {
+            assert!(path.len() <= PATH_ROOM, "{path}");
+        }
+
+        #[cfg(feature = "bus")]
+        {
+            let mut buf = String::new();
+            let path = build_bus_stops_path(&mut buf, -38.898303, -177.028099, 300).unwrap();
+            assert!(path.len() <= PATH_ROOM, "{path}");
+        }
+    }
 }